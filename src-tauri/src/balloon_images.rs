@@ -0,0 +1,220 @@
+//! Balloon Images
+//!
+//! ゴーストが使うバルーン（ユーザーの指定、ghost の descript.txt の `balloon` の順）を探し、
+//! 画像を透過処理して `balloon://localhost/<ファイル名>` で返す。
+//! 見つからなければ既定のバルーンを使う。
+//!
+//! 文字の表示範囲などは画像の大きさで決まるため、画像ごとに解決した位置を
+//! [`BalloonMetrics`] にまとめてフロントエンドに渡す。
+
+use crate::surface_compositor::{self, Frame};
+use mascot_nanai_ui::balloon::{
+    Balloon, BalloonCursor, BalloonFont, BalloonImage, Color, Point, Rect,
+};
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// 既定のバルーン（assets/balloon の中）
+pub const DEFAULT_BALLOON: &str = "nanai_std";
+
+/// バルーンのディレクトリを探す
+///
+/// `name` はディレクトリ名か descript.txt の `name`。見つからなければ既定のバルーン。
+pub fn find_balloon(roots: &[PathBuf], name: Option<&str>) -> Option<PathBuf> {
+    let dirs: Vec<PathBuf> = roots
+        .iter()
+        .filter_map(|root| fs::read_dir(root).ok())
+        .flat_map(|entries| entries.filter_map(Result::ok))
+        .map(|entry| entry.path())
+        .filter(|path| path.is_dir())
+        .collect();
+    let matches = |dir: &Path, wanted: &str| {
+        dir.file_name()
+            .is_some_and(|file| file.to_string_lossy().eq_ignore_ascii_case(wanted))
+            || Balloon::load(dir)
+                .is_ok_and(|balloon| balloon.name().is_some_and(|name| name == wanted))
+    };
+
+    for wanted in [name, Some(DEFAULT_BALLOON)].into_iter().flatten() {
+        if let Some(dir) = dirs.iter().find(|dir| matches(dir, wanted)) {
+            return Some(dir.clone());
+        }
+        if name == Some(wanted) {
+            println!("⚠️ Balloon not found: {}", wanted);
+        }
+    }
+    None
+}
+
+/// 画像ごとの文字の位置など（座標は画像の左上から、負の値は解決済み）
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BalloonMetrics {
+    /// バルーンのディレクトリ名
+    pub balloon: String,
+    pub name: Option<String>,
+    /// `balloon://localhost/` に続けるファイル名
+    pub image: String,
+    pub width: u32,
+    pub height: u32,
+    pub text_rect: Rect,
+    pub line_height: i32,
+    pub font: BalloonFont,
+    pub anchor_font_color: Option<Color>,
+    pub cursor: BalloonCursor,
+    pub arrows: [Option<Point>; 2],
+    pub sstp_marker: Option<Point>,
+    pub sstp_message: Option<Point>,
+    /// 数字の表示（`number.xr` を右端から解決した位置）
+    pub number: Option<Point>,
+}
+
+/// 1つのバルーンの画像（透過処理した結果はキャッシュする）
+pub struct BalloonImages {
+    balloon: Balloon,
+    frames: Mutex<HashMap<String, Arc<Frame>>>,
+    pngs: Mutex<HashMap<String, Arc<Vec<u8>>>>,
+}
+
+impl BalloonImages {
+    pub fn load(dir: &Path) -> Result<Self, String> {
+        let balloon =
+            Balloon::load(dir).map_err(|e| format!("Failed to read balloon {:?}: {}", dir, e))?;
+        Ok(BalloonImages {
+            balloon,
+            frames: Mutex::new(HashMap::new()),
+            pngs: Mutex::new(HashMap::new()),
+        })
+    }
+
+    pub fn balloon(&self) -> &Balloon {
+        &self.balloon
+    }
+
+    /// 透過処理した画像
+    pub fn frame(&self, file: &str) -> Result<Arc<Frame>, String> {
+        let key = file.to_lowercase();
+        if let Some(frame) = self.frames.lock().get(&key) {
+            return Ok(frame.clone());
+        }
+        // バルーンのディレクトリ直下の画像だけを返す
+        let file = self
+            .balloon
+            .file(file)
+            .filter(|file| file.to_lowercase().ends_with(".png"))
+            .ok_or_else(|| format!("Balloon image not found: {}", file))?;
+        let frame = Arc::new(surface_compositor::load_image(&self.balloon.dir, file)?);
+        self.frames.lock().insert(key, frame.clone());
+        Ok(frame)
+    }
+
+    /// 透過処理した画像のPNG
+    pub fn png(&self, file: &str) -> Result<Arc<Vec<u8>>, String> {
+        let key = file.to_lowercase();
+        if let Some(png) = self.pngs.lock().get(&key) {
+            return Ok(png.clone());
+        }
+        let png = Arc::new(self.frame(file)?.to_png()?);
+        self.pngs.lock().insert(key, png.clone());
+        Ok(png)
+    }
+
+    /// 画像を選び、文字の位置などを解決する
+    pub fn metrics(&self, image: BalloonImage) -> Result<BalloonMetrics, String> {
+        let file = self
+            .balloon
+            .resolve(image)
+            .ok_or_else(|| format!("No balloon image for {:?}", image))?
+            .to_string();
+        let frame = self.frame(&file)?;
+        let descript = self
+            .balloon
+            .descript_for(&file)
+            .map_err(|e| format!("Failed to read descript for {}: {}", file, e))?;
+        let (width, height) = (frame.width as i32, frame.height as i32);
+        let resolve = |point: Option<Point>| point.map(|point| point.resolve(width, height));
+
+        Ok(BalloonMetrics {
+            balloon: self
+                .balloon
+                .dir
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default(),
+            name: self.balloon.name().map(str::to_string),
+            image: file,
+            width: frame.width,
+            height: frame.height,
+            text_rect: descript.text_rect(width, height),
+            line_height: descript.line_height(),
+            anchor_font_color: descript.anchor_font_color,
+            cursor: descript.cursor.clone(),
+            arrows: descript.arrows.map(resolve),
+            sstp_marker: resolve(descript.sstp_marker),
+            sstp_message: resolve(descript.sstp_message),
+            number: descript.number.as_ref().map(|number| Point {
+                x: width + number.x_right,
+                y: Point { x: 0, y: number.y }.resolve(width, height).y,
+            }),
+            font: descript.font,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_balloon_and_metrics() {
+        let temp = tempfile::tempdir().unwrap();
+        let root = temp.path();
+        let dir = root.join("Sample");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("descript.txt"),
+            "charset,UTF-8\nname,サンプル\nvalidrect.left,10\nvalidrect.top,8\nvalidrect.right,-10\n\
+             validrect.bottom,-20\narrow1.x,-12\narrow1.y,-16\n",
+        )
+        .unwrap();
+        let mut frame = Frame::new(100, 60);
+        frame.rgba.fill(255);
+        fs::write(dir.join("balloons0.png"), frame.to_png().unwrap()).unwrap();
+        fs::create_dir_all(root.join(DEFAULT_BALLOON)).unwrap();
+
+        let roots = [root.to_path_buf()];
+        assert_eq!(find_balloon(&roots, Some("サンプル")), Some(dir.clone()));
+        assert_eq!(find_balloon(&roots, Some("sample")), Some(dir.clone()));
+        assert_eq!(
+            find_balloon(&roots, Some("missing")),
+            Some(root.join(DEFAULT_BALLOON))
+        );
+
+        let images = BalloonImages::load(&dir).unwrap();
+        let metrics = images
+            .metrics(BalloonImage::Character {
+                scope: 0,
+                id: 1,
+                right: true,
+            })
+            .unwrap();
+        assert_eq!(metrics.image, "balloons0.png");
+        assert_eq!((metrics.width, metrics.height), (100, 60));
+        assert_eq!(
+            metrics.text_rect,
+            Rect {
+                left: 10,
+                top: 8,
+                right: 90,
+                bottom: 40,
+            }
+        );
+        assert_eq!(metrics.arrows[1], Some(Point { x: 88, y: 44 }));
+        assert!(images.metrics(BalloonImage::Online { id: 0 }).is_err());
+        assert!(images.png("../descript.txt").is_err());
+    }
+}
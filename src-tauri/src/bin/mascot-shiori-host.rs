@@ -0,0 +1,9 @@
+//! 1つのSHIORIを読み込み、標準入出力でリクエストを中継するヘルパー
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Err(e) = mascot_nanai_lib::shiori_host::run_host(&args) {
+        eprintln!("❌ {}", e);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,9 @@
+//! ゴーストのディレクトリから `.nar` を作るコマンド（`nar pack <ghost-dir>`）
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Err(e) = mascot_nanai_lib::nar_pack::run_cli(&args) {
+        eprintln!("❌ {}", e);
+        std::process::exit(1);
+    }
+}
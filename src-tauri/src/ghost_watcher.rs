@@ -0,0 +1,163 @@
+//! Ghost Watcher
+//!
+//! 読み込み中のゴーストのディレクトリを監視し、辞書やシェルが書き換えられたら知らせる。
+//! 保存のたびに何度も届く変更はまとめてから通知する。
+
+use crate::shiori_manager::GhostInfo;
+use notify_debouncer_mini::notify::{RecommendedWatcher, RecursiveMode};
+use notify_debouncer_mini::{DebounceEventResult, Debouncer, new_debouncer};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// 変更をまとめる時間
+pub const DEBOUNCE_MS: u64 = 500;
+
+/// SHIORIが自分で書き出すファイル（読み込み直しの繰り返しを防ぐ）
+const IGNORED_EXTENSIONS: [&str; 6] = ["log", "cfg", "sav", "bak", "tmp", "swp"];
+
+/// 変更の種類
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ReloadKind {
+    /// 辞書など ghost/ 以下（SHIORIを読み込み直す）
+    Shiori,
+    /// shell/ 以下（シェルの定義を読み直す）
+    Shell,
+}
+
+/// まとめた変更
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GhostChange {
+    pub ghost_id: String,
+    pub kind: ReloadKind,
+    pub paths: Vec<PathBuf>,
+}
+
+/// ゴーストのディレクトリの監視（破棄すると監視を止める）
+pub struct GhostWatcher {
+    ghost_id: String,
+    _debouncer: Debouncer<RecommendedWatcher>,
+}
+
+impl GhostWatcher {
+    /// 監視を始める（`on_change` は監視用のスレッドから呼ばれる）
+    pub fn watch<F>(ghost: &GhostInfo, on_change: F) -> Result<Self, String>
+    where
+        F: Fn(GhostChange) + Send + 'static,
+    {
+        let ghost_id = ghost.id.clone();
+        let root = ghost.path.clone();
+        let id = ghost_id.clone();
+        let mut debouncer = new_debouncer(
+            Duration::from_millis(DEBOUNCE_MS),
+            move |result: DebounceEventResult| match result {
+                Ok(events) => {
+                    // ディレクトリ自体の更新日時の変更は無視する
+                    let paths: Vec<PathBuf> = events
+                        .into_iter()
+                        .map(|event| event.path)
+                        .filter(|path| !path.is_dir())
+                        .collect();
+                    for change in changes(&id, &root, &paths) {
+                        on_change(change);
+                    }
+                }
+                Err(e) => println!("⚠️ Ghost watch error: {}", e),
+            },
+        )
+        .map_err(|e| format!("Failed to create ghost watcher: {}", e))?;
+
+        debouncer
+            .watcher()
+            .watch(&ghost.path, RecursiveMode::Recursive)
+            .map_err(|e| format!("Failed to watch {:?}: {}", ghost.path, e))?;
+        println!("👀 Watching ghost directory: {:?}", ghost.path);
+
+        Ok(GhostWatcher {
+            ghost_id,
+            _debouncer: debouncer,
+        })
+    }
+
+    pub fn ghost_id(&self) -> &str {
+        &self.ghost_id
+    }
+}
+
+/// 変更されたファイルを種類ごとにまとめる（SHIORIの変更を先に返す）
+pub fn changes(ghost_id: &str, root: &Path, paths: &[PathBuf]) -> Vec<GhostChange> {
+    [ReloadKind::Shiori, ReloadKind::Shell]
+        .into_iter()
+        .filter_map(|kind| {
+            let mut matched: Vec<PathBuf> = paths
+                .iter()
+                .filter(|path| classify(root, path) == Some(kind))
+                .cloned()
+                .collect();
+            matched.sort();
+            matched.dedup();
+            (!matched.is_empty()).then(|| GhostChange {
+                ghost_id: ghost_id.to_string(),
+                kind,
+                paths: matched,
+            })
+        })
+        .collect()
+}
+
+/// 変更されたファイルの種類（読み込み直さなくてよいファイルは `None`）
+pub fn classify(root: &Path, path: &Path) -> Option<ReloadKind> {
+    let relative = path.strip_prefix(root).ok()?;
+    let name = relative.file_name()?.to_string_lossy().to_lowercase();
+    if name.starts_with('.') || name.ends_with('~') || name.contains("savedata") {
+        return None;
+    }
+    let ignored = Path::new(&name)
+        .extension()
+        .is_some_and(|ext| IGNORED_EXTENSIONS.iter().any(|ignored| ext == *ignored));
+    if ignored {
+        return None;
+    }
+
+    match relative.components().next()?.as_os_str().to_str()? {
+        "ghost" => Some(ReloadKind::Shiori),
+        "shell" => Some(ReloadKind::Shell),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_changes_are_grouped_by_kind() {
+        let root = Path::new("/ghost/nanai");
+        let paths: Vec<PathBuf> = [
+            "shell/master/surfaces.txt",
+            "ghost/master/dic_talk.txt",
+            "ghost/master/satori_savedata.txt",
+            "ghost/master/yaya.log",
+            "ghost/master/.dic_talk.txt.swp",
+            "ghost/master/dic_talk.txt",
+            "shell/master/surface0.png",
+            "readme.txt",
+        ]
+        .iter()
+        .map(|path| root.join(path))
+        .collect();
+
+        let changes = changes("nanai", root, &paths);
+        assert_eq!(changes.len(), 2);
+        assert_eq!(changes[0].kind, ReloadKind::Shiori);
+        assert_eq!(
+            changes[0].paths,
+            vec![root.join("ghost/master/dic_talk.txt")]
+        );
+        assert_eq!(changes[1].kind, ReloadKind::Shell);
+        assert_eq!(changes[1].paths.len(), 2);
+
+        assert_eq!(classify(root, Path::new("/elsewhere/ghost/x.txt")), None);
+    }
+}
@@ -1,6 +1,6 @@
 use mascot_nanai_ui::open_shift_jis_file;
 // Learn more about Tauri commands at https://tauri.app/develop/calling-rust/
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use tauri::Emitter;
 use tauri::Manager;
 
@@ -12,32 +12,112 @@ use tauri::menu::MenuBuilder;
 #[cfg(desktop)]
 use tauri::tray::{TrayIconBuilder, TrayIconEvent};
 
-// SHIORI関連モジュール（一時的にコメントアウト）
-// mod shiori_cpp_integration;
-// mod shiori_manager;
+// SHIORI関連モジュール
+pub mod balloon_images;
+pub mod ghost_watcher;
+pub mod mouse_events;
+pub mod nar;
+pub mod nar_pack;
+pub mod network_update;
+pub mod sakura_playback;
+pub mod sakura_variables;
+pub mod shiori_charset;
+pub mod shiori_cpp_integration;
+pub mod shiori_engine;
+pub mod shiori_ffi;
+pub mod shiori_host;
+pub mod shiori_legacy;
+pub mod shiori_manager;
+pub mod shiori_mock;
+pub mod shiori_process;
+pub mod shiori_protocol;
+pub mod shiori_satori;
+pub mod surface_compositor;
+pub mod update_list;
 
-// use shiori_manager::{GhostInfo, ShioriManager};
-
-// 簡易ゴースト情報
-#[derive(Debug, Clone, serde::Serialize)]
-struct GhostInfo {
-    name: String,
-    path: String,
-}
+use balloon_images::{BalloonImages, BalloonMetrics};
+use ghost_watcher::{GhostChange, GhostWatcher, ReloadKind};
+use mascot_nanai_ui::balloon::BalloonImage;
+use mascot_nanai_ui::sakura_env;
+use mascot_nanai_ui::sakura_player::PlayerEvent;
+use mouse_events::{MouseInput, MouseTracker, StrokeConfig};
+use nar::{InstallReport, NarInstaller};
+use network_update::{NetworkUpdater, UpdateReport};
+use sakura_playback::SakuraPlayback;
+use sakura_variables::EnvironmentSettings;
+use shiori_manager::{GhostInfo, GhostScanError, GhostScanReport, ShioriManager};
+use shiori_mock::MockShiori;
+use std::sync::Arc;
+use surface_compositor::SurfaceCompositor;
 
 // アプリケーション状態を定義
 struct AppState {
     recent_files: std::sync::Mutex<Vec<String>>,
-    // shiori_manager: Arc<ShioriManager>,
+    shiori_manager: Arc<ShioriManager>,
+    /// さくらスクリプトの環境変数に使う設定
+    environment: std::sync::Mutex<EnvironmentSettings>,
+    /// 現在のゴーストのディレクトリの監視
+    ghost_watcher: std::sync::Mutex<Option<GhostWatcher>>,
+    /// 現在のゴーストのシェルの合成（ゴーストIDと組）
+    surface_compositor: std::sync::Mutex<Option<(String, Arc<SurfaceCompositor>)>>,
+    /// マウスが乗っている当たり判定
+    mouse_tracker: std::sync::Mutex<MouseTracker>,
+    /// ユーザーが選んだバルーン（無ければゴーストの descript.txt の `balloon`）
+    balloon_override: std::sync::Mutex<Option<String>>,
+    /// 使っているバルーンの画像（ディレクトリと組）
+    balloon_images: std::sync::Mutex<Option<(PathBuf, Arc<BalloonImages>)>>,
 }
 
 impl AppState {
     fn new() -> Self {
         AppState {
             recent_files: std::sync::Mutex::new(Vec::new()),
-            // shiori_manager: ShioriManager::new(),
+            shiori_manager: ShioriManager::new(),
+            environment: std::sync::Mutex::new(EnvironmentSettings::default()),
+            ghost_watcher: std::sync::Mutex::new(None),
+            surface_compositor: std::sync::Mutex::new(None),
+            mouse_tracker: std::sync::Mutex::new(MouseTracker::new()),
+            balloon_override: std::sync::Mutex::new(None),
+            balloon_images: std::sync::Mutex::new(None),
+        }
+    }
+
+    /// 現在のゴーストのシェルの合成（ゴーストが変わったら読み込み直す）
+    fn surface_compositor(&self) -> Result<Arc<SurfaceCompositor>, String> {
+        let ghost = self
+            .shiori_manager
+            .current_ghost()
+            .and_then(|ghost_id| self.shiori_manager.get_ghost_info(&ghost_id))
+            .ok_or("No ghost loaded")?;
+        let mut current = self
+            .surface_compositor
+            .lock()
+            .map_err(|e| format!("Failed to lock surface compositor: {}", e))?;
+        match current.as_ref() {
+            Some((ghost_id, compositor)) if *ghost_id == ghost.id => Ok(compositor.clone()),
+            _ => {
+                let compositor = Arc::new(SurfaceCompositor::load(&ghost.shell_dir())?);
+                *current = Some((ghost.id, compositor.clone()));
+                Ok(compositor)
+            }
         }
     }
+
+    /// スクリプトの環境変数を、現在のゴーストと時刻で展開する
+    fn expand_variables(&self, script: &str) -> String {
+        let ghost = self
+            .shiori_manager
+            .current_ghost()
+            .and_then(|name| self.shiori_manager.get_ghost_info(&name));
+        let settings = self
+            .environment
+            .lock()
+            .map(|settings| settings.clone())
+            .unwrap_or_default();
+        let environment =
+            sakura_variables::environment(ghost.as_ref(), &settings, sakura_variables::local_now());
+        sakura_env::expand_script(script, &environment)
+    }
 }
 
 // エラーメッセージを全ウィンドウにemitするヘルパー関数
@@ -118,14 +198,44 @@ fn get_recent_files(app_handle: tauri::AppHandle<tauri::Wry>) -> Result<Vec<Stri
 #[derive(Debug, Clone, serde::Serialize)]
 struct ScanResult {
     ghosts: Vec<GhostInfo>,
+    /// 解析できなかったディレクトリ
+    errors: Vec<GhostScanError>,
     resolved_path: String,
     input_path: String,
 }
 
+/// 同梱のゴーストのディレクトリ
+const GHOST_ASSET_DIR: &str = "assets/ghost";
+
+/// ゴーストを探すディレクトリを追加する環境変数（`PATH` と同じ区切り）
+const GHOST_PATH_ENV: &str = "MASCOT_GHOST_PATH";
+
+/// ゴーストを探すディレクトリ
+///
+/// 同梱のゴースト、アプリのデータディレクトリの ghost、
+/// 環境変数 `MASCOT_GHOST_PATH` で指定したディレクトリの順。
+fn ghost_roots(app_handle: &tauri::AppHandle) -> Vec<PathBuf> {
+    let mut roots = Vec::new();
+    match resolve_asset_path(GHOST_ASSET_DIR, app_handle) {
+        Ok(root) => roots.push(root),
+        Err(e) => println!("⚠️ Ghost asset directory not resolved: {}", e),
+    }
+    if let Ok(install_root) = install_root(app_handle) {
+        let installed = install_root.join("ghost");
+        if installed.is_dir() {
+            roots.push(installed);
+        }
+    }
+    if let Some(paths) = std::env::var_os(GHOST_PATH_ENV) {
+        roots.extend(std::env::split_paths(&paths));
+    }
+    roots
+}
+
 /// ゴーストディレクトリをスキャンしてSHIORIを検出
 #[tauri::command]
 async fn scan_ghost_directory(
-    _state: tauri::State<'_, AppState>,
+    state: tauri::State<'_, AppState>,
     ghost_dir: String,
     app_handle: tauri::AppHandle,
 ) -> Result<ScanResult, String> {
@@ -135,20 +245,10 @@ async fn scan_ghost_directory(
     println!("🔍 Input path: {}", ghost_dir);
     println!("🔍 Resolved absolute path: {:?}", ghost_path);
 
-    // 簡易ゴーストスキャン（mock_nanaiのみ検出）
-    let mut ghosts = Vec::new();
-
-    // mock_nanaiが存在するかチェック
-    let mock_nanai_path = ghost_path.join("mock_nanai");
-    if mock_nanai_path.exists() {
-        ghosts.push(GhostInfo {
-            name: "mock_nanai".to_string(),
-            path: mock_nanai_path.to_string_lossy().to_string(),
-        });
-    }
-
+    let report = state.shiori_manager.scan_ghost_directory(&ghost_path);
     let result = ScanResult {
-        ghosts,
+        ghosts: report.ghosts,
+        errors: report.errors,
         resolved_path: ghost_path.to_string_lossy().to_string(),
         input_path: ghost_dir,
     };
@@ -158,6 +258,181 @@ async fn scan_ghost_directory(
     Ok(result)
 }
 
+/// 同梱のバルーンのディレクトリ
+const BALLOON_ASSET_DIR: &str = "assets/balloon";
+
+/// バルーンを探すディレクトリ（同梱のバルーン、アプリのデータディレクトリの balloon の順）
+fn balloon_roots(app_handle: &tauri::AppHandle) -> Vec<PathBuf> {
+    let mut roots = Vec::new();
+    match resolve_asset_path(BALLOON_ASSET_DIR, app_handle) {
+        Ok(root) => roots.push(root),
+        Err(e) => println!("⚠️ Balloon asset directory not resolved: {}", e),
+    }
+    if let Ok(install_root) = install_root(app_handle) {
+        roots.push(install_root.join("balloon"));
+    }
+    roots
+}
+
+/// 現在のゴーストのバルーン（選ばれたバルーンが変わったら読み込み直す）
+fn current_balloon(app_handle: &tauri::AppHandle) -> Result<Arc<BalloonImages>, String> {
+    let state = app_handle.state::<AppState>();
+    let name = state
+        .balloon_override
+        .lock()
+        .map_err(|e| format!("Failed to lock balloon override: {}", e))?
+        .clone()
+        .or_else(|| {
+            state
+                .shiori_manager
+                .current_ghost()
+                .and_then(|ghost_id| state.shiori_manager.get_ghost_info(&ghost_id))
+                .and_then(|ghost| ghost.descript.balloon)
+        });
+    let dir = balloon_images::find_balloon(&balloon_roots(app_handle), name.as_deref())
+        .ok_or("No balloon found")?;
+
+    let mut current = state
+        .balloon_images
+        .lock()
+        .map_err(|e| format!("Failed to lock balloon images: {}", e))?;
+    match current.as_ref() {
+        Some((current_dir, images)) if *current_dir == dir => Ok(images.clone()),
+        _ => {
+            println!("🎈 Loading balloon: {:?}", dir);
+            let images = Arc::new(BalloonImages::load(&dir)?);
+            *current = Some((dir, images.clone()));
+            Ok(images)
+        }
+    }
+}
+
+/// バルーンを選ぶ（`None` ならゴーストの既定のバルーンに戻す）
+#[tauri::command]
+async fn set_balloon(
+    state: tauri::State<'_, AppState>,
+    name: Option<String>,
+) -> Result<(), String> {
+    *state
+        .balloon_override
+        .lock()
+        .map_err(|e| format!("Failed to lock balloon override: {}", e))? = name;
+    Ok(())
+}
+
+/// バルーンの画像を選び、文字を置く範囲などを返す（画像は `balloon://localhost/<image>`）
+#[tauri::command]
+async fn get_balloon_metrics(
+    app_handle: tauri::AppHandle,
+    image: BalloonImage,
+) -> Result<BalloonMetrics, String> {
+    current_balloon(&app_handle)?.metrics(image)
+}
+
+/// `.nar` のインストール先（ghost/ balloon/ plugin/ を置く）
+fn install_root(app_handle: &tauri::AppHandle) -> Result<PathBuf, String> {
+    app_handle
+        .path()
+        .app_data_dir()
+        .map_err(|e| format!("Failed to get app data directory: {}", e))
+}
+
+/// `.nar` をインストールして、結果を現在のゴーストに知らせる
+///
+/// 成功したら `OnInstallComplete`、失敗したら `OnInstallFailure` を送り、応答を再生する。
+/// 展開と再スキャンは時間がかかるので、ブロックしてよいスレッドで行う。
+#[tauri::command]
+async fn install_nar(path: String, app_handle: tauri::AppHandle) -> Result<InstallReport, String> {
+    tauri::async_runtime::spawn_blocking(move || -> Result<InstallReport, String> {
+        let state = app_handle.state::<AppState>();
+        let playback = app_handle.state::<SakuraPlayback>();
+        let manager = &state.shiori_manager;
+        let ghosts: Vec<GhostInfo> = manager.get_all_ghosts().into_values().collect();
+        let current_ghost = manager
+            .current_ghost()
+            .and_then(|ghost_id| manager.get_ghost_info(&ghost_id));
+        let root = install_root(&app_handle)?;
+        let installer = NarInstaller {
+            root: &root,
+            ghosts: &ghosts,
+            current_ghost: current_ghost.as_ref(),
+        };
+
+        let (event, references, result) = match installer.install(Path::new(&path)) {
+            Ok(report) => {
+                manager.scan_ghost_roots(&ghost_roots(&app_handle));
+                // 同じディレクトリのバルーンが入れ替わっているかもしれない
+                if let Ok(mut images) = state.balloon_images.lock() {
+                    *images = None;
+                }
+                ("OnInstallComplete", report.references(), Ok(report))
+            }
+            Err(e) => {
+                println!("❌ NAR install failed: {}", e);
+                let reason = e.reason.as_str().to_string();
+                ("OnInstallFailure", vec![reason], Err(e.to_string()))
+            }
+        };
+        if manager.is_shiori_loaded() {
+            let references: Vec<&str> = references.iter().map(String::as_str).collect();
+            match manager.send_event(event, &references) {
+                Ok(response) => play_response(&state, &playback, response.value()),
+                Err(e) => println!("⚠️ {} failed: {}", event, e),
+            }
+        }
+        result
+    })
+    .await
+    .map_err(|e| format!("NAR install task failed: {}", e))?
+}
+
+/// 現在のゴーストをネットワーク更新
+///
+/// `homeurl` はSHIORIのリソースを優先し、無ければ descript.txt を使う。
+/// 更新したファイルはゴーストの監視で読み込み直される。
+/// ダウンロードは待ち時間が長いので、ブロックしてよいスレッドで行う。
+#[tauri::command]
+async fn network_update(app_handle: tauri::AppHandle) -> Result<UpdateReport, String> {
+    tauri::async_runtime::spawn_blocking(move || -> Result<UpdateReport, String> {
+        let state = app_handle.state::<AppState>();
+        let playback = app_handle.state::<SakuraPlayback>();
+        let manager = &state.shiori_manager;
+        let ghost = manager
+            .current_ghost()
+            .and_then(|ghost_id| manager.get_ghost_info(&ghost_id))
+            .ok_or("No ghost loaded")?;
+        let homeurl = manager
+            .request(&ghost.id, &shiori_protocol::ShioriRequest::get("homeurl"))
+            .ok()
+            .and_then(|response| response.value().map(str::to_string))
+            .filter(|homeurl| !homeurl.is_empty())
+            .or_else(|| ghost.descript.homeurl.clone())
+            .ok_or("The ghost has no homeurl")?;
+
+        NetworkUpdater::new()
+            .update(&ghost, &homeurl, |event, references| {
+                match manager.send_event_to(&ghost.id, event, references) {
+                    Ok(response) => play_response(&state, &playback, response.value()),
+                    Err(e) => println!("⚠️ {} failed: {}", event, e),
+                }
+            })
+            .map_err(|e| e.to_string())
+    })
+    .await
+    .map_err(|e| format!("Network update task failed: {}", e))?
+}
+
+/// ゴーストを探すディレクトリをすべてスキャン
+#[tauri::command]
+async fn scan_all_ghosts(
+    state: tauri::State<'_, AppState>,
+    app_handle: tauri::AppHandle,
+) -> Result<GhostScanReport, String> {
+    Ok(state
+        .shiori_manager
+        .scan_ghost_roots(&ghost_roots(&app_handle)))
+}
+
 /// アセットパスを解決（開発/本番環境対応）
 fn resolve_asset_path(
     relative_path: &str,
@@ -244,48 +519,95 @@ fn normalize_path(path: &PathBuf) -> PathBuf {
     }
 }
 
-/// ゴーストを読み込み（簡易版）
+/// ゴーストを読み込み（ゴーストIDで指定）
 #[tauri::command]
 async fn load_ghost(
-    _state: tauri::State<'_, AppState>,
-    ghost_name: String,
+    state: tauri::State<'_, AppState>,
+    ghost_id: String,
+    app_handle: tauri::AppHandle,
 ) -> Result<String, String> {
-    println!("📥 Loading ghost: {}", ghost_name);
-    Ok(format!(
-        "Ghost '{}' loaded successfully (simplified)",
-        ghost_name
-    ))
+    println!("📥 Loading ghost: {}", ghost_id);
+    state.shiori_manager.load_ghost(&ghost_id)?;
+    watch_current_ghost(&app_handle);
+    Ok(format!("Ghost '{}' loaded successfully", ghost_id))
 }
 
-/// SHIORIにリクエストを送信（簡易版）
+/// SHIORIにリクエストを送信（ワイヤー形式のレスポンスを返す）
 #[tauri::command]
 async fn send_shiori_request(
-    _state: tauri::State<'_, AppState>,
+    state: tauri::State<'_, AppState>,
     request: String,
 ) -> Result<String, String> {
     println!("📤 SHIORI Request: {}", request);
-    Ok("Hello from simplified SHIORI!".to_string())
+    let response = state.shiori_manager.send_request(&request)?;
+    Ok(response.to_wire())
 }
 
-/// SHIORIにイベントを送信（簡易版）
+/// SHIORIにイベントを送信（返ってきたスクリプトは `sakura-event` で届く）
 #[tauri::command]
 async fn send_shiori_event(
-    _state: tauri::State<'_, AppState>,
+    state: tauri::State<'_, AppState>,
+    playback: tauri::State<'_, SakuraPlayback>,
     event: String,
-) -> Result<String, String> {
+) -> Result<(), String> {
     println!("📤 SHIORI Event: {}", event);
-    match event.as_str() {
-        "OnMouseClick" => Ok("クリックされました！".to_string()),
-        "OnBoot" => Ok("こんにちは！マスコットナナイです。".to_string()),
-        _ => Ok("こんにちは！".to_string()),
+    let response = state.shiori_manager.send_event(&event, &[])?;
+    play_response(&state, &playback, response.value());
+    Ok(())
+}
+
+/// マウス操作を当たり判定付きのSHIORIのイベントにして送る（返ってきたスクリプトは `sakura-event` で届く）
+#[tauri::command]
+async fn mouse_event(
+    state: tauri::State<'_, AppState>,
+    playback: tauri::State<'_, SakuraPlayback>,
+    input: MouseInput,
+) -> Result<(), String> {
+    let collision = state
+        .surface_compositor()
+        .ok()
+        .and_then(|compositor| compositor.hit_test(input.surface, input.x, input.y));
+    let events = state
+        .mouse_tracker
+        .lock()
+        .map_err(|e| format!("Failed to lock mouse tracker: {}", e))?
+        .handle(&input, collision.as_deref());
+
+    // 1つのイベントが失敗しても残りは送る
+    for event in events {
+        if event.id != "OnMouseMove" {
+            println!("🖱️ {} {:?}", event.id, event.references);
+        }
+        match state.shiori_manager.on_mouse_event(&event) {
+            Ok(response) => play_response(&state, &playback, response.value()),
+            Err(e) => println!("⚠️ {} failed: {}", event.id, e),
+        }
     }
+    Ok(())
 }
 
-/// マウスクリックイベントを送信（簡易版）
+/// なでなでの認識の設定を取得
 #[tauri::command]
-async fn on_mouse_click() -> Result<String, String> {
-    println!("🖱️ Mouse click event");
-    Ok("Mouse clicked".to_string())
+async fn get_stroke_config(state: tauri::State<'_, AppState>) -> Result<StrokeConfig, String> {
+    let tracker = state
+        .mouse_tracker
+        .lock()
+        .map_err(|e| format!("Failed to lock mouse tracker: {}", e))?;
+    Ok(tracker.stroke_config().clone())
+}
+
+/// なでなでの認識の設定を変更（送るイベント名・折り返しの回数など）
+#[tauri::command]
+async fn set_stroke_config(
+    state: tauri::State<'_, AppState>,
+    config: StrokeConfig,
+) -> Result<(), String> {
+    state
+        .mouse_tracker
+        .lock()
+        .map_err(|e| format!("Failed to lock mouse tracker: {}", e))?
+        .set_stroke_config(config);
+    Ok(())
 }
 
 /// 秒数変化イベントを送信（簡易版）
@@ -295,32 +617,181 @@ async fn on_second_change() -> Result<String, String> {
     Ok("Second changed".to_string())
 }
 
-/// 現在のゴースト情報を取得（簡易版）
+/// 現在のゴースト情報を取得
 #[tauri::command]
-async fn get_current_ghost(
-    _state: tauri::State<'_, AppState>,
-) -> Result<Option<GhostInfo>, String> {
-    // 実際にはゴーストが選択されていない場合はNoneを返す
-    Ok(None)
+async fn get_current_ghost(state: tauri::State<'_, AppState>) -> Result<Option<GhostInfo>, String> {
+    let manager = &state.shiori_manager;
+    Ok(manager
+        .current_ghost()
+        .and_then(|ghost_id| manager.get_ghost_info(&ghost_id)))
 }
 
-/// すべてのゴースト情報を取得（簡易版）
+/// すべてのゴースト情報を取得（ID順）
 #[tauri::command]
-async fn get_all_ghosts(_state: tauri::State<'_, AppState>) -> Result<Vec<GhostInfo>, String> {
-    // 実際のスキャン結果のみを返す（空の場合は空配列）
-    Ok(vec![])
+async fn get_all_ghosts(state: tauri::State<'_, AppState>) -> Result<Vec<GhostInfo>, String> {
+    let mut ghosts: Vec<GhostInfo> = state
+        .shiori_manager
+        .get_all_ghosts()
+        .into_values()
+        .collect();
+    ghosts.sort_by(|a, b| a.id.cmp(&b.id));
+    Ok(ghosts)
 }
 
-/// SHIORIの状態を取得（簡易版）
+/// SHIORIの状態を取得
 #[tauri::command]
-async fn get_shiori_status(_state: tauri::State<'_, AppState>) -> Result<bool, String> {
-    Ok(true) // 常にロード済みとする
+async fn get_shiori_status(state: tauri::State<'_, AppState>) -> Result<bool, String> {
+    Ok(state.shiori_manager.is_shiori_loaded())
 }
 
-/// 現在のゴーストを終了（簡易版）
+/// 現在のゴーストを終了
 #[tauri::command]
-async fn unload_current_ghost(_state: tauri::State<'_, AppState>) -> Result<String, String> {
-    Ok("Ghost unloaded successfully (simplified)".to_string())
+async fn unload_current_ghost(
+    state: tauri::State<'_, AppState>,
+    app_handle: tauri::AppHandle,
+) -> Result<String, String> {
+    state.shiori_manager.unload_current_ghost()?;
+    watch_current_ghost(&app_handle);
+    Ok("Ghost unloaded successfully".to_string())
+}
+
+/// `ghost-reloaded` イベントの内容
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct GhostReloaded {
+    ghost_id: String,
+    kind: ReloadKind,
+    /// 変更されたファイル
+    paths: Vec<PathBuf>,
+    /// 読み込み直せなかった理由
+    error: Option<String>,
+}
+
+/// 現在のゴーストのディレクトリを監視する（ゴーストが変わったら監視し直す）
+fn watch_current_ghost(app_handle: &tauri::AppHandle) {
+    let state = app_handle.state::<AppState>();
+    let ghost = state
+        .shiori_manager
+        .current_ghost()
+        .and_then(|ghost_id| state.shiori_manager.get_ghost_info(&ghost_id));
+    let Ok(mut watcher) = state.ghost_watcher.lock() else {
+        return;
+    };
+    if watcher.as_ref().map(GhostWatcher::ghost_id) == ghost.as_ref().map(|g| g.id.as_str()) {
+        return;
+    }
+
+    *watcher = None;
+    let Some(ghost) = ghost else {
+        return;
+    };
+    load_seriko_shell(app_handle);
+    let handle = app_handle.clone();
+    match GhostWatcher::watch(&ghost, move |change| reload_ghost(&handle, change)) {
+        Ok(new_watcher) => *watcher = Some(new_watcher),
+        Err(e) => println!("⚠️ Ghost hot-reload disabled: {}", e),
+    }
+}
+
+/// 現在のゴーストのシェルをアニメーション（`seriko-event`）に使う
+fn load_seriko_shell(app_handle: &tauri::AppHandle) {
+    let Some(playback) = app_handle.try_state::<SakuraPlayback>() else {
+        return;
+    };
+    match app_handle.state::<AppState>().surface_compositor() {
+        Ok(compositor) => playback.set_shell(compositor.definition().clone()),
+        Err(e) => println!("⚠️ SERIKO disabled: {}", e),
+    }
+}
+
+/// 変更されたゴーストを読み込み直して `ghost-reloaded` を送る
+fn reload_ghost(app_handle: &tauri::AppHandle, change: GhostChange) {
+    println!(
+        "🔄 Ghost files changed ({:?}): {:?}",
+        change.kind, change.paths
+    );
+    let manager = &app_handle.state::<AppState>().shiori_manager;
+    let result = match change.kind {
+        // 前回の読み込み直しで終了していれば、もう一度読み込む
+        ReloadKind::Shiori if !manager.loaded_ghosts().contains(&change.ghost_id) => {
+            manager.load_ghost(&change.ghost_id)
+        }
+        ReloadKind::Shiori => manager.reload_ghost(&change.ghost_id),
+        ReloadKind::Shell => {
+            // シェルは次に表示するときに読み込み直す
+            if let Ok(mut compositor) = app_handle.state::<AppState>().surface_compositor.lock() {
+                *compositor = None;
+            }
+            let result = manager.refresh_ghost_info(&change.ghost_id).map(|_| ());
+            load_seriko_shell(app_handle);
+            result
+        }
+    };
+    if let Err(e) = &result {
+        println!("❌ {}", e);
+    }
+
+    let reloaded = GhostReloaded {
+        ghost_id: change.ghost_id,
+        kind: change.kind,
+        paths: change.paths,
+        error: result.err(),
+    };
+    if let Err(e) = app_handle.emit("ghost-reloaded", reloaded) {
+        eprintln!("emit failed: {e}");
+    }
+}
+
+/// `surface://localhost/<サーフェスID>` で現在のゴーストの合成したサーフェスを返す
+fn surface_protocol(
+    app_handle: &tauri::AppHandle,
+    request: tauri::http::Request<Vec<u8>>,
+) -> tauri::http::Response<Vec<u8>> {
+    let response = |status: u16, content_type: &str, body: Vec<u8>| {
+        tauri::http::Response::builder()
+            .status(status)
+            .header("Content-Type", content_type)
+            .header("Access-Control-Allow-Origin", "*")
+            .body(body)
+            .unwrap_or_default()
+    };
+    let Ok(surface_id) = request.uri().path().trim_matches('/').parse::<u32>() else {
+        return response(400, "text/plain", b"Invalid surface id".to_vec());
+    };
+    let png = app_handle
+        .state::<AppState>()
+        .surface_compositor()
+        .and_then(|compositor| compositor.png(surface_id));
+    match png {
+        Ok(png) => response(200, "image/png", png.to_vec()),
+        Err(e) => {
+            println!("⚠️ surface{}: {}", surface_id, e);
+            response(404, "text/plain", e.into_bytes())
+        }
+    }
+}
+
+/// `balloon://localhost/<ファイル名>` で現在のバルーンの透過処理した画像を返す
+fn balloon_protocol(
+    app_handle: &tauri::AppHandle,
+    request: tauri::http::Request<Vec<u8>>,
+) -> tauri::http::Response<Vec<u8>> {
+    let response = |status: u16, content_type: &str, body: Vec<u8>| {
+        tauri::http::Response::builder()
+            .status(status)
+            .header("Content-Type", content_type)
+            .header("Access-Control-Allow-Origin", "*")
+            .body(body)
+            .unwrap_or_default()
+    };
+    let file = request.uri().path().trim_matches('/');
+    match current_balloon(app_handle).and_then(|images| images.png(file)) {
+        Ok(png) => response(200, "image/png", png.to_vec()),
+        Err(e) => {
+            println!("⚠️ balloon {}: {}", file, e);
+            response(404, "text/plain", e.into_bytes())
+        }
+    }
 }
 
 /// 簡易ゴーストスキャン（JavaScriptから呼び出し用）
@@ -334,6 +805,98 @@ async fn scan_ghosts(
     scan_ghost_directory(state, ghost_path, app_handle).await
 }
 
+/// さくらスクリプトを再生（イベントは `sakura-event` で届く、戻り値は再生の番号）
+#[tauri::command]
+async fn play_sakura_script(
+    state: tauri::State<'_, AppState>,
+    playback: tauri::State<'_, SakuraPlayback>,
+    script: String,
+) -> Result<u64, String> {
+    Ok(playback.play(&state.expand_variables(&script)))
+}
+
+/// 環境変数（`%username` `%screenwidth` など）の設定を更新
+#[tauri::command]
+async fn set_environment_settings(
+    state: tauri::State<'_, AppState>,
+    settings: EnvironmentSettings,
+) -> Result<(), String> {
+    let mut environment = state
+        .environment
+        .lock()
+        .map_err(|e| format!("Failed to lock environment settings: {}", e))?;
+    *environment = settings;
+    Ok(())
+}
+
+/// 選択肢・アンカーが選ばれた（SHIORIに送り、返ってきたスクリプトを再生する）
+#[tauri::command]
+async fn select_sakura_choice(
+    state: tauri::State<'_, AppState>,
+    playback: tauri::State<'_, SakuraPlayback>,
+    generation: u64,
+    index: usize,
+) -> Result<(), String> {
+    let choice = playback
+        .select_choice(generation, index)
+        .ok_or_else(|| format!("Choice {} is no longer available", index))?;
+    println!("👉 Choice selected: {} ({})", choice.title, choice.id);
+
+    let response = state.shiori_manager.send_choice(&choice)?;
+    play_response(&state, &playback, response.value());
+    Ok(())
+}
+
+/// SHIORIが返したスクリプトがあれば環境変数を展開して再生する
+fn play_response(state: &AppState, playback: &SakuraPlayback, script: Option<&str>) {
+    if let Some(script) = script.filter(|script| !script.is_empty()) {
+        playback.play(&state.expand_variables(script));
+    }
+}
+
+/// バルーンのクリック（クリック待ちの解除・表示の早送り）
+#[tauri::command]
+async fn sakura_click(playback: tauri::State<'_, SakuraPlayback>) -> Result<(), String> {
+    playback.click();
+    Ok(())
+}
+
+/// さくらスクリプトの再生を一時停止
+#[tauri::command]
+async fn pause_sakura_script(playback: tauri::State<'_, SakuraPlayback>) -> Result<(), String> {
+    playback.pause();
+    Ok(())
+}
+
+/// さくらスクリプトの再生を再開
+#[tauri::command]
+async fn resume_sakura_script(playback: tauri::State<'_, SakuraPlayback>) -> Result<(), String> {
+    playback.resume();
+    Ok(())
+}
+
+/// 開発用モックSHIORIのフィクスチャ
+const MOCK_SHIORI_FIXTURE: &str = "assets/ghost/mock_shiori/ghost/master/mock_shiori.toml";
+
+/// モックSHIORIを読み込む
+///
+/// 環境変数 `MASCOT_MOCK_SHIORI` を指定したときだけ読み込む（通常のゴーストを隠さないため）。
+/// 値が `1` なら同梱のフィクスチャ、それ以外はフィクスチャのパス。
+fn load_mock_shiori(app_handle: &tauri::AppHandle) -> Result<(), String> {
+    let fixture = match std::env::var_os(shiori_mock::FIXTURE_ENV) {
+        None => return Ok(()),
+        Some(value) if value == "1" => resolve_asset_path(MOCK_SHIORI_FIXTURE, app_handle)?,
+        Some(path) => PathBuf::from(path),
+    };
+
+    println!("🎭 Loading mock SHIORI: {:?}", fixture);
+    let engine = MockShiori::from_file(&fixture)?;
+    app_handle
+        .state::<AppState>()
+        .shiori_manager
+        .attach_engine(MockShiori::ghost_info(&fixture), Box::new(engine))
+}
+
 /// デバッグ用のテストコマンド
 #[tauri::command]
 async fn test_command() -> Result<String, String> {
@@ -379,9 +942,66 @@ pub fn run() {
 
                 app.set_menu(menu)?;
             }
+
+            // SHIORIホストがあれば、SHIORIは別プロセスで動かす（クラッシュしても本体は落ちない）
+            if let Some(host) = shiori_process::default_host_executable() {
+                println!("🧩 Using SHIORI host: {:?}", host);
+                let app_handle = app.app_handle().clone();
+                let on_crash: shiori_process::CrashHandler =
+                    Arc::new(move |msg| emit_error_to_all(&app_handle, msg));
+                app.state::<AppState>()
+                    .shiori_manager
+                    .set_engine_factory(shiori_process::engine_factory(host, on_crash));
+            }
+
+            let app_handle = app.app_handle().clone();
+            let seriko_handle = app.app_handle().clone();
+            let seriko_sink: sakura_playback::SerikoSink = Box::new(move |event| {
+                if let Err(e) = seriko_handle.emit("seriko-event", event) {
+                    eprintln!("emit failed: {e}");
+                }
+            });
+            app.manage(SakuraPlayback::spawn(
+                Box::new(move |event| {
+                    if let PlayerEvent::ChoiceTimeout { script } = &event.event {
+                        let state = app_handle.state::<AppState>();
+                        match state.shiori_manager.on_choice_timeout(script) {
+                            Ok(response) => play_response(
+                                &state,
+                                &app_handle.state::<SakuraPlayback>(),
+                                response.value(),
+                            ),
+                            Err(e) => println!("⚠️ OnChoiceTimeout failed: {}", e),
+                        }
+                    }
+                    if let Err(e) = app_handle.emit("sakura-event", event) {
+                        eprintln!("emit failed: {e}");
+                    }
+                }),
+                seriko_sink,
+            ));
+
+            let report = app
+                .state::<AppState>()
+                .shiori_manager
+                .scan_ghost_roots(&ghost_roots(app.app_handle()));
+            for error in &report.errors {
+                println!("⚠️ Ghost not loaded {:?}: {}", error.path, error.error);
+            }
+
+            if let Err(e) = load_mock_shiori(app.app_handle()) {
+                println!("⚠️ Mock SHIORI not loaded: {}", e);
+            }
+            watch_current_ghost(app.app_handle());
             Ok(())
         })
         .manage(AppState::new())
+        .register_uri_scheme_protocol("surface", |context, request| {
+            surface_protocol(context.app_handle(), request)
+        })
+        .register_uri_scheme_protocol("balloon", |context, request| {
+            balloon_protocol(context.app_handle(), request)
+        })
         .plugin(tauri_plugin_opener::init())
         .invoke_handler(tauri::generate_handler![
             open_file,
@@ -389,15 +1009,28 @@ pub fn run() {
             get_recent_files,
             scan_ghost_directory,
             scan_ghosts,
+            scan_all_ghosts,
+            install_nar,
+            network_update,
             load_ghost,
             send_shiori_request,
             send_shiori_event,
-            on_mouse_click,
+            mouse_event,
+            get_stroke_config,
+            set_stroke_config,
             on_second_change,
             get_current_ghost,
             get_all_ghosts,
             get_shiori_status,
             unload_current_ghost,
+            play_sakura_script,
+            set_environment_settings,
+            select_sakura_choice,
+            sakura_click,
+            pause_sakura_script,
+            resume_sakura_script,
+            set_balloon,
+            get_balloon_metrics,
             test_command
         ])
         .run(tauri::generate_context!())
@@ -0,0 +1,449 @@
+//! Mouse Events
+//!
+//! フロントエンドのマウス操作を、当たり判定の名前を付けたSHIORIのイベント
+//! （`OnMouseClick` `OnMouseMove` `OnMouseEnter` など）に変換する。
+//! 同じ当たり判定の上で往復させる「なでなで」などの操作もここで認識する。
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::Instant;
+
+/// マウス操作の種類
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum MouseAction {
+    Move,
+    Down,
+    Up,
+    Click,
+    DoubleClick,
+    Wheel,
+    /// キャラクターの外に出た
+    Leave,
+}
+
+impl MouseAction {
+    /// SHIORIのイベント名
+    pub fn event_id(&self) -> &'static str {
+        match self {
+            MouseAction::Move => "OnMouseMove",
+            MouseAction::Down => "OnMouseDown",
+            MouseAction::Up => "OnMouseUp",
+            MouseAction::Click => "OnMouseClick",
+            MouseAction::DoubleClick => "OnMouseDoubleClick",
+            MouseAction::Wheel => "OnMouseWheel",
+            MouseAction::Leave => "OnMouseLeave",
+        }
+    }
+}
+
+/// フロントエンドから届くマウス操作（座標はサーフェスの画像上の位置）
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MouseInput {
+    pub action: MouseAction,
+    pub scope: u32,
+    /// 表示中のサーフェス
+    pub surface: u32,
+    pub x: i32,
+    pub y: i32,
+    /// ホイールの回転量（奥へ回すと正）
+    #[serde(default)]
+    pub wheel: i32,
+    /// 0が左、1が右、2が中ボタン
+    #[serde(default)]
+    pub button: u32,
+    /// `mouse` `touch` など
+    #[serde(default = "default_device")]
+    pub device: String,
+}
+
+fn default_device() -> String {
+    "mouse".to_string()
+}
+
+/// SHIORIに送るイベント
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MouseEvent {
+    pub id: String,
+    /// x、y、ホイール、キャラクター、当たり判定の名前、ボタン、入力デバイス
+    pub references: [String; 7],
+}
+
+impl MouseEvent {
+    pub fn new(id: &str, input: &MouseInput, collision: &str) -> Self {
+        MouseEvent {
+            id: id.to_string(),
+            references: [
+                input.x.to_string(),
+                input.y.to_string(),
+                input.wheel.to_string(),
+                input.scope.to_string(),
+                collision.to_string(),
+                input.button.to_string(),
+                input.device.clone(),
+            ],
+        }
+    }
+
+    pub fn references(&self) -> Vec<&str> {
+        self.references.iter().map(String::as_str).collect()
+    }
+}
+
+/// マウスの動きから認識する操作（なでなでなど）
+pub trait Gesture: Send {
+    /// マウス操作を1つ渡す（`collision` はその位置の当たり判定の名前、無ければ空文字列）
+    ///
+    /// 操作を認識したら、SHIORIに送るイベントを返す。
+    fn feed(&mut self, input: &MouseInput, collision: &str, now_ms: u64) -> Option<MouseEvent>;
+}
+
+/// なでなでの設定
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct StrokeConfig {
+    /// 送るイベント（Referenceは `OnMouseMove` と同じ）
+    pub event: String,
+    /// 何回折り返したらなでたとみなすか
+    pub reversals: u32,
+    /// 折り返しとみなす前に、同じ向きに動かす距離（ピクセル）
+    pub min_distance: i32,
+    /// この時間動かなければ数え直す（ミリ秒）
+    pub timeout_ms: u64,
+}
+
+impl Default for StrokeConfig {
+    fn default() -> Self {
+        StrokeConfig {
+            event: "OnMouseStroke".to_string(),
+            reversals: 4,
+            min_distance: 8,
+            timeout_ms: 1000,
+        }
+    }
+}
+
+/// 1つの軸の動き
+#[derive(Debug, Clone, Copy, Default)]
+struct Axis {
+    /// 動いている向き（-1、0、1）
+    direction: i32,
+    /// その向きに動いた距離
+    run: i32,
+    /// 逆向きに戻した距離（`min_distance` に届いたら折り返し）
+    back: i32,
+}
+
+impl Axis {
+    /// 動きを足して、折り返したかを返す
+    fn advance(&mut self, delta: i32, min_distance: i32) -> bool {
+        if delta == 0 {
+            return false;
+        }
+        if self.direction == 0 || delta.signum() == self.direction {
+            self.direction = delta.signum();
+            self.run += delta.abs();
+            self.back = 0;
+            return false;
+        }
+        self.back += delta.abs();
+        if self.back < min_distance {
+            return false;
+        }
+        let reversed = self.run >= min_distance;
+        *self = Axis {
+            direction: delta.signum(),
+            run: self.back,
+            back: 0,
+        };
+        reversed
+    }
+}
+
+/// なでている最中の状態
+#[derive(Debug, Clone, Default)]
+struct StrokeState {
+    collision: String,
+    last: (i32, i32),
+    last_at: u64,
+    x: Axis,
+    y: Axis,
+    reversals: u32,
+}
+
+/// 同じ当たり判定の上で往復させる「なでなで」を認識する
+#[derive(Debug, Default)]
+pub struct Stroke {
+    config: StrokeConfig,
+    /// キャラクターごとの状態
+    states: HashMap<u32, StrokeState>,
+}
+
+impl Stroke {
+    pub fn new(config: StrokeConfig) -> Self {
+        Stroke {
+            config,
+            states: HashMap::new(),
+        }
+    }
+
+    pub fn config(&self) -> &StrokeConfig {
+        &self.config
+    }
+
+    pub fn set_config(&mut self, config: StrokeConfig) {
+        self.config = config;
+        self.states.clear();
+    }
+}
+
+impl Gesture for Stroke {
+    fn feed(&mut self, input: &MouseInput, collision: &str, now_ms: u64) -> Option<MouseEvent> {
+        if collision.is_empty() {
+            self.states.remove(&input.scope);
+            return None;
+        }
+        if input.action != MouseAction::Move {
+            return None;
+        }
+
+        let state = self.states.entry(input.scope).or_default();
+        if state.collision != collision
+            || now_ms.saturating_sub(state.last_at) > self.config.timeout_ms
+        {
+            *state = StrokeState {
+                collision: collision.to_string(),
+                last: (input.x, input.y),
+                ..StrokeState::default()
+            };
+        }
+        let (dx, dy) = (input.x - state.last.0, input.y - state.last.1);
+        state.last = (input.x, input.y);
+        state.last_at = now_ms;
+        // 縦と横のどちらかで折り返せば1回
+        let reversed_x = state.x.advance(dx, self.config.min_distance);
+        let reversed_y = state.y.advance(dy, self.config.min_distance);
+        if reversed_x || reversed_y {
+            state.reversals += 1;
+        }
+        if state.reversals < self.config.reversals.max(1) {
+            return None;
+        }
+
+        state.reversals = 0;
+        Some(MouseEvent::new(&self.config.event, input, collision))
+    }
+}
+
+/// キャラクターごとに、マウスが乗っている当たり判定を覚えておく
+pub struct MouseTracker {
+    hover: HashMap<u32, String>,
+    stroke: Stroke,
+    /// 追加の操作の認識
+    gestures: Vec<Box<dyn Gesture>>,
+    origin: Instant,
+}
+
+impl Default for MouseTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MouseTracker {
+    pub fn new() -> Self {
+        MouseTracker {
+            hover: HashMap::new(),
+            stroke: Stroke::default(),
+            gestures: Vec::new(),
+            origin: Instant::now(),
+        }
+    }
+
+    /// 操作の認識を追加する
+    pub fn add_gesture(&mut self, gesture: Box<dyn Gesture>) {
+        self.gestures.push(gesture);
+    }
+
+    pub fn stroke_config(&self) -> &StrokeConfig {
+        self.stroke.config()
+    }
+
+    pub fn set_stroke_config(&mut self, config: StrokeConfig) {
+        self.stroke.set_config(config);
+    }
+
+    /// マウス操作をイベントにする（`collision` はその位置の当たり判定の名前）
+    ///
+    /// 当たり判定が変わったら、操作のイベントの前に `OnMouseLeave` `OnMouseEnter` を入れ、
+    /// なでなでなどを認識したら操作のイベントの後に入れる。
+    pub fn handle(&mut self, input: &MouseInput, collision: Option<&str>) -> Vec<MouseEvent> {
+        let now_ms = self.origin.elapsed().as_millis() as u64;
+        self.handle_at(input, collision, now_ms)
+    }
+
+    fn handle_at(
+        &mut self,
+        input: &MouseInput,
+        collision: Option<&str>,
+        now_ms: u64,
+    ) -> Vec<MouseEvent> {
+        let mut events = Vec::new();
+        let collision = match input.action {
+            MouseAction::Leave => "",
+            _ => collision.unwrap_or_default(),
+        };
+        let previous = match input.action {
+            MouseAction::Leave => self.hover.remove(&input.scope),
+            _ => self.hover.insert(input.scope, collision.to_string()),
+        }
+        .unwrap_or_default();
+        if previous != collision {
+            if !previous.is_empty() {
+                events.push(MouseEvent::new("OnMouseLeave", input, &previous));
+            }
+            if !collision.is_empty() {
+                events.push(MouseEvent::new("OnMouseEnter", input, collision));
+            }
+        }
+        if input.action != MouseAction::Leave {
+            events.push(MouseEvent::new(input.action.event_id(), input, collision));
+        }
+
+        let gestures = std::iter::once(&mut self.stroke as &mut dyn Gesture)
+            .chain(self.gestures.iter_mut().map(|gesture| gesture.as_mut()));
+        for gesture in gestures {
+            events.extend(gesture.feed(input, collision, now_ms));
+        }
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn input(action: MouseAction, x: i32) -> MouseInput {
+        MouseInput {
+            action,
+            scope: 1,
+            surface: 10,
+            x,
+            y: 20,
+            wheel: 0,
+            button: 0,
+            device: default_device(),
+        }
+    }
+
+    fn ids(events: &[MouseEvent]) -> Vec<(&str, &str)> {
+        events
+            .iter()
+            .map(|event| (event.id.as_str(), event.references[4].as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn test_enter_leave_and_references() {
+        let mut tracker = MouseTracker::new();
+
+        assert_eq!(
+            ids(&tracker.handle(&input(MouseAction::Move, 5), None)),
+            vec![("OnMouseMove", "")]
+        );
+        assert_eq!(
+            ids(&tracker.handle(&input(MouseAction::Move, 40), Some("Head"))),
+            vec![("OnMouseEnter", "Head"), ("OnMouseMove", "Head")]
+        );
+        assert_eq!(
+            ids(&tracker.handle(&input(MouseAction::Move, 80), Some("Bust"))),
+            vec![
+                ("OnMouseLeave", "Head"),
+                ("OnMouseEnter", "Bust"),
+                ("OnMouseMove", "Bust")
+            ]
+        );
+
+        let mut wheel = input(MouseAction::Wheel, 80);
+        wheel.wheel = -120;
+        wheel.device = "touch".to_string();
+        assert_eq!(
+            tracker.handle(&wheel, Some("Bust"))[0].references(),
+            vec!["80", "20", "-120", "1", "Bust", "0", "touch"]
+        );
+
+        assert_eq!(
+            ids(&tracker.handle(&input(MouseAction::Leave, 200), None)),
+            vec![("OnMouseLeave", "Bust")]
+        );
+        assert!(
+            tracker
+                .handle(&input(MouseAction::Leave, 200), None)
+                .is_empty()
+        );
+
+        let click: MouseInput = serde_json::from_str(
+            r#"{"action":"doubleClick","scope":0,"surface":0,"x":1,"y":2,"button":1}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            tracker.handle(&click, None)[0],
+            MouseEvent {
+                id: "OnMouseDoubleClick".to_string(),
+                references: ["1", "2", "0", "0", "", "1", "mouse"].map(String::from),
+            }
+        );
+    }
+
+    /// ダブルクリックだけを数える操作（追加の認識のテスト用）
+    struct DoubleClicks(u32);
+
+    impl Gesture for DoubleClicks {
+        fn feed(&mut self, input: &MouseInput, collision: &str, _: u64) -> Option<MouseEvent> {
+            if input.action != MouseAction::DoubleClick {
+                return None;
+            }
+            self.0 += 1;
+            (self.0 == 2).then(|| MouseEvent::new("OnMouseTwice", input, collision))
+        }
+    }
+
+    #[test]
+    fn test_stroke_and_gestures() {
+        let mut tracker = MouseTracker::new();
+        tracker.set_stroke_config(StrokeConfig {
+            reversals: 3,
+            ..StrokeConfig::default()
+        });
+        let stroke = |tracker: &mut MouseTracker, xs: &[i32], collision, now_ms| {
+            xs.iter()
+                .flat_map(|x| {
+                    tracker.handle_at(&input(MouseAction::Move, *x), Some(collision), now_ms)
+                })
+                .filter(|event| event.id == "OnMouseStroke")
+                .collect::<Vec<_>>()
+        };
+
+        // 小さく揺れただけでは折り返しに数えない
+        assert!(stroke(&mut tracker, &[0, 10, 20, 18, 20, 30, 0], "Head", 0).is_empty());
+        let events = stroke(&mut tracker, &[20, 0], "Head", 100);
+        assert_eq!(
+            ids(&events),
+            vec![("OnMouseStroke", "Head")],
+            "3回目の折り返しでなでたとみなす"
+        );
+
+        // 時間が空いたり、別の当たり判定に移ったりしたら数え直す
+        assert!(stroke(&mut tracker, &[20, 0, 20], "Head", 1200).is_empty());
+        assert!(stroke(&mut tracker, &[0, 20], "Bust", 1300).is_empty());
+
+        tracker.add_gesture(Box::new(DoubleClicks(0)));
+        let double_click = input(MouseAction::DoubleClick, 0);
+        tracker.handle_at(&double_click, Some("Bust"), 1400);
+        assert_eq!(
+            ids(&tracker.handle_at(&double_click, Some("Bust"), 1500)),
+            vec![("OnMouseDoubleClick", "Bust"), ("OnMouseTwice", "Bust")]
+        );
+    }
+}
@@ -0,0 +1,792 @@
+//! NAR Installer
+//!
+//! ゴースト・シェル・バルーンなどを配布する `.nar`（中身はzip）をインストールする。
+//! install.txt の `type` でインストール先を決め、既にあれば上書きする（delete.txt のファイルは削除）。
+//!
+//! ```text
+//! <root>/ghost/<directory>                 ghost
+//! <root>/ghost/<ゴースト>/shell/<directory>  shell（accept のゴースト、無ければ現在のゴースト）
+//! <root>/ghost/<ゴースト>                    supplement（ゴーストのディレクトリ名で <root> に置く）
+//! <root>/balloon/<directory>               balloon、ghost に同梱された balloon.directory
+//! <root>/plugin/<directory>                plugin
+//! ```
+
+use crate::shiori_manager::GhostInfo;
+use encoding_rs::SHIFT_JIS;
+use mascot_nanai_ui::descript::Descript;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io::{Cursor, Read, Seek};
+use std::path::{Path, PathBuf};
+
+pub const INSTALL_TXT: &str = "install.txt";
+pub const DELETE_TXT: &str = "delete.txt";
+
+/// 展開するファイルの合計サイズの上限
+pub const MAX_EXTRACTED_SIZE: u64 = 1024 * 1024 * 1024;
+
+/// package の中に入れられる package の深さ（package の中の package は拒否する）
+pub const MAX_PACKAGE_DEPTH: usize = 1;
+
+/// install.txt の `type`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NarType {
+    Ghost,
+    Shell,
+    Balloon,
+    Supplement,
+    Plugin,
+    /// 複数の `.nar` をまとめたもの
+    Package,
+}
+
+impl NarType {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "ghost" => Some(NarType::Ghost),
+            "shell" => Some(NarType::Shell),
+            "balloon" => Some(NarType::Balloon),
+            "supplement" => Some(NarType::Supplement),
+            "plugin" => Some(NarType::Plugin),
+            "package" => Some(NarType::Package),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            NarType::Ghost => "ghost",
+            NarType::Shell => "shell",
+            NarType::Balloon => "balloon",
+            NarType::Supplement => "supplement",
+            NarType::Plugin => "plugin",
+            NarType::Package => "package",
+        }
+    }
+}
+
+/// install.txt の内容
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InstallInfo {
+    pub kind: NarType,
+    pub name: String,
+    /// インストール先のディレクトリ名（supplement と package では省略できる）
+    pub directory: Option<String>,
+    /// shell・supplement を受け入れるゴーストの名前
+    pub accept: Option<String>,
+    /// ghost に同梱されたバルーンのインストール先
+    pub balloon_directory: Option<String>,
+    /// 同梱されたバルーンのアーカイブ内の場所（省略時は `balloon_directory`）
+    pub balloon_source_directory: Option<String>,
+}
+
+impl InstallInfo {
+    pub fn parse(bytes: &[u8]) -> Result<Self, InstallError> {
+        let descript = Descript::from_bytes(bytes);
+        let kind = descript.kind.as_deref().unwrap_or_default();
+        let kind = NarType::parse(kind).ok_or_else(|| {
+            InstallError::new(
+                FailureReason::InvalidType,
+                format!("Unknown install type: {:?}", kind),
+            )
+        })?;
+
+        let directory = match descript.get("directory") {
+            Some(directory) => Some(directory_name(directory)?),
+            None if matches!(kind, NarType::Supplement | NarType::Package) => None,
+            None => {
+                return Err(InstallError::new(
+                    FailureReason::InvalidType,
+                    "install.txt has no directory",
+                ));
+            }
+        };
+        let balloon_directory = descript
+            .get("balloon.directory")
+            .map(directory_name)
+            .transpose()?;
+        let balloon_source_directory = descript
+            .get("balloon.source.directory")
+            .map(archive_path)
+            .transpose()?;
+
+        Ok(InstallInfo {
+            kind,
+            name: descript
+                .name
+                .clone()
+                .or(directory.clone())
+                .unwrap_or_default(),
+            directory,
+            accept: descript.get("accept").map(str::to_string),
+            balloon_directory,
+            balloon_source_directory,
+        })
+    }
+}
+
+/// インストールに失敗した理由（`OnInstallFailure` の Reference0）
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum FailureReason {
+    /// アーカイブを展開できない
+    Extraction,
+    /// install.txt が無い・`type` が分からない
+    InvalidType,
+    /// 危険なパスを含む・受け入れるゴーストが無い
+    Rejected,
+}
+
+impl FailureReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FailureReason::Extraction => "unzip",
+            FailureReason::InvalidType => "invalid type",
+            FailureReason::Rejected => "artificial",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InstallError {
+    pub reason: FailureReason,
+    pub message: String,
+}
+
+impl InstallError {
+    fn new(reason: FailureReason, message: impl Into<String>) -> Self {
+        InstallError {
+            reason,
+            message: message.into(),
+        }
+    }
+
+    fn io(context: &str, path: &Path, e: std::io::Error) -> Self {
+        Self::new(
+            FailureReason::Extraction,
+            format!("{} {:?}: {}", context, path, e),
+        )
+    }
+}
+
+impl fmt::Display for InstallError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.message, self.reason.as_str())
+    }
+}
+
+/// 展開した `.nar` の中身（パスは `/` 区切り）
+#[derive(Debug, Clone, Default)]
+pub struct NarArchive {
+    pub files: BTreeMap<String, Vec<u8>>,
+}
+
+impl NarArchive {
+    pub fn open(path: &Path) -> Result<Self, InstallError> {
+        let file = fs::File::open(path).map_err(|e| InstallError::io("Failed to open", path, e))?;
+        Self::read(file)
+    }
+
+    pub fn from_bytes(bytes: Vec<u8>) -> Result<Self, InstallError> {
+        Self::read(Cursor::new(bytes))
+    }
+
+    /// zipを読み込む（危険なパスが1つでもあれば何も展開しない）
+    pub fn read(reader: impl Read + Seek) -> Result<Self, InstallError> {
+        Self::read_limited(reader, MAX_EXTRACTED_SIZE)
+    }
+
+    /// 展開したサイズの合計が `limit` を超えるzipは拒否する
+    pub fn read_limited(reader: impl Read + Seek, limit: u64) -> Result<Self, InstallError> {
+        let unzip = |e: zip::result::ZipError| {
+            InstallError::new(FailureReason::Extraction, format!("Broken archive: {}", e))
+        };
+        let mut zip = zip::ZipArchive::new(reader).map_err(unzip)?;
+        let mut files = BTreeMap::new();
+        let mut total = 0u64;
+        for index in 0..zip.len() {
+            let mut entry = zip.by_index(index).map_err(unzip)?;
+            // 古いnarはファイル名がShift_JIS
+            let name = match std::str::from_utf8(entry.name_raw()) {
+                Ok(name) => name.to_string(),
+                Err(_) => SHIFT_JIS.decode(entry.name_raw()).0.into_owned(),
+            };
+            let path = archive_path(&name)?;
+            if entry.is_dir() || path.is_empty() {
+                continue;
+            }
+            // ヘッダーのサイズは偽れるので、読んだ量でも確かめる
+            let remaining = limit - total;
+            let too_large = || {
+                InstallError::new(
+                    FailureReason::Rejected,
+                    format!("Archive exceeds {} bytes when extracted", limit),
+                )
+            };
+            if entry.size() > remaining {
+                return Err(too_large());
+            }
+            let mut data = Vec::new();
+            (&mut entry)
+                .take(remaining + 1)
+                .read_to_end(&mut data)
+                .map_err(|e| {
+                    InstallError::new(
+                        FailureReason::Extraction,
+                        format!("Failed to extract {}: {}", name, e),
+                    )
+                })?;
+            if data.len() as u64 > remaining {
+                return Err(too_large());
+            }
+            total += data.len() as u64;
+            files.insert(path, data);
+        }
+        Ok(NarArchive { files })
+    }
+
+    /// 展開したファイルの合計サイズ
+    pub fn size(&self) -> u64 {
+        self.files.values().map(|data| data.len() as u64).sum()
+    }
+
+    /// ファイルを探す（大文字小文字は区別しない）
+    pub fn get(&self, path: &str) -> Option<&[u8]> {
+        self.files
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(path))
+            .map(|(_, data)| data.as_slice())
+    }
+
+    pub fn install_info(&self) -> Result<InstallInfo, InstallError> {
+        let bytes = self.get(INSTALL_TXT).ok_or_else(|| {
+            InstallError::new(FailureReason::InvalidType, "install.txt not found")
+        })?;
+        InstallInfo::parse(bytes)
+    }
+
+    /// delete.txt に書かれたパス
+    pub fn delete_list(&self) -> Result<Vec<String>, InstallError> {
+        let Some(bytes) = self.get(DELETE_TXT) else {
+            return Ok(Vec::new());
+        };
+        let text = match std::str::from_utf8(bytes) {
+            Ok(text) => text.to_string(),
+            Err(_) => SHIFT_JIS.decode(bytes).0.into_owned(),
+        };
+        text.trim_start_matches('\u{feff}')
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with("//"))
+            .map(archive_path)
+            .filter(|path| !matches!(path.as_deref(), Ok("")))
+            .collect()
+    }
+}
+
+/// インストールした結果
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct InstallReport {
+    pub kind: NarType,
+    pub name: String,
+    /// インストールしたディレクトリ
+    pub path: PathBuf,
+    /// 既にあったディレクトリに上書きした
+    pub upgraded: bool,
+    /// ghost に同梱されていたバルーン
+    pub balloon: Option<Box<InstallReport>>,
+    /// package に含まれていたもの
+    pub items: Vec<InstallReport>,
+}
+
+impl InstallReport {
+    /// `OnInstallComplete` のリファレンス
+    pub fn references(&self) -> Vec<String> {
+        match &self.balloon {
+            Some(balloon) => vec![
+                format!("{},balloon", self.kind.as_str()),
+                self.name.clone(),
+                balloon.name.clone(),
+            ],
+            None => vec![self.kind.as_str().to_string(), self.name.clone()],
+        }
+    }
+}
+
+/// インストール先
+pub struct NarInstaller<'a> {
+    /// ghost/ balloon/ plugin/ を置くディレクトリ
+    pub root: &'a Path,
+    /// shell・supplement を受け入れるゴーストの候補
+    pub ghosts: &'a [GhostInfo],
+    /// `accept` が無いときのインストール先
+    pub current_ghost: Option<&'a GhostInfo>,
+}
+
+impl NarInstaller<'_> {
+    /// `.nar` ファイルをインストール
+    pub fn install(&self, nar_path: &Path) -> Result<InstallReport, InstallError> {
+        println!("📦 Installing NAR: {:?}", nar_path);
+        self.install_archive(&NarArchive::open(nar_path)?)
+    }
+
+    pub fn install_archive(&self, archive: &NarArchive) -> Result<InstallReport, InstallError> {
+        self.install_nested(
+            archive,
+            MAX_EXTRACTED_SIZE.saturating_sub(archive.size()),
+            0,
+        )
+    }
+
+    /// `budget` は package の中の `.nar` を展開してよい残りのサイズ、`depth` は package の深さ
+    fn install_nested(
+        &self,
+        archive: &NarArchive,
+        budget: u64,
+        depth: usize,
+    ) -> Result<InstallReport, InstallError> {
+        let info = archive.install_info()?;
+        let deletes = archive.delete_list()?;
+        let directory = info.directory.as_deref().unwrap_or_default();
+
+        let path = match info.kind {
+            NarType::Ghost => self.root.join("ghost").join(directory),
+            NarType::Balloon => self.root.join("balloon").join(directory),
+            NarType::Plugin => self.root.join("plugin").join(directory),
+            NarType::Shell => self.ghost_dir(&info)?.join("shell").join(directory),
+            NarType::Supplement => self.ghost_dir(&info)?,
+            NarType::Package => return self.install_package(archive, info, budget, depth),
+        };
+
+        // ghost に同梱されたバルーンは別の場所にインストールする
+        let balloon_source = match (info.kind, &info.balloon_directory) {
+            (NarType::Ghost, Some(directory)) => Some(
+                info.balloon_source_directory
+                    .clone()
+                    .unwrap_or_else(|| directory.clone()),
+            ),
+            _ => None,
+        };
+        let files: Vec<(&str, &[u8])> = archive
+            .files
+            .iter()
+            .filter(|(name, _)| {
+                balloon_source
+                    .as_deref()
+                    .is_none_or(|source| !is_under(name, source))
+            })
+            .map(|(name, data)| (name.as_str(), data.as_slice()))
+            .collect();
+        let upgraded = write_tree(&path, &files, &deletes)?;
+
+        let balloon = match (&balloon_source, &info.balloon_directory) {
+            (Some(source), Some(directory)) => {
+                let files: Vec<(&str, &[u8])> = archive
+                    .files
+                    .iter()
+                    .filter_map(|(name, data)| {
+                        let name = name.strip_prefix(source.as_str())?.strip_prefix('/')?;
+                        Some((name, data.as_slice()))
+                    })
+                    .collect();
+                if files.is_empty() {
+                    None
+                } else {
+                    let path = self.root.join("balloon").join(directory);
+                    let upgraded = write_tree(&path, &files, &[])?;
+                    Some(Box::new(InstallReport {
+                        kind: NarType::Balloon,
+                        name: directory.clone(),
+                        path,
+                        upgraded,
+                        balloon: None,
+                        items: Vec::new(),
+                    }))
+                }
+            }
+            _ => None,
+        };
+
+        println!(
+            "✅ Installed {} {:?} to {:?}",
+            info.kind.as_str(),
+            info.name,
+            path
+        );
+        Ok(InstallReport {
+            kind: info.kind,
+            name: info.name,
+            path,
+            upgraded,
+            balloon,
+            items: Vec::new(),
+        })
+    }
+
+    /// package に含まれる `.nar` を順にインストールする
+    ///
+    /// 中の `.nar` を展開したサイズは合わせて `budget` まで。
+    fn install_package(
+        &self,
+        archive: &NarArchive,
+        info: InstallInfo,
+        mut budget: u64,
+        depth: usize,
+    ) -> Result<InstallReport, InstallError> {
+        if depth >= MAX_PACKAGE_DEPTH {
+            return Err(InstallError::new(
+                FailureReason::Rejected,
+                "Packages inside packages are not supported",
+            ));
+        }
+        let mut items = Vec::new();
+        for (name, data) in &archive.files {
+            if name.to_lowercase().ends_with(".nar") {
+                let nested = NarArchive::read_limited(Cursor::new(data.as_slice()), budget)?;
+                budget -= nested.size();
+                items.push(self.install_nested(&nested, budget, depth + 1)?);
+            }
+        }
+        if items.is_empty() {
+            return Err(InstallError::new(
+                FailureReason::InvalidType,
+                "Package contains no .nar files",
+            ));
+        }
+        Ok(InstallReport {
+            kind: NarType::Package,
+            name: info.name,
+            path: self.root.to_path_buf(),
+            upgraded: items.iter().any(|item| item.upgraded),
+            balloon: None,
+            items,
+        })
+    }
+
+    /// shell・supplement のインストール先（受け入れるゴーストと同じ名前の `<root>/ghost/` の中）
+    ///
+    /// 同梱のゴーストなど、読み込んだ場所には書き込めないことがある。
+    fn ghost_dir(&self, info: &InstallInfo) -> Result<PathBuf, InstallError> {
+        let ghost = self.accepting_ghost(info)?;
+        let directory = ghost.path.file_name().ok_or_else(|| {
+            InstallError::new(
+                FailureReason::Rejected,
+                format!("Invalid ghost directory: {:?}", ghost.path),
+            )
+        })?;
+        Ok(self.root.join("ghost").join(directory))
+    }
+
+    /// `accept` のゴースト（無ければ現在のゴースト）
+    fn accepting_ghost(&self, info: &InstallInfo) -> Result<&GhostInfo, InstallError> {
+        let ghost = match &info.accept {
+            Some(accept) => self.ghosts.iter().find(|ghost| {
+                ghost.descript.name.as_deref() == Some(accept.as_str()) || ghost.name == *accept
+            }),
+            None => self.current_ghost,
+        };
+        ghost.ok_or_else(|| {
+            InstallError::new(
+                FailureReason::Rejected,
+                format!(
+                    "No ghost accepts {} {:?} (accept: {:?})",
+                    info.kind.as_str(),
+                    info.name,
+                    info.accept
+                ),
+            )
+        })
+    }
+}
+
+/// アーカイブ内のパスを `/` 区切りにする（絶対パスと `..` は拒否する）
+pub(crate) fn archive_path(name: &str) -> Result<String, InstallError> {
+    let name = name.replace('\\', "/");
+    let unsafe_path = name.starts_with('/')
+        || name.as_bytes().get(1) == Some(&b':')
+        || name.split('/').any(|part| part == "..");
+    if unsafe_path {
+        return Err(InstallError::new(
+            FailureReason::Rejected,
+            format!("Unsafe path in archive: {}", name),
+        ));
+    }
+    Ok(name
+        .split('/')
+        .filter(|part| !part.is_empty() && *part != ".")
+        .collect::<Vec<_>>()
+        .join("/"))
+}
+
+/// インストール先のディレクトリ名（1階層だけ）
+fn directory_name(value: &str) -> Result<String, InstallError> {
+    let directory = archive_path(value)?;
+    if directory.is_empty() || directory.contains('/') {
+        return Err(InstallError::new(
+            FailureReason::Rejected,
+            format!("Invalid install directory: {}", value),
+        ));
+    }
+    Ok(directory)
+}
+
+fn is_under(path: &str, directory: &str) -> bool {
+    path.strip_prefix(directory)
+        .is_some_and(|rest| rest.starts_with('/'))
+}
+
+/// ファイルを書き出す（既にあれば delete.txt のファイルを消してから上書きし、`true` を返す）
+///
+/// いったん隣の一時ディレクトリに書き出すので、展開に失敗しても既存のディレクトリは壊れない。
+fn write_tree(
+    target: &Path,
+    files: &[(&str, &[u8])],
+    deletes: &[String],
+) -> Result<bool, InstallError> {
+    let parent = target.parent().unwrap_or(target);
+    let staging = parent.join(format!(
+        ".{}.installing",
+        target.file_name().unwrap_or_default().to_string_lossy()
+    ));
+    let _ = fs::remove_dir_all(&staging);
+
+    let extract = || -> Result<(), InstallError> {
+        for (name, data) in files {
+            let path = staging.join(name);
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir)
+                    .map_err(|e| InstallError::io("Failed to create", dir, e))?;
+            }
+            fs::write(&path, data).map_err(|e| InstallError::io("Failed to write", &path, e))?;
+        }
+        fs::create_dir_all(&staging).map_err(|e| InstallError::io("Failed to create", &staging, e))
+    };
+    if let Err(e) = extract() {
+        let _ = fs::remove_dir_all(&staging);
+        return Err(e);
+    }
+
+    let upgraded = target.exists();
+    let result = if upgraded {
+        for delete in deletes {
+            let path = target.join(delete);
+            let removed = if path.is_dir() {
+                fs::remove_dir_all(&path)
+            } else {
+                fs::remove_file(&path)
+            };
+            if removed.is_ok() {
+                println!("🗑️ Deleted by delete.txt: {:?}", path);
+            }
+        }
+        move_tree(&staging, target)
+    } else {
+        fs::rename(&staging, target).map_err(|e| InstallError::io("Failed to move", target, e))
+    };
+    let _ = fs::remove_dir_all(&staging);
+    result.map(|_| upgraded)
+}
+
+/// 一時ディレクトリのファイルを既存のディレクトリへ移す
+fn move_tree(from: &Path, to: &Path) -> Result<(), InstallError> {
+    for entry in walkdir::WalkDir::new(from) {
+        let entry = entry.map_err(|e| {
+            InstallError::new(
+                FailureReason::Extraction,
+                format!("Directory walk error: {}", e),
+            )
+        })?;
+        let Ok(relative) = entry.path().strip_prefix(from) else {
+            continue;
+        };
+        let path = to.join(relative);
+        if entry.file_type().is_dir() {
+            fs::create_dir_all(&path)
+                .map_err(|e| InstallError::io("Failed to create", &path, e))?;
+        } else {
+            if path.is_dir() {
+                let _ = fs::remove_dir_all(&path);
+            }
+            fs::rename(entry.path(), &path)
+                .map_err(|e| InstallError::io("Failed to move", &path, e))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shiori_charset::ShioriCharset;
+    use crate::shiori_manager::ShioriType;
+    use std::io::Write;
+    use zip::write::SimpleFileOptions;
+
+    fn zip(files: &[(&str, impl AsRef<[u8]>)]) -> Vec<u8> {
+        let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, data) in files {
+            zip.start_file(*name, SimpleFileOptions::default()).unwrap();
+            zip.write_all(data.as_ref()).unwrap();
+        }
+        zip.finish().unwrap().into_inner()
+    }
+
+    fn nar(files: &[(&str, &str)]) -> NarArchive {
+        NarArchive::from_bytes(zip(files)).unwrap()
+    }
+
+    #[test]
+    fn test_install_ghost_with_balloon_and_upgrade() {
+        let temp = tempfile::tempdir().unwrap();
+        let root = temp.path();
+        let installer = NarInstaller {
+            root,
+            ghosts: &[],
+            current_ghost: None,
+        };
+
+        let report = installer
+            .install_archive(&nar(&[
+                (
+                    "install.txt",
+                    "charset,UTF-8\r\ntype,ghost\r\nname,ナナイ\r\ndirectory,nanai\r\nballoon.directory,nanai_balloon\r\nballoon.source.directory,balloon\r\n",
+                ),
+                ("ghost/master/descript.txt", "name,ナナイ"),
+                ("ghost/master/dic_old.txt", "old"),
+                ("balloon/descript.txt", "name,バルーン"),
+            ]))
+            .unwrap();
+        assert!(!report.upgraded);
+        assert_eq!(
+            report.references(),
+            vec!["ghost,balloon", "ナナイ", "nanai_balloon"]
+        );
+        assert!(root.join("ghost/nanai/ghost/master/dic_old.txt").exists());
+        assert!(!root.join("ghost/nanai/balloon").exists());
+        assert!(root.join("balloon/nanai_balloon/descript.txt").exists());
+
+        // 上書きするときは delete.txt のファイルを消す
+        let report = installer
+            .install_archive(&nar(&[
+                (
+                    "install.txt",
+                    "type,ghost\r\nname,nanai\r\ndirectory,nanai\r\n",
+                ),
+                ("delete.txt", "ghost\\master\\dic_old.txt\r\n"),
+                ("ghost/master/dic_new.txt", "new"),
+            ]))
+            .unwrap();
+        assert!(report.upgraded);
+        let master = root.join("ghost/nanai/ghost/master");
+        assert!(!master.join("dic_old.txt").exists());
+        assert!(master.join("dic_new.txt").exists());
+        assert!(master.join("descript.txt").exists());
+    }
+
+    #[test]
+    fn test_unsafe_archives_are_rejected() {
+        let reason = |files: &[(&str, &str)]| {
+            let archive = NarArchive::from_bytes(zip(files))?;
+            archive.install_info()?;
+            archive.delete_list().map(|_| ())
+        };
+        let install = "type,ghost\r\ndirectory,x\r\n";
+
+        for files in [
+            vec![("install.txt", install), ("../evil.txt", "")],
+            vec![("install.txt", install), ("/etc/evil", "")],
+            vec![("install.txt", install), ("C:\\evil", "")],
+            vec![("install.txt", "type,ghost\r\ndirectory,../x\r\n")],
+            vec![("install.txt", install), ("delete.txt", "..\\..\\evil")],
+        ] {
+            assert_eq!(reason(&files).unwrap_err().reason, FailureReason::Rejected);
+        }
+        assert_eq!(
+            reason(&[("install.txt", "type,wallpaper\r\ndirectory,x\r\n")])
+                .unwrap_err()
+                .reason,
+            FailureReason::InvalidType
+        );
+        assert_eq!(
+            reason(&[("readme.txt", "")]).unwrap_err().reason,
+            FailureReason::InvalidType
+        );
+
+        // 受け入れるゴーストが無いシェル
+        let temp = tempfile::tempdir().unwrap();
+        let root = temp.path().join("root");
+        let installer = NarInstaller {
+            root: &root,
+            ghosts: &[],
+            current_ghost: None,
+        };
+        let error = installer
+            .install_archive(&nar(&[(
+                "install.txt",
+                "type,shell\r\ndirectory,x\r\naccept,nobody\r\n",
+            )]))
+            .unwrap_err();
+        assert_eq!(error.reason, FailureReason::Rejected);
+
+        // package の中の package と、中の .nar の展開で上限を超えるもの
+        let ghost = zip(&[
+            ("install.txt", "type,ghost\r\ndirectory,x\r\n"),
+            ("ghost/master/dic.txt", &"x".repeat(100)),
+        ]);
+        let package = |nested: &[u8]| {
+            zip(&[
+                ("install.txt", b"type,package\r\n".as_slice()),
+                ("inner.nar", nested),
+            ])
+        };
+        let error = installer
+            .install_archive(&NarArchive::from_bytes(package(&package(&ghost))).unwrap())
+            .unwrap_err();
+        assert_eq!(error.reason, FailureReason::Rejected);
+        let error = installer
+            .install_nested(&NarArchive::from_bytes(package(&ghost)).unwrap(), 64, 0)
+            .unwrap_err();
+        assert_eq!(error.reason, FailureReason::Rejected);
+        assert!(!root.exists());
+
+        // 展開すると大きすぎるもの
+        let bytes = zip(&[("install.txt", install), ("big.txt", &"x".repeat(100))]);
+        let error = NarArchive::read_limited(Cursor::new(bytes), 64).unwrap_err();
+        assert_eq!(error.reason, FailureReason::Rejected);
+    }
+
+    #[test]
+    fn test_shell_installs_under_root() {
+        let temp = tempfile::tempdir().unwrap();
+        let root = temp.path();
+        // 読み込んだゴーストはインストール先とは別の場所にある
+        let ghost = GhostInfo {
+            id: "/bundled/ghost/nanai".to_string(),
+            name: "nanai".to_string(),
+            path: PathBuf::from("/bundled/ghost/nanai"),
+            shiori_type: ShioriType::Mock,
+            shiori_dll: None,
+            charset: ShioriCharset::Utf8,
+            native_library: None,
+            descript: Descript::parse("name,ナナイ"),
+        };
+        let installer = NarInstaller {
+            root,
+            ghosts: std::slice::from_ref(&ghost),
+            current_ghost: None,
+        };
+        let report = installer
+            .install_archive(&nar(&[
+                (
+                    "install.txt",
+                    "charset,UTF-8\r\ntype,shell\r\ndirectory,summer\r\naccept,ナナイ\r\n",
+                ),
+                ("surface0.png", ""),
+            ]))
+            .unwrap();
+        assert_eq!(report.path, root.join("ghost/nanai/shell/summer"));
+        assert!(report.path.join("surface0.png").exists());
+    }
+}
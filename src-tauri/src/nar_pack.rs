@@ -0,0 +1,465 @@
+//! NAR Packager
+//!
+//! ゴーストのディレクトリから `.nar` を作る（`nar pack <ghost-dir>`）。
+//! developer_options.txt で `nonar` を付けたファイルは入れず、`noupdate` を付けたファイルは
+//! updates2.dau・updates.txt に載せない。作ったアーカイブはインストールし直して確かめる。
+//!
+//! ```text
+//! // developer_options.txt（オプションを省略すると両方）
+//! ghost/master/satori_savedata.txt,noupdate
+//! memo/,nonar,noupdate
+//! *.psd,nonar
+//! ```
+
+use crate::nar::{INSTALL_TXT, NarArchive, NarInstaller, NarType};
+use crate::shiori_charset::ShioriCharset;
+use crate::shiori_manager::{self, GhostInfo, ShioriType};
+use crate::update_list::{self, UPDATES_TXT, UPDATES2_DAU, UpdateEntry};
+use mascot_nanai_ui::descript::Descript;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+use zip::write::SimpleFileOptions;
+
+pub const DEVELOPER_OPTIONS_TXT: &str = "developer_options.txt";
+
+/// 書かなくても除くもの（隠しファイル、ユーザーごとのデータ、作ったnarなど）
+const DEFAULT_EXCLUDES: [&str; 8] = [
+    ".*",
+    ".*/",
+    "profile/",
+    "*.log",
+    "*.nar",
+    "thumbs.db",
+    "desktop.ini",
+    DEVELOPER_OPTIONS_TXT,
+];
+
+/// developer_options.txt の1行
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExcludeRule {
+    /// `*` `?` を使えるパターン（`/` で終われば ディレクトリ、`/` を含まなければファイル名）
+    pub pattern: String,
+    pub no_nar: bool,
+    pub no_update: bool,
+}
+
+impl ExcludeRule {
+    fn new(pattern: &str, no_nar: bool, no_update: bool) -> Self {
+        ExcludeRule {
+            pattern: pattern.replace('\\', "/").to_lowercase(),
+            no_nar,
+            no_update,
+        }
+    }
+
+    /// `/` 区切りの相対パスに当てはまるか
+    pub fn matches(&self, path: &str) -> bool {
+        let path = path.to_lowercase();
+        let parts: Vec<&str> = path.split('/').collect();
+        if let Some(directory) = self.pattern.strip_suffix('/') {
+            // パスの途中のディレクトリ
+            return (1..parts.len()).any(|end| {
+                glob(directory, &parts[..end].join("/"))
+                    || (!directory.contains('/') && glob(directory, parts[end - 1]))
+            });
+        }
+        if self.pattern.contains('/') {
+            glob(&self.pattern, &path)
+        } else {
+            glob(&self.pattern, parts.last().unwrap_or(&""))
+        }
+    }
+}
+
+/// developer_options.txt を読む
+pub fn parse_developer_options(text: &str) -> Vec<ExcludeRule> {
+    text.trim_start_matches('\u{feff}')
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with("//"))
+        .map(|line| {
+            let mut fields = line.split(',').map(str::trim);
+            let pattern = fields.next().unwrap_or_default();
+            let options: Vec<String> = fields.map(str::to_lowercase).collect();
+            let has = |name: &str| options.is_empty() || options.iter().any(|o| o == name);
+            ExcludeRule::new(pattern, has("nonar"), has("noupdate"))
+        })
+        .collect()
+}
+
+/// `*` は `/` 以外の0文字以上、`?` は1文字
+fn glob(pattern: &str, text: &str) -> bool {
+    fn matches(pattern: &[char], text: &[char]) -> bool {
+        match pattern.split_first() {
+            None => text.is_empty(),
+            Some(('*', rest)) => (0..=text.len())
+                .take_while(|&skip| !text[..skip].contains(&'/'))
+                .any(|skip| matches(rest, &text[skip..])),
+            Some(('?', rest)) => {
+                text.first().is_some_and(|c| *c != '/') && matches(rest, &text[1..])
+            }
+            Some((c, rest)) => text.first() == Some(c) && matches(rest, &text[1..]),
+        }
+    }
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    matches(&pattern, &text)
+}
+
+/// 作った `.nar` の内容
+#[derive(Debug, Clone, Default)]
+pub struct PackReport {
+    pub output: PathBuf,
+    /// アーカイブに入れたファイル
+    pub files: Vec<String>,
+    /// 除いたファイル
+    pub excluded: Vec<String>,
+    /// updates2.dau に載せたファイルの数
+    pub updates: usize,
+    /// install.txt が無かったのでアーカイブの中に作った
+    pub generated_install_txt: bool,
+}
+
+/// ゴーストのディレクトリから `.nar` を作る
+///
+/// install.txt が無ければ作り、updates2.dau・updates.txt と一緒にアーカイブの中にだけ入れる
+/// （ゴーストのディレクトリには書き込まない）。
+pub fn pack(ghost_dir: &Path, output: &Path) -> Result<PackReport, String> {
+    let ghost_dir = &fs::canonicalize(ghost_dir).unwrap_or_else(|_| ghost_dir.to_path_buf());
+    if !shiori_manager::is_ghost_root(ghost_dir) {
+        return Err(format!(
+            "Not a ghost directory (ghost/master/descript.txt not found): {:?}",
+            ghost_dir
+        ));
+    }
+    let mut report = PackReport {
+        output: output.to_path_buf(),
+        ..Default::default()
+    };
+
+    let mut rules: Vec<ExcludeRule> = DEFAULT_EXCLUDES
+        .iter()
+        .map(|pattern| ExcludeRule::new(pattern, true, true))
+        .collect();
+    if let Ok(text) = fs::read_to_string(ghost_dir.join(DEVELOPER_OPTIONS_TXT)) {
+        rules.extend(parse_developer_options(&text));
+    }
+
+    // ファイルを集める（updates2.dau・updates.txt は作り直す）
+    let mut files: Vec<(String, Vec<u8>)> = Vec::new();
+    let mut updates: Vec<UpdateEntry> = Vec::new();
+    for entry in WalkDir::new(ghost_dir).sort_by_file_name() {
+        let entry = entry.map_err(|e| format!("Directory walk error: {}", e))?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let relative = entry
+            .path()
+            .strip_prefix(ghost_dir)
+            .map_err(|e| e.to_string())?
+            .to_string_lossy()
+            .replace('\\', "/");
+        if [UPDATES2_DAU, UPDATES_TXT].contains(&relative.as_str()) {
+            continue;
+        }
+        let matched: Vec<&ExcludeRule> = rules.iter().filter(|r| r.matches(&relative)).collect();
+        if matched.iter().any(|rule| rule.no_nar) {
+            report.excluded.push(relative);
+            continue;
+        }
+        let data =
+            fs::read(entry.path()).map_err(|e| format!("Failed to read {}: {}", relative, e))?;
+        if !matched.iter().any(|rule| rule.no_update) {
+            updates.push(UpdateEntry::new(&relative, &data));
+        }
+        files.push((relative, data));
+    }
+
+    if !files
+        .iter()
+        .any(|(name, _)| name.eq_ignore_ascii_case(INSTALL_TXT))
+    {
+        let data = default_install_txt(ghost_dir).into_bytes();
+        updates.push(UpdateEntry::new(INSTALL_TXT, &data));
+        files.push((INSTALL_TXT.to_string(), data));
+        report.generated_install_txt = true;
+    }
+
+    report.updates = updates.len();
+    for (name, content) in [
+        (UPDATES2_DAU, update_list::format_updates2(&updates)),
+        (UPDATES_TXT, update_list::format_updates_txt(&updates)),
+    ] {
+        files.push((name.to_string(), content.into_bytes()));
+    }
+
+    write_zip(output, &files)?;
+    report.files = files.into_iter().map(|(name, _)| name).collect();
+    Ok(report)
+}
+
+/// install.txt の既定の内容
+fn default_install_txt(ghost_dir: &Path) -> String {
+    let directory = ghost_dir
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_string();
+    let name = Descript::load(&ghost_dir.join("ghost").join("master").join("descript.txt"))
+        .ok()
+        .and_then(|descript| descript.name)
+        .unwrap_or_else(|| directory.clone());
+    format!(
+        "charset,UTF-8\r\ntype,ghost\r\nname,{}\r\ndirectory,{}\r\n",
+        name, directory
+    )
+}
+
+fn write_zip(output: &Path, files: &[(String, Vec<u8>)]) -> Result<(), String> {
+    let file =
+        fs::File::create(output).map_err(|e| format!("Failed to create {:?}: {}", output, e))?;
+    let mut zip = zip::ZipWriter::new(file);
+    let options = SimpleFileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+    for (name, data) in files {
+        zip.start_file(name.as_str(), options)
+            .and_then(|_| zip.write_all(data).map_err(Into::into))
+            .map_err(|e| format!("Failed to add {}: {}", name, e))?;
+    }
+    zip.finish()
+        .map_err(|e| format!("Failed to write {:?}: {}", output, e))?;
+    Ok(())
+}
+
+/// 一時ディレクトリにインストールして、すべてのファイルが元通りに展開されるか確かめる
+///
+/// ghost・shell・balloon に対応する（shell は仮のゴーストに受け入れさせる）。
+pub fn verify(nar_path: &Path) -> Result<(), String> {
+    let archive = NarArchive::open(nar_path).map_err(|e| e.to_string())?;
+    let info = archive.install_info().map_err(|e| e.to_string())?;
+    if !matches!(
+        info.kind,
+        NarType::Ghost | NarType::Shell | NarType::Balloon
+    ) {
+        return Err(format!(
+            "Cannot verify {} archives (only ghost, shell and balloon)",
+            info.kind.as_str()
+        ));
+    }
+    let temp = tempfile::tempdir().map_err(|e| format!("Failed to create temp dir: {}", e))?;
+    let root = temp.path();
+
+    let accepting = GhostInfo {
+        id: "verify".to_string(),
+        name: info.accept.clone().unwrap_or_else(|| "verify".to_string()),
+        path: root.join("ghost").join("verify"),
+        shiori_type: ShioriType::Unknown(String::new()),
+        shiori_dll: None,
+        charset: ShioriCharset::default(),
+        native_library: None,
+        descript: Descript::default(),
+    };
+    let installer = NarInstaller {
+        root,
+        ghosts: std::slice::from_ref(&accepting),
+        current_ghost: Some(&accepting),
+    };
+    installer
+        .install_archive(&archive)
+        .map_err(|e| e.to_string())
+        .and_then(|report| {
+            let balloon_source = info
+                .balloon_source_directory
+                .or(info.balloon_directory)
+                .map(|source| format!("{}/", source));
+            for (name, data) in &archive.files {
+                let installed = match (&report.balloon, &balloon_source) {
+                    (Some(balloon), Some(source)) if name.starts_with(source.as_str()) => {
+                        balloon.path.join(&name[source.len()..])
+                    }
+                    _ => report.path.join(name),
+                };
+                if fs::read(&installed).ok().as_ref() != Some(data) {
+                    return Err(format!("{} is not installed correctly", name));
+                }
+            }
+            if info.kind == NarType::Ghost && !shiori_manager::is_ghost_root(&report.path) {
+                return Err("Installed ghost has no ghost/master/descript.txt".to_string());
+            }
+            if info.kind != NarType::Ghost && !report.path.join("descript.txt").is_file() {
+                return Err(format!(
+                    "Installed {} has no descript.txt",
+                    info.kind.as_str()
+                ));
+            }
+            Ok(())
+        })
+}
+
+/// `nar` コマンドのエントリポイント
+pub fn run_cli(args: &[String]) -> Result<(), String> {
+    const USAGE: &str = "usage: nar pack <ghost-dir> [-o <output.nar>] [--no-verify]";
+    let [command, ghost_dir, options @ ..] = args else {
+        return Err(USAGE.to_string());
+    };
+    if command != "pack" {
+        return Err(USAGE.to_string());
+    }
+
+    let ghost_dir = PathBuf::from(ghost_dir);
+    let mut output = None;
+    let mut verify_output = true;
+    let mut options = options.iter();
+    while let Some(option) = options.next() {
+        match option.as_str() {
+            "-o" | "--output" => output = options.next().map(PathBuf::from),
+            "--no-verify" => verify_output = false,
+            _ => return Err(USAGE.to_string()),
+        }
+    }
+    let output = output.unwrap_or_else(|| {
+        let name = fs::canonicalize(&ghost_dir)
+            .ok()
+            .and_then(|dir| {
+                dir.file_name()
+                    .map(|name| name.to_string_lossy().to_string())
+            })
+            .unwrap_or_else(|| "ghost".to_string());
+        PathBuf::from(format!("{}.nar", name))
+    });
+
+    let report = pack(&ghost_dir, &output)?;
+    if report.generated_install_txt {
+        println!("📝 Generated {} in the archive", INSTALL_TXT);
+    }
+    for excluded in &report.excluded {
+        println!("🚫 Excluded: {}", excluded);
+    }
+    println!(
+        "📦 Packed {} files ({} in updates2.dau) into {:?}",
+        report.files.len(),
+        report.updates,
+        report.output
+    );
+
+    if verify_output {
+        verify(&output)?;
+        println!("✅ Verified: the archive installs cleanly");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exclude_rules() {
+        let rules = parse_developer_options(
+            "// コメント\r\nghost/master/satori_savedata.txt,noupdate\r\nmemo/,nonar\r\n*.PSD\r\n",
+        );
+        assert_eq!(
+            rules[0],
+            ExcludeRule::new("ghost/master/satori_savedata.txt", false, true)
+        );
+        assert!(rules[0].matches("ghost/master/satori_savedata.txt"));
+        assert!(rules[1].matches("memo/todo.txt"));
+        assert!(rules[1].matches("shell/master/memo/todo.txt"));
+        assert!(!rules[1].matches("memo.txt"));
+        assert!(rules[2].no_nar && rules[2].no_update);
+        assert!(rules[2].matches("shell/master/surface0.psd"));
+
+        let hidden = ExcludeRule::new(".*/", true, true);
+        assert!(hidden.matches(".git/config"));
+        assert!(!hidden.matches("ghost/master/dic.txt"));
+    }
+
+    #[test]
+    fn test_pack_and_verify() {
+        let temp = tempfile::tempdir().unwrap();
+        let root = temp.path();
+        let ghost = root.join("nanai");
+        let master = ghost.join("ghost/master");
+        fs::create_dir_all(master.join("profile")).unwrap();
+        fs::create_dir_all(ghost.join(".git")).unwrap();
+        fs::write(
+            master.join("descript.txt"),
+            "charset,UTF-8\r\nname,ナナイ\r\n",
+        )
+        .unwrap();
+        fs::write(master.join("dic_talk.txt"), "＊\r\nこんにちは").unwrap();
+        fs::write(master.join("satori_savedata.txt"), "save").unwrap();
+        fs::write(master.join("profile/satori.dat"), "user").unwrap();
+        fs::write(ghost.join(".git/config"), "").unwrap();
+        fs::write(ghost.join("memo.psd"), "").unwrap();
+        fs::write(
+            ghost.join(DEVELOPER_OPTIONS_TXT),
+            "ghost/master/satori_savedata.txt,noupdate\r\n*.psd,nonar\r\n",
+        )
+        .unwrap();
+
+        let output = root.join("nanai.nar");
+        let report = pack(&ghost, &output).unwrap();
+        assert!(report.generated_install_txt);
+        assert_eq!(
+            report.files,
+            vec![
+                "ghost/master/descript.txt",
+                "ghost/master/dic_talk.txt",
+                "ghost/master/satori_savedata.txt",
+                "install.txt",
+                "updates2.dau",
+                "updates.txt",
+            ]
+        );
+        assert_eq!(report.excluded.len(), 4);
+
+        // 作ったファイルはアーカイブの中にだけある
+        for name in [INSTALL_TXT, UPDATES2_DAU, UPDATES_TXT] {
+            assert!(!ghost.join(name).exists(), "{}", name);
+        }
+        let archive = NarArchive::open(&output).unwrap();
+
+        // noupdate のファイルは更新の一覧に載らない
+        let updates =
+            update_list::parse(std::str::from_utf8(archive.get(UPDATES2_DAU).unwrap()).unwrap());
+        let paths: Vec<&str> = updates.iter().map(|u| u.path.as_str()).collect();
+        assert_eq!(
+            paths,
+            vec![
+                "ghost/master/descript.txt",
+                "ghost/master/dic_talk.txt",
+                "install.txt"
+            ]
+        );
+        let install = Descript::from_bytes(archive.get(INSTALL_TXT).unwrap());
+        assert_eq!(install.name.as_deref(), Some("ナナイ"));
+        assert_eq!(install.get("directory"), Some("nanai"));
+
+        verify(&output).unwrap();
+
+        // shell は仮のゴーストに受け入れさせて確かめ、supplement は確かめられない
+        let shell = root.join("shell.nar");
+        write_zip(
+            &shell,
+            &[
+                (
+                    INSTALL_TXT.to_string(),
+                    b"type,shell\r\ndirectory,summer\r\naccept,nanai\r\n".to_vec(),
+                ),
+                ("descript.txt".to_string(), b"name,summer".to_vec()),
+                ("surface0.png".to_string(), Vec::new()),
+            ],
+        )
+        .unwrap();
+        verify(&shell).unwrap();
+        let supplement = root.join("supplement.nar");
+        write_zip(
+            &supplement,
+            &[(INSTALL_TXT.to_string(), b"type,supplement\r\n".to_vec())],
+        )
+        .unwrap();
+        assert!(verify(&supplement).is_err());
+    }
+}
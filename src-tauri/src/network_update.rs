@@ -0,0 +1,560 @@
+//! Network Update
+//!
+//! ゴーストの `homeurl` から updates2.dau（無ければ updates.txt）を取得し、
+//! MD5が手元と違うファイルだけをダウンロードして置き換える（ネットワーク更新）。
+//! ダウンロードしたファイルは `.update` に置いてMD5を確かめ、すべて揃ってから入れ替えるので、
+//! 途中で失敗してもゴーストは元のまま（入れ替えの途中で失敗したら `.update-backup` から戻す）。
+//!
+//! ```text
+//! OnUpdateBegin             名前, パス, -, ghost
+//! OnUpdateReady             更新するファイルの数, -, -, ghost
+//! OnUpdate.OnDownloadBegin  ファイル, 番号, 総数
+//! OnUpdateComplete          none / changed, 更新したファイル（カンマ区切り）
+//! OnUpdateFailure           timeout / md5 miss / 404 / fileio / artificial, ファイル
+//! ```
+
+use crate::nar;
+use crate::shiori_manager::GhostInfo;
+use crate::update_list::{self, UPDATES_TXT, UPDATES2_DAU, UpdateEntry};
+use serde::Serialize;
+use std::fmt;
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+use std::time::Duration;
+
+/// ダウンロードしたファイルを置くディレクトリ（ゴーストのディレクトリ内）
+const STAGING_DIR: &str = ".update";
+/// 入れ替える前のファイルを置くディレクトリ（ゴーストのディレクトリ内）
+const BACKUP_DIR: &str = ".update-backup";
+const TIMEOUT_SECS: u64 = 30;
+/// 1ファイルの大きさの上限
+const MAX_DOWNLOAD_SIZE: u64 = 256 * 1024 * 1024;
+
+/// OnUpdateFailure の Reference0
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailureReason {
+    /// 接続できない・途中で切れた
+    Timeout,
+    /// ダウンロードしたファイルのMD5が一覧と違う
+    Md5Miss,
+    /// サーバーがエラーを返した
+    NotFound,
+    /// ファイルを書き込めない
+    FileIo,
+    /// homeurl が無い・一覧が壊れている・危険なパス
+    Artificial,
+}
+
+impl FailureReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FailureReason::Timeout => "timeout",
+            FailureReason::Md5Miss => "md5 miss",
+            FailureReason::NotFound => "404",
+            FailureReason::FileIo => "fileio",
+            FailureReason::Artificial => "artificial",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UpdateError {
+    pub reason: FailureReason,
+    pub message: String,
+    /// 失敗したファイル
+    pub file: Option<String>,
+}
+
+impl UpdateError {
+    fn new(reason: FailureReason, message: impl Into<String>) -> Self {
+        UpdateError {
+            reason,
+            message: message.into(),
+            file: None,
+        }
+    }
+
+    fn with_file(mut self, file: &str) -> Self {
+        self.file = Some(file.to_string());
+        self
+    }
+
+    fn io(file: &str, path: &Path, e: std::io::Error) -> Self {
+        Self::new(FailureReason::FileIo, format!("{:?}: {}", path, e)).with_file(file)
+    }
+
+    /// OnUpdateFailure のReference
+    pub fn references(&self) -> Vec<String> {
+        vec![
+            self.reason.as_str().to_string(),
+            self.file.clone().unwrap_or_default(),
+        ]
+    }
+}
+
+impl fmt::Display for UpdateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.message, self.reason.as_str())
+    }
+}
+
+/// 更新した結果
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct UpdateReport {
+    pub ghost_id: String,
+    /// 置き換えたファイル（`/` 区切り）
+    pub updated: Vec<String>,
+}
+
+impl UpdateReport {
+    /// OnUpdateComplete のReference
+    pub fn references(&self) -> Vec<String> {
+        if self.updated.is_empty() {
+            vec!["none".to_string()]
+        } else {
+            vec!["changed".to_string(), self.updated.join(",")]
+        }
+    }
+}
+
+/// ネットワーク更新
+pub struct NetworkUpdater {
+    agent: ureq::Agent,
+}
+
+impl Default for NetworkUpdater {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl NetworkUpdater {
+    pub fn new() -> Self {
+        NetworkUpdater {
+            agent: ureq::AgentBuilder::new()
+                .timeout(Duration::from_secs(TIMEOUT_SECS))
+                .build(),
+        }
+    }
+
+    /// `homeurl` からゴーストを更新する（`on_event` にはSHIORIへ送るイベントが順に渡される）
+    pub fn update<F>(
+        &self,
+        ghost: &GhostInfo,
+        homeurl: &str,
+        mut on_event: F,
+    ) -> Result<UpdateReport, UpdateError>
+    where
+        F: FnMut(&str, &[&str]),
+    {
+        let name = ghost.descript.name.as_deref().unwrap_or(&ghost.name);
+        on_event(
+            "OnUpdateBegin",
+            &[name, &ghost.path.to_string_lossy(), "", "ghost"],
+        );
+        println!("🔄 Network update: {} <- {}", ghost.id, homeurl);
+
+        let result = self.run(ghost, homeurl, &mut on_event);
+        let _ = fs::remove_dir_all(ghost.path.join(STAGING_DIR));
+        let _ = fs::remove_dir_all(ghost.path.join(BACKUP_DIR));
+
+        let (event, references) = match &result {
+            Ok(report) => {
+                println!("✅ Network update finished: {} files", report.updated.len());
+                ("OnUpdateComplete", report.references())
+            }
+            Err(e) => {
+                println!("❌ Network update failed: {}", e);
+                ("OnUpdateFailure", e.references())
+            }
+        };
+        let references: Vec<&str> = references.iter().map(String::as_str).collect();
+        on_event(event, &references);
+        result
+    }
+
+    fn run<F>(
+        &self,
+        ghost: &GhostInfo,
+        homeurl: &str,
+        on_event: &mut F,
+    ) -> Result<UpdateReport, UpdateError>
+    where
+        F: FnMut(&str, &[&str]),
+    {
+        let base = base_url(homeurl)?;
+        let (list_name, list) = self.fetch_list(&base)?;
+        let entries = update_list::parse(&String::from_utf8_lossy(&list));
+        if entries.is_empty() {
+            return Err(
+                UpdateError::new(FailureReason::Artificial, "Empty update list")
+                    .with_file(list_name),
+            );
+        }
+
+        let changed = changed_entries(&ghost.path, &entries)?;
+        let total = changed.len().to_string();
+        on_event("OnUpdateReady", &[&total, "", "", "ghost"]);
+
+        let staging = ghost.path.join(STAGING_DIR);
+        let _ = fs::remove_dir_all(&staging);
+        for (index, entry) in changed.iter().enumerate() {
+            on_event(
+                "OnUpdate.OnDownloadBegin",
+                &[&entry.path, &index.to_string(), &total],
+            );
+            let data = self.get(&format!("{}{}", base, url_path(&entry.path)), &entry.path)?;
+            if update_list::md5_hex(&data) != entry.md5 {
+                return Err(UpdateError::new(
+                    FailureReason::Md5Miss,
+                    format!("MD5 mismatch: {}", entry.path),
+                )
+                .with_file(&entry.path));
+            }
+            write_file(&staging.join(&entry.path), &data, &entry.path)?;
+        }
+        write_file(&staging.join(list_name), &list, list_name)?;
+
+        // すべて揃ってから入れ替える
+        let mut files: Vec<&str> = changed.iter().map(|entry| entry.path.as_str()).collect();
+        files.push(list_name);
+        replace_files(&ghost.path, &files)?;
+        for entry in &changed {
+            println!("📥 Updated: {}", entry.path);
+        }
+
+        Ok(UpdateReport {
+            ghost_id: ghost.id.clone(),
+            updated: changed.into_iter().map(|entry| entry.path).collect(),
+        })
+    }
+
+    /// updates2.dau を取得する（無ければ updates.txt）
+    fn fetch_list(&self, base: &str) -> Result<(&'static str, Vec<u8>), UpdateError> {
+        match self.get(&format!("{}{}", base, UPDATES2_DAU), UPDATES2_DAU) {
+            Err(e) if e.reason == FailureReason::NotFound => self
+                .get(&format!("{}{}", base, UPDATES_TXT), UPDATES_TXT)
+                .map(|list| (UPDATES_TXT, list)),
+            result => result.map(|list| (UPDATES2_DAU, list)),
+        }
+    }
+
+    fn get(&self, url: &str, file: &str) -> Result<Vec<u8>, UpdateError> {
+        let response = self.agent.get(url).call().map_err(|e| match e {
+            ureq::Error::Status(code, _) => {
+                UpdateError::new(FailureReason::NotFound, format!("HTTP {}: {}", code, url))
+                    .with_file(file)
+            }
+            ureq::Error::Transport(e) => {
+                UpdateError::new(FailureReason::Timeout, format!("{}: {}", url, e)).with_file(file)
+            }
+        })?;
+
+        let mut data = Vec::new();
+        response
+            .into_reader()
+            .take(MAX_DOWNLOAD_SIZE + 1)
+            .read_to_end(&mut data)
+            .map_err(|e| {
+                UpdateError::new(FailureReason::Timeout, format!("{}: {}", url, e)).with_file(file)
+            })?;
+        if data.len() as u64 > MAX_DOWNLOAD_SIZE {
+            return Err(
+                UpdateError::new(FailureReason::Artificial, format!("Too large: {}", url))
+                    .with_file(file),
+            );
+        }
+        Ok(data)
+    }
+}
+
+/// MD5が手元のファイルと違う（または手元に無い）ファイル
+pub fn changed_entries(
+    ghost_dir: &Path,
+    entries: &[UpdateEntry],
+) -> Result<Vec<UpdateEntry>, UpdateError> {
+    let mut changed = Vec::new();
+    for entry in entries {
+        let path = nar::archive_path(&entry.path).map_err(|e| {
+            UpdateError::new(FailureReason::Artificial, e.message).with_file(&entry.path)
+        })?;
+        let is_list =
+            path.eq_ignore_ascii_case(UPDATES2_DAU) || path.eq_ignore_ascii_case(UPDATES_TXT);
+        if path.is_empty() || entry.path.ends_with('/') || is_list {
+            continue;
+        }
+        let unchanged = fs::read(ghost_dir.join(&path))
+            .is_ok_and(|data| update_list::md5_hex(&data) == entry.md5);
+        if !unchanged && !changed.iter().any(|other: &UpdateEntry| other.path == path) {
+            changed.push(UpdateEntry {
+                path,
+                ..entry.clone()
+            });
+        }
+    }
+    Ok(changed)
+}
+
+/// `homeurl` を `/` で終わるURLにする
+fn base_url(homeurl: &str) -> Result<String, UpdateError> {
+    let homeurl = homeurl.trim();
+    let lower = homeurl.to_ascii_lowercase();
+    if !lower.starts_with("http://") && !lower.starts_with("https://") {
+        return Err(UpdateError::new(
+            FailureReason::Artificial,
+            format!("Invalid homeurl: {:?}", homeurl),
+        ));
+    }
+    if homeurl.ends_with('/') {
+        Ok(homeurl.to_string())
+    } else {
+        Ok(format!("{}/", homeurl))
+    }
+}
+
+/// パスをURLに使える形にする（`/` 以外の記号と非ASCII文字をエンコード）
+fn url_path(path: &str) -> String {
+    path.bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+/// `.update` のファイルでゴーストのファイルを置き換える（途中で失敗したら元に戻す）
+fn replace_files(ghost_dir: &Path, files: &[&str]) -> Result<(), UpdateError> {
+    let staging = ghost_dir.join(STAGING_DIR);
+    let backup = ghost_dir.join(BACKUP_DIR);
+    let _ = fs::remove_dir_all(&backup);
+
+    let mut touched = Vec::new();
+    let result = files.iter().try_for_each(|file| {
+        let target = ghost_dir.join(file);
+        if target.is_file() {
+            let saved = backup.join(file);
+            if let Some(parent) = saved.parent() {
+                fs::create_dir_all(parent).map_err(|e| UpdateError::io(file, parent, e))?;
+            }
+            fs::rename(&target, &saved).map_err(|e| UpdateError::io(file, &target, e))?;
+        }
+        // ここからは元に戻す対象（元のファイルは退避済み）
+        touched.push(*file);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent).map_err(|e| UpdateError::io(file, parent, e))?;
+        }
+        fs::rename(staging.join(file), &target).map_err(|e| UpdateError::io(file, &target, e))
+    });
+
+    if result.is_err() {
+        for file in touched.into_iter().rev() {
+            let target = ghost_dir.join(file);
+            let saved = backup.join(file);
+            let restored = if saved.is_file() {
+                fs::rename(&saved, &target)
+            } else {
+                fs::remove_file(&target)
+            };
+            if let Err(e) = restored
+                && e.kind() != std::io::ErrorKind::NotFound
+            {
+                println!("⚠️ Failed to restore {}: {}", file, e);
+            }
+        }
+    }
+    result
+}
+
+fn write_file(path: &Path, data: &[u8], file: &str) -> Result<(), UpdateError> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|e| UpdateError::io(file, parent, e))?;
+    }
+    fs::write(path, data).map_err(|e| UpdateError::io(file, path, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shiori_charset::ShioriCharset;
+    use crate::shiori_manager::ShioriType;
+    use mascot_nanai_ui::descript::Descript;
+    use std::collections::HashMap;
+
+    /// `files` を返すだけのHTTPサーバー（無いパスは404）
+    fn serve(files: Vec<(&str, Vec<u8>)>) -> String {
+        let files: HashMap<String, Vec<u8>> = files
+            .into_iter()
+            .map(|(path, data)| (format!("/{}", path), data))
+            .collect();
+        let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+        let port = server.server_addr().to_ip().unwrap().port();
+        std::thread::spawn(move || {
+            for request in server.incoming_requests() {
+                let response = match files.get(request.url()) {
+                    Some(data) => tiny_http::Response::from_data(data.clone()),
+                    None => tiny_http::Response::from_data(Vec::new()).with_status_code(404),
+                };
+                let _ = request.respond(response);
+            }
+        });
+        format!("http://127.0.0.1:{}/ghost/nanai", port)
+    }
+
+    fn ghost(dir: &Path, files: &[(&str, &str)]) -> GhostInfo {
+        for (path, text) in files {
+            let path = dir.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, text).unwrap();
+        }
+        GhostInfo {
+            id: dir.to_string_lossy().to_string(),
+            name: "nanai".to_string(),
+            path: dir.to_path_buf(),
+            shiori_type: ShioriType::Mock,
+            shiori_dll: None,
+            charset: ShioriCharset::Utf8,
+            native_library: None,
+            descript: Descript::load(&dir.join("ghost/master/descript.txt")).unwrap_or_default(),
+        }
+    }
+
+    fn update(
+        ghost: &GhostInfo,
+        homeurl: &str,
+    ) -> (Result<UpdateReport, UpdateError>, Vec<String>) {
+        let mut events = Vec::new();
+        let result = NetworkUpdater::new().update(ghost, homeurl, |event, references| {
+            events.push(format!("{} {}", event, references.join("|")));
+        });
+        (result, events)
+    }
+
+    #[test]
+    fn test_update_downloads_changed_files() {
+        let descript = "charset,UTF-8\r\nname,ナナイ\r\n";
+        let dir = tempfile::tempdir().unwrap();
+        let ghost = ghost(
+            dir.path(),
+            &[
+                ("ghost/master/descript.txt", descript),
+                ("ghost/master/dic_talk.txt", "old"),
+            ],
+        );
+        let files = [
+            ("ghost/master/descript.txt", descript.as_bytes().to_vec()),
+            ("ghost/master/dic_talk.txt", b"new".to_vec()),
+            ("shell/master/surfaces 2.txt", b"surface0".to_vec()),
+        ];
+        let entries: Vec<UpdateEntry> = files
+            .iter()
+            .map(|(path, data)| UpdateEntry::new(path, data))
+            .collect();
+        let homeurl = serve(vec![
+            (
+                "ghost/nanai/updates2.dau",
+                update_list::format_updates2(&entries).into_bytes(),
+            ),
+            ("ghost/nanai/ghost/master/dic_talk.txt", b"new".to_vec()),
+            (
+                "ghost/nanai/shell/master/surfaces%202.txt",
+                b"surface0".to_vec(),
+            ),
+        ]);
+
+        let (result, events) = update(&ghost, &homeurl);
+        let report = result.unwrap();
+        assert_eq!(
+            report.updated,
+            vec!["ghost/master/dic_talk.txt", "shell/master/surfaces 2.txt"]
+        );
+        assert_eq!(
+            events[1..],
+            [
+                "OnUpdateReady 2|||ghost",
+                "OnUpdate.OnDownloadBegin ghost/master/dic_talk.txt|0|2",
+                "OnUpdate.OnDownloadBegin shell/master/surfaces 2.txt|1|2",
+                "OnUpdateComplete changed|ghost/master/dic_talk.txt,shell/master/surfaces 2.txt",
+            ]
+        );
+        assert!(events[0].starts_with("OnUpdateBegin ナナイ|"));
+        assert_eq!(
+            fs::read_to_string(ghost.path.join("ghost/master/dic_talk.txt")).unwrap(),
+            "new"
+        );
+        assert!(ghost.path.join(UPDATES2_DAU).is_file());
+        assert!(!ghost.path.join(STAGING_DIR).exists());
+
+        // 2回目は何も更新しない
+        let (result, events) = update(&ghost, &format!("{}/", homeurl));
+        assert!(result.unwrap().updated.is_empty());
+        assert_eq!(events.last().unwrap(), "OnUpdateComplete none");
+    }
+
+    #[test]
+    fn test_failed_update_keeps_ghost() {
+        let dir = tempfile::tempdir().unwrap();
+        let ghost = ghost(
+            dir.path(),
+            &[
+                ("ghost/master/descript.txt", "name,ナナイ\r\n"),
+                ("ghost/master/dic_talk.txt", "old"),
+            ],
+        );
+        // updates2.dau が無ければ updates.txt を使う
+        let entries = [
+            UpdateEntry::new("ghost/master/dic_talk.txt", b"new"),
+            UpdateEntry::new("ghost/master/dic_event.txt", b"expected"),
+        ];
+        let homeurl = serve(vec![
+            (
+                "ghost/nanai/updates.txt",
+                update_list::format_updates_txt(&entries).into_bytes(),
+            ),
+            ("ghost/nanai/ghost/master/dic_talk.txt", b"new".to_vec()),
+            ("ghost/nanai/ghost/master/dic_event.txt", b"broken".to_vec()),
+        ]);
+
+        let (result, events) = update(&ghost, &homeurl);
+        assert_eq!(result.unwrap_err().reason, FailureReason::Md5Miss);
+        assert_eq!(
+            events.last().unwrap(),
+            "OnUpdateFailure md5 miss|ghost/master/dic_event.txt"
+        );
+        assert_eq!(
+            fs::read_to_string(ghost.path.join("ghost/master/dic_talk.txt")).unwrap(),
+            "old"
+        );
+        assert!(!ghost.path.join(STAGING_DIR).exists());
+
+        // 入れ替えの途中で失敗したら、入れ替えたファイルも元に戻す（memo はファイルなので置けない）
+        fs::write(ghost.path.join("memo"), "").unwrap();
+        let entries = [
+            UpdateEntry::new("ghost/master/dic_talk.txt", b"new"),
+            UpdateEntry::new("memo/todo.txt", b"todo"),
+        ];
+        let homeurl = serve(vec![
+            (
+                "ghost/nanai/updates2.dau",
+                update_list::format_updates2(&entries).into_bytes(),
+            ),
+            ("ghost/nanai/ghost/master/dic_talk.txt", b"new".to_vec()),
+            ("ghost/nanai/memo/todo.txt", b"todo".to_vec()),
+        ]);
+        let (result, _) = update(&ghost, &homeurl);
+        assert_eq!(result.unwrap_err().reason, FailureReason::FileIo);
+        assert_eq!(
+            fs::read_to_string(ghost.path.join("ghost/master/dic_talk.txt")).unwrap(),
+            "old"
+        );
+        assert!(!ghost.path.join(UPDATES2_DAU).exists());
+        assert!(!ghost.path.join(BACKUP_DIR).exists());
+
+        let (result, _) = update(&ghost, "ftp://example.com/");
+        assert_eq!(result.unwrap_err().reason, FailureReason::Artificial);
+        let (result, _) = update(&ghost, "http://127.0.0.1:1/");
+        assert_eq!(result.unwrap_err().reason, FailureReason::Timeout);
+    }
+}
@@ -0,0 +1,151 @@
+//! Sakura Playback
+//!
+//! `SakuraPlayer` を専用スレッドで実時間に沿って進め、
+//! 出てきたイベントをコールバック（Tauriのイベント送信）に渡す。
+//! 同じスレッドで `SerikoScheduler` も進め、`\s` `\i` などをアニメーションに反映する。
+
+use mascot_nanai_ui::sakura_choice::SakuraChoice;
+use mascot_nanai_ui::sakura_player::{SakuraEvent, SakuraPlayer, SystemClock};
+use mascot_nanai_ui::seriko::{SerikoEvent, SerikoScheduler};
+use mascot_nanai_ui::surfaces::ShellDefinition;
+use parking_lot::{Condvar, Mutex};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// イベントの送り先
+pub type EventSink = Box<dyn Fn(SakuraEvent) + Send>;
+
+/// アニメーションのイベントの送り先
+pub type SerikoSink = Box<dyn Fn(SerikoEvent) + Send>;
+
+/// シェルを読み込んだときのキャラクターごとのサーフェス（sakura、kero）
+const DEFAULT_SURFACES: [(u32, i32); 2] = [(0, 0), (1, 10)];
+
+struct Engines {
+    player: SakuraPlayer<SystemClock>,
+    seriko: SerikoScheduler<SystemClock>,
+}
+
+struct Shared {
+    engines: Mutex<Engines>,
+    /// スクリプトの追加やクリックで再生スレッドを起こす
+    wake: Condvar,
+}
+
+/// 再生スレッドへのハンドル
+#[derive(Clone)]
+pub struct SakuraPlayback {
+    shared: Arc<Shared>,
+}
+
+impl SakuraPlayback {
+    /// 再生スレッドを起動する
+    pub fn spawn(sink: EventSink, seriko_sink: SerikoSink) -> Self {
+        let clock = SystemClock::new();
+        let shared = Arc::new(Shared {
+            engines: Mutex::new(Engines {
+                player: SakuraPlayer::new(clock.clone()),
+                seriko: SerikoScheduler::new(clock, ShellDefinition::default()),
+            }),
+            wake: Condvar::new(),
+        });
+
+        let worker = shared.clone();
+        thread::Builder::new()
+            .name("sakura-playback".to_string())
+            .spawn(move || run(&worker, &sink, &seriko_sink))
+            .expect("failed to spawn sakura playback thread");
+
+        SakuraPlayback { shared }
+    }
+
+    /// スクリプトを再生する（再生中のものは打ち切る）
+    pub fn play(&self, script: &str) -> u64 {
+        self.with_player(|player| {
+            player.play(script);
+            player.generation()
+        })
+    }
+
+    pub fn click(&self) {
+        self.with_player(|player| player.click());
+    }
+
+    pub fn pause(&self) {
+        self.with_player(|player| player.pause());
+    }
+
+    pub fn resume(&self) {
+        self.with_player(|player| player.resume());
+    }
+
+    /// 表示中の選択肢を選ぶ（古い再生の選択肢なら `None`）
+    pub fn select_choice(&self, generation: u64, index: usize) -> Option<SakuraChoice> {
+        self.with_player(|player| player.select_choice(generation, index))
+    }
+
+    pub fn cancel(&self) {
+        self.with_player(|player| player.cancel());
+    }
+
+    /// アニメーションに使うシェルを切り替える
+    pub fn set_shell(&self, definition: ShellDefinition) {
+        self.with_seriko(|seriko| {
+            seriko.set_definition(definition);
+            for (scope, surface) in DEFAULT_SURFACES {
+                if seriko.surface(scope).is_none() {
+                    seriko.set_surface(scope, surface);
+                }
+            }
+        });
+    }
+
+    /// プレイヤーを操作して再生スレッドを起こす
+    fn with_player<T>(&self, f: impl FnOnce(&mut SakuraPlayer<SystemClock>) -> T) -> T {
+        let result = f(&mut self.shared.engines.lock().player);
+        self.shared.wake.notify_one();
+        result
+    }
+
+    fn with_seriko(&self, f: impl FnOnce(&mut SerikoScheduler<SystemClock>)) {
+        f(&mut self.shared.engines.lock().seriko);
+        self.shared.wake.notify_one();
+    }
+}
+
+fn run(shared: &Shared, sink: &EventSink, seriko_sink: &SerikoSink) {
+    let mut engines = shared.engines.lock();
+    loop {
+        let events = engines.player.poll();
+        for event in &events {
+            engines.seriko.apply(&event.event);
+        }
+        let frames = engines.seriko.poll();
+        if !events.is_empty() || !frames.is_empty() {
+            // 送信中にコマンドを受け付けられるようロックを外す
+            parking_lot::MutexGuard::unlocked(&mut engines, || {
+                for event in events {
+                    sink(event);
+                }
+                for frame in frames {
+                    seriko_sink(frame);
+                }
+            });
+            continue;
+        }
+
+        let next = [engines.player.next_due_in(), engines.seriko.next_due_in()]
+            .into_iter()
+            .flatten()
+            .min();
+        match next {
+            Some(ms) => {
+                shared
+                    .wake
+                    .wait_for(&mut engines, Duration::from_millis(ms.max(1)));
+            }
+            None => shared.wake.wait(&mut engines),
+        }
+    }
+}
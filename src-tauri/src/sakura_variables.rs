@@ -0,0 +1,129 @@
+//! Sakura Variables
+//!
+//! さくらスクリプトの環境変数（`%selfname` `%hour` など）の値を、
+//! 読み込み中のゴースト・時計・アプリの設定から用意する。
+
+use crate::shiori_manager::GhostInfo;
+use chrono::{Datelike, Local, NaiveDateTime, Timelike};
+use mascot_nanai_ui::sakura_env::{LocalTime, SakuraEnvironment};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// フロントエンドから設定される値
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct EnvironmentSettings {
+    pub username: Option<String>,
+    pub screen_width: Option<u32>,
+    pub screen_height: Option<u32>,
+}
+
+/// 現在の現地時刻
+pub fn local_now() -> NaiveDateTime {
+    Local::now().naive_local()
+}
+
+/// OSが起動してからの秒数
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub fn system_uptime_secs() -> Option<u64> {
+    let uptime = std::fs::read_to_string("/proc/uptime").ok()?;
+    let secs: f64 = uptime.split_whitespace().next()?.parse().ok()?;
+    Some(secs as u64)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+pub fn system_uptime_secs() -> Option<u64> {
+    None
+}
+
+/// 環境変数の値を集める（`now` は現在時刻）
+pub fn environment(
+    ghost: Option<&GhostInfo>,
+    settings: &EnvironmentSettings,
+    now: NaiveDateTime,
+) -> SakuraEnvironment {
+    let mut properties = HashMap::new();
+    if let Some(ghost) = ghost {
+        let mut set = |key: &str, value: Option<&String>| {
+            if let Some(value) = value {
+                properties.insert(format!("currentghost.{}", key), value.clone());
+            }
+        };
+        let descript = &ghost.descript;
+        set("name", descript.name.as_ref());
+        set("sakuraname", descript.sakura_name.as_ref());
+        set("keroname", descript.kero_name.as_ref());
+        set("craftmanname", descript.craftmanw.as_ref());
+        set("craftmanurl", descript.craftmanurl.as_ref());
+        set("homeurl", descript.homeurl.as_ref());
+        properties.insert(
+            "currentghost.path".to_string(),
+            ghost.path.to_string_lossy().to_string(),
+        );
+    }
+
+    SakuraEnvironment {
+        username: settings.username.clone(),
+        selfname: ghost.and_then(|ghost| ghost.descript.sakura_name.clone()),
+        selfname2: ghost.and_then(|ghost| ghost.descript.sakura_name2.clone()),
+        keroname: ghost.and_then(|ghost| ghost.descript.kero_name.clone()),
+        friendname: None,
+        screen_width: settings.screen_width,
+        screen_height: settings.screen_height,
+        uptime_secs: system_uptime_secs(),
+        time: LocalTime {
+            year: now.year(),
+            month: now.month(),
+            day: now.day(),
+            hour: now.hour(),
+            minute: now.minute(),
+            second: now.second(),
+        },
+        properties,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shiori_charset::ShioriCharset;
+    use crate::shiori_manager::ShioriType;
+    use chrono::NaiveDate;
+    use mascot_nanai_ui::descript::Descript;
+    use mascot_nanai_ui::sakura_env::expand_script;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_environment_from_ghost_and_clock() {
+        let ghost = GhostInfo {
+            id: "/ghost/mock_nanai".to_string(),
+            name: "mock_nanai".to_string(),
+            path: PathBuf::from("/ghost/mock_nanai"),
+            shiori_type: ShioriType::Mock,
+            shiori_dll: None,
+            charset: ShioriCharset::Utf8,
+            native_library: None,
+            descript: Descript::parse("name,Mock Nanai\nsakura.name,ナナイ\nkero.name,モック"),
+        };
+        let settings = EnvironmentSettings {
+            username: Some("ユーザー".to_string()),
+            ..Default::default()
+        };
+        let now = NaiveDate::from_ymd_opt(2024, 12, 31)
+            .unwrap()
+            .and_hms_opt(8, 30, 0)
+            .unwrap();
+
+        let env = environment(Some(&ghost), &settings, now);
+        assert_eq!(
+            expand_script(
+                "%selfnameと%keroname、%month月%day日%hour時%minute分。%usernameさん %property[currentghost.name]",
+                &env
+            ),
+            "ナナイとモック、12月31日8時30分。ユーザーさん Mock Nanai"
+        );
+
+        // ゴーストが無ければ名前は展開しない
+        let env = environment(None, &settings, now);
+        assert_eq!(expand_script("%selfname", &env), "%selfname");
+    }
+}
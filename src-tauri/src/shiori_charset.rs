@@ -0,0 +1,186 @@
+//! SHIORI Charset
+//!
+//! SHIORIとの通信で使う文字コードの判定と変換。
+//! すべてのエンジンはこのモジュールを通してリクエストをエンコードし、
+//! レスポンスをデコードする。
+
+use crate::shiori_protocol::ShioriRequest;
+use encoding_rs::{EUC_JP, Encoding, ISO_2022_JP, SHIFT_JIS, UTF_8};
+use serde::{Deserialize, Serialize};
+
+/// SHIORI通信で扱う文字コード
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum ShioriCharset {
+    #[default]
+    Utf8,
+    ShiftJis,
+    EucJp,
+    Iso2022Jp,
+}
+
+impl ShioriCharset {
+    /// Charsetヘッダやdescript.txtの表記から判定
+    pub fn from_label(label: &str) -> Option<Self> {
+        let label = label.trim();
+
+        // encoding_rsが知らない伺か特有の表記
+        match label.to_ascii_lowercase().as_str() {
+            "cp932" | "ms932" | "windows-31j" | "sjis" => return Some(ShioriCharset::ShiftJis),
+            _ => {}
+        }
+
+        let encoding = Encoding::for_label(label.as_bytes())?;
+        if encoding == UTF_8 {
+            Some(ShioriCharset::Utf8)
+        } else if encoding == SHIFT_JIS {
+            Some(ShioriCharset::ShiftJis)
+        } else if encoding == EUC_JP {
+            Some(ShioriCharset::EucJp)
+        } else if encoding == ISO_2022_JP {
+            Some(ShioriCharset::Iso2022Jp)
+        } else {
+            None
+        }
+    }
+
+    /// Charsetヘッダに書く名前
+    pub fn label(&self) -> &'static str {
+        match self {
+            ShioriCharset::Utf8 => "UTF-8",
+            ShioriCharset::ShiftJis => "Shift_JIS",
+            ShioriCharset::EucJp => "EUC-JP",
+            ShioriCharset::Iso2022Jp => "ISO-2022-JP",
+        }
+    }
+
+    fn encoding(&self) -> &'static Encoding {
+        match self {
+            ShioriCharset::Utf8 => UTF_8,
+            ShioriCharset::ShiftJis => SHIFT_JIS,
+            ShioriCharset::EucJp => EUC_JP,
+            ShioriCharset::Iso2022Jp => ISO_2022_JP,
+        }
+    }
+
+    /// 文字列をこの文字コードのバイト列に変換
+    pub fn encode(&self, text: &str) -> Vec<u8> {
+        let (bytes, _, had_errors) = self.encoding().encode(text);
+        if had_errors {
+            println!(
+                "Warning: Some characters couldn't be encoded as {}",
+                self.label()
+            );
+        }
+        bytes.into_owned()
+    }
+
+    /// この文字コードのバイト列を文字列に変換
+    pub fn decode(&self, bytes: &[u8]) -> String {
+        let (text, had_errors) = self.encoding().decode_without_bom_handling(bytes);
+        if had_errors {
+            println!(
+                "Warning: Some characters couldn't be decoded as {}",
+                self.label()
+            );
+        }
+        text.into_owned()
+    }
+}
+
+/// リクエストを指定の文字コードでエンコード（Charsetヘッダも合わせて書き換える）
+pub fn encode_request(request: &ShioriRequest, charset: ShioriCharset) -> Vec<u8> {
+    let mut request = request.clone();
+    request.headers.set("Charset", charset.label());
+    charset.encode(&request.to_wire())
+}
+
+/// レスポンスのバイト列からCharsetヘッダを探す
+///
+/// ヘッダ名と文字コード名はASCIIなので、デコード前のバイト列を行単位で見ればよい。
+pub fn sniff_charset(bytes: &[u8]) -> Option<ShioriCharset> {
+    bytes
+        .split(|&b| b == b'\n')
+        .skip(1)
+        .map(|line| line.strip_suffix(b"\r").unwrap_or(line))
+        .take_while(|line| !line.is_empty())
+        .find_map(|line| {
+            let line = std::str::from_utf8(line).ok()?;
+            let (key, value) = line.split_once(':')?;
+            if key.trim().eq_ignore_ascii_case("Charset") {
+                ShioriCharset::from_label(value)
+            } else {
+                None
+            }
+        })
+}
+
+/// レスポンスをデコード（Charsetヘッダが無ければ fallback を使う）
+pub fn decode_response(bytes: &[u8], fallback: ShioriCharset) -> String {
+    sniff_charset(bytes).unwrap_or(fallback).decode(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_label() {
+        assert_eq!(
+            ShioriCharset::from_label("Shift_JIS"),
+            Some(ShioriCharset::ShiftJis)
+        );
+        assert_eq!(
+            ShioriCharset::from_label("windows-31j"),
+            Some(ShioriCharset::ShiftJis)
+        );
+        assert_eq!(
+            ShioriCharset::from_label(" utf-8 "),
+            Some(ShioriCharset::Utf8)
+        );
+        assert_eq!(
+            ShioriCharset::from_label("EUC-JP"),
+            Some(ShioriCharset::EucJp)
+        );
+        assert_eq!(
+            ShioriCharset::from_label("ISO-2022-JP"),
+            Some(ShioriCharset::Iso2022Jp)
+        );
+        assert_eq!(ShioriCharset::from_label("KOI8-R"), None);
+    }
+
+    #[test]
+    fn test_encode_request_rewrites_charset() {
+        let request = ShioriRequest::get("OnBoot").with_references(&["ちぇっか"]);
+        let bytes = encode_request(&request, ShioriCharset::ShiftJis);
+
+        assert_eq!(sniff_charset(&bytes), Some(ShioriCharset::ShiftJis));
+        let text = ShioriCharset::ShiftJis.decode(&bytes);
+        assert!(text.contains("Charset: Shift_JIS\r\n"));
+        assert!(text.contains("Reference0: ちぇっか\r\n"));
+        assert!(!bytes.windows(3).any(|w| w == "ち".as_bytes()));
+    }
+
+    #[test]
+    fn test_decode_response_uses_charset_header() {
+        for charset in [
+            ShioriCharset::ShiftJis,
+            ShioriCharset::EucJp,
+            ShioriCharset::Iso2022Jp,
+            ShioriCharset::Utf8,
+        ] {
+            let raw = format!(
+                "SHIORI/3.0 200 OK\r\nCharset: {}\r\nValue: \\0こんにちは\\e\r\n\r\n",
+                charset.label()
+            );
+            let bytes = charset.encode(&raw);
+            assert_eq!(decode_response(&bytes, ShioriCharset::Utf8), raw);
+        }
+    }
+
+    #[test]
+    fn test_decode_response_falls_back() {
+        let raw = "SHIORI/3.0 200 OK\r\nValue: やあ\r\n\r\n";
+        let bytes = ShioriCharset::ShiftJis.encode(raw);
+        assert_eq!(decode_response(&bytes, ShioriCharset::ShiftJis), raw);
+    }
+}
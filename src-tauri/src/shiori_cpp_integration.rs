@@ -5,10 +5,11 @@
  * FFI経由でSHIORI/3.0プロトコルを実装する
  */
 
-use once_cell::sync::Lazy;
-use std::ffi::{CStr, CString};
+use crate::shiori_engine::ShioriTransport;
+use std::ffi::{CStr, CString, c_void};
 use std::os::raw::{c_char, c_int, c_long};
-use std::sync::{Arc, Mutex};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
 
 // YAYA C++関数への外部宣言
 unsafe extern "C" {
@@ -17,73 +18,55 @@ unsafe extern "C" {
     fn yaya_finalize() -> c_int;
     fn yaya_set_directory(dir: *const c_char) -> c_int;
 
-    // SHIORI/3.0標準関数（YAYA C++実装）
-    fn yaya_load(h: c_long, length: c_long) -> c_int;
+    // SHIORI/3.0標準関数（YAYA C++実装、h は HANDLE_TYPE = void*）
+    fn yaya_load(h: *const c_void, length: c_long) -> c_int;
     fn yaya_unload() -> c_int;
-    fn yaya_request(h: c_long, length: *mut c_long) -> *mut c_char;
+    fn yaya_request(h: *const c_void, length: *mut c_long) -> *mut c_char;
 
     // YAYA特殊関数
     fn yaya_get_version() -> *const c_char;
     fn yaya_set_encoding(encoding: c_int) -> c_int;
-}
 
-// SATORIYA関数への外部宣言（将来実装用）
-unsafe extern "C" {
-    // 雛形 - SATORIYA実装時に追加
-    fn satoriya_initialize() -> c_int;
-    fn satoriya_finalize() -> c_int;
+    // メモリ管理
+    fn yaya_free_string(s: *mut c_char);
 }
 
-/// SHIORI エンジンタイプ
-#[derive(Debug, Clone, PartialEq)]
-pub enum EngineType {
-    YAYA,
-    SATORIYA,
-    None,
-}
+/// 組み込みYAYAの使用中フラグ
+///
+/// C++側はプロセス全体で1つの状態しか持たないため、同時に使えるのは1インスタンスまで。
+/// 複数のYAYAゴーストを同時に動かす場合は外部プロセスで実行する。
+static YAYA_IN_USE: AtomicBool = AtomicBool::new(false);
 
-/// SHIORI実行状態
-#[derive(Debug)]
-struct ShioriState {
-    engine: EngineType,
+/// YAYA SHIORI 統合クラス
+pub struct YayaEngine {
     initialized: bool,
-    ghost_path: Option<String>,
 }
 
-/// グローバルSHIORIステート（シングルトン）
-static SHIORI_STATE: Lazy<Arc<Mutex<ShioriState>>> = Lazy::new(|| {
-    Arc::new(Mutex::new(ShioriState {
-        engine: EngineType::None,
-        initialized: false,
-        ghost_path: None,
-    }))
-});
-
-/// YAYA SHIORI 統合クラス
-pub struct YayaEngine;
-
 impl YayaEngine {
-    /// YAYA初期化
-    pub fn initialize(ghost_dir: &str) -> Result<(), String> {
-        let mut state = SHIORI_STATE
-            .lock()
-            .map_err(|_| "Failed to lock SHIORI state")?;
+    pub fn new() -> Self {
+        YayaEngine { initialized: false }
+    }
 
-        if state.initialized && state.engine == EngineType::YAYA {
+    /// YAYA初期化
+    fn initialize(&mut self, ghost_dir: &str) -> Result<(), String> {
+        if self.initialized {
             return Ok(());
         }
 
-        // 既存エンジンの終了
-        if state.initialized {
-            Self::cleanup_internal(&mut state)?;
-        }
-
         let dir_cstr = CString::new(ghost_dir).map_err(|_| "Invalid ghost directory path")?;
 
+        if YAYA_IN_USE
+            .compare_exchange(false, true, Ordering::AcqRel, Ordering::Acquire)
+            .is_err()
+        {
+            return Err("Built-in YAYA is already used by another ghost".to_string());
+        }
+
         unsafe {
             // YAYA初期化
             let result = yaya_initialize();
             if result != 1 {
+                YAYA_IN_USE.store(false, Ordering::Release);
                 return Err(format!("YAYA initialization failed: {}", result));
             }
 
@@ -91,6 +74,7 @@ impl YayaEngine {
             let result = yaya_set_directory(dir_cstr.as_ptr());
             if result != 1 {
                 yaya_finalize();
+                YAYA_IN_USE.store(false, Ordering::Release);
                 return Err(format!("Failed to set YAYA directory: {}", result));
             }
 
@@ -98,74 +82,11 @@ impl YayaEngine {
             yaya_set_encoding(65001);
         }
 
-        state.engine = EngineType::YAYA;
-        state.initialized = true;
-        state.ghost_path = Some(ghost_dir.to_string());
+        self.initialized = true;
 
         Ok(())
     }
 
-    /// SHIORI load実行
-    pub fn load() -> Result<i32, String> {
-        let state = SHIORI_STATE
-            .lock()
-            .map_err(|_| "Failed to lock SHIORI state")?;
-
-        if !state.initialized || state.engine != EngineType::YAYA {
-            return Err("YAYA not initialized".to_string());
-        }
-
-        unsafe {
-            let result = yaya_load(0, 0);
-            Ok(result)
-        }
-    }
-
-    /// SHIORI unload実行
-    pub fn unload() -> Result<i32, String> {
-        let state = SHIORI_STATE
-            .lock()
-            .map_err(|_| "Failed to lock SHIORI state")?;
-
-        if !state.initialized || state.engine != EngineType::YAYA {
-            return Err("YAYA not initialized".to_string());
-        }
-
-        unsafe {
-            let result = yaya_unload();
-            Ok(result)
-        }
-    }
-
-    /// SHIORI request実行
-    pub fn request(input: &str) -> Result<String, String> {
-        let state = SHIORI_STATE
-            .lock()
-            .map_err(|_| "Failed to lock SHIORI state")?;
-
-        if !state.initialized || state.engine != EngineType::YAYA {
-            return Err("YAYA not initialized".to_string());
-        }
-
-        let input_cstr = CString::new(input).map_err(|_| "Invalid input string")?;
-
-        unsafe {
-            let mut output_length: c_long = 0;
-            let input_ptr = input_cstr.as_ptr() as c_long;
-
-            let output_ptr = yaya_request(input_ptr, &mut output_length);
-
-            if output_ptr.is_null() {
-                return Err("YAYA request failed".to_string());
-            }
-
-            let output_cstr = CStr::from_ptr(output_ptr);
-            let output = output_cstr.to_string_lossy().into_owned();
-
-            Ok(output)
-        }
-    }
-
     /// YAYAバージョン取得
     pub fn get_version() -> Result<String, String> {
         unsafe {
@@ -178,128 +99,78 @@ impl YayaEngine {
             Ok(version_cstr.to_string_lossy().into_owned())
         }
     }
-
-    /// YAYA終了
-    pub fn finalize() -> Result<(), String> {
-        let mut state = SHIORI_STATE
-            .lock()
-            .map_err(|_| "Failed to lock SHIORI state")?;
-
-        Self::cleanup_internal(&mut state)
-    }
-
-    fn cleanup_internal(state: &mut std::sync::MutexGuard<ShioriState>) -> Result<(), String> {
-        if state.initialized && state.engine == EngineType::YAYA {
-            unsafe {
-                yaya_finalize();
-            }
-        }
-
-        state.engine = EngineType::None;
-        state.initialized = false;
-        state.ghost_path = None;
-
-        Ok(())
-    }
 }
 
-/// SATORIYA SHIORI 統合クラス（将来実装）
-pub struct SatoriyaEngine;
-
-impl SatoriyaEngine {
-    pub fn initialize(_ghost_dir: &str) -> Result<(), String> {
-        Err("SATORIYA engine not yet implemented".to_string())
-    }
-
-    pub fn load() -> Result<i32, String> {
-        Err("SATORIYA engine not yet implemented".to_string())
-    }
-
-    pub fn unload() -> Result<i32, String> {
-        Err("SATORIYA engine not yet implemented".to_string())
-    }
-
-    pub fn request(_input: &str) -> Result<String, String> {
-        Err("SATORIYA engine not yet implemented".to_string())
+impl Default for YayaEngine {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
-/// 統合SHIORIインターフェイス
-pub struct IntegratedShiori;
-
-impl IntegratedShiori {
-    /// 自動検出してSHIORI初期化
-    pub fn initialize_auto(ghost_dir: &str) -> Result<EngineType, String> {
-        // YAYA検出ロジック（yaya.txt, aya.dll等）
-        let ghost_path = std::path::Path::new(ghost_dir);
+impl ShioriTransport for YayaEngine {
+    /// SHIORI load実行
+    fn load(&mut self, ghost_dir: &Path) -> Result<(), String> {
+        let ghost_dir = ghost_dir.to_string_lossy();
+        self.initialize(&ghost_dir)?;
 
-        if ghost_path.join("yaya.txt").exists()
-            || ghost_path.join("aya.dll").exists()
-            || ghost_path.join("yaya.dll").exists()
-        {
-            YayaEngine::initialize(ghost_dir)?;
-            return Ok(EngineType::YAYA);
+        let result = unsafe { yaya_load(ghost_dir.as_ptr().cast(), ghost_dir.len() as c_long) };
+        if result != 1 {
+            let _ = self.unload();
+            return Err(format!("YAYA load failed with code: {}", result));
         }
 
-        // SATORIYA検出ロジック（satori.dll等）
-        if ghost_path.join("satori.dll").exists() {
-            // SatoriyaEngine::initialize(ghost_dir)?;
-            return Err("SATORIYA detected but not yet supported".to_string());
+        Ok(())
+    }
+
+    /// SHIORI request実行（エンコード済みのバイト列を送受信）
+    fn request(&mut self, input: &[u8]) -> Result<Vec<u8>, String> {
+        if !self.initialized {
+            return Err("YAYA not initialized".to_string());
         }
 
-        Err("No compatible SHIORI engine detected".to_string())
-    }
+        unsafe {
+            let mut length = input.len() as c_long;
+            let input_ptr = input.as_ptr().cast();
 
-    /// 現在のエンジンタイプ取得
-    pub fn current_engine() -> Result<EngineType, String> {
-        let state = SHIORI_STATE
-            .lock()
-            .map_err(|_| "Failed to lock SHIORI state")?;
+            let output_ptr = yaya_request(input_ptr, &mut length);
 
-        Ok(state.engine.clone())
-    }
+            if output_ptr.is_null() {
+                return Err("YAYA request failed".to_string());
+            }
 
-    /// SHIORIリクエスト実行（エンジン自動判定）
-    pub fn request(input: &str) -> Result<String, String> {
-        let engine_type = Self::current_engine()?;
+            let output =
+                std::slice::from_raw_parts(output_ptr as *const u8, length as usize).to_vec();
+            yaya_free_string(output_ptr);
 
-        match engine_type {
-            EngineType::YAYA => YayaEngine::request(input),
-            EngineType::SATORIYA => SatoriyaEngine::request(input),
-            EngineType::None => Err("No SHIORI engine initialized".to_string()),
+            Ok(output)
         }
     }
 
-    /// SHIORIロード実行
-    pub fn load() -> Result<i32, String> {
-        let engine_type = Self::current_engine()?;
-
-        match engine_type {
-            EngineType::YAYA => YayaEngine::load(),
-            EngineType::SATORIYA => SatoriyaEngine::load(),
-            EngineType::None => Err("No SHIORI engine initialized".to_string()),
+    /// SHIORI unload実行とYAYA終了
+    fn unload(&mut self) -> Result<(), String> {
+        if !self.initialized {
+            return Ok(());
         }
-    }
 
-    /// SHIORIアンロード実行
-    pub fn unload() -> Result<i32, String> {
-        let engine_type = Self::current_engine()?;
+        let result = unsafe {
+            let result = yaya_unload();
+            yaya_finalize();
+            result
+        };
+
+        self.initialized = false;
+        YAYA_IN_USE.store(false, Ordering::Release);
 
-        match engine_type {
-            EngineType::YAYA => YayaEngine::unload(),
-            EngineType::SATORIYA => SatoriyaEngine::unload(),
-            EngineType::None => Err("No SHIORI engine initialized".to_string()),
+        if result == 1 {
+            Ok(())
+        } else {
+            Err(format!("YAYA unload failed with code: {}", result))
         }
     }
+}
 
-    /// 全SHIORI終了
-    pub fn finalize() -> Result<(), String> {
-        let engine_type = Self::current_engine()?;
-
-        match engine_type {
-            EngineType::YAYA => YayaEngine::finalize(),
-            EngineType::SATORIYA => Ok(()), // 未実装
-            EngineType::None => Ok(()),
-        }
+impl Drop for YayaEngine {
+    fn drop(&mut self) {
+        let _ = self.unload();
     }
 }
@@ -0,0 +1,182 @@
+//! SHIORI Engine
+//!
+//! SHIORIエンジンの共通インターフェイス。
+//! ゴーストごとにエンジンのインスタンスを持ち、`ShioriManager`から利用する。
+
+use crate::shiori_charset::{self, ShioriCharset};
+use crate::shiori_cpp_integration::YayaEngine;
+use crate::shiori_ffi::ShioriInterface;
+use crate::shiori_legacy::{self, ProtocolVersion};
+use crate::shiori_manager::{GhostInfo, ShioriType};
+use crate::shiori_mock::MockShiori;
+use crate::shiori_protocol::{ShioriRequest, ShioriResponse};
+use crate::shiori_satori::SatoriEngine;
+use std::path::Path;
+
+/// SHIORIエンジン
+pub trait ShioriEngine: Send {
+    /// ゴーストのディレクトリ（ghost/master）を渡して初期化
+    fn load(&mut self, ghost_dir: &Path) -> Result<(), String>;
+
+    /// SHIORI/3.0 リクエストを処理
+    fn request(&mut self, request: &ShioriRequest) -> Result<ShioriResponse, String>;
+
+    /// 終了処理
+    fn unload(&mut self) -> Result<(), String>;
+
+    /// SHIORIのバージョン文字列
+    fn version(&mut self) -> Result<String, String> {
+        let response = self.request(&ShioriRequest::get("version"))?;
+        response
+            .value()
+            .map(str::to_string)
+            .ok_or_else(|| "SHIORI returned no version".to_string())
+    }
+}
+
+/// バイト列で通信する低レベルのSHIORI（FFI・動的ライブラリ・外部プロセスなど）
+pub trait ShioriTransport: Send {
+    fn load(&mut self, ghost_dir: &Path) -> Result<(), String>;
+    fn request(&mut self, request: &[u8]) -> Result<Vec<u8>, String>;
+    fn unload(&mut self) -> Result<(), String>;
+}
+
+/// `ShioriTransport`に文字コード変換とプロトコル判定を被せたエンジン
+pub struct TransportEngine<T: ShioriTransport> {
+    transport: T,
+    charset: ShioriCharset,
+    protocol: ProtocolVersion,
+}
+
+impl<T: ShioriTransport> TransportEngine<T> {
+    pub fn new(transport: T, charset: ShioriCharset) -> Self {
+        TransportEngine {
+            transport,
+            charset,
+            protocol: ProtocolVersion::Shiori3,
+        }
+    }
+
+    /// 判定済みのプロトコル
+    pub fn protocol(&self) -> ProtocolVersion {
+        self.protocol
+    }
+
+    fn round_trip(&mut self, raw: &str) -> Result<String, String> {
+        let output = self.transport.request(&self.charset.encode(raw))?;
+        Ok(shiori_charset::decode_response(&output, self.charset))
+    }
+}
+
+impl<T: ShioriTransport> ShioriEngine for TransportEngine<T> {
+    fn load(&mut self, ghost_dir: &Path) -> Result<(), String> {
+        self.transport.load(ghost_dir)?;
+
+        // プロトコルバージョンを判定（2.xのゴーストは変換して扱う）
+        let mut probe = shiori_legacy::version_probe();
+        probe.headers.set("Charset", self.charset.label());
+        let probe_response = self.round_trip(&probe.to_wire())?;
+        self.protocol = shiori_legacy::negotiate(&probe_response);
+        println!("🤝 Negotiated SHIORI protocol: {:?}", self.protocol);
+
+        Ok(())
+    }
+
+    fn request(&mut self, request: &ShioriRequest) -> Result<ShioriResponse, String> {
+        let mut request = request.clone();
+        request.headers.set("Charset", self.charset.label());
+
+        match self.protocol {
+            ProtocolVersion::Shiori3 => {
+                ShioriResponse::parse(&self.round_trip(&request.to_wire())?)
+            }
+            ProtocolVersion::Shiori2 => match shiori_legacy::translate_request(&request) {
+                Some(legacy) => {
+                    shiori_legacy::normalize_response(&self.round_trip(&legacy.to_wire())?)
+                }
+                None => Ok(ShioriResponse::no_content()),
+            },
+        }
+    }
+
+    fn unload(&mut self) -> Result<(), String> {
+        self.transport.unload()
+    }
+}
+
+/// ゴースト情報から適切なエンジンを作成
+///
+/// ネイティブSHIORIが同梱されていればそれを動的に読み込み、
+/// 無ければSHIORIの種類に応じた組み込みエンジン（YAYA・里々）を使う。
+pub fn create_engine(ghost_info: &GhostInfo) -> Result<Box<dyn ShioriEngine>, String> {
+    if let Some(library) = &ghost_info.native_library {
+        match ShioriInterface::open(library) {
+            Ok(interface) => {
+                println!("📦 Using native SHIORI: {:?}", library);
+                return Ok(Box::new(TransportEngine::new(
+                    interface,
+                    ghost_info.charset,
+                )));
+            }
+            Err(e) => println!("⚠️ {}; falling back to built-in engine", e),
+        }
+    }
+
+    match &ghost_info.shiori_type {
+        ShioriType::YAYA => Ok(Box::new(TransportEngine::new(
+            YayaEngine::new(),
+            ghost_info.charset,
+        ))),
+        ShioriType::SATORIYA => Ok(Box::new(SatoriEngine::new(ghost_info.charset))),
+        ShioriType::Mock => Ok(Box::new(MockShiori::new())),
+        ShioriType::Unknown(reason) => Err(format!("No compatible SHIORI engine: {}", reason)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// SHIORI/2.x しか話せない偽のトランスポート
+    struct LegacyTransport {
+        requests: Vec<String>,
+    }
+
+    impl ShioriTransport for LegacyTransport {
+        fn load(&mut self, _ghost_dir: &Path) -> Result<(), String> {
+            Ok(())
+        }
+
+        fn request(&mut self, request: &[u8]) -> Result<Vec<u8>, String> {
+            let request = ShioriCharset::ShiftJis.decode(request);
+            let response = if request.starts_with("GET Sentence") {
+                "SHIORI/2.2 200 OK\r\nCharset: Shift_JIS\r\nSentence: \\0やあ\\e\r\n\r\n"
+            } else {
+                "SHIORI/2.0 400 Bad Request\r\n\r\n"
+            };
+            self.requests.push(request);
+            Ok(ShioriCharset::ShiftJis.encode(response))
+        }
+
+        fn unload(&mut self) -> Result<(), String> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_transport_engine_translates_legacy_ghost() {
+        let transport = LegacyTransport {
+            requests: Vec::new(),
+        };
+        let mut engine = TransportEngine::new(transport, ShioriCharset::ShiftJis);
+        engine.load(Path::new(".")).unwrap();
+        assert_eq!(engine.protocol(), ProtocolVersion::Shiori2);
+
+        let response = engine.request(&ShioriRequest::get("OnBoot")).unwrap();
+        assert_eq!(response.value(), Some("\\0やあ\\e"));
+
+        let sent = engine.transport.requests.last().unwrap();
+        assert!(sent.starts_with("GET Sentence SHIORI/2.2\r\nEvent: OnBoot\r\n"));
+        assert!(sent.contains("Charset: Shift_JIS\r\n"));
+    }
+}
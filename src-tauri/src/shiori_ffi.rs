@@ -1,140 +1,218 @@
 //! SHIORI FFI Bindings
 //!
-//! YAYAとSATORIYAのC++コードとのFFI（Foreign Function Interface）定義
+//! ゴーストが同梱するネイティブSHIORI（.so / .dylib / .dll）を実行時に読み込み、
+//! 標準の `load` / `unload` / `request` エクスポートを呼び出す
 
-use libc::{c_char, c_int, c_long, c_void};
-use std::ffi::{CStr, CString};
-use std::ptr;
+use crate::shiori_engine::ShioriTransport;
+use crate::shiori_protocol::{ShioriMethod, ShioriRequest};
+use libc::{c_int, c_long, c_void};
+use libloading::Library;
+use std::path::{Path, PathBuf};
 
-/// SHIORI/3.0プロトコルの戻り値
+/// SHIORIとの間で受け渡すメモリブロック
 pub type HGLOBAL = *mut c_void;
 
-/// YAYA SHIORI エクスポート関数の定義
-extern "C" {
-    // YAYAの主要なエクスポート関数
-    pub fn load(h: HGLOBAL, len: c_long) -> c_int;
-    pub fn unload() -> c_int;
-    pub fn request(h: HGLOBAL, len: *mut c_long) -> HGLOBAL;
+/// SHIORI エクスポート関数の型
+type LoadFn = unsafe extern "C" fn(h: HGLOBAL, len: c_long) -> c_int;
+type UnloadFn = unsafe extern "C" fn() -> c_int;
+type RequestFn = unsafe extern "C" fn(h: HGLOBAL, len: *mut c_long) -> HGLOBAL;
 
-    // YAYA固有の関数
-    pub fn SHIORI_FW(h: HGLOBAL, len: *mut c_long) -> HGLOBAL;
+#[cfg(windows)]
+#[link(name = "kernel32")]
+unsafe extern "system" {
+    fn GlobalAlloc(flags: u32, bytes: usize) -> HGLOBAL;
+    fn GlobalFree(h: HGLOBAL) -> HGLOBAL;
 }
 
-/// SHIORI関数の安全なRustラッパー
+/// SHIORIに渡すメモリを確保してデータを書き込む
+///
+/// SHIORIの取り決めでは、引数のメモリは呼び出し側が確保してSHIORI側が解放し、
+/// 戻り値のメモリはSHIORI側が確保して呼び出し側が解放する。
+/// WindowsではGlobalAlloc/GlobalFree、それ以外ではmalloc/freeを使う。
+fn global_alloc(data: &[u8]) -> Result<HGLOBAL, String> {
+    // 長さ0でもNULLにならないよう最低1バイト確保する
+    let size = data.len().max(1);
+
+    #[cfg(windows)]
+    let h = unsafe { GlobalAlloc(0, size) };
+    #[cfg(not(windows))]
+    let h = unsafe { libc::malloc(size) };
+
+    if h.is_null() {
+        return Err("Failed to allocate memory for SHIORI".to_string());
+    }
+
+    unsafe {
+        std::ptr::copy_nonoverlapping(data.as_ptr(), h as *mut u8, data.len());
+    }
+    Ok(h)
+}
+
+/// SHIORIが確保したメモリを解放
+fn global_free(h: HGLOBAL) {
+    #[cfg(windows)]
+    unsafe {
+        GlobalFree(h);
+    }
+    #[cfg(not(windows))]
+    unsafe {
+        libc::free(h);
+    }
+}
+
+/// descript.txtの`shiori`エントリからこのプラットフォームで読み込めるライブラリを探す
+///
+/// `satori.dll` の場合、Linuxでは `satori.so` → `libsatori.so` の順に探す。
+pub fn resolve_native_library(shiori_dir: &Path, shiori_name: &str) -> Option<PathBuf> {
+    let file_name = Path::new(shiori_name).file_name()?.to_string_lossy();
+    let stem = Path::new(file_name.as_ref())
+        .file_stem()?
+        .to_string_lossy()
+        .to_string();
+
+    let native_names = if cfg!(windows) {
+        vec![format!("{}.dll", stem)]
+    } else if cfg!(target_os = "macos") {
+        vec![format!("{}.dylib", stem), format!("lib{}.dylib", stem)]
+    } else {
+        vec![format!("{}.so", stem), format!("lib{}.so", stem)]
+    };
+
+    native_names
+        .iter()
+        .map(|name| shiori_dir.join(name))
+        .find(|path| path.is_file())
+}
+
+/// 動的に読み込んだネイティブSHIORI
 pub struct ShioriInterface {
+    // 関数ポインタより先に解放されないよう、ライブラリ本体を保持する
+    library: Library,
+    path: PathBuf,
     is_loaded: bool,
 }
 
 impl ShioriInterface {
-    pub fn new() -> Self {
-        ShioriInterface { is_loaded: false }
+    /// ライブラリを開き、必要なエクスポートがあることを確認する
+    pub fn open(path: &Path) -> Result<Self, String> {
+        let library = unsafe { Library::new(path) }
+            .map_err(|e| format!("Failed to open SHIORI library {:?}: {}", path, e))?;
+
+        let interface = ShioriInterface {
+            library,
+            path: path.to_path_buf(),
+            is_loaded: false,
+        };
+
+        // 標準エクスポートの存在確認
+        interface.symbol::<LoadFn>(b"load")?;
+        interface.symbol::<UnloadFn>(b"unload")?;
+        interface.symbol::<RequestFn>(b"request")?;
+
+        Ok(interface)
     }
 
-    /// SHIORIの初期化
-    pub fn load(&mut self, dirpath: &str) -> Result<(), String> {
-        if self.is_loaded {
-            return Err("SHIORI is already loaded".to_string());
+    fn symbol<T: Copy>(&self, name: &[u8]) -> Result<T, String> {
+        unsafe {
+            self.library
+                .get::<T>(name)
+                .map(|symbol| *symbol)
+                .map_err(|e| {
+                    format!(
+                        "SHIORI library {:?} has no `{}` export: {}",
+                        self.path,
+                        String::from_utf8_lossy(name),
+                        e
+                    )
+                })
         }
+    }
 
-        let dirpath_cstring =
-            CString::new(dirpath).map_err(|e| format!("Invalid directory path: {}", e))?;
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
 
-        let dirpath_ptr = dirpath_cstring.as_ptr() as HGLOBAL;
-        let dirpath_len = dirpath_cstring.as_bytes().len() as c_long;
+    pub fn is_loaded(&self) -> bool {
+        self.is_loaded
+    }
+}
 
-        unsafe {
-            let result = load(dirpath_ptr, dirpath_len);
-            if result == 1 {
-                self.is_loaded = true;
-                Ok(())
-            } else {
-                Err(format!("SHIORI load failed with code: {}", result))
-            }
+impl ShioriTransport for ShioriInterface {
+    /// SHIORIの初期化
+    fn load(&mut self, ghost_dir: &Path) -> Result<(), String> {
+        if self.is_loaded {
+            return Err("SHIORI is already loaded".to_string());
         }
-    }
 
-    /// SHIORIの終了処理
-    pub fn unload(&mut self) -> Result<(), String> {
-        if !self.is_loaded {
-            return Ok(());
+        // ディレクトリパスは末尾に区切り文字を付けて渡す
+        let mut dirpath = ghost_dir.to_string_lossy().into_owned();
+        if !dirpath.ends_with(std::path::MAIN_SEPARATOR) {
+            dirpath.push(std::path::MAIN_SEPARATOR);
         }
 
-        unsafe {
-            let result = unload();
-            self.is_loaded = false;
+        let load = self.symbol::<LoadFn>(b"load")?;
+        let h = global_alloc(dirpath.as_bytes())?;
 
-            if result == 1 {
-                Ok(())
-            } else {
-                Err(format!("SHIORI unload failed with code: {}", result))
-            }
+        // hの解放はSHIORI側の責任
+        let result = unsafe { load(h, dirpath.len() as c_long) };
+        if result == 0 {
+            return Err(format!("SHIORI load failed with code: {}", result));
         }
+
+        self.is_loaded = true;
+        Ok(())
     }
 
-    /// SHIORIリクエストの送信
-    pub fn request(&self, request_str: &str) -> Result<String, String> {
+    /// SHIORIリクエストの送信（エンコード済みのバイト列を送受信）
+    ///
+    /// 文字コードの変換は呼び出し側で`shiori_charset`を使って行う。
+    fn request(&mut self, request_bytes: &[u8]) -> Result<Vec<u8>, String> {
         if !self.is_loaded {
             return Err("SHIORI is not loaded".to_string());
         }
 
-        let request_cstring =
-            CString::new(request_str).map_err(|e| format!("Invalid request string: {}", e))?;
-
-        let request_ptr = request_cstring.as_ptr() as HGLOBAL;
-        let mut response_len: c_long = 0;
+        let request = self.symbol::<RequestFn>(b"request")?;
+        let h = global_alloc(request_bytes)?;
+        let mut response_len = request_bytes.len() as c_long;
 
         unsafe {
-            let response_ptr = request(request_ptr, &mut response_len);
+            // hの解放はSHIORI側の責任
+            let response_ptr = request(h, &mut response_len);
 
             if response_ptr.is_null() {
                 return Err("SHIORI request returned null".to_string());
             }
 
-            // レスポンスを文字列に変換
-            let response_slice =
-                std::slice::from_raw_parts(response_ptr as *const u8, response_len as usize);
-
-            // UTF-8として解釈
-            let response_str = String::from_utf8_lossy(response_slice).to_string();
+            let response = if response_len > 0 {
+                std::slice::from_raw_parts(response_ptr as *const u8, response_len as usize)
+                    .to_vec()
+            } else {
+                Vec::new()
+            };
 
-            // メモリを解放（YAYAが提供する方法で解放する必要がある）
-            // TODO: 適切なメモリ解放方法を実装
+            // 戻り値のメモリは呼び出し側が解放する
+            global_free(response_ptr);
 
-            Ok(response_str)
+            Ok(response)
         }
     }
 
-    /// SHIORI/3.0 フレームワークリクエスト
-    pub fn shiori_fw(&self, request_str: &str) -> Result<String, String> {
+    /// SHIORIの終了処理
+    fn unload(&mut self) -> Result<(), String> {
         if !self.is_loaded {
-            return Err("SHIORI is not loaded".to_string());
+            return Ok(());
         }
 
-        let request_cstring =
-            CString::new(request_str).map_err(|e| format!("Invalid request string: {}", e))?;
-
-        let request_ptr = request_cstring.as_ptr() as HGLOBAL;
-        let mut response_len: c_long = 0;
-
-        unsafe {
-            let response_ptr = SHIORI_FW(request_ptr, &mut response_len);
-
-            if response_ptr.is_null() {
-                return Err("SHIORI_FW request returned null".to_string());
-            }
-
-            let response_slice =
-                std::slice::from_raw_parts(response_ptr as *const u8, response_len as usize);
-
-            let response_str = String::from_utf8_lossy(response_slice).to_string();
+        let unload = self.symbol::<UnloadFn>(b"unload")?;
+        let result = unsafe { unload() };
+        self.is_loaded = false;
 
-            Ok(response_str)
+        if result != 0 {
+            Ok(())
+        } else {
+            Err(format!("SHIORI unload failed with code: {}", result))
         }
     }
-
-    pub fn is_loaded(&self) -> bool {
-        self.is_loaded
-    }
 }
 
 impl Drop for ShioriInterface {
@@ -147,27 +225,23 @@ impl Drop for ShioriInterface {
 
 /// SHIORIリクエストの構築ヘルパー
 pub struct ShioriRequestBuilder {
-    method: String,
-    version: String,
-    headers: Vec<(String, String)>,
+    request: ShioriRequest,
 }
 
 impl ShioriRequestBuilder {
     pub fn new() -> Self {
         ShioriRequestBuilder {
-            method: "GET".to_string(),
-            version: "SHIORI/3.0".to_string(),
-            headers: Vec::new(),
+            request: ShioriRequest::new(ShioriMethod::Get),
         }
     }
 
-    pub fn method(mut self, method: &str) -> Self {
-        self.method = method.to_string();
+    pub fn method(mut self, method: ShioriMethod) -> Self {
+        self.request.method = method;
         self
     }
 
     pub fn header(mut self, key: &str, value: &str) -> Self {
-        self.headers.push((key.to_string(), value.to_string()));
+        self.request.headers.push(key, value);
         self
     }
 
@@ -180,22 +254,27 @@ impl ShioriRequestBuilder {
     }
 
     pub fn event(self, event: &str) -> Self {
-        self.header("Event", event)
+        self.header("ID", event)
     }
 
     pub fn reference(self, index: usize, value: &str) -> Self {
         self.header(&format!("Reference{}", index), value)
     }
 
-    pub fn build(self) -> String {
-        let mut request = format!("{} {} {}\r\n", self.method, self.version, self.version);
+    /// 型付きリクエストとして取得
+    pub fn build_request(self) -> ShioriRequest {
+        self.request
+    }
 
-        for (key, value) in self.headers {
-            request.push_str(&format!("{}: {}\r\n", key, value));
-        }
+    /// ワイヤー形式の文字列として取得
+    pub fn build(self) -> String {
+        self.request.to_wire()
+    }
+}
 
-        request.push_str("\r\n");
-        request
+impl Default for ShioriRequestBuilder {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
@@ -204,24 +283,24 @@ impl ShioriRequestBuilder {
     /// OnBootイベントのリクエストを作成
     pub fn on_boot() -> Self {
         ShioriRequestBuilder::new()
-            .method("NOTIFY")
-            .header("Event", "OnBoot")
+            .method(ShioriMethod::Notify)
+            .event("OnBoot")
             .charset("UTF-8")
     }
 
     /// OnCloseイベントのリクエストを作成
     pub fn on_close() -> Self {
         ShioriRequestBuilder::new()
-            .method("NOTIFY")
-            .header("Event", "OnClose")
+            .method(ShioriMethod::Notify)
+            .event("OnClose")
             .charset("UTF-8")
     }
 
     /// OnMouseClickイベントのリクエストを作成
     pub fn on_mouse_click(x: i32, y: i32, button: &str) -> Self {
         ShioriRequestBuilder::new()
-            .method("NOTIFY")
-            .header("Event", "OnMouseClick")
+            .method(ShioriMethod::Notify)
+            .event("OnMouseClick")
             .reference(0, &x.to_string())
             .reference(1, &y.to_string())
             .reference(2, button)
@@ -231,8 +310,96 @@ impl ShioriRequestBuilder {
     /// OnSecondChangeイベントのリクエストを作成
     pub fn on_second_change() -> Self {
         ShioriRequestBuilder::new()
-            .method("NOTIFY")
-            .header("Event", "OnSecondChange")
+            .method(ShioriMethod::Notify)
+            .event("OnSecondChange")
             .charset("UTF-8")
     }
 }
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::process::Command;
+
+    /// load/unload/requestだけを持つ最小のSHIORI
+    const TEST_SHIORI_SOURCE: &str = r#"
+#include <stdlib.h>
+#include <string.h>
+#include <stdio.h>
+
+static char g_dir[1024];
+
+int load(void *h, long len) {
+    long n = len < 1023 ? len : 1023;
+    memcpy(g_dir, h, n);
+    g_dir[n] = '\0';
+    free(h);
+    return 1;
+}
+
+int unload(void) {
+    return 1;
+}
+
+void *request(void *h, long *len) {
+    char *response = malloc(256);
+    *len = snprintf(response, 256,
+        "SHIORI/3.0 200 OK\r\nCharset: UTF-8\r\nValue: %ld bytes from %s\r\n\r\n", *len, g_dir);
+    free(h);
+    return response;
+}
+"#;
+
+    /// テスト用のSHIORIをビルド（Cコンパイラ `cc` が必要。無ければテストを失敗させる）
+    fn build_test_shiori(dir: &Path) -> PathBuf {
+        std::fs::create_dir_all(dir).unwrap();
+        let source = dir.join("test_shiori.c");
+        let library = dir.join("test_shiori.so");
+        std::fs::write(&source, TEST_SHIORI_SOURCE).unwrap();
+
+        let status = Command::new("cc")
+            .args(["-shared", "-fPIC", "-o"])
+            .arg(&library)
+            .arg(&source)
+            .status()
+            .expect("C compiler `cc` is required for the native SHIORI test");
+        assert!(status.success(), "Failed to build the test SHIORI");
+        library
+    }
+
+    #[test]
+    fn test_native_shiori_round_trip() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path().to_path_buf();
+        let library = build_test_shiori(&dir);
+
+        // .dll指定でも同じディレクトリの.soが見つかる
+        assert_eq!(
+            resolve_native_library(&dir, "test_shiori.dll"),
+            Some(library.clone())
+        );
+        assert_eq!(resolve_native_library(&dir, "missing.dll"), None);
+
+        let mut shiori = ShioriInterface::open(&library).unwrap();
+        shiori.load(&dir).unwrap();
+        assert!(shiori.is_loaded());
+
+        let request = ShioriRequest::get("OnBoot").to_wire();
+        let response = shiori.request(request.as_bytes()).unwrap();
+        let response = String::from_utf8(response).unwrap();
+        assert!(response.starts_with("SHIORI/3.0 200 OK\r\n"));
+        assert!(response.contains(&format!(
+            "Value: {} bytes from {}/",
+            request.len(),
+            dir.display()
+        )));
+
+        shiori.unload().unwrap();
+        assert!(!shiori.is_loaded());
+    }
+
+    #[test]
+    fn test_open_rejects_missing_library() {
+        assert!(ShioriInterface::open(Path::new("/nonexistent/shiori.so")).is_err());
+    }
+}
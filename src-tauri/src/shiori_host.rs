@@ -0,0 +1,256 @@
+//! SHIORI Host
+//!
+//! `mascot-shiori-host` の中身。1つのSHIORIを読み込み、
+//! 標準入出力上のフレーム単位のプロトコルでリクエストを中継する。
+//!
+//! フレーム形式: 種別1バイト + ペイロード長（u32 リトルエンディアン） + ペイロード
+
+use crate::shiori_cpp_integration::YayaEngine;
+use crate::shiori_engine::ShioriTransport;
+use crate::shiori_ffi::ShioriInterface;
+use crate::shiori_manager::{GhostInfo, ShioriType};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+/// ホストに送るコマンド
+pub const FRAME_LOAD: u8 = b'L';
+pub const FRAME_REQUEST: u8 = b'R';
+pub const FRAME_UNLOAD: u8 = b'U';
+
+/// ホストからの返信
+pub const FRAME_OK: u8 = b'O';
+pub const FRAME_ERROR: u8 = b'E';
+
+/// 1フレームの最大サイズ（壊れた長さで巨大な確保をしないため）
+const MAX_FRAME_LEN: usize = 64 * 1024 * 1024;
+
+/// フレームを書き込む
+pub fn write_frame<W: Write>(writer: &mut W, kind: u8, payload: &[u8]) -> io::Result<()> {
+    let len = u32::try_from(payload.len())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "frame too large"))?;
+    writer.write_all(&[kind])?;
+    writer.write_all(&len.to_le_bytes())?;
+    writer.write_all(payload)?;
+    writer.flush()
+}
+
+/// フレームを読み込む（相手が閉じていれば `None`）
+pub fn read_frame<R: Read>(reader: &mut R) -> io::Result<Option<(u8, Vec<u8>)>> {
+    let mut kind = [0u8; 1];
+    match reader.read_exact(&mut kind) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+
+    let mut len = [0u8; 4];
+    reader.read_exact(&mut len)?;
+    let len = u32::from_le_bytes(len) as usize;
+    if len > MAX_FRAME_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("frame too large: {} bytes", len),
+        ));
+    }
+
+    let mut payload = vec![0u8; len];
+    reader.read_exact(&mut payload)?;
+    Ok(Some((kind[0], payload)))
+}
+
+/// ホストで動かすSHIORI
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HostTarget {
+    /// 動的ライブラリとして配布されたSHIORI
+    Native(PathBuf),
+    /// 組み込みのYAYA
+    BuiltinYaya,
+}
+
+impl HostTarget {
+    /// ゴースト情報からホストで動かすSHIORIを決める
+    pub fn for_ghost(ghost_info: &GhostInfo) -> Result<Self, String> {
+        if let Some(library) = &ghost_info.native_library {
+            return Ok(HostTarget::Native(library.clone()));
+        }
+
+        match &ghost_info.shiori_type {
+            ShioriType::YAYA => Ok(HostTarget::BuiltinYaya),
+            other => Err(format!("SHIORI host cannot run {:?}", other)),
+        }
+    }
+
+    /// ホストのコマンドライン引数
+    pub fn to_args(&self) -> Vec<String> {
+        match self {
+            HostTarget::Native(path) => {
+                vec!["--library".to_string(), path.to_string_lossy().to_string()]
+            }
+            HostTarget::BuiltinYaya => vec!["--builtin".to_string(), "yaya".to_string()],
+        }
+    }
+
+    /// コマンドライン引数から復元
+    pub fn from_args(args: &[String]) -> Result<Self, String> {
+        match args {
+            [flag, path] if flag == "--library" => Ok(HostTarget::Native(PathBuf::from(path))),
+            [flag, name] if flag == "--builtin" && name == "yaya" => Ok(HostTarget::BuiltinYaya),
+            _ => Err("usage: mascot-shiori-host (--library <path> | --builtin yaya)".to_string()),
+        }
+    }
+
+    /// SHIORIを開く
+    pub fn open(&self) -> Result<Box<dyn ShioriTransport>, String> {
+        match self {
+            HostTarget::Native(path) => Ok(Box::new(ShioriInterface::open(path)?)),
+            HostTarget::BuiltinYaya => Ok(Box::new(YayaEngine::new())),
+        }
+    }
+}
+
+/// フレームを1つずつ処理し、アンロードか入力の終端で戻る
+pub fn serve<R: Read, W: Write>(
+    transport: &mut dyn ShioriTransport,
+    reader: &mut R,
+    writer: &mut W,
+) -> io::Result<()> {
+    while let Some((kind, payload)) = read_frame(reader)? {
+        let result = match kind {
+            FRAME_LOAD => {
+                let ghost_dir = String::from_utf8_lossy(&payload).to_string();
+                transport.load(Path::new(&ghost_dir)).map(|_| Vec::new())
+            }
+            FRAME_REQUEST => transport.request(&payload),
+            FRAME_UNLOAD => transport.unload().map(|_| Vec::new()),
+            other => Err(format!("Unknown frame type: {:#04x}", other)),
+        };
+
+        match result {
+            Ok(output) => write_frame(writer, FRAME_OK, &output)?,
+            Err(e) => write_frame(writer, FRAME_ERROR, e.as_bytes())?,
+        }
+
+        if kind == FRAME_UNLOAD {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+/// プロトコル用の標準出力を取り出す
+///
+/// SHIORIやログの `println!` がフレームに混ざらないよう、
+/// 元の標準出力を複製してから fd 1 を標準エラー出力に付け替える。
+#[cfg(unix)]
+fn protocol_stdout() -> io::Result<Box<dyn Write>> {
+    use std::fs::File;
+    use std::os::fd::FromRawFd;
+
+    unsafe {
+        let fd = libc::dup(libc::STDOUT_FILENO);
+        if fd < 0 || libc::dup2(libc::STDERR_FILENO, libc::STDOUT_FILENO) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Box::new(File::from_raw_fd(fd)))
+    }
+}
+
+#[cfg(not(unix))]
+fn protocol_stdout() -> io::Result<Box<dyn Write>> {
+    Ok(Box::new(io::stdout()))
+}
+
+/// ホストのエントリポイント
+pub fn run_host(args: &[String]) -> Result<(), String> {
+    let target = HostTarget::from_args(args)?;
+    let mut writer = protocol_stdout().map_err(|e| format!("Failed to set up stdout: {}", e))?;
+    let mut transport = target.open()?;
+
+    eprintln!("🧩 SHIORI host started: {:?}", target);
+    let result = serve(transport.as_mut(), &mut io::stdin().lock(), &mut writer)
+        .map_err(|e| format!("SHIORI host I/O error: {}", e));
+    let _ = transport.unload();
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// リクエストを大文字にして返すだけのSHIORI
+    struct UpperTransport {
+        loaded: Option<PathBuf>,
+    }
+
+    impl ShioriTransport for UpperTransport {
+        fn load(&mut self, ghost_dir: &Path) -> Result<(), String> {
+            self.loaded = Some(ghost_dir.to_path_buf());
+            Ok(())
+        }
+
+        fn request(&mut self, request: &[u8]) -> Result<Vec<u8>, String> {
+            if self.loaded.is_none() {
+                return Err("not loaded".to_string());
+            }
+            Ok(request.to_ascii_uppercase())
+        }
+
+        fn unload(&mut self) -> Result<(), String> {
+            self.loaded = None;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_frame_round_trip() {
+        let mut buffer = Vec::new();
+        write_frame(&mut buffer, FRAME_REQUEST, b"GET SHIORI/3.0").unwrap();
+        write_frame(&mut buffer, FRAME_UNLOAD, b"").unwrap();
+
+        let mut reader = Cursor::new(buffer);
+        assert_eq!(
+            read_frame(&mut reader).unwrap(),
+            Some((FRAME_REQUEST, b"GET SHIORI/3.0".to_vec()))
+        );
+        assert_eq!(
+            read_frame(&mut reader).unwrap(),
+            Some((FRAME_UNLOAD, Vec::new()))
+        );
+        assert_eq!(read_frame(&mut reader).unwrap(), None);
+    }
+
+    #[test]
+    fn test_serve() {
+        let mut input = Vec::new();
+        write_frame(&mut input, FRAME_REQUEST, b"early").unwrap();
+        write_frame(&mut input, FRAME_LOAD, b"/ghost/master/").unwrap();
+        write_frame(&mut input, FRAME_REQUEST, b"get shiori/3.0").unwrap();
+        write_frame(&mut input, FRAME_UNLOAD, b"").unwrap();
+        write_frame(&mut input, FRAME_REQUEST, b"ignored").unwrap();
+
+        let mut transport = UpperTransport { loaded: None };
+        let mut output = Vec::new();
+        serve(&mut transport, &mut Cursor::new(input), &mut output).unwrap();
+
+        let mut replies = Cursor::new(output);
+        let mut next = || read_frame(&mut replies).unwrap();
+        assert_eq!(next(), Some((FRAME_ERROR, b"not loaded".to_vec())));
+        assert_eq!(next(), Some((FRAME_OK, Vec::new())));
+        assert_eq!(next(), Some((FRAME_OK, b"GET SHIORI/3.0".to_vec())));
+        assert_eq!(next(), Some((FRAME_OK, Vec::new())));
+        assert_eq!(next(), None);
+    }
+
+    #[test]
+    fn test_target_args() {
+        let native = HostTarget::Native(PathBuf::from("/ghost/master/satori.so"));
+        assert_eq!(HostTarget::from_args(&native.to_args()), Ok(native));
+        assert_eq!(
+            HostTarget::from_args(&HostTarget::BuiltinYaya.to_args()),
+            Ok(HostTarget::BuiltinYaya)
+        );
+        assert!(HostTarget::from_args(&[]).is_err());
+    }
+}
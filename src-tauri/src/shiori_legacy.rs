@@ -0,0 +1,266 @@
+//! SHIORI/2.x Compatibility
+//!
+//! SHIORI/2.x しか話せない古いゴーストのために、
+//! プロトコルバージョンの判定と 3.0 ⇔ 2.x の相互変換を行う
+
+use crate::shiori_protocol::{
+    ShioriHeaders, ShioriMethod, ShioriRequest, ShioriResponse, ShioriStatus, ShioriVersion,
+};
+use serde::{Deserialize, Serialize};
+
+/// ゴーストが話すプロトコル
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ProtocolVersion {
+    Shiori3,
+    Shiori2,
+}
+
+/// SHIORI/2.x のリクエストコマンド
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LegacyCommand {
+    GetSentence,
+    GetString,
+    GetVersion,
+    NotifyOwnerGhostName,
+    NotifyOtherGhostName,
+    Teach,
+}
+
+impl LegacyCommand {
+    /// リクエスト行のメソッド部分
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LegacyCommand::GetSentence => "GET Sentence",
+            LegacyCommand::GetString => "GET String",
+            LegacyCommand::GetVersion => "GET Version",
+            LegacyCommand::NotifyOwnerGhostName => "NOTIFY OwnerGhostName",
+            LegacyCommand::NotifyOtherGhostName => "NOTIFY OtherGhostName",
+            LegacyCommand::Teach => "TEACH",
+        }
+    }
+}
+
+/// SHIORI/2.x リクエスト
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LegacyRequest {
+    pub command: LegacyCommand,
+    pub version: ShioriVersion,
+    pub headers: ShioriHeaders,
+}
+
+impl LegacyRequest {
+    fn new(command: LegacyCommand, minor: u8) -> Self {
+        LegacyRequest {
+            command,
+            version: ShioriVersion { major: 2, minor },
+            headers: ShioriHeaders::new(),
+        }
+    }
+
+    /// ワイヤー形式の文字列に変換
+    pub fn to_wire(&self) -> String {
+        let mut out = format!("{} {}\r\n", self.command.as_str(), self.version);
+        self.headers.write_wire(&mut out);
+        out.push_str("\r\n");
+        out
+    }
+}
+
+/// バージョン判定用のリクエスト（3.0形式の GET version）
+pub fn version_probe() -> ShioriRequest {
+    ShioriRequest::get("version")
+}
+
+/// 判定用リクエストへの生レスポンスからプロトコルを決定
+///
+/// 3.0 を理解するSHIORIは `SHIORI/3.0` で応答する。2.x のSHIORIは
+/// `SHIORI/2.x 400 Bad Request` などを返すか、解析不能な応答を返す。
+pub fn negotiate(probe_response: &str) -> ProtocolVersion {
+    match ShioriResponse::parse(probe_response) {
+        Ok(response)
+            if response.version.major >= 3 && response.status != ShioriStatus::BadRequest =>
+        {
+            ProtocolVersion::Shiori3
+        }
+        _ => ProtocolVersion::Shiori2,
+    }
+}
+
+/// 3.0 リクエストを 2.x リクエストに変換
+///
+/// 2.x に対応する表現が無い NOTIFY は `None` を返す（送信せず 204 として扱う）。
+pub fn translate_request(request: &ShioriRequest) -> Option<LegacyRequest> {
+    let id = request.id().unwrap_or_default();
+    let references = request.references();
+    let reference = |index: usize| {
+        references
+            .iter()
+            .find(|(i, _)| *i == index)
+            .map(|(_, v)| *v)
+            .unwrap_or_default()
+    };
+
+    let legacy = match (request.method, id) {
+        (_, "version") => LegacyRequest::new(LegacyCommand::GetVersion, 0),
+        (_, "ownerghostname") => {
+            let mut legacy = LegacyRequest::new(LegacyCommand::NotifyOwnerGhostName, 0);
+            legacy.headers.push("Ghost", reference(0));
+            legacy
+        }
+        (_, "otherghostname") => {
+            let mut legacy = LegacyRequest::new(LegacyCommand::NotifyOtherGhostName, 3);
+            for (_, name) in &references {
+                legacy.headers.push("Ghost", name);
+            }
+            legacy
+        }
+        (ShioriMethod::Notify, _) => return None,
+        (ShioriMethod::Get, "OnTeach") => {
+            let mut legacy = LegacyRequest::new(LegacyCommand::Teach, 4);
+            legacy.headers.push("Word", reference(0));
+            legacy
+        }
+        (ShioriMethod::Get, "OnCommunicate") => {
+            let mut legacy = LegacyRequest::new(LegacyCommand::GetSentence, 3);
+            legacy.headers.push("Sender", reference(0));
+            legacy.headers.push("Sentence", reference(1));
+            for (index, value) in references.iter().filter(|(i, _)| *i >= 2) {
+                legacy
+                    .headers
+                    .push(&format!("Reference{}", index - 2), value);
+            }
+            legacy
+        }
+        (ShioriMethod::Get, event) if event.starts_with("On") => {
+            let mut legacy = LegacyRequest::new(LegacyCommand::GetSentence, 2);
+            legacy.headers.push("Event", event);
+            for (index, value) in &references {
+                legacy.headers.push(&format!("Reference{}", index), value);
+            }
+            legacy
+        }
+        (ShioriMethod::Get, resource) => {
+            let mut legacy = LegacyRequest::new(LegacyCommand::GetString, 5);
+            legacy.headers.push("ID", resource);
+            legacy
+        }
+    };
+
+    Some(with_common_headers(legacy, request))
+}
+
+/// Sender・Charset・SecurityLevel を引き継ぐ
+fn with_common_headers(mut legacy: LegacyRequest, request: &ShioriRequest) -> LegacyRequest {
+    if let (None, Some(sender)) = (legacy.headers.get("Sender"), request.sender()) {
+        legacy.headers.push("Sender", sender);
+    }
+    if let Some(charset) = request.charset() {
+        legacy.headers.push("Charset", charset);
+    }
+    if let Some(level) = request.security_level() {
+        legacy.headers.push("SecurityLevel", level);
+    }
+    legacy
+}
+
+/// 2.x レスポンスを 3.0 形式に正規化
+///
+/// `Sentence`・`String`・`Word`・`Version` のいずれかを `Value` に置き換える。
+pub fn normalize_response(raw: &str) -> Result<ShioriResponse, String> {
+    let legacy = ShioriResponse::parse(raw)?;
+    let mut response = ShioriResponse::new(legacy.status);
+
+    let mut has_value = false;
+    for (key, value) in legacy.headers.iter() {
+        let is_value = ["Sentence", "String", "Word", "Version", "Value"]
+            .iter()
+            .any(|name| key.eq_ignore_ascii_case(name));
+
+        if is_value {
+            if !has_value {
+                response.headers.push("Value", value);
+                has_value = true;
+            }
+        } else {
+            response.headers.push(key, value);
+        }
+    }
+
+    // 2.x では内容が空の 200 もよくあるので 204 として扱う
+    if response.status == ShioriStatus::Ok && response.value().is_none_or(|v| v.is_empty()) {
+        response.status = ShioriStatus::NoContent;
+    }
+
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_negotiate() {
+        assert_eq!(
+            negotiate("SHIORI/3.0 200 OK\r\nValue: YAYA\r\n\r\n"),
+            ProtocolVersion::Shiori3
+        );
+        assert_eq!(
+            negotiate("SHIORI/2.0 400 Bad Request\r\n\r\n"),
+            ProtocolVersion::Shiori2
+        );
+        assert_eq!(negotiate("garbage"), ProtocolVersion::Shiori2);
+    }
+
+    #[test]
+    fn test_translate_event_to_sentence() {
+        let request = ShioriRequest::get("OnMouseClick").with_references(&["10", "20"]);
+        let legacy = translate_request(&request).unwrap();
+
+        assert_eq!(legacy.command, LegacyCommand::GetSentence);
+        assert_eq!(
+            legacy.to_wire(),
+            "GET Sentence SHIORI/2.2\r\nEvent: OnMouseClick\r\nReference0: 10\r\nReference1: 20\r\nSender: mascot_nanai\r\nCharset: UTF-8\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn test_translate_special_ids() {
+        let version = translate_request(&ShioriRequest::get("version")).unwrap();
+        assert_eq!(version.command, LegacyCommand::GetVersion);
+
+        let owner = translate_request(
+            &ShioriRequest::notify("ownerghostname").with_references(&["ナナイ"]),
+        )
+        .unwrap();
+        assert_eq!(owner.command, LegacyCommand::NotifyOwnerGhostName);
+        assert_eq!(owner.headers.get("Ghost"), Some("ナナイ"));
+
+        let teach =
+            translate_request(&ShioriRequest::get("OnTeach").with_references(&["りんご"])).unwrap();
+        assert_eq!(teach.to_wire().lines().next(), Some("TEACH SHIORI/2.4"));
+        assert_eq!(teach.headers.get("Word"), Some("りんご"));
+
+        let string = translate_request(&ShioriRequest::get("homeurl")).unwrap();
+        assert_eq!(string.command, LegacyCommand::GetString);
+        assert_eq!(string.headers.get("ID"), Some("homeurl"));
+
+        assert!(translate_request(&ShioriRequest::notify("OnSecondChange")).is_none());
+    }
+
+    #[test]
+    fn test_normalize_response() {
+        let response = normalize_response(
+            "SHIORI/2.0 200 OK\r\nSentence: \\0\\s[0]やあ\\e\r\nTo: kero\r\n\r\n",
+        )
+        .unwrap();
+        assert_eq!(response.version, ShioriVersion::V3_0);
+        assert_eq!(response.value(), Some("\\0\\s[0]やあ\\e"));
+        assert_eq!(response.headers.get("To"), Some("kero"));
+
+        let empty = normalize_response("SHIORI/2.2 200 OK\r\nSentence: \r\n\r\n").unwrap();
+        assert_eq!(empty.status, ShioriStatus::NoContent);
+
+        let version = normalize_response("SHIORI/2.0 200 OK\r\nVersion: 1.2\r\n\r\n").unwrap();
+        assert_eq!(version.value(), Some("1.2"));
+    }
+}
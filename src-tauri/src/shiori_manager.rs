@@ -2,11 +2,17 @@
 //!
 //! ゴーストディレクトリの検出、SHIORI種別の判定、実行管理を行う
 
-use crate::shiori_cpp_integration::{EngineType, IntegratedShiori};
-use parking_lot::RwLock;
+use crate::mouse_events::MouseEvent;
+use crate::shiori_charset::ShioriCharset;
+use crate::shiori_engine::{self, ShioriEngine};
+use crate::shiori_ffi;
+use crate::shiori_mock;
+use crate::shiori_protocol::{ShioriRequest, ShioriResponse};
+use mascot_nanai_ui::descript::Descript;
+use mascot_nanai_ui::sakura_choice::{ChoiceAction, SakuraChoice};
+use parking_lot::{Mutex, RwLock};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::fs;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use walkdir::WalkDir;
@@ -15,36 +21,105 @@ use walkdir::WalkDir;
 pub enum ShioriType {
     YAYA,
     SATORIYA,
+    /// フィクスチャで動く開発用のSHIORI
+    Mock,
     Unknown(String),
 }
 
-impl From<EngineType> for ShioriType {
-    fn from(engine: EngineType) -> Self {
-        match engine {
-            EngineType::YAYA => ShioriType::YAYA,
-            EngineType::SATORIYA => ShioriType::SATORIYA,
-            EngineType::None => ShioriType::Unknown("None".to_string()),
-        }
-    }
-}
-
 /// ゴーストの情報
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GhostInfo {
+    /// ゴーストID（ゴーストのディレクトリから決まる。同名のゴーストがあっても重ならない）
+    pub id: String,
+    /// フォルダ名
     pub name: String,
     pub path: PathBuf,
     pub shiori_type: ShioriType,
     pub shiori_dll: Option<PathBuf>,
-    pub description: Option<String>,
-    pub craftman: Option<String>,
-    pub version: Option<String>,
+    pub charset: ShioriCharset,
+    /// このプラットフォームで読み込めるネイティブSHIORI（.so など）
+    pub native_library: Option<PathBuf>,
+    /// ghost/master/descript.txt の内容
+    pub descript: Descript,
+}
+
+impl GhostInfo {
+    /// SHIORIに渡すディレクトリ（通常は ghost/master）
+    pub fn shiori_dir(&self) -> PathBuf {
+        if let Some(dir) = self.shiori_dll.as_ref().and_then(|dll| dll.parent()) {
+            return dir.to_path_buf();
+        }
+
+        let master = self.path.join("ghost").join("master");
+        if master.is_dir() {
+            master
+        } else {
+            self.path.clone()
+        }
+    }
+
+    /// 既定のシェルのディレクトリ（descript.txt の `shell`、無ければ shell/master）
+    pub fn shell_dir(&self) -> PathBuf {
+        let shell = self.descript.default_shell.as_deref().unwrap_or("master");
+        self.path.join("shell").join(shell)
+    }
+}
+
+/// スキャンで解析できなかったディレクトリ
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GhostScanError {
+    pub path: PathBuf,
+    pub error: String,
+}
+
+/// ゴーストスキャンの結果
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct GhostScanReport {
+    /// 見つかったゴースト（ID順）
+    pub ghosts: Vec<GhostInfo>,
+    pub errors: Vec<GhostScanError>,
+}
+
+/// ゴーストのディレクトリからゴーストIDを作る
+pub fn ghost_id(ghost_path: &Path) -> String {
+    normalize(ghost_path).to_string_lossy().replace('\\', "/")
 }
 
+/// ghost/master/descript.txt を持つディレクトリか
+pub fn is_ghost_root(path: &Path) -> bool {
+    path.join("ghost")
+        .join("master")
+        .join("descript.txt")
+        .exists()
+}
+
+/// ghost/master など、ゴーストの中のディレクトリからゴーストのディレクトリを探す
+pub fn find_ghost_root(path: &Path) -> Option<PathBuf> {
+    path.ancestors()
+        .take(3)
+        .find(|dir| is_ghost_root(dir))
+        .map(Path::to_path_buf)
+}
+
+/// 絶対パスにする（存在しなければそのまま）
+fn normalize(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// ゴースト情報からエンジンを作成する関数
+pub type EngineFactory =
+    Box<dyn Fn(&GhostInfo) -> Result<Box<dyn ShioriEngine>, String> + Send + Sync>;
+
+/// 読み込み済みゴーストのエンジン（ゴーストごとに個別にロックする）
+type LoadedEngine = Arc<Mutex<Box<dyn ShioriEngine>>>;
+
 /// SHIORIマネージャー
 pub struct ShioriManager {
+    /// ゴーストIDごとのゴースト情報
     ghosts: RwLock<HashMap<String, GhostInfo>>,
     current_ghost: RwLock<Option<String>>,
-    active_engine: RwLock<Option<EngineType>>,
+    engines: RwLock<HashMap<String, LoadedEngine>>,
+    engine_factory: RwLock<EngineFactory>,
 }
 
 impl ShioriManager {
@@ -52,83 +127,107 @@ impl ShioriManager {
         Arc::new(ShioriManager {
             ghosts: RwLock::new(HashMap::new()),
             current_ghost: RwLock::new(None),
-            active_engine: RwLock::new(None),
+            engines: RwLock::new(HashMap::new()),
+            engine_factory: RwLock::new(Box::new(shiori_engine::create_engine)),
         })
     }
 
-    /// ゴーストディレクトリをスキャンしてSHIORIを検出
-    pub fn scan_ghost_directory(&self, ghost_dir: &Path) -> Result<(), String> {
-        println!("🔍 Scanning ghost directory: {:?}", ghost_dir);
+    /// エンジンの作成方法を差し替える（テストでの偽エンジン注入など）
+    pub fn set_engine_factory(&self, factory: EngineFactory) {
+        *self.engine_factory.write() = factory;
+    }
 
-        if !ghost_dir.exists() {
-            return Err(format!("Ghost directory not found: {:?}", ghost_dir));
-        }
+    /// ゴースト情報を直接登録
+    pub fn register_ghost(&self, ghost_info: GhostInfo) {
+        self.ghosts
+            .write()
+            .insert(ghost_info.id.clone(), ghost_info);
+    }
 
-        let mut found_ghosts = HashMap::new();
+    /// ゴーストディレクトリをスキャンしてSHIORIを検出
+    pub fn scan_ghost_directory(&self, ghost_dir: &Path) -> GhostScanReport {
+        self.scan_ghost_roots(&[ghost_dir.to_path_buf()])
+    }
 
-        // ゴーストディレクトリ内を再帰的に検索
-        for entry in WalkDir::new(ghost_dir).max_depth(4) {
-            let entry = entry.map_err(|e| format!("Directory walk error: {}", e))?;
-            let path = entry.path();
+    /// 複数のディレクトリ以下のゴーストを探す
+    ///
+    /// スキャンしたディレクトリ以下のゴースト情報だけを置き換える。
+    /// 読み込み済みのゴーストは見つからなくなっても残す。
+    pub fn scan_ghost_roots(&self, roots: &[PathBuf]) -> GhostScanReport {
+        let mut report = GhostScanReport::default();
+        let mut found_ghosts: HashMap<String, GhostInfo> = HashMap::new();
+        let mut scanned_roots = Vec::new();
+
+        for root in roots {
+            println!("🔍 Scanning ghost directory: {:?}", root);
+            if !root.is_dir() {
+                report.errors.push(GhostScanError {
+                    path: root.clone(),
+                    error: format!("Ghost directory not found: {:?}", root),
+                });
+                continue;
+            }
+            let root = normalize(root);
+
+            // ghost/master/descript.txt を持つディレクトリをゴーストとみなす
+            let mut walker = WalkDir::new(&root).max_depth(4).into_iter();
+            while let Some(entry) = walker.next() {
+                let entry = match entry {
+                    Ok(entry) => entry,
+                    Err(e) => {
+                        report.errors.push(GhostScanError {
+                            path: e.path().unwrap_or(&root).to_path_buf(),
+                            error: format!("Directory walk error: {}", e),
+                        });
+                        continue;
+                    }
+                };
+                if !entry.file_type().is_dir() || !is_ghost_root(entry.path()) {
+                    continue;
+                }
+                // ゴーストの中（シェルなど）はそれ以上探さない
+                walker.skip_current_dir();
 
-            // descript.txtファイルを探す
-            if path.file_name() == Some("descript.txt".as_ref()) {
-                println!("📄 Found descript.txt at: {:?}", path);
-
-                // ゴーストのルートディレクトリを特定
-                if let Some(ghost_root) = self.find_ghost_root_from_descript(path) {
-                    println!("👻 Analyzing ghost root directory: {:?}", ghost_root);
-                    match self.analyze_ghost_directory(&ghost_root) {
-                        Ok(ghost_info) => {
-                            println!("✅ Successfully analyzed ghost: {}", ghost_info.name);
-                            found_ghosts.insert(ghost_info.name.clone(), ghost_info);
-                        }
-                        Err(e) => {
-                            println!(
-                                "❌ Failed to analyze ghost directory {:?}: {}",
-                                ghost_root, e
-                            );
-                        }
+                match self.analyze_ghost_directory(entry.path()) {
+                    Ok(ghost_info) => {
+                        found_ghosts.insert(ghost_info.id.clone(), ghost_info);
                     }
-                } else {
-                    println!(
-                        "❌ Could not determine ghost root for descript.txt at: {:?}",
-                        path
-                    );
+                    Err(error) => report.errors.push(GhostScanError {
+                        path: entry.path().to_path_buf(),
+                        error,
+                    }),
                 }
             }
+            scanned_roots.push(root);
         }
 
-        println!("📊 Total ghosts found: {}", found_ghosts.len());
+        println!(
+            "📊 Total ghosts found: {} ({} errors)",
+            found_ghosts.len(),
+            report.errors.len()
+        );
 
         // 結果を更新
+        let engines = self.engines.read();
         let mut ghosts = self.ghosts.write();
-        *ghosts = found_ghosts;
-
-        Ok(())
-    }
-
-    /// descript.txtのパスからゴーストのルートディレクトリを特定
-    fn find_ghost_root_from_descript(&self, descript_path: &Path) -> Option<PathBuf> {
-        let mut current = descript_path.parent()?; // ghost/master/ or similar
-
-        // masterディレクトリの場合、さらに上のghostディレクトリに移動
-        if current.file_name()?.to_string_lossy() == "master" {
-            current = current.parent()?; // ghost/
-        }
-
-        // ghostディレクトリの場合、さらに上のゴーストルートディレクトリに移動
-        if current.file_name()?.to_string_lossy() == "ghost" {
-            current = current.parent()?; // ghost_name/
-        }
-
-        Some(current.to_path_buf())
+        ghosts.retain(|id, ghost| {
+            engines.contains_key(id)
+                || !scanned_roots
+                    .iter()
+                    .any(|root| ghost.path.starts_with(root))
+        });
+        report.ghosts = found_ghosts.values().cloned().collect();
+        report.ghosts.sort_by(|a, b| a.id.cmp(&b.id));
+        ghosts.extend(found_ghosts);
+
+        report
     }
 
     /// 個別のゴーストディレクトリを解析
     fn analyze_ghost_directory(&self, ghost_path: &Path) -> Result<GhostInfo, String> {
         println!("🔍 Analyzing ghost directory: {:?}", ghost_path);
 
+        let ghost_path = &normalize(ghost_path);
         let ghost_name = ghost_path
             .file_name()
             .unwrap_or_default()
@@ -158,47 +257,48 @@ impl ShioriManager {
 
         println!("📝 Reading descript.txt from: {:?}", descript_path);
 
-        // descript.txtを読み込み（Shift_JISエンコーディング対応）
-        let descript_content = self
-            .read_shift_jis_file(&descript_path)
+        // descript.txtを読み込み（charset行に従って変換、無ければShift_JIS）
+        let descript = Descript::load(descript_path)
             .map_err(|e| format!("Failed to read descript.txt: {}", e))?;
 
-        println!(
-            "📄 Descript content length: {} chars",
-            descript_content.len()
-        );
-
-        let (description, craftman, version) = self.parse_descript(&descript_content);
+        // charset指定が無い場合は伺かの慣例に従いShift_JISとみなす
+        let charset = descript
+            .charset
+            .as_deref()
+            .and_then(ShioriCharset::from_label)
+            .unwrap_or(ShioriCharset::ShiftJis);
 
         // SHIORIファイルを検索（ghost/master/ ディレクトリから）
         let shiori_search_dir = descript_path.parent().unwrap_or(ghost_path);
         let (shiori_type, shiori_dll) = self.detect_shiori_type(shiori_search_dir)?;
 
+        // このプラットフォーム向けのネイティブSHIORIが同梱されていれば使う
+        let shiori_name = descript.shiori.clone().or_else(|| {
+            shiori_dll
+                .as_ref()
+                .and_then(|dll| dll.file_name())
+                .map(|name| name.to_string_lossy().to_string())
+        });
+        let native_library = shiori_name
+            .and_then(|name| shiori_ffi::resolve_native_library(shiori_search_dir, &name));
+
         println!(
             "✅ Ghost analyzed - Name: {}, Type: {:?}",
             ghost_name, shiori_type
         );
 
         Ok(GhostInfo {
+            id: ghost_id(ghost_path),
             name: ghost_name,
             path: ghost_path.to_path_buf(),
             shiori_type,
             shiori_dll,
-            description,
-            craftman,
-            version,
+            charset,
+            native_library,
+            descript,
         })
     }
 
-    /// Shift_JISファイルを読み込み
-    fn read_shift_jis_file(&self, path: &Path) -> Result<String, std::io::Error> {
-        use encoding_rs::SHIFT_JIS;
-
-        let bytes = fs::read(path)?;
-        let (decoded, _, _) = SHIFT_JIS.decode(&bytes);
-        Ok(decoded.into_owned())
-    }
-
     /// SHIORIの種類とDLLパスを検出
     fn detect_shiori_type(
         &self,
@@ -211,36 +311,36 @@ impl ShioriManager {
             let entry = entry.map_err(|e| format!("SHIORI detection error: {}", e))?;
             let path = entry.path();
 
-            if let Some(extension) = path.extension() {
-                if extension == "dll" {
-                    println!("🔍 Found DLL: {:?}", path);
-
-                    let file_name = path
-                        .file_name()
-                        .unwrap_or_default()
-                        .to_string_lossy()
-                        .to_lowercase();
-
-                    // YAYA系のDLL
-                    if file_name.contains("yaya") || file_name.contains("aya") {
-                        println!("✅ Detected YAYA SHIORI: {}", file_name);
-                        return Ok((ShioriType::YAYA, Some(path.to_path_buf())));
-                    }
+            if let Some(extension) = path.extension()
+                && extension == "dll"
+            {
+                println!("🔍 Found DLL: {:?}", path);
+
+                let file_name = path
+                    .file_name()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .to_lowercase();
+
+                // YAYA系のDLL
+                if file_name.contains("yaya") || file_name.contains("aya") {
+                    println!("✅ Detected YAYA SHIORI: {}", file_name);
+                    return Ok((ShioriType::YAYA, Some(path.to_path_buf())));
+                }
 
-                    // SATORIYA系のDLL
-                    if file_name.contains("satori") {
-                        println!("✅ Detected SATORIYA SHIORI: {}", file_name);
-                        return Ok((ShioriType::SATORIYA, Some(path.to_path_buf())));
-                    }
+                // SATORIYA系のDLL
+                if file_name.contains("satori") {
+                    println!("✅ Detected SATORIYA SHIORI: {}", file_name);
+                    return Ok((ShioriType::SATORIYA, Some(path.to_path_buf())));
+                }
 
-                    // 汎用的なSHIORIファイル名
-                    if file_name == "shiori.dll" {
-                        println!("🔍 Found generic shiori.dll, guessing type from directory...");
-                        // DLLの内容やディレクトリ構造から推測
-                        let shiori_type = self.guess_shiori_type_from_directory(ghost_path);
-                        println!("✅ Guessed SHIORI type: {:?}", shiori_type);
-                        return Ok((shiori_type, Some(path.to_path_buf())));
-                    }
+                // 汎用的なSHIORIファイル名
+                if file_name == "shiori.dll" {
+                    println!("🔍 Found generic shiori.dll, guessing type from directory...");
+                    // DLLの内容やディレクトリ構造から推測
+                    let shiori_type = self.guess_shiori_type_from_directory(ghost_path);
+                    println!("✅ Guessed SHIORI type: {:?}", shiori_type);
+                    return Ok((shiori_type, Some(path.to_path_buf())));
                 }
             }
         }
@@ -252,6 +352,14 @@ impl ShioriManager {
 
     /// ディレクトリ構造からSHIORIタイプを推測
     fn guess_shiori_type_from_directory(&self, ghost_path: &Path) -> ShioriType {
+        // モックSHIORIのフィクスチャ
+        if shiori_mock::FIXTURE_FILES
+            .iter()
+            .any(|name| ghost_path.join(name).exists())
+        {
+            return ShioriType::Mock;
+        }
+
         // 典型的なYAYAファイルの存在をチェック
         let yaya_files = ["yaya.txt", "yaya_shiori3.dll", "aya5.dll"];
         for yaya_file in &yaya_files {
@@ -271,98 +379,214 @@ impl ShioriManager {
         ShioriType::Unknown("No SHIORI detected".to_string())
     }
 
-    /// descript.txtファイルを解析
-    fn parse_descript(&self, content: &str) -> (Option<String>, Option<String>, Option<String>) {
-        let mut description = None;
-        let mut craftman = None;
-        let mut version = None;
-
-        for line in content.lines() {
-            if let Some((key, value)) = line.split_once(',') {
-                match key.trim() {
-                    "name" => description = Some(value.trim().to_string()),
-                    "craftman" => craftman = Some(value.trim().to_string()),
-                    "version" => version = Some(value.trim().to_string()),
-                    _ => {}
-                }
-            }
+    /// ゴーストを読み込み（読み込んだゴーストが現在のゴーストになる）
+    pub fn load_ghost(&self, ghost_id: &str) -> Result<(), String> {
+        // 同じゴーストが読み込み済みなら一度終了して読み込み直す
+        if self.engines.read().contains_key(ghost_id) {
+            self.unload_ghost(ghost_id)?;
         }
 
-        (description, craftman, version)
+        // ゴースト情報を取得
+        let ghost_info = self
+            .get_ghost_info(ghost_id)
+            .ok_or_else(|| format!("Ghost not found: {}", ghost_id))?;
+
+        // SHIORIを初期化してロード
+        let engine = (self.engine_factory.read())(&ghost_info)?;
+        self.start_engine(&ghost_info, engine)
     }
 
-    /// ゴーストを読み込み
-    pub fn load_ghost(&self, ghost_name: &str) -> Result<(), String> {
-        // 既存のSHIORIを終了
-        if let Some(_) = *self.active_engine.read() {
-            IntegratedShiori::finalize()?;
-            *self.active_engine.write() = None;
+    /// 作成済みのエンジンでゴーストを読み込む（モックSHIORIなど）
+    pub fn attach_engine(
+        &self,
+        ghost_info: GhostInfo,
+        engine: Box<dyn ShioriEngine>,
+    ) -> Result<(), String> {
+        if self.engines.read().contains_key(&ghost_info.id) {
+            self.unload_ghost(&ghost_info.id)?;
         }
+        self.register_ghost(ghost_info.clone());
+        self.start_engine(&ghost_info, engine)
+    }
 
-        // ゴースト情報を取得
-        let ghost_info = {
-            let ghosts = self.ghosts.read();
-            ghosts
-                .get(ghost_name)
-                .ok_or_else(|| format!("Ghost not found: {}", ghost_name))?
-                .clone()
-        };
+    fn start_engine(
+        &self,
+        ghost_info: &GhostInfo,
+        engine: Box<dyn ShioriEngine>,
+    ) -> Result<(), String> {
+        let engine = self.boot_engine(ghost_info, engine)?;
+        self.engines
+            .write()
+            .insert(ghost_info.id.clone(), Arc::new(Mutex::new(engine)));
+        *self.current_ghost.write() = Some(ghost_info.id.clone());
 
-        // SHIORIを初期化
-        let ghost_path_str = ghost_info.path.to_string_lossy();
-        let engine_type = IntegratedShiori::initialize_auto(&ghost_path_str)?;
+        Ok(())
+    }
 
-        // SHIORIロード実行
-        IntegratedShiori::load()?;
+    /// エンジンを読み込んでOnBootイベントを送信
+    fn boot_engine(
+        &self,
+        ghost_info: &GhostInfo,
+        mut engine: Box<dyn ShioriEngine>,
+    ) -> Result<Box<dyn ShioriEngine>, String> {
+        engine.load(&ghost_info.shiori_dir())?;
+        let _boot_response = engine.request(&ShioriRequest::get("OnBoot"))?;
+        Ok(engine)
+    }
 
-        // OnBootイベントを送信
-        let boot_request = "GET SHIORI/3.0\r\nID: OnBoot\r\n\r\n";
-        let _boot_response = IntegratedShiori::request(boot_request)?;
+    /// ゴーストを読み込み直す（辞書の変更を反映する）
+    ///
+    /// 古いSHIORIに OnClose を送って終了してから、新しいSHIORIを読み込んで OnBoot を送る。
+    /// 新しいSHIORIを読み込めなかった場合は元のゴースト情報で読み込み直し、
+    /// それも失敗したらゴーストを終了した状態にする。
+    pub fn reload_ghost(&self, ghost_id: &str) -> Result<(), String> {
+        let engine = self
+            .engines
+            .read()
+            .get(ghost_id)
+            .cloned()
+            .ok_or_else(|| format!("Ghost is not loaded: {}", ghost_id))?;
+        let old_info = self
+            .get_ghost_info(ghost_id)
+            .ok_or_else(|| format!("Ghost not found: {}", ghost_id))?;
+        // descript.txt が読めなければ古いSHIORIのまま動かす
+        let new_info = self.reanalyze(&old_info)?;
+
+        println!("🔄 Reloading ghost: {}", ghost_id);
+        let mut engine = engine.lock();
+        let _ = engine.request(&ShioriRequest::get("OnClose"));
+        if let Err(e) = engine.unload() {
+            println!("⚠️ Failed to unload SHIORI before reload: {}", e);
+        }
 
-        // アクティブなエンジンとして設定
-        *self.active_engine.write() = Some(engine_type);
-        *self.current_ghost.write() = Some(ghost_name.to_string());
+        let create = |info: &GhostInfo| {
+            let new_engine = (self.engine_factory.read())(info)?;
+            self.boot_engine(info, new_engine)
+        };
+        match create(&new_info) {
+            Ok(new_engine) => {
+                *engine = new_engine;
+                self.register_ghost(new_info);
+                Ok(())
+            }
+            Err(e) => {
+                match create(&old_info) {
+                    Ok(old_engine) => *engine = old_engine,
+                    Err(restore_error) => {
+                        println!("❌ Failed to restore ghost {}: {}", ghost_id, restore_error);
+                        drop(engine);
+                        self.remove_engine(ghost_id);
+                    }
+                }
+                Err(format!("Failed to reload ghost {}: {}", ghost_id, e))
+            }
+        }
+    }
 
-        Ok(())
+    /// ゴースト情報を読み直す（シェルの変更など、SHIORIはそのまま）
+    pub fn refresh_ghost_info(&self, ghost_id: &str) -> Result<GhostInfo, String> {
+        let old_info = self
+            .get_ghost_info(ghost_id)
+            .ok_or_else(|| format!("Ghost not found: {}", ghost_id))?;
+        let new_info = self.reanalyze(&old_info)?;
+        self.register_ghost(new_info.clone());
+        Ok(new_info)
     }
 
-    /// SHIORIにリクエストを送信
-    pub fn send_request(&self, request: &str) -> Result<String, String> {
-        if self.active_engine.read().is_none() {
-            return Err("No SHIORI engine is active".to_string());
+    /// スキャンで見つけたゴーストは解析し直す（直接登録したゴーストはそのまま）
+    fn reanalyze(&self, ghost_info: &GhostInfo) -> Result<GhostInfo, String> {
+        if is_ghost_root(&ghost_info.path) {
+            self.analyze_ghost_directory(&ghost_info.path)
+        } else {
+            Ok(ghost_info.clone())
         }
+    }
 
-        IntegratedShiori::request(request)
+    /// 指定したゴーストのSHIORIにリクエストを送信
+    pub fn request(
+        &self,
+        ghost_id: &str,
+        request: &ShioriRequest,
+    ) -> Result<ShioriResponse, String> {
+        let engine = self
+            .engines
+            .read()
+            .get(ghost_id)
+            .cloned()
+            .ok_or_else(|| format!("Ghost is not loaded: {}", ghost_id))?;
+
+        engine.lock().request(request)
     }
 
-    /// イベントを送信
-    pub fn send_event(&self, event: &str, references: &[&str]) -> Result<String, String> {
-        if self.active_engine.read().is_none() {
-            return Err("No SHIORI engine is active".to_string());
-        }
+    /// 現在のゴーストのSHIORIにリクエストを送信
+    fn request_current(&self, request: &ShioriRequest) -> Result<ShioriResponse, String> {
+        let ghost_id = self
+            .current_ghost()
+            .ok_or_else(|| "No SHIORI engine is active".to_string())?;
 
-        // SHIORI/3.0リクエスト形式でイベントを構築
-        let mut request = format!("GET SHIORI/3.0\r\nID: {}\r\n", event);
+        self.request(&ghost_id, request)
+    }
 
-        for (i, reference) in references.iter().enumerate() {
-            request.push_str(&format!("Reference{}: {}\r\n", i, reference));
-        }
-        request.push_str("\r\n");
+    /// SHIORIにリクエストを送信（ワイヤー形式の文字列を解析して送信）
+    pub fn send_request(&self, request: &str) -> Result<ShioriResponse, String> {
+        self.request_current(&ShioriRequest::parse(request)?)
+    }
 
-        IntegratedShiori::request(&request)
+    /// イベントを送信
+    pub fn send_event(&self, event: &str, references: &[&str]) -> Result<ShioriResponse, String> {
+        self.request_current(&ShioriRequest::get(event).with_references(references))
     }
 
-    /// マウスクリックイベントを送信
-    pub fn on_mouse_click(&self, x: i32, y: i32, button: &str) -> Result<String, String> {
-        self.send_event("OnMouseClick", &[&x.to_string(), &y.to_string(), button])
+    /// 指定したゴーストにイベントを送信
+    pub fn send_event_to(
+        &self,
+        ghost_id: &str,
+        event: &str,
+        references: &[&str],
+    ) -> Result<ShioriResponse, String> {
+        self.request(
+            ghost_id,
+            &ShioriRequest::get(event).with_references(references),
+        )
+    }
+
+    /// マウスイベント（`OnMouseClick` など）を送信
+    pub fn on_mouse_event(&self, event: &MouseEvent) -> Result<ShioriResponse, String> {
+        self.send_event(&event.id, &event.references())
     }
 
     /// 秒数変化イベントを送信
-    pub fn on_second_change(&self) -> Result<String, String> {
+    pub fn on_second_change(&self) -> Result<ShioriResponse, String> {
         self.send_event("OnSecondChange", &[])
     }
 
-    /// 現在読み込まれているゴースト名を取得
+    /// 選ばれた選択肢・アンカーを送信
+    ///
+    /// `OnChoiceSelectEx` に応答が無ければ `OnChoiceSelect` を送る。
+    /// IDが `script:` で始まる場合はSHIORIを呼ばずにそのスクリプトを返す。
+    pub fn send_choice(&self, choice: &SakuraChoice) -> Result<ShioriResponse, String> {
+        let events = match choice.action() {
+            ChoiceAction::Script(script) => return Ok(ShioriResponse::ok(&script)),
+            ChoiceAction::Events(events) => events,
+        };
+
+        let mut response = ShioriResponse::no_content();
+        for event in events {
+            let references: Vec<&str> = event.references.iter().map(String::as_str).collect();
+            response = self.send_event(&event.id, &references)?;
+            if response.value().is_some_and(|value| !value.is_empty()) {
+                break;
+            }
+        }
+        Ok(response)
+    }
+
+    /// 選択肢のタイムアウトを送信
+    pub fn on_choice_timeout(&self, script: &str) -> Result<ShioriResponse, String> {
+        self.send_event("OnChoiceTimeout", &[script])
+    }
+
+    /// 現在のゴーストIDを取得
     pub fn current_ghost(&self) -> Option<String> {
         self.current_ghost.read().clone()
     }
@@ -373,29 +597,325 @@ impl ShioriManager {
     }
 
     /// 特定のゴースト情報を取得
-    pub fn get_ghost_info(&self, name: &str) -> Option<GhostInfo> {
-        self.ghosts.read().get(name).cloned()
+    pub fn get_ghost_info(&self, ghost_id: &str) -> Option<GhostInfo> {
+        self.ghosts.read().get(ghost_id).cloned()
+    }
+
+    /// 読み込み済みのゴーストID一覧
+    pub fn loaded_ghosts(&self) -> Vec<String> {
+        self.engines.read().keys().cloned().collect()
     }
 
     /// SHIORIの状態を取得
     pub fn is_shiori_loaded(&self) -> bool {
-        self.active_engine.read().is_some()
+        self.current_ghost()
+            .is_some_and(|name| self.engines.read().contains_key(&name))
     }
 
-    /// SHIORIを終了
+    /// 指定したゴーストを終了
+    pub fn unload_ghost(&self, ghost_id: &str) -> Result<(), String> {
+        let was_current = self.current_ghost().as_deref() == Some(ghost_id);
+        let engine = self
+            .remove_engine(ghost_id)
+            .ok_or_else(|| format!("Ghost is not loaded: {}", ghost_id))?;
+
+        let result = {
+            let mut engine = engine.lock();
+            // OnCloseイベントを送信
+            let _ = engine.request(&ShioriRequest::get("OnClose"));
+            engine.unload()
+        };
+        // 終了できなければ読み込み済みのまま元に戻す
+        if let Err(e) = &result {
+            println!("⚠️ Failed to unload ghost {}: {}", ghost_id, e);
+            self.engines.write().insert(ghost_id.to_string(), engine);
+            if was_current {
+                *self.current_ghost.write() = Some(ghost_id.to_string());
+            }
+        }
+        result
+    }
+
+    /// 読み込み済みの一覧から外す（現在のゴーストだった場合は他の読み込み済みゴーストに切り替える）
+    fn remove_engine(&self, ghost_id: &str) -> Option<LoadedEngine> {
+        let engine = self.engines.write().remove(ghost_id)?;
+        let mut current = self.current_ghost.write();
+        if current.as_deref() == Some(ghost_id) {
+            *current = self.engines.read().keys().next().cloned();
+        }
+        Some(engine)
+    }
+
+    /// 現在のゴーストを終了
     pub fn unload_current_ghost(&self) -> Result<(), String> {
-        // OnCloseイベントを送信
-        if self.is_shiori_loaded() {
-            let _ = self.send_event("OnClose", &[]);
+        match self.current_ghost() {
+            Some(ghost_id) => self.unload_ghost(&ghost_id),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shiori_protocol::ShioriStatus;
+
+    /// ゴースト名をそのまま返す偽エンジン
+    struct FakeEngine {
+        name: String,
+        log: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl ShioriEngine for FakeEngine {
+        fn load(&mut self, _ghost_dir: &Path) -> Result<(), String> {
+            self.log.lock().push(format!("{}:load", self.name));
+            Ok(())
         }
 
-        // SHIORIを終了
-        if self.active_engine.read().is_some() {
-            IntegratedShiori::finalize()?;
-            *self.active_engine.write() = None;
+        fn request(&mut self, request: &ShioriRequest) -> Result<ShioriResponse, String> {
+            let id = request.id().unwrap_or_default();
+            self.log.lock().push(format!("{}:{}", self.name, id));
+            Ok(ShioriResponse::ok(&format!("{}:{}", self.name, id)))
         }
-        *self.current_ghost.write() = None;
 
-        Ok(())
+        fn unload(&mut self) -> Result<(), String> {
+            self.log.lock().push(format!("{}:unload", self.name));
+            if self.name == "stuck" {
+                return Err("unload failed".to_string());
+            }
+            Ok(())
+        }
+    }
+
+    fn ghost(name: &str) -> GhostInfo {
+        GhostInfo {
+            id: name.to_string(),
+            name: name.to_string(),
+            path: PathBuf::from(name),
+            shiori_type: ShioriType::Unknown("fake".to_string()),
+            shiori_dll: None,
+            charset: ShioriCharset::Utf8,
+            native_library: None,
+            descript: Descript::default(),
+        }
+    }
+
+    #[test]
+    fn test_multiple_ghosts_with_injected_engines() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let manager = ShioriManager::new();
+        let factory_log = log.clone();
+        manager.set_engine_factory(Box::new(move |info| {
+            Ok(Box::new(FakeEngine {
+                name: info.name.clone(),
+                log: factory_log.clone(),
+            }))
+        }));
+        manager.register_ghost(ghost("sakura"));
+        manager.register_ghost(ghost("kero"));
+
+        manager.load_ghost("sakura").unwrap();
+        manager.load_ghost("kero").unwrap();
+        assert_eq!(manager.current_ghost().as_deref(), Some("kero"));
+
+        let response = manager.send_event("OnMouseClick", &[]).unwrap();
+        assert_eq!(response.status, ShioriStatus::Ok);
+        assert_eq!(response.value(), Some("kero:OnMouseClick"));
+        let response = manager
+            .send_event_to("sakura", "OnSecondChange", &[])
+            .unwrap();
+        assert_eq!(response.value(), Some("sakura:OnSecondChange"));
+
+        manager.unload_current_ghost().unwrap();
+        assert_eq!(manager.current_ghost().as_deref(), Some("sakura"));
+        assert!(manager.is_shiori_loaded());
+
+        assert_eq!(
+            *log.lock(),
+            vec![
+                "sakura:load",
+                "sakura:OnBoot",
+                "kero:load",
+                "kero:OnBoot",
+                "kero:OnMouseClick",
+                "sakura:OnSecondChange",
+                "kero:OnClose",
+                "kero:unload",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_reload_restores_previous_engine_on_failure() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let log = Arc::new(Mutex::new(Vec::new()));
+        // 残りの失敗回数
+        let failures = Arc::new(AtomicUsize::new(0));
+        let manager = ShioriManager::new();
+        let (factory_log, factory_failures) = (log.clone(), failures.clone());
+        manager.set_engine_factory(Box::new(move |info| {
+            if factory_failures
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+                .is_ok()
+            {
+                return Err("broken dictionary".to_string());
+            }
+            Ok(Box::new(FakeEngine {
+                name: info.name.clone(),
+                log: factory_log.clone(),
+            }))
+        }));
+        manager.register_ghost(ghost("sakura"));
+        manager.register_ghost(ghost("kero"));
+        manager.load_ghost("sakura").unwrap();
+        manager.load_ghost("kero").unwrap();
+        manager.send_event_to("sakura", "OnTest", &[]).unwrap();
+        log.lock().clear();
+
+        manager.reload_ghost("sakura").unwrap();
+        assert_eq!(
+            *log.lock(),
+            vec![
+                "sakura:OnClose",
+                "sakura:unload",
+                "sakura:load",
+                "sakura:OnBoot"
+            ]
+        );
+
+        // 新しいSHIORIが読み込めなければ元に戻す（現在のゴーストは変わらない）
+        failures.store(1, Ordering::SeqCst);
+        assert!(manager.reload_ghost("sakura").is_err());
+        assert!(manager.loaded_ghosts().contains(&"sakura".to_string()));
+        assert_eq!(manager.current_ghost().as_deref(), Some("kero"));
+
+        // 元にも戻せなければ終了した状態になる
+        failures.store(2, Ordering::SeqCst);
+        assert!(manager.reload_ghost("kero").is_err());
+        assert_eq!(manager.loaded_ghosts(), vec!["sakura".to_string()]);
+        assert_eq!(manager.current_ghost().as_deref(), Some("sakura"));
+        assert!(manager.send_event_to("kero", "OnTest", &[]).is_err());
+    }
+
+    #[test]
+    fn test_unload_failure_keeps_ghost_loaded() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let manager = ShioriManager::new();
+        let factory_log = log.clone();
+        manager.set_engine_factory(Box::new(move |info| {
+            Ok(Box::new(FakeEngine {
+                name: info.name.clone(),
+                log: factory_log.clone(),
+            }))
+        }));
+        manager.register_ghost(ghost("sakura"));
+        manager.register_ghost(ghost("stuck"));
+        manager.load_ghost("sakura").unwrap();
+        manager.load_ghost("stuck").unwrap();
+
+        assert!(manager.unload_current_ghost().is_err());
+        assert_eq!(manager.current_ghost().as_deref(), Some("stuck"));
+        assert_eq!(manager.loaded_ghosts().len(), 2);
+        assert!(manager.send_event_to("stuck", "OnTest", &[]).is_ok());
+    }
+
+    #[test]
+    fn test_choice_selection_falls_back_to_plain_event() {
+        use crate::shiori_mock::{MockFixture, MockShiori};
+        use mascot_nanai_ui::sakura_choice;
+        use mascot_nanai_ui::sakura_script;
+
+        let fixture = MockFixture::from_toml(
+            r#"
+[events.OnChoiceSelect]
+responses = [{ references = { "0" = "yes", "1" = "r0" }, value = "はい" }]
+
+[events.OnAnchorSelectEx]
+responses = [{ references = { "0" = "リンク" }, value = "アンカー" }]
+"#,
+        )
+        .unwrap();
+        let manager = ShioriManager::new();
+        let mut info = ghost("mock");
+        info.shiori_type = ShioriType::Mock;
+        manager
+            .attach_engine(info, Box::new(MockShiori::with_fixture(fixture)))
+            .unwrap();
+
+        let tokens =
+            sakura_script::parse("\\q[はい,yes,r0]\\_a[link]リンク\\_a\\q[直接,script:\\e]");
+        let choices = sakura_choice::collect(&tokens);
+        let value = |index: usize| {
+            let response = manager.send_choice(&choices[index]).unwrap();
+            response.value().map(str::to_string)
+        };
+        assert_eq!(value(0).as_deref(), Some("はい"));
+        assert_eq!(value(1).as_deref(), Some("アンカー"));
+        assert_eq!(value(2).as_deref(), Some("\\e"));
+    }
+
+    #[test]
+    fn test_analyze_reads_descript_in_its_charset() {
+        let assets = Path::new(env!("CARGO_MANIFEST_DIR")).join("../assets/ghost");
+        let manager = ShioriManager::new();
+
+        let utf8 = manager
+            .analyze_ghost_directory(&assets.join("test_ghost"))
+            .unwrap();
+        assert_eq!(utf8.charset, ShioriCharset::Utf8);
+        assert_eq!(
+            utf8.descript.sakura_name.as_deref(),
+            Some("テストキャラクター")
+        );
+        assert_eq!(utf8.descript.craftmanw.as_deref(), Some("テスト開発者"));
+
+        let sjis = manager
+            .analyze_ghost_directory(&assets.join("mock_nanai"))
+            .unwrap();
+        assert_eq!(sjis.charset, ShioriCharset::ShiftJis);
+        assert_eq!(sjis.descript.kind.as_deref(), Some("ghost"));
+        assert!(sjis.descript.sakura_name.is_some());
+    }
+
+    #[test]
+    fn test_scan_keeps_ghosts_with_the_same_name() {
+        let assets = Path::new(env!("CARGO_MANIFEST_DIR")).join("../assets/ghost");
+        let manager = ShioriManager::new();
+        let report = manager.scan_ghost_directory(&assets);
+        let names: Vec<_> = report.ghosts.iter().map(|g| g.name.as_str()).collect();
+        for name in ["mock_nanai", "mock_shiori", "test_ghost"] {
+            assert!(names.contains(&name), "{} not found in {:?}", name, names);
+        }
+        // シェルの descript.txt はゴーストとして扱わない
+        assert!(report.errors.is_empty(), "{:?}", report.errors);
+
+        // 別のディレクトリにある同名のゴーストと、壊れたゴースト
+        let temp = tempfile::tempdir().unwrap();
+        let root = temp.path();
+        for dir in ["a/sakura", "b/sakura"] {
+            let master = root.join(dir).join("ghost").join("master");
+            std::fs::create_dir_all(&master).unwrap();
+            std::fs::write(master.join("descript.txt"), "charset,UTF-8\nname,さくら\n").unwrap();
+        }
+        std::fs::create_dir_all(root.join("c/broken/ghost/master/descript.txt")).unwrap();
+
+        let report = manager.scan_ghost_roots(&[root.join("a"), root.join("b"), root.join("c")]);
+        assert_eq!(report.ghosts.len(), 2);
+        assert_ne!(report.ghosts[0].id, report.ghosts[1].id);
+        assert_eq!(report.errors.len(), 1);
+        assert!(report.errors[0].path.ends_with("broken"));
+        assert!(manager.get_ghost_info(&report.ghosts[1].id).is_some());
+
+        // 最初にスキャンしたゴーストも残っている
+        assert_eq!(manager.get_all_ghosts().len(), names.len() + 2);
+    }
+
+    #[test]
+    fn test_unknown_ghost() {
+        let manager = ShioriManager::new();
+        assert!(manager.load_ghost("missing").is_err());
+        assert!(manager.send_event("OnBoot", &[]).is_err());
+        assert!(!manager.is_shiori_loaded());
     }
 }
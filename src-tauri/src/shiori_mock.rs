@@ -0,0 +1,452 @@
+//! Mock SHIORI
+//!
+//! TOML/JSONのフィクスチャに書いた応答を返すだけのSHIORI。
+//! YAYAなどをビルドせずにフロントエンドの動作確認をしたり、
+//! 結果が決まった統合テストを書いたりするために使う。
+//!
+//! ```toml
+//! seed = 1
+//!
+//! [events]
+//! OnBoot = "こんにちは！"                      # 常に同じ応答
+//! OnSecondChange = ["1秒", "2秒"]              # 順番に返す
+//!
+//! [events.OnMouseDoubleClick]
+//! mode = "first"                               # sequence / random / first
+//! responses = [
+//!   { references = { "4" = "Head" }, value = "頭をつつかないで" },
+//!   { value = "なに？" },
+//! ]
+//!
+//! [events."*"]                                 # 定義の無いイベント
+//! responses = [{ status = 204 }]
+//! ```
+
+use crate::shiori_charset::ShioriCharset;
+use crate::shiori_engine::ShioriEngine;
+use crate::shiori_manager::{self, GhostInfo, ShioriType};
+use crate::shiori_protocol::{ShioriMethod, ShioriRequest, ShioriResponse, ShioriStatus};
+use mascot_nanai_ui::descript::Descript;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// モックSHIORIを使うときに指定する環境変数（`1` なら同梱のフィクスチャ、それ以外はパス）
+pub const FIXTURE_ENV: &str = "MASCOT_MOCK_SHIORI";
+
+/// ゴーストディレクトリ内で探すフィクスチャのファイル名
+pub const FIXTURE_FILES: [&str; 2] = ["mock_shiori.toml", "mock_shiori.json"];
+
+/// どのイベントにも当てはまらない場合に使うキー
+const FALLBACK_EVENT: &str = "*";
+
+/// フィクスチャ全体
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct MockFixture {
+    /// `random` で使う乱数の種
+    #[serde(default)]
+    pub seed: Option<u64>,
+    /// `GET version` への応答
+    #[serde(default)]
+    pub version: Option<String>,
+    #[serde(default)]
+    pub events: BTreeMap<String, MockEvent>,
+}
+
+/// イベントごとの応答の定義
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum MockEvent {
+    Text(String),
+    Sequence(Vec<MockResponse>),
+    Rule(MockRule),
+}
+
+/// 応答の選び方
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MockMode {
+    /// 呼ばれるたびに次の応答（最後まで行ったら最初に戻る）
+    #[default]
+    Sequence,
+    /// ランダムに選ぶ
+    Random,
+    /// 条件に合う最初の応答
+    First,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct MockRule {
+    #[serde(default)]
+    pub mode: MockMode,
+    pub responses: Vec<MockResponse>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum MockResponse {
+    Text(String),
+    Detailed(MockDetailedResponse),
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct MockDetailedResponse {
+    #[serde(default)]
+    pub value: Option<String>,
+    /// ステータスコード（省略時は value があれば 200、無ければ 204）
+    #[serde(default)]
+    pub status: Option<u16>,
+    /// Reference の一致条件（キーは `"0"` または `"Reference0"`）
+    #[serde(default)]
+    pub references: BTreeMap<String, String>,
+    /// 応答に追加するヘッダ
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+}
+
+impl MockFixture {
+    pub fn from_toml(text: &str) -> Result<Self, String> {
+        toml::from_str(text).map_err(|e| format!("Invalid mock SHIORI fixture: {}", e))
+    }
+
+    pub fn from_json(text: &str) -> Result<Self, String> {
+        serde_json::from_str(text).map_err(|e| format!("Invalid mock SHIORI fixture: {}", e))
+    }
+
+    /// 拡張子で形式を判断して読み込む
+    pub fn from_file(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read mock SHIORI fixture {:?}: {}", path, e))?;
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("json") => Self::from_json(&text),
+            _ => Self::from_toml(&text),
+        }
+    }
+}
+
+impl MockEvent {
+    fn mode(&self) -> MockMode {
+        match self {
+            MockEvent::Rule(rule) => rule.mode,
+            _ => MockMode::Sequence,
+        }
+    }
+
+    fn responses(&self) -> Vec<MockDetailedResponse> {
+        let responses = match self {
+            MockEvent::Text(text) => return vec![MockResponse::Text(text.clone()).detailed()],
+            MockEvent::Sequence(responses) => responses,
+            MockEvent::Rule(rule) => &rule.responses,
+        };
+        responses.iter().map(MockResponse::detailed).collect()
+    }
+}
+
+impl MockResponse {
+    fn detailed(&self) -> MockDetailedResponse {
+        match self {
+            MockResponse::Text(text) => MockDetailedResponse {
+                value: Some(text.clone()),
+                ..Default::default()
+            },
+            MockResponse::Detailed(response) => response.clone(),
+        }
+    }
+}
+
+impl MockDetailedResponse {
+    /// リクエストの Reference が条件に合うか
+    fn matches(&self, request: &ShioriRequest) -> bool {
+        self.references.iter().all(|(key, expected)| {
+            let index = key.strip_prefix("Reference").unwrap_or(key);
+            index
+                .parse::<usize>()
+                .is_ok_and(|index| request.reference(index).unwrap_or_default() == expected)
+        })
+    }
+
+    fn to_response(&self) -> ShioriResponse {
+        let status = match (self.status, &self.value) {
+            (Some(code), _) => ShioriStatus::from_code(code),
+            (None, Some(_)) => ShioriStatus::Ok,
+            (None, None) => ShioriStatus::NoContent,
+        };
+
+        let mut response = ShioriResponse::new(status);
+        if let Some(value) = &self.value {
+            response.headers.set("Value", value);
+        }
+        for (key, value) in &self.headers {
+            response.headers.set(key, value);
+        }
+        response
+    }
+}
+
+/// フィクスチャで動くSHIORI
+pub struct MockShiori {
+    fixture: Option<MockFixture>,
+    counters: HashMap<String, usize>,
+    rng: fastrand::Rng,
+    requests: Vec<ShioriRequest>,
+}
+
+impl MockShiori {
+    /// フィクスチャは `load` でゴーストディレクトリから読み込む
+    pub fn new() -> Self {
+        MockShiori {
+            fixture: None,
+            counters: HashMap::new(),
+            rng: fastrand::Rng::new(),
+            requests: Vec::new(),
+        }
+    }
+
+    pub fn with_fixture(fixture: MockFixture) -> Self {
+        let mut mock = MockShiori::new();
+        mock.set_fixture(fixture);
+        mock
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self, String> {
+        Ok(Self::with_fixture(MockFixture::from_file(path)?))
+    }
+
+    fn set_fixture(&mut self, fixture: MockFixture) {
+        if let Some(seed) = fixture.seed {
+            self.rng = fastrand::Rng::with_seed(seed);
+        }
+        self.counters.clear();
+        self.fixture = Some(fixture);
+    }
+
+    /// これまでに受け取ったリクエスト
+    pub fn requests(&self) -> &[ShioriRequest] {
+        &self.requests
+    }
+
+    /// フィクスチャを直接読み込むためのゴースト情報
+    ///
+    /// 同じディレクトリに descript.txt があればその内容を使う。
+    pub fn ghost_info(fixture_path: &Path) -> GhostInfo {
+        let dir = fixture_path
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_else(|| PathBuf::from("."));
+        let descript = Descript::load(&dir.join("descript.txt")).unwrap_or_else(|_| Descript {
+            name: Some("Mock SHIORI".to_string()),
+            ..Default::default()
+        });
+        // フィクスチャが ghost/master にあればゴーストのディレクトリを使う
+        let path = shiori_manager::find_ghost_root(&dir).unwrap_or(dir);
+        GhostInfo {
+            id: shiori_manager::ghost_id(&path),
+            name: "mock_shiori".to_string(),
+            path,
+            shiori_type: ShioriType::Mock,
+            shiori_dll: None,
+            charset: ShioriCharset::Utf8,
+            native_library: None,
+            descript,
+        }
+    }
+
+    fn respond(&mut self, request: &ShioriRequest) -> ShioriResponse {
+        let Some(fixture) = &self.fixture else {
+            return ShioriResponse::new(ShioriStatus::InternalServerError);
+        };
+        let id = request.id().unwrap_or_default();
+
+        if id == "version" && !fixture.events.contains_key(id) {
+            let version = fixture
+                .version
+                .clone()
+                .unwrap_or_else(|| format!("MockShiori/{}", env!("CARGO_PKG_VERSION")));
+            return ShioriResponse::ok(&version);
+        }
+
+        let Some((key, event)) = fixture
+            .events
+            .get_key_value(id)
+            .or_else(|| fixture.events.get_key_value(FALLBACK_EVENT))
+        else {
+            return ShioriResponse::no_content();
+        };
+
+        let candidates: Vec<MockDetailedResponse> = event
+            .responses()
+            .into_iter()
+            .filter(|response| response.matches(request))
+            .collect();
+        if candidates.is_empty() {
+            return ShioriResponse::no_content();
+        }
+
+        let index = match event.mode() {
+            MockMode::First => 0,
+            MockMode::Random => self.rng.usize(..candidates.len()),
+            MockMode::Sequence => {
+                let counter = self.counters.entry(key.clone()).or_insert(0);
+                let index = *counter % candidates.len();
+                *counter += 1;
+                index
+            }
+        };
+        candidates[index].to_response()
+    }
+}
+
+impl Default for MockShiori {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ShioriEngine for MockShiori {
+    fn load(&mut self, ghost_dir: &Path) -> Result<(), String> {
+        if self.fixture.is_some() {
+            return Ok(());
+        }
+
+        let path = FIXTURE_FILES
+            .iter()
+            .map(|name| ghost_dir.join(name))
+            .find(|path| path.exists())
+            .ok_or_else(|| format!("No mock SHIORI fixture in {:?}", ghost_dir))?;
+        println!("🎭 Loading mock SHIORI fixture: {:?}", path);
+        self.set_fixture(MockFixture::from_file(&path)?);
+        Ok(())
+    }
+
+    fn request(&mut self, request: &ShioriRequest) -> Result<ShioriResponse, String> {
+        self.requests.push(request.clone());
+        match request.method {
+            ShioriMethod::Get => Ok(self.respond(request)),
+            ShioriMethod::Notify => Ok(ShioriResponse::no_content()),
+        }
+    }
+
+    fn unload(&mut self) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIXTURE: &str = r#"
+seed = 7
+
+[events]
+OnBoot = "こんにちは！"
+OnSecondChange = ["1", "2", { status = 204 }]
+
+[events.OnMouseDoubleClick]
+mode = "first"
+responses = [
+  { references = { "4" = "Head" }, value = "頭をつつかないで" },
+  { references = { "Reference3" = "1" }, value = "相方です", headers = { Marker = "kero" } },
+  { value = "なに？" },
+]
+
+[events.OnRandom]
+mode = "random"
+responses = ["a", "b", "c", "d"]
+"#;
+
+    fn get(mock: &mut MockShiori, id: &str, references: &[&str]) -> ShioriResponse {
+        mock.request(&ShioriRequest::get(id).with_references(references))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_text_sequence_and_reference_matching() {
+        let mut mock = MockShiori::with_fixture(MockFixture::from_toml(FIXTURE).unwrap());
+
+        assert_eq!(get(&mut mock, "OnBoot", &[]).value(), Some("こんにちは！"));
+
+        let sequence: Vec<ShioriStatus> = (0..4)
+            .map(|_| get(&mut mock, "OnSecondChange", &[]).status)
+            .collect();
+        assert_eq!(
+            sequence,
+            [
+                ShioriStatus::Ok,
+                ShioriStatus::Ok,
+                ShioriStatus::NoContent,
+                ShioriStatus::Ok
+            ]
+        );
+
+        let head = get(
+            &mut mock,
+            "OnMouseDoubleClick",
+            &["0", "0", "0", "0", "Head"],
+        );
+        assert_eq!(head.value(), Some("頭をつつかないで"));
+        let kero = get(&mut mock, "OnMouseDoubleClick", &["0", "0", "0", "1", ""]);
+        assert_eq!(kero.value(), Some("相方です"));
+        assert_eq!(kero.headers.get("Marker"), Some("kero"));
+        let other = get(&mut mock, "OnMouseDoubleClick", &["0", "0", "0", "0", ""]);
+        assert_eq!(other.value(), Some("なに？"));
+
+        assert_eq!(
+            get(&mut mock, "OnUnknown", &[]).status,
+            ShioriStatus::NoContent
+        );
+        assert!(get(&mut mock, "version", &[]).value().is_some());
+        assert_eq!(mock.requests().len(), 10);
+    }
+
+    #[test]
+    fn test_random_is_deterministic_with_seed() {
+        let picks = || {
+            let mut mock = MockShiori::with_fixture(MockFixture::from_toml(FIXTURE).unwrap());
+            (0..8)
+                .map(|_| get(&mut mock, "OnRandom", &[]).value().unwrap().to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(picks(), picks());
+    }
+
+    #[test]
+    fn test_json_fixture_and_fallback() {
+        let fixture = MockFixture::from_json(
+            r#"{ "events": { "OnBoot": ["やあ"], "*": "知らないイベント" } }"#,
+        )
+        .unwrap();
+        let mut mock = MockShiori::with_fixture(fixture);
+
+        assert_eq!(get(&mut mock, "OnBoot", &[]).value(), Some("やあ"));
+        assert_eq!(
+            get(&mut mock, "OnClose", &[]).value(),
+            Some("知らないイベント")
+        );
+        let notify = mock.request(&ShioriRequest::notify("OnBoot")).unwrap();
+        assert_eq!(notify.status, ShioriStatus::NoContent);
+
+        assert!(MockFixture::from_toml("[events]\nOnBoot = 1").is_err());
+    }
+
+    #[test]
+    fn test_dev_fixture_loads_from_ghost_directory() {
+        let dir =
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("../assets/ghost/mock_shiori/ghost/master");
+        let mut mock = MockShiori::new();
+        mock.load(&dir).unwrap();
+        assert_eq!(
+            get(&mut mock, "OnBoot", &[]).value(),
+            Some("こんにちは！マスコットナナイです。")
+        );
+        assert_eq!(
+            get(&mut mock, "OnMouseClick", &[]).value(),
+            Some("クリックされました！")
+        );
+        // マウスの移動で話し出したり、homeurl に文章を返したりしない
+        for id in ["OnMouseMove", "homeurl"] {
+            assert_eq!(get(&mut mock, id, &[]).status, ShioriStatus::NoContent);
+        }
+    }
+}
@@ -0,0 +1,388 @@
+//! SHIORI Process
+//!
+//! SHIORIを `mascot-shiori-host` の子プロセスで実行するトランスポート。
+//! SHIORIがクラッシュしてもアプリ本体は巻き込まれず、ホストを再起動して続行する。
+//! 返信が時間内に無い（ハングした）場合もクラッシュと同じく再起動する。
+
+use crate::shiori_engine::{ShioriEngine, ShioriTransport, TransportEngine};
+use crate::shiori_host::{self, HostTarget};
+use crate::shiori_manager::{EngineFactory, GhostInfo};
+use std::io::{self, BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::Arc;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+
+/// ホストのクラッシュを通知する関数
+pub type CrashHandler = Arc<dyn Fn(String) + Send + Sync>;
+
+/// ホスト実行ファイルの場所を上書きする環境変数
+pub const HOST_ENV: &str = "MASCOT_SHIORI_HOST";
+
+/// 連続して再起動を試みる回数
+const MAX_RESTARTS: u32 = 3;
+
+/// SHIORIの読み込みを待つ時間（大きな辞書の読み込みにかかる分）
+const LOAD_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// リクエスト・アンロードの返信を待つ時間
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+type FrameResult = io::Result<Option<(u8, Vec<u8>)>>;
+
+/// 既定のホスト実行ファイル（本体と同じディレクトリ）
+pub fn default_host_executable() -> Option<PathBuf> {
+    if let Some(path) = std::env::var_os(HOST_ENV) {
+        return Some(PathBuf::from(path));
+    }
+
+    let exe = std::env::current_exe().ok()?;
+    let host = exe.parent()?.join(format!(
+        "mascot-shiori-host{}",
+        std::env::consts::EXE_SUFFIX
+    ));
+    host.exists().then_some(host)
+}
+
+/// 起動中のホスト
+struct HostProcess {
+    child: Child,
+    stdin: BufWriter<ChildStdin>,
+    /// 読み込み用スレッドが受け取ったフレーム（時間切れを待てるよう別スレッドで読む）
+    frames: Receiver<FrameResult>,
+}
+
+impl HostProcess {
+    fn spawn(executable: &Path, target: &HostTarget) -> Result<Self, String> {
+        let mut child = Command::new(executable)
+            .args(target.to_args())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .spawn()
+            .map_err(|e| format!("Failed to start SHIORI host {:?}: {}", executable, e))?;
+
+        let stdin = child.stdin.take().ok_or("SHIORI host has no stdin")?;
+        let stdout = child.stdout.take().ok_or("SHIORI host has no stdout")?;
+
+        // ホストが終了するか、受け取る側が無くなったら終わる
+        let (sender, frames) = mpsc::channel();
+        thread::spawn(move || {
+            let mut stdout = BufReader::new(stdout);
+            loop {
+                let frame = shiori_host::read_frame(&mut stdout);
+                let done = !matches!(frame, Ok(Some(_)));
+                if sender.send(frame).is_err() || done {
+                    break;
+                }
+            }
+        });
+
+        Ok(HostProcess {
+            child,
+            stdin: BufWriter::new(stdin),
+            frames,
+        })
+    }
+
+    /// フレームを送って返信を待つ
+    ///
+    /// 外側の `Err` はホストとの通信の失敗（クラッシュ・時間切れ）、
+    /// 内側の `Err` はSHIORIが返したエラー。
+    fn call(
+        &mut self,
+        kind: u8,
+        payload: &[u8],
+        timeout: Duration,
+    ) -> Result<Result<Vec<u8>, String>, String> {
+        shiori_host::write_frame(&mut self.stdin, kind, payload)
+            .map_err(|e| format!("Failed to write to SHIORI host: {}", e))?;
+
+        match self.frames.recv_timeout(timeout) {
+            Ok(Ok(Some((shiori_host::FRAME_OK, output)))) => Ok(Ok(output)),
+            Ok(Ok(Some((shiori_host::FRAME_ERROR, message)))) => {
+                Ok(Err(String::from_utf8_lossy(&message).to_string()))
+            }
+            Ok(Ok(Some((other, _)))) => {
+                Err(format!("Unexpected frame from SHIORI host: {:#04x}", other))
+            }
+            Ok(Ok(None)) | Err(RecvTimeoutError::Disconnected) => {
+                Err(format!("SHIORI host exited ({})", self.exit_status()))
+            }
+            Ok(Err(e)) => Err(format!("Failed to read from SHIORI host: {}", e)),
+            Err(RecvTimeoutError::Timeout) => {
+                Err(format!("SHIORI host did not respond within {:?}", timeout))
+            }
+        }
+    }
+
+    fn exit_status(&mut self) -> String {
+        match self.child.wait() {
+            Ok(status) => status.to_string(),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    fn kill(mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// 子プロセスのSHIORIと通信するトランスポート
+pub struct ProcessTransport {
+    executable: PathBuf,
+    target: HostTarget,
+    process: Option<HostProcess>,
+    ghost_dir: Option<PathBuf>,
+    on_crash: Option<CrashHandler>,
+    timeout: Duration,
+}
+
+impl ProcessTransport {
+    pub fn new(executable: PathBuf, target: HostTarget) -> Self {
+        ProcessTransport {
+            executable,
+            target,
+            process: None,
+            ghost_dir: None,
+            on_crash: None,
+            timeout: REQUEST_TIMEOUT,
+        }
+    }
+
+    /// リクエストの返信を待つ時間を設定
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// クラッシュ時の通知先を設定
+    pub fn on_crash(mut self, handler: CrashHandler) -> Self {
+        self.on_crash = Some(handler);
+        self
+    }
+
+    fn report(&self, message: &str) {
+        println!("💥 {}", message);
+        if let Some(handler) = &self.on_crash {
+            handler(message.to_string());
+        }
+    }
+
+    /// ホストを起動してSHIORIを読み込む
+    fn start(&mut self) -> Result<(), String> {
+        let ghost_dir = self
+            .ghost_dir
+            .clone()
+            .ok_or_else(|| "SHIORI is not loaded".to_string())?;
+        let mut process = HostProcess::spawn(&self.executable, &self.target)?;
+
+        let payload = ghost_dir.to_string_lossy();
+        match process.call(shiori_host::FRAME_LOAD, payload.as_bytes(), LOAD_TIMEOUT) {
+            Ok(Ok(_)) => {
+                self.process = Some(process);
+                Ok(())
+            }
+            Ok(Err(e)) | Err(e) => {
+                process.kill();
+                Err(e)
+            }
+        }
+    }
+
+    /// ホストを起動し直す（`crash` はクラッシュの理由、既に通知済みなら `None`）
+    fn restart(&mut self, crash: Option<&str>) -> Result<(), String> {
+        if let Some(process) = self.process.take() {
+            process.kill();
+        }
+        if let Some(reason) = crash {
+            self.report(&format!("SHIORI host crashed: {}; restarting", reason));
+        }
+
+        let mut last_error = crash.unwrap_or("host is not running").to_string();
+        for attempt in 1..=MAX_RESTARTS {
+            match self.start() {
+                Ok(()) => {
+                    println!("🔁 SHIORI host restarted (attempt {})", attempt);
+                    return Ok(());
+                }
+                Err(e) => last_error = e,
+            }
+        }
+
+        let message = format!("SHIORI host could not be restarted: {}", last_error);
+        self.report(&message);
+        Err(message)
+    }
+}
+
+impl ShioriTransport for ProcessTransport {
+    fn load(&mut self, ghost_dir: &Path) -> Result<(), String> {
+        self.unload()?;
+        self.ghost_dir = Some(ghost_dir.to_path_buf());
+        self.start().inspect_err(|e| {
+            self.report(&format!("SHIORI host failed to load: {}", e));
+        })
+    }
+
+    fn request(&mut self, request: &[u8]) -> Result<Vec<u8>, String> {
+        if self.process.is_none() {
+            if self.ghost_dir.is_none() {
+                return Err("SHIORI is not loaded".to_string());
+            }
+            self.restart(None)?;
+        }
+
+        let timeout = self.timeout;
+        let process = self.process.as_mut().ok_or("SHIORI host is not running")?;
+        match process.call(shiori_host::FRAME_REQUEST, request, timeout) {
+            Ok(result) => result,
+            Err(e) => {
+                // 再起動後に1度だけ再送する
+                self.restart(Some(&e))?;
+                let process = self.process.as_mut().ok_or("SHIORI host is not running")?;
+                match process.call(shiori_host::FRAME_REQUEST, request, timeout) {
+                    Ok(result) => result,
+                    Err(e) => {
+                        // 同じリクエストで再び落ちた場合は次のリクエストで起動し直す
+                        if let Some(process) = self.process.take() {
+                            process.kill();
+                        }
+                        self.report(&format!("SHIORI host crashed again: {}", e));
+                        Err(e)
+                    }
+                }
+            }
+        }
+    }
+
+    fn unload(&mut self) -> Result<(), String> {
+        self.ghost_dir = None;
+        let Some(mut process) = self.process.take() else {
+            return Ok(());
+        };
+
+        let result = process.call(shiori_host::FRAME_UNLOAD, &[], self.timeout);
+        if result.is_err() {
+            // 返信が無ければ終了を待たずに止める
+            process.kill();
+        } else {
+            let _ = process.child.wait();
+        }
+        result.and_then(|result| result.map(|_| ()))
+    }
+}
+
+impl Drop for ProcessTransport {
+    fn drop(&mut self) {
+        let _ = self.unload();
+    }
+}
+
+/// 外部プロセスで動くエンジンを作成
+pub fn create_engine(
+    ghost_info: &GhostInfo,
+    executable: &Path,
+    on_crash: CrashHandler,
+) -> Result<Box<dyn ShioriEngine>, String> {
+    let target = HostTarget::for_ghost(ghost_info)?;
+    let transport = ProcessTransport::new(executable.to_path_buf(), target).on_crash(on_crash);
+    Ok(Box::new(TransportEngine::new(
+        transport,
+        ghost_info.charset,
+    )))
+}
+
+/// ホストで動かせるゴーストは外部プロセスで、それ以外は従来通りに作成するファクトリ
+pub fn engine_factory(executable: PathBuf, on_crash: CrashHandler) -> EngineFactory {
+    Box::new(move |ghost_info| match HostTarget::for_ghost(ghost_info) {
+        Ok(_) => create_engine(ghost_info, &executable, on_crash.clone()),
+        Err(_) => crate::shiori_engine::create_engine(ghost_info),
+    })
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use parking_lot::Mutex;
+    use std::os::unix::fs::PermissionsExt;
+
+    /// 読み込みには応え、1回目の起動では `first_run` のように振る舞い、
+    /// 2回目以降はリクエストに `ok` を返す偽のホスト
+    fn fake_host(dir: &Path, first_run: &str) -> PathBuf {
+        let script = dir.join("fake-host.sh");
+        std::fs::write(
+            &script,
+            format!(
+                "#!/bin/sh\n\
+                 printf 'O\\000\\000\\000\\000'\n\
+                 if [ ! -e \"$0.started\" ]; then\n\
+                 touch \"$0.started\"\n\
+                 {}\n\
+                 fi\n\
+                 printf 'O\\002\\000\\000\\000ok'\n\
+                 cat > /dev/null\n",
+                first_run
+            ),
+        )
+        .unwrap();
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+        script
+    }
+
+    fn transport(host: PathBuf, reports: &Arc<Mutex<Vec<String>>>) -> ProcessTransport {
+        let sink = reports.clone();
+        ProcessTransport::new(host, HostTarget::Native(PathBuf::from("test.so")))
+            .timeout(Duration::from_millis(300))
+            .on_crash(Arc::new(move |message| sink.lock().push(message)))
+    }
+
+    #[test]
+    fn test_host_crash_during_request_restarts() {
+        let dir = tempfile::tempdir().unwrap();
+        let reports = Arc::new(Mutex::new(Vec::new()));
+        let mut transport = transport(fake_host(dir.path(), "exit 1"), &reports);
+
+        transport.load(dir.path()).unwrap();
+        assert_eq!(transport.request(b"GET").unwrap(), b"ok");
+        assert!(reports.lock()[0].starts_with("SHIORI host crashed"));
+    }
+
+    #[test]
+    fn test_hung_host_times_out_and_restarts() {
+        let dir = tempfile::tempdir().unwrap();
+        let reports = Arc::new(Mutex::new(Vec::new()));
+        let mut transport = transport(fake_host(dir.path(), "exec sleep 30"), &reports);
+
+        transport.load(dir.path()).unwrap();
+        assert_eq!(transport.request(b"GET").unwrap(), b"ok");
+        let reports = reports.lock();
+        assert!(reports[0].contains("did not respond"), "{:?}", reports);
+    }
+
+    #[test]
+    fn test_dead_host_is_reported() {
+        let reports = Arc::new(Mutex::new(Vec::new()));
+        let sink = reports.clone();
+        let mut transport = ProcessTransport::new(
+            PathBuf::from("false"),
+            HostTarget::Native(PathBuf::from("missing.so")),
+        )
+        .on_crash(Arc::new(move |message| sink.lock().push(message)));
+
+        assert!(transport.load(Path::new("/tmp")).is_err());
+        assert!(transport.request(b"GET SHIORI/3.0\r\n\r\n").is_err());
+
+        let reports = reports.lock();
+        assert!(reports[0].starts_with("SHIORI host failed to load"));
+        assert!(
+            reports
+                .last()
+                .unwrap()
+                .starts_with("SHIORI host could not be restarted")
+        );
+    }
+}
@@ -0,0 +1,554 @@
+//! SHIORI Protocol
+//!
+//! SHIORI/3.0 のリクエスト・レスポンスを型として扱うためのモデルとパーサ
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// ベースウェア側の送信者名（Senderヘッダに使用）
+pub const SENDER_NAME: &str = "mascot_nanai";
+
+/// 既定の文字コード
+pub const DEFAULT_CHARSET: &str = "UTF-8";
+
+/// SHIORIリクエストのメソッド
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ShioriMethod {
+    Get,
+    Notify,
+}
+
+impl ShioriMethod {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ShioriMethod::Get => "GET",
+            ShioriMethod::Notify => "NOTIFY",
+        }
+    }
+
+    fn parse(token: &str) -> Result<Self, String> {
+        match token {
+            "GET" => Ok(ShioriMethod::Get),
+            "NOTIFY" => Ok(ShioriMethod::Notify),
+            other => Err(format!("Unknown SHIORI method: {}", other)),
+        }
+    }
+}
+
+/// プロトコルバージョン（例: SHIORI/3.0）
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ShioriVersion {
+    pub major: u8,
+    pub minor: u8,
+}
+
+impl ShioriVersion {
+    pub const V3_0: ShioriVersion = ShioriVersion { major: 3, minor: 0 };
+
+    pub(crate) fn parse(token: &str) -> Result<Self, String> {
+        let numbers = token
+            .strip_prefix("SHIORI/")
+            .ok_or_else(|| format!("Invalid SHIORI version: {}", token))?;
+        let (major, minor) = numbers
+            .split_once('.')
+            .ok_or_else(|| format!("Invalid SHIORI version: {}", token))?;
+
+        Ok(ShioriVersion {
+            major: major
+                .parse()
+                .map_err(|_| format!("Invalid SHIORI version: {}", token))?,
+            minor: minor
+                .parse()
+                .map_err(|_| format!("Invalid SHIORI version: {}", token))?,
+        })
+    }
+}
+
+impl Default for ShioriVersion {
+    fn default() -> Self {
+        ShioriVersion::V3_0
+    }
+}
+
+impl fmt::Display for ShioriVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SHIORI/{}.{}", self.major, self.minor)
+    }
+}
+
+/// レスポンスのステータスコード
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ShioriStatus {
+    /// 200 OK
+    Ok,
+    /// 204 No Content（ゴーストは何も喋らない）
+    NoContent,
+    /// 311 Not Enough（TEACHの情報不足）
+    NotEnough,
+    /// 312 Advice（TEACHの解釈不能）
+    Advice,
+    /// 400 Bad Request
+    BadRequest,
+    /// 500 Internal Server Error
+    InternalServerError,
+    /// 上記以外のステータス
+    Other(u16),
+}
+
+impl ShioriStatus {
+    pub fn code(&self) -> u16 {
+        match self {
+            ShioriStatus::Ok => 200,
+            ShioriStatus::NoContent => 204,
+            ShioriStatus::NotEnough => 311,
+            ShioriStatus::Advice => 312,
+            ShioriStatus::BadRequest => 400,
+            ShioriStatus::InternalServerError => 500,
+            ShioriStatus::Other(code) => *code,
+        }
+    }
+
+    pub fn from_code(code: u16) -> Self {
+        match code {
+            200 => ShioriStatus::Ok,
+            204 => ShioriStatus::NoContent,
+            311 => ShioriStatus::NotEnough,
+            312 => ShioriStatus::Advice,
+            400 => ShioriStatus::BadRequest,
+            500 => ShioriStatus::InternalServerError,
+            other => ShioriStatus::Other(other),
+        }
+    }
+
+    pub fn reason_phrase(&self) -> &'static str {
+        match self {
+            ShioriStatus::Ok => "OK",
+            ShioriStatus::NoContent => "No Content",
+            ShioriStatus::NotEnough => "Not Enough",
+            ShioriStatus::Advice => "Advice",
+            ShioriStatus::BadRequest => "Bad Request",
+            ShioriStatus::InternalServerError => "Internal Server Error",
+            ShioriStatus::Other(_) => "Unknown",
+        }
+    }
+
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.code())
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.code() >= 400
+    }
+}
+
+/// ErrorLevelヘッダの値
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ErrorLevel {
+    Info,
+    Notice,
+    Warning,
+    Error,
+    Critical,
+    Other(String),
+}
+
+impl ErrorLevel {
+    fn parse(value: &str) -> Self {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "info" => ErrorLevel::Info,
+            "notice" => ErrorLevel::Notice,
+            "warning" => ErrorLevel::Warning,
+            "error" => ErrorLevel::Error,
+            "critical" => ErrorLevel::Critical,
+            _ => ErrorLevel::Other(value.trim().to_string()),
+        }
+    }
+}
+
+/// 順序を保持するヘッダ一覧（キーの比較は大文字小文字を区別しない）
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ShioriHeaders(Vec<(String, String)>);
+
+impl ShioriHeaders {
+    pub fn new() -> Self {
+        ShioriHeaders(Vec::new())
+    }
+
+    /// ヘッダを取得（同名が複数ある場合は最初のもの）
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// ヘッダを末尾に追加
+    pub fn push(&mut self, key: &str, value: &str) {
+        self.0.push((key.to_string(), value.to_string()));
+    }
+
+    /// ヘッダを設定（既存の値があれば位置を保ったまま置き換える）
+    pub fn set(&mut self, key: &str, value: &str) {
+        match self.0.iter_mut().find(|(k, _)| k.eq_ignore_ascii_case(key)) {
+            Some(entry) => entry.1 = value.to_string(),
+            None => self.push(key, value),
+        }
+    }
+
+    /// ヘッダを削除
+    pub fn remove(&mut self, key: &str) -> Option<String> {
+        let index = self
+            .0
+            .iter()
+            .position(|(k, _)| k.eq_ignore_ascii_case(key))?;
+        Some(self.0.remove(index).1)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Reference0, Reference1, ... を番号順に取得
+    pub fn references(&self) -> Vec<(usize, &str)> {
+        let mut references: Vec<(usize, &str)> = self
+            .0
+            .iter()
+            .filter_map(|(k, v)| {
+                let index = k.strip_prefix("Reference")?.parse().ok()?;
+                Some((index, v.as_str()))
+            })
+            .collect();
+        references.sort_by_key(|(index, _)| *index);
+        references
+    }
+
+    /// ヘッダ行を解析して追加
+    fn parse_line(&mut self, line: &str) -> Result<(), String> {
+        let (key, value) = line
+            .split_once(": ")
+            .or_else(|| line.split_once(':'))
+            .ok_or_else(|| format!("Malformed SHIORI header: {}", line))?;
+        self.push(key.trim(), value);
+        Ok(())
+    }
+
+    pub(crate) fn write_wire(&self, out: &mut String) {
+        for (key, value) in &self.0 {
+            out.push_str(key);
+            out.push_str(": ");
+            out.push_str(value);
+            out.push_str("\r\n");
+        }
+    }
+}
+
+/// 開始行とヘッダ行に分割（ヘッダは空行まで）
+fn split_message(raw: &str) -> Result<(&str, Vec<&str>), String> {
+    let mut lines = raw
+        .split('\n')
+        .map(|line| line.strip_suffix('\r').unwrap_or(line));
+
+    let start_line = lines
+        .next()
+        .filter(|line| !line.is_empty())
+        .ok_or_else(|| "Empty SHIORI message".to_string())?;
+
+    let header_lines = lines.take_while(|line| !line.is_empty()).collect();
+
+    Ok((start_line, header_lines))
+}
+
+/// SHIORI/3.0 リクエスト
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ShioriRequest {
+    pub method: ShioriMethod,
+    pub version: ShioriVersion,
+    pub headers: ShioriHeaders,
+}
+
+impl ShioriRequest {
+    pub fn new(method: ShioriMethod) -> Self {
+        ShioriRequest {
+            method,
+            version: ShioriVersion::V3_0,
+            headers: ShioriHeaders::new(),
+        }
+    }
+
+    /// GETリクエストを作成（Charset・Senderは既定値）
+    pub fn get(id: &str) -> Self {
+        Self::with_id(ShioriMethod::Get, id)
+    }
+
+    /// NOTIFYリクエストを作成（Charset・Senderは既定値）
+    pub fn notify(id: &str) -> Self {
+        Self::with_id(ShioriMethod::Notify, id)
+    }
+
+    fn with_id(method: ShioriMethod, id: &str) -> Self {
+        let mut request = ShioriRequest::new(method);
+        request.headers.push("Charset", DEFAULT_CHARSET);
+        request.headers.push("Sender", SENDER_NAME);
+        request.headers.push("ID", id);
+        request
+    }
+
+    /// ReferenceNを順番に追加
+    pub fn with_references(mut self, references: &[&str]) -> Self {
+        for (i, reference) in references.iter().enumerate() {
+            self.headers.push(&format!("Reference{}", i), reference);
+        }
+        self
+    }
+
+    pub fn id(&self) -> Option<&str> {
+        self.headers.get("ID")
+    }
+
+    pub fn charset(&self) -> Option<&str> {
+        self.headers.get("Charset")
+    }
+
+    pub fn sender(&self) -> Option<&str> {
+        self.headers.get("Sender")
+    }
+
+    pub fn security_level(&self) -> Option<&str> {
+        self.headers.get("SecurityLevel")
+    }
+
+    pub fn reference(&self, index: usize) -> Option<&str> {
+        self.headers.get(&format!("Reference{}", index))
+    }
+
+    pub fn references(&self) -> Vec<(usize, &str)> {
+        self.headers.references()
+    }
+
+    /// 文字列からリクエストを解析
+    pub fn parse(raw: &str) -> Result<Self, String> {
+        let (start_line, header_lines) = split_message(raw)?;
+
+        let (method, version) = start_line
+            .split_once(' ')
+            .ok_or_else(|| format!("Malformed SHIORI request line: {}", start_line))?;
+
+        let mut headers = ShioriHeaders::new();
+        for line in header_lines {
+            headers.parse_line(line)?;
+        }
+
+        Ok(ShioriRequest {
+            method: ShioriMethod::parse(method)?,
+            version: ShioriVersion::parse(version.trim())?,
+            headers,
+        })
+    }
+
+    /// ワイヤー形式の文字列に変換
+    pub fn to_wire(&self) -> String {
+        let mut out = format!("{} {}\r\n", self.method.as_str(), self.version);
+        self.headers.write_wire(&mut out);
+        out.push_str("\r\n");
+        out
+    }
+}
+
+/// SHIORI/3.0 レスポンス
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ShioriResponse {
+    pub version: ShioriVersion,
+    pub status: ShioriStatus,
+    pub headers: ShioriHeaders,
+}
+
+impl ShioriResponse {
+    pub fn new(status: ShioriStatus) -> Self {
+        ShioriResponse {
+            version: ShioriVersion::V3_0,
+            status,
+            headers: ShioriHeaders::new(),
+        }
+    }
+
+    /// Valueを持つ200レスポンスを作成
+    pub fn ok(value: &str) -> Self {
+        let mut response = ShioriResponse::new(ShioriStatus::Ok);
+        response.headers.push("Charset", DEFAULT_CHARSET);
+        response.headers.push("Value", value);
+        response
+    }
+
+    /// 204レスポンスを作成
+    pub fn no_content() -> Self {
+        let mut response = ShioriResponse::new(ShioriStatus::NoContent);
+        response.headers.push("Charset", DEFAULT_CHARSET);
+        response
+    }
+
+    pub fn value(&self) -> Option<&str> {
+        self.headers.get("Value")
+    }
+
+    pub fn charset(&self) -> Option<&str> {
+        self.headers.get("Charset")
+    }
+
+    pub fn sender(&self) -> Option<&str> {
+        self.headers.get("Sender")
+    }
+
+    pub fn security_level(&self) -> Option<&str> {
+        self.headers.get("SecurityLevel")
+    }
+
+    pub fn reference(&self, index: usize) -> Option<&str> {
+        self.headers.get(&format!("Reference{}", index))
+    }
+
+    pub fn references(&self) -> Vec<(usize, &str)> {
+        self.headers.references()
+    }
+
+    /// ErrorLevel（複数ある場合は\x01区切り）
+    pub fn error_levels(&self) -> Vec<ErrorLevel> {
+        self.headers
+            .get("ErrorLevel")
+            .map(|levels| levels.split('\u{1}').map(ErrorLevel::parse).collect())
+            .unwrap_or_default()
+    }
+
+    /// ErrorDescription（複数ある場合は\x01区切り）
+    pub fn error_descriptions(&self) -> Vec<&str> {
+        self.headers
+            .get("ErrorDescription")
+            .map(|descriptions| descriptions.split('\u{1}').collect())
+            .unwrap_or_default()
+    }
+
+    /// ゴーストが何も喋らない応答かどうか（204、または空のValue）
+    pub fn is_silent(&self) -> bool {
+        self.status == ShioriStatus::NoContent
+            || (self.status.is_success() && self.value().is_none_or(|v| v.is_empty()))
+    }
+
+    /// 文字列からレスポンスを解析
+    pub fn parse(raw: &str) -> Result<Self, String> {
+        let (start_line, header_lines) = split_message(raw)?;
+
+        let mut parts = start_line.splitn(3, ' ');
+        let version = parts.next().unwrap_or_default();
+        let code = parts
+            .next()
+            .ok_or_else(|| format!("Malformed SHIORI status line: {}", start_line))?;
+        let code: u16 = code
+            .trim()
+            .parse()
+            .map_err(|_| format!("Invalid SHIORI status code: {}", code))?;
+
+        let mut headers = ShioriHeaders::new();
+        for line in header_lines {
+            headers.parse_line(line)?;
+        }
+
+        Ok(ShioriResponse {
+            version: ShioriVersion::parse(version)?,
+            status: ShioriStatus::from_code(code),
+            headers,
+        })
+    }
+
+    /// ワイヤー形式の文字列に変換
+    pub fn to_wire(&self) -> String {
+        let mut out = format!(
+            "{} {} {}\r\n",
+            self.version,
+            self.status.code(),
+            self.status.reason_phrase()
+        );
+        self.headers.write_wire(&mut out);
+        out.push_str("\r\n");
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_round_trip() {
+        let raw = "GET SHIORI/3.0\r\nCharset: UTF-8\r\nSender: SSP\r\nSecurityLevel: local\r\nID: OnMouseClick\r\nReference0: 120\r\nReference1: 64\r\nReference3: 0\r\n\r\n";
+        let request = ShioriRequest::parse(raw).unwrap();
+
+        assert_eq!(request.method, ShioriMethod::Get);
+        assert_eq!(request.version, ShioriVersion::V3_0);
+        assert_eq!(request.id(), Some("OnMouseClick"));
+        assert_eq!(request.sender(), Some("SSP"));
+        assert_eq!(request.security_level(), Some("local"));
+        assert_eq!(request.references(), vec![(0, "120"), (1, "64"), (3, "0")]);
+        assert_eq!(request.to_wire(), raw);
+    }
+
+    #[test]
+    fn test_response_statuses() {
+        let ok = ShioriResponse::parse(
+            "SHIORI/3.0 200 OK\r\nCharset: UTF-8\r\nValue: \\0\\s[0]こんにちは\\e\r\n\r\n",
+        )
+        .unwrap();
+        assert_eq!(ok.status, ShioriStatus::Ok);
+        assert_eq!(ok.value(), Some("\\0\\s[0]こんにちは\\e"));
+        assert!(!ok.is_silent());
+
+        let silent = ShioriResponse::parse("SHIORI/3.0 204 No Content\r\n\r\n").unwrap();
+        assert!(silent.is_silent());
+        assert!(!silent.status.is_error());
+
+        let failed = ShioriResponse::parse(
+            "SHIORI/3.0 500 Internal Server Error\nErrorLevel: error\u{1}warning\nErrorDescription: syntax error\u{1}unknown word\n\n",
+        )
+        .unwrap();
+        assert!(failed.status.is_error());
+        assert_eq!(
+            failed.error_levels(),
+            vec![ErrorLevel::Error, ErrorLevel::Warning]
+        );
+        assert_eq!(
+            failed.error_descriptions(),
+            vec!["syntax error", "unknown word"]
+        );
+
+        let teach = ShioriResponse::parse("SHIORI/3.0 311 Not Enough\r\n\r\n").unwrap();
+        assert_eq!(teach.status, ShioriStatus::NotEnough);
+        assert_eq!(
+            ShioriResponse::parse("SHIORI/3.0 312 Advice\r\n\r\n")
+                .unwrap()
+                .status,
+            ShioriStatus::Advice
+        );
+    }
+
+    #[test]
+    fn test_response_round_trip_keeps_header_order() {
+        let raw = "SHIORI/3.0 200 OK\r\nSender: nanai\r\nValue: a: b\r\nCharset: UTF-8\r\n\r\n";
+        let response = ShioriResponse::parse(raw).unwrap();
+        assert_eq!(response.value(), Some("a: b"));
+        assert_eq!(response.to_wire(), raw);
+    }
+
+    #[test]
+    fn test_malformed_messages() {
+        assert!(ShioriResponse::parse("").is_err());
+        assert!(ShioriResponse::parse("SHIORI/3.0 OK\r\n\r\n").is_err());
+        assert!(ShioriRequest::parse("FETCH SHIORI/3.0\r\n\r\n").is_err());
+        assert!(ShioriRequest::parse("GET SHIORI/3.0\r\nnot a header\r\n\r\n").is_err());
+    }
+}
@@ -0,0 +1,942 @@
+//! Satori Engine
+//!
+//! 里々（Satori）の辞書をRustで解釈するSHIORIエンジン。
+//! `satori.dll` を読み込めない環境でも里々のゴーストを動かすために使う。
+//!
+//! 対応している記法:
+//! - `＊名前` トーク（名前が空ならランダムトーク、タブの後に条件を書ける）
+//! - `＠名前` 単語群
+//! - `：` 話者の切り替え、`＞` `≫` ジャンプ、`＿` 選択肢、`＄` 変数への代入
+//! - `（）` インライン呼び出し（単語群・トーク・変数・`Ｒ０`・時刻・一部の関数）
+//! - `φ` エスケープ、`replace.txt` / `replace_after.txt`、`satori_savedata.txt` の保存
+
+use crate::shiori_charset::ShioriCharset;
+use crate::shiori_engine::ShioriEngine;
+use crate::shiori_protocol::{ShioriMethod, ShioriRequest, ShioriResponse};
+use chrono::{Datelike, Local, NaiveDateTime, Timelike};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// 現在時刻を返す関数（テストでは固定の時刻を差し込む）
+pub type SatoriClock = Box<dyn Fn() -> NaiveDateTime + Send>;
+
+const SAVEDATA_FILE: &str = "satori_savedata.txt";
+const SAVEBACKUP_FILE: &str = "satori_savebackup.txt";
+
+/// ジャンプやインライン呼び出しの入れ子の上限
+const MAX_DEPTH: usize = 32;
+
+/// 条件式の演算子（長いものから順に探す）
+const OPERATORS: [(&str, &str); 12] = [
+    ("＝＝", "=="),
+    ("==", "=="),
+    ("！＝", "!="),
+    ("!=", "!="),
+    ("＞＝", ">="),
+    (">=", ">="),
+    ("＜＝", "<="),
+    ("<=", "<="),
+    ("＞", ">"),
+    (">", ">"),
+    ("＜", "<"),
+    ("<", "<"),
+];
+
+/// `＊` で始まるトーク
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct TalkBlock {
+    condition: Option<String>,
+    lines: Vec<String>,
+}
+
+/// 読み込んだ辞書
+#[derive(Debug, Default)]
+struct SatoriDictionary {
+    talks: HashMap<String, Vec<TalkBlock>>,
+    words: HashMap<String, Vec<String>>,
+}
+
+/// 解析中のエントリ
+enum Entry {
+    Talk(String, TalkBlock),
+    Word(String, Vec<String>),
+}
+
+impl SatoriDictionary {
+    /// 辞書ファイル1つ分を追加（最初の `＊` `＠` より前は説明文として読み飛ばす）
+    fn parse(&mut self, text: &str, replace: &[(String, String)]) {
+        let mut entry: Option<Entry> = None;
+
+        for line in text.lines() {
+            if line.starts_with('＃') {
+                continue;
+            }
+            let line = apply_replace(line, replace);
+
+            if let Some(header) = line.strip_prefix('＊') {
+                self.finish(entry.take());
+                let (name, condition) = split_tab(header);
+                entry = Some(Entry::Talk(
+                    name.to_string(),
+                    TalkBlock {
+                        condition: condition.map(str::to_string),
+                        lines: Vec::new(),
+                    },
+                ));
+            } else if let Some(name) = line.strip_prefix('＠') {
+                self.finish(entry.take());
+                entry = Some(Entry::Word(split_tab(name).0.to_string(), Vec::new()));
+            } else {
+                match &mut entry {
+                    Some(Entry::Talk(_, block)) => block.lines.push(line),
+                    Some(Entry::Word(_, words)) if !line.trim().is_empty() => words.push(line),
+                    _ => {}
+                }
+            }
+        }
+
+        self.finish(entry);
+    }
+
+    fn finish(&mut self, entry: Option<Entry>) {
+        match entry {
+            Some(Entry::Talk(name, mut block)) => {
+                while block.lines.last().is_some_and(|l| l.trim().is_empty()) {
+                    block.lines.pop();
+                }
+                self.talks.entry(name).or_default().push(block);
+            }
+            Some(Entry::Word(name, words)) => {
+                self.words.entry(name).or_default().extend(words);
+            }
+            None => {}
+        }
+    }
+}
+
+/// トークの組み立て中の状態
+#[derive(Default)]
+struct Speech {
+    out: String,
+    speaker: usize,
+    started: bool,
+    spoken: [bool; 2],
+    line_open: bool,
+}
+
+impl Speech {
+    /// 現在の話者で1行喋る
+    fn line(&mut self, text: &str) {
+        if self.line_open {
+            self.out.push_str("\\n");
+        }
+        self.out.push_str(text);
+        self.line_open = true;
+        self.started = true;
+        self.spoken[self.speaker] = true;
+    }
+
+    /// `：` による話者の切り替え（トーク最初の `：` は切り替えない）
+    fn switch(&mut self, separator: &str) {
+        if !self.started {
+            self.started = true;
+            return;
+        }
+        self.speaker ^= 1;
+        self.out
+            .push_str(if self.speaker == 0 { "\\0" } else { "\\1" });
+        if self.spoken[self.speaker] {
+            self.out.push_str(separator);
+        }
+        self.line_open = false;
+    }
+}
+
+/// 里々エンジン
+pub struct SatoriEngine {
+    charset: ShioriCharset,
+    ghost_dir: Option<PathBuf>,
+    dictionary: SatoriDictionary,
+    variables: BTreeMap<String, String>,
+    replace_after: Vec<(String, String)>,
+    references: Vec<String>,
+    rng: fastrand::Rng,
+    clock: SatoriClock,
+    seconds_since_talk: u32,
+    stroke_counts: HashMap<String, u32>,
+}
+
+impl SatoriEngine {
+    pub fn new(charset: ShioriCharset) -> Self {
+        SatoriEngine {
+            charset,
+            ghost_dir: None,
+            dictionary: SatoriDictionary::default(),
+            variables: BTreeMap::new(),
+            replace_after: Vec::new(),
+            references: Vec::new(),
+            rng: fastrand::Rng::new(),
+            clock: Box::new(|| Local::now().naive_local()),
+            seconds_since_talk: 0,
+            stroke_counts: HashMap::new(),
+        }
+    }
+
+    /// 乱数の種を固定する
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.rng = fastrand::Rng::with_seed(seed);
+        self
+    }
+
+    /// 時刻の取得方法を差し替える
+    pub fn with_clock(mut self, clock: SatoriClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// 変数の値
+    pub fn variable(&self, name: &str) -> Option<&str> {
+        self.variables.get(name).map(String::as_str)
+    }
+
+    fn read_text(&self, path: &Path) -> Option<String> {
+        fs::read(path).ok().map(|bytes| self.charset.decode(&bytes))
+    }
+
+    fn read_pairs(&self, path: &Path) -> Vec<(String, String)> {
+        let Some(text) = self.read_text(path) else {
+            return Vec::new();
+        };
+        text.lines()
+            .filter(|line| !line.starts_with('＃'))
+            .filter_map(|line| line.split_once('\t'))
+            .filter(|(from, _)| !from.is_empty())
+            .map(|(from, to)| (from.to_string(), to.to_string()))
+            .collect()
+    }
+
+    /// 辞書・置換表・セーブデータを読み込む
+    fn load_dictionaries(&mut self, ghost_dir: &Path) -> Result<(), String> {
+        let replace = self.read_pairs(&ghost_dir.join("replace.txt"));
+        self.replace_after = self.read_pairs(&ghost_dir.join("replace_after.txt"));
+
+        let mut files: Vec<PathBuf> = fs::read_dir(ghost_dir)
+            .map_err(|e| format!("Failed to read ghost directory {:?}: {}", ghost_dir, e))?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                let name = path.file_name().unwrap_or_default().to_string_lossy();
+                name.starts_with("dic") && name.ends_with(".txt")
+            })
+            .collect();
+        files.sort();
+
+        if files.is_empty() {
+            return Err(format!("No Satori dictionaries found in {:?}", ghost_dir));
+        }
+
+        self.dictionary = SatoriDictionary::default();
+        for file in &files {
+            if let Some(text) = self.read_text(file) {
+                self.dictionary.parse(&text, &replace);
+            }
+        }
+        println!("📚 Loaded {} Satori dictionaries", files.len());
+
+        // セーブデータを読み込み、satori_conf.txt の＊初期化で未設定の変数だけ埋める
+        self.variables.clear();
+        if let Some(text) = self.read_text(&ghost_dir.join(SAVEDATA_FILE)) {
+            for line in text.lines() {
+                if let Some((name, value)) = line.strip_prefix('＄').and_then(split_assignment) {
+                    self.variables.insert(name.to_string(), value.to_string());
+                }
+            }
+        }
+
+        if let Some(text) = self.read_text(&ghost_dir.join("satori_conf.txt")) {
+            let mut conf = SatoriDictionary::default();
+            conf.parse(&text, &replace);
+            let lines = conf
+                .talks
+                .remove("初期化")
+                .and_then(|blocks| blocks.into_iter().next())
+                .map(|block| block.lines)
+                .unwrap_or_default();
+            for line in lines {
+                if let Some((name, value)) = line.strip_prefix('＄').and_then(split_assignment) {
+                    let name = self.expand(name, 0);
+                    if !self.variables.contains_key(&name) {
+                        let value = self.expand(value, 0);
+                        self.variables.insert(name, value);
+                    }
+                }
+            }
+        }
+
+        let boot_count = self.number_variable("起動回数").unwrap_or(0) + 1;
+        self.variables
+            .insert("起動回数".to_string(), boot_count.to_string());
+
+        Ok(())
+    }
+
+    /// セーブデータを書き出す（直前のセーブデータはバックアップに残す）
+    fn save(&self) -> Result<(), String> {
+        let Some(ghost_dir) = &self.ghost_dir else {
+            return Ok(());
+        };
+
+        let path = ghost_dir.join(SAVEDATA_FILE);
+        if path.exists() {
+            let _ = fs::copy(&path, ghost_dir.join(SAVEBACKUP_FILE));
+        }
+
+        let mut text = String::from("＊セーブデータ\r\n");
+        for (name, value) in &self.variables {
+            text.push_str(&format!("＄{}\t{}\r\n", name, value));
+        }
+        fs::write(&path, self.charset.encode(&text))
+            .map_err(|e| format!("Failed to write {:?}: {}", path, e))
+    }
+
+    fn number_variable(&self, name: &str) -> Option<i64> {
+        self.variables.get(name).and_then(|v| parse_number(v))
+    }
+
+    fn variable_or(&self, name: &str, default: &str) -> String {
+        self.variables
+            .get(name)
+            .cloned()
+            .unwrap_or_else(|| default.to_string())
+    }
+
+    /// 条件を満たすトークを1つ選ぶ
+    fn pick_talk(&mut self, name: &str, depth: usize) -> Option<Vec<String>> {
+        let blocks = self.dictionary.talks.get(name)?.clone();
+        let candidates: Vec<TalkBlock> = blocks
+            .into_iter()
+            .filter(|block| match &block.condition {
+                Some(condition) => self.eval_condition(condition, depth + 1),
+                None => true,
+            })
+            .collect();
+
+        if candidates.is_empty() {
+            return None;
+        }
+        let index = self.rng.usize(..candidates.len());
+        Some(candidates[index].lines.clone())
+    }
+
+    /// トークをさくらスクリプトにする
+    fn talk(&mut self, name: &str) -> Option<String> {
+        let lines = self.pick_talk(name, 0)?;
+        let mut speech = Speech::default();
+        self.render(&lines, &mut speech, 0);
+        if speech.out.is_empty() {
+            return None;
+        }
+        self.seconds_since_talk = 0;
+
+        let mut script = if self.variable_or("会話時サーフェス戻し", "有効") == "有効"
+        {
+            format!(
+                "\\1\\s[{}]\\0\\s[{}]",
+                self.variable_or("デフォルトサーフェス1", "10"),
+                self.variable_or("デフォルトサーフェス0", "0")
+            )
+        } else {
+            "\\0".to_string()
+        };
+        script.push_str(&speech.out);
+        script.push_str("\\e");
+        Some(apply_replace(&script, &self.replace_after))
+    }
+
+    /// トークの各行を処理（ジャンプした場合は `true`）
+    fn render(&mut self, lines: &[String], speech: &mut Speech, depth: usize) -> bool {
+        if depth > MAX_DEPTH {
+            println!("⚠️ Satori: talk nesting too deep");
+            return true;
+        }
+
+        for line in lines {
+            if let Some(rest) = line.strip_prefix('＞') {
+                if self.jump(rest, speech, depth) {
+                    return true;
+                }
+            } else if let Some(rest) = line.strip_prefix('≫') {
+                self.jump(rest, speech, depth);
+            } else if let Some(rest) = line.strip_prefix('＿') {
+                let (label, id) = split_tab(rest);
+                let label = self.expand(label, depth);
+                let id = id
+                    .map(|id| self.expand(id, depth))
+                    .unwrap_or_else(|| label.clone());
+                speech.line(&format!("\\q[{},{}]", label, id));
+            } else if let Some(rest) = line.strip_prefix('＄') {
+                if let Some((name, value)) = split_assignment(rest) {
+                    let name = self.expand(name, depth);
+                    let value = self.expand(value, depth);
+                    self.variables.insert(name, value);
+                }
+            } else if let Some(rest) = line.strip_prefix('：') {
+                let separator = self.variable_or("スコープ切り換え時", "\\n[half]");
+                speech.switch(&separator);
+                let text = self.expand(rest, depth);
+                speech.line(&text);
+            } else {
+                let text = self.expand(line, depth);
+                speech.line(&text);
+            }
+        }
+
+        false
+    }
+
+    /// `＞ジャンプ先<TAB>条件`（ジャンプ先が無ければ何もしない）
+    fn jump(&mut self, rest: &str, speech: &mut Speech, depth: usize) -> bool {
+        let (target, condition) = split_tab(rest);
+        if let Some(condition) = condition
+            && !self.eval_condition(condition, depth)
+        {
+            return false;
+        }
+
+        let target = self.expand(target, depth);
+        match self.pick_talk(&target, depth) {
+            Some(lines) => {
+                self.render(&lines, speech, depth + 1);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// `（）` を展開する
+    fn expand(&mut self, text: &str, depth: usize) -> String {
+        let mut out = String::new();
+        let mut chars = text.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                'φ' => {
+                    if let Some(next) = chars.next() {
+                        out.push(next);
+                    }
+                }
+                '（' => {
+                    // 対応する閉じ括弧までを取り出す
+                    let mut inner = String::new();
+                    let mut level = 1;
+                    let mut closed = false;
+                    while let Some(c) = chars.next() {
+                        match c {
+                            'φ' => {
+                                inner.push(c);
+                                if let Some(next) = chars.next() {
+                                    inner.push(next);
+                                }
+                                continue;
+                            }
+                            '（' => level += 1,
+                            '）' => {
+                                level -= 1;
+                                if level == 0 {
+                                    closed = true;
+                                    break;
+                                }
+                            }
+                            _ => {}
+                        }
+                        inner.push(c);
+                    }
+
+                    let name = self.expand(&inner, depth + 1);
+                    if !closed {
+                        out.push('（');
+                        out.push_str(&name);
+                    } else if let Some(value) = self.resolve(&name, depth + 1) {
+                        out.push_str(&value);
+                    } else {
+                        out.push('（');
+                        out.push_str(&name);
+                        out.push('）');
+                    }
+                }
+                _ => out.push(c),
+            }
+        }
+
+        out
+    }
+
+    /// インライン呼び出しの名前を値にする（解決できなければ `None`）
+    fn resolve(&mut self, name: &str, depth: usize) -> Option<String> {
+        if depth > MAX_DEPTH {
+            return None;
+        }
+
+        if let Some(index) = reference_index(name) {
+            return Some(self.references.get(index).cloned().unwrap_or_default());
+        }
+
+        if let Some(value) = self.call_function(name) {
+            return Some(value);
+        }
+
+        if let Some(value) = self.variables.get(name) {
+            return Some(value.clone());
+        }
+
+        if let Some(words) = self.dictionary.words.get(name)
+            && !words.is_empty()
+        {
+            let word = words[self.rng.usize(..words.len())].clone();
+            return Some(self.expand(&word, depth));
+        }
+
+        if let Some(lines) = self.pick_talk(name, depth) {
+            let mut speech = Speech::default();
+            self.render(&lines, &mut speech, depth + 1);
+            return Some(speech.out);
+        }
+
+        if !name.is_empty() && name.chars().all(|c| to_ascii_digit(c).is_some()) {
+            return Some(format!("\\s[{}]", normalize_digits(name)));
+        }
+
+        self.time_value(name)
+    }
+
+    /// `（関数名,引数,…）` 形式の組み込み関数
+    fn call_function(&mut self, name: &str) -> Option<String> {
+        let mut parts = name.split([',', '，', '\x01']);
+        let function = parts.next()?;
+        let args: Vec<&str> = parts.collect();
+        if args.is_empty() {
+            return None;
+        }
+
+        let flag = |b: bool| if b { "1" } else { "0" }.to_string();
+        match function {
+            "バイト値" => {
+                let code = parse_number(args[0])?;
+                char::from_u32(u32::try_from(code).ok()?).map(String::from)
+            }
+            "is_empty" => Some(flag(args.iter().all(|a| a.is_empty()))),
+            "compare" => Some(flag(args.len() == 2 && args[0] == args[1])),
+            "乱数" if args.len() == 2 => {
+                let (low, high) = (parse_number(args[0])?, parse_number(args[1])?);
+                (low <= high).then(|| self.rng.i64(low..=high).to_string())
+            }
+            _ => None,
+        }
+    }
+
+    fn time_value(&self, name: &str) -> Option<String> {
+        let now = (self.clock)();
+        let value = match name {
+            "現在年" => now.year().to_string(),
+            "現在月" => now.month().to_string(),
+            "現在日" => now.day().to_string(),
+            "現在時" => now.hour().to_string(),
+            "現在分" => now.minute().to_string(),
+            "現在秒" => now.second().to_string(),
+            "現在曜日" => ["日", "月", "火", "水", "木", "金", "土"]
+                [now.weekday().num_days_from_sunday() as usize]
+                .to_string(),
+            _ => return None,
+        };
+        Some(value)
+    }
+
+    /// 条件式を評価
+    fn eval_condition(&mut self, condition: &str, depth: usize) -> bool {
+        let expanded = self.expand(condition, depth);
+
+        for (token, op) in OPERATORS {
+            if let Some((left, right)) = expanded.split_once(token) {
+                let (left, right) = (left.trim(), right.trim());
+                return match (parse_number(left), parse_number(right)) {
+                    (Some(l), Some(r)) => match op {
+                        "==" => l == r,
+                        "!=" => l != r,
+                        ">=" => l >= r,
+                        "<=" => l <= r,
+                        ">" => l > r,
+                        _ => l < r,
+                    },
+                    _ => match op {
+                        "==" => left == right,
+                        "!=" => left != right,
+                        _ => false,
+                    },
+                };
+            }
+        }
+
+        let value = expanded.trim();
+        !value.is_empty() && parse_number(value) != Some(0)
+    }
+
+    /// OnSecondChange: 喋り間隔ごとにランダムトーク
+    fn on_second_change(&mut self) -> Option<String> {
+        self.seconds_since_talk += 1;
+
+        let interval = self.number_variable("喋り間隔").unwrap_or(0);
+        let can_talk = self.references.get(3).is_none_or(|r| r != "0");
+        if interval > 0 && i64::from(self.seconds_since_talk) >= interval && can_talk {
+            self.talk("")
+        } else {
+            None
+        }
+    }
+
+    /// OnMouseMove: 同じ場所で一定回数動かされたら「なでられ」
+    fn on_mouse_move(&mut self) -> Option<String> {
+        let character = self.references.get(3).cloned().unwrap_or_default();
+        let collision = self.references.get(4).cloned().unwrap_or_default();
+        if collision.is_empty() {
+            self.stroke_counts.clear();
+            return None;
+        }
+
+        let key = format!("{}{}", character, collision);
+        let threshold = self
+            .number_variable("なでられ反応回数")
+            .unwrap_or(60)
+            .max(1);
+        let count = self.stroke_counts.entry(key.clone()).or_insert(0);
+        *count += 1;
+        if i64::from(*count) < threshold {
+            return None;
+        }
+
+        self.stroke_counts.clear();
+        self.talk(&format!("{}なでられ", key))
+    }
+
+    /// GET リクエストの処理
+    fn get(&mut self, id: &str) -> Option<String> {
+        match id {
+            "version" => return Some(format!("Satori-rs/{}", env!("CARGO_PKG_VERSION"))),
+            "OnSecondChange" => return self.on_second_change(),
+            "OnMouseMove" => return self.on_mouse_move(),
+            _ => {}
+        }
+
+        if id.starts_with("On") {
+            if let Some(script) = self.talk(id) {
+                return Some(script);
+            }
+            // 選択肢のIDはそのままトーク名として扱う
+            if matches!(id, "OnChoiceSelect" | "OnAnchorSelect") {
+                let target = self.references.first().cloned().unwrap_or_default();
+                return self.talk(&target);
+            }
+            return None;
+        }
+
+        // それ以外はリソース（homeurl など）
+        if self.dictionary.words.contains_key(id) || self.dictionary.talks.contains_key(id) {
+            let value = self.resolve(id, 0)?;
+            return Some(apply_replace(&value, &self.replace_after));
+        }
+        None
+    }
+}
+
+impl ShioriEngine for SatoriEngine {
+    fn load(&mut self, ghost_dir: &Path) -> Result<(), String> {
+        self.load_dictionaries(ghost_dir)?;
+        self.ghost_dir = Some(ghost_dir.to_path_buf());
+
+        // 起動時に必ず呼ばれるトーク（出力は使わない）
+        self.references.clear();
+        let _ = self.talk("OnSatoriLoad");
+        Ok(())
+    }
+
+    fn request(&mut self, request: &ShioriRequest) -> Result<ShioriResponse, String> {
+        let id = request.id().unwrap_or_default().to_string();
+
+        let references = request.references();
+        let count = references.iter().map(|(i, _)| i + 1).max().unwrap_or(0);
+        self.references = vec![String::new(); count];
+        for (index, value) in references {
+            self.references[index] = value.to_string();
+        }
+
+        let value = match request.method {
+            ShioriMethod::Get => self.get(&id),
+            ShioriMethod::Notify => None,
+        };
+
+        let mut response = match value {
+            Some(value) => ShioriResponse::ok(&value),
+            None => ShioriResponse::no_content(),
+        };
+        response.headers.set("Charset", self.charset.label());
+        Ok(response)
+    }
+
+    fn unload(&mut self) -> Result<(), String> {
+        if self.ghost_dir.is_none() {
+            return Ok(());
+        }
+        self.references.clear();
+        let _ = self.talk("OnSatoriUnload");
+        let result = self.save();
+        self.ghost_dir = None;
+        result
+    }
+}
+
+/// タブで名前と残りに分ける
+fn split_tab(text: &str) -> (&str, Option<&str>) {
+    match text.split_once('\t') {
+        Some((name, rest)) => (name, Some(rest)),
+        None => (text, None),
+    }
+}
+
+/// `名前<TAB>値` または `名前＝値`
+fn split_assignment(text: &str) -> Option<(&str, &str)> {
+    text.split_once('\t').or_else(|| text.split_once('＝'))
+}
+
+fn apply_replace(text: &str, pairs: &[(String, String)]) -> String {
+    pairs
+        .iter()
+        .fold(text.to_string(), |text, (from, to)| text.replace(from, to))
+}
+
+fn to_ascii_digit(c: char) -> Option<char> {
+    match c {
+        '0'..='9' => Some(c),
+        '０'..='９' => char::from_u32(c as u32 - '０' as u32 + '0' as u32),
+        _ => None,
+    }
+}
+
+fn normalize_digits(text: &str) -> String {
+    text.chars()
+        .map(|c| match c {
+            '－' | '−' => '-',
+            _ => to_ascii_digit(c).unwrap_or(c),
+        })
+        .collect()
+}
+
+/// 先頭の数値を読む（全角数字や `１８０秒` のような単位付きにも対応）
+fn parse_number(text: &str) -> Option<i64> {
+    let text = normalize_digits(text.trim());
+    let end = text
+        .char_indices()
+        .find(|&(i, c)| !(c.is_ascii_digit() || (i == 0 && c == '-')))
+        .map(|(i, _)| i)
+        .unwrap_or(text.len());
+    text[..end].parse().ok()
+}
+
+/// `Ｒ０` / `R0` の番号
+fn reference_index(name: &str) -> Option<usize> {
+    let digits = name.strip_prefix('Ｒ').or_else(|| name.strip_prefix('R'))?;
+    if digits.is_empty() || !digits.chars().all(|c| to_ascii_digit(c).is_some()) {
+        return None;
+    }
+    normalize_digits(digits).parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+    use tempfile::TempDir;
+
+    fn fixed_clock() -> SatoriClock {
+        Box::new(|| {
+            NaiveDate::from_ymd_opt(2024, 4, 1)
+                .unwrap()
+                .and_hms_opt(12, 0, 0)
+                .unwrap()
+        })
+    }
+
+    /// mock_nanai の ghost/master を一時ディレクトリに複製（セーブデータで汚さないため）
+    fn copy_mock_nanai() -> TempDir {
+        let source =
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("../assets/ghost/mock_nanai/ghost/master");
+        let dir = tempfile::tempdir().unwrap();
+        for entry in fs::read_dir(&source).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_file() {
+                fs::copy(&path, dir.path().join(path.file_name().unwrap())).unwrap();
+            }
+        }
+        dir
+    }
+
+    fn load_mock_nanai() -> (SatoriEngine, TempDir) {
+        let dir = copy_mock_nanai();
+        let mut engine = SatoriEngine::new(ShioriCharset::ShiftJis)
+            .with_seed(1)
+            .with_clock(fixed_clock());
+        engine.load(dir.path()).unwrap();
+        (engine, dir)
+    }
+
+    fn get(engine: &mut SatoriEngine, id: &str, references: &[&str]) -> Option<String> {
+        let request = ShioriRequest::get(id).with_references(references);
+        engine
+            .request(&request)
+            .unwrap()
+            .value()
+            .map(str::to_string)
+    }
+
+    #[test]
+    fn test_mock_nanai_events() {
+        let (mut engine, _dir) = load_mock_nanai();
+
+        assert_eq!(
+            get(&mut engine, "OnBoot", &[]).as_deref(),
+            Some("\\1\\s[10]\\0\\s[0]起動\\e")
+        );
+        // ＞（Ｒ３）（Ｒ４）つつかれ → ＊0つつかれ → （） でランダムトーク
+        assert_eq!(
+            get(&mut engine, "OnMouseDoubleClick", &["0", "0", "0", "0", ""]).as_deref(),
+            Some("\\1\\s[10]\\0\\s[0]ランダムトーク\\e")
+        );
+        // 存在しないジャンプ先は読み飛ばし、条件を満たさない行は無視する
+        assert_eq!(
+            get(&mut engine, "OnMouseWheel", &["0", "0", "120", "0", ""]),
+            None
+        );
+        // replace_after.txt
+        assert_eq!(
+            get(&mut engine, "OnSSTPBreak", &[]).as_deref(),
+            Some("\\1\\s[10]\\0\\s[0]…\\w7…\\w7！？\\e")
+        );
+        // 解決できないインライン呼び出しはそのまま、φ は次の文字をエスケープ
+        assert_eq!(
+            get(&mut engine, "OnShellChanged", &["master"]).as_deref(),
+            Some("\\1\\s[10]\\0\\s[0]（shell：masterに変更しました）\\e")
+        );
+        assert_eq!(get(&mut engine, "OnUnknownEvent", &[]), None);
+
+        engine.unload().unwrap();
+    }
+
+    #[test]
+    fn test_mock_nanai_menu_and_choices() {
+        let (mut engine, _dir) = load_mock_nanai();
+
+        assert_eq!(
+            get(&mut engine, "OnKeyPress", &["m"]).as_deref(),
+            Some(
+                "\\1\\s[10]\\0\\s[0]なにかな。\\n\\n\\q[喋り頻度を変更,喋り頻度変更]\\n\\q[なんでもない,なんでもない]\\e"
+            )
+        );
+        assert_eq!(
+            get(&mut engine, "OnChoiceSelect", &["３０秒おき"]).as_deref(),
+            Some("\\1\\s[10]\\0\\s[0]（３０秒に変更しました）\\e")
+        );
+        assert_eq!(engine.variable("喋り間隔"), Some("３０秒"));
+
+        // 30秒経つとランダムトーク
+        for _ in 0..29 {
+            assert_eq!(get(&mut engine, "OnSecondChange", &[]), None);
+        }
+        assert_eq!(
+            get(&mut engine, "OnSecondChange", &[]).as_deref(),
+            Some("\\1\\s[10]\\0\\s[0]ランダムトーク\\e")
+        );
+
+        engine.unload().unwrap();
+    }
+
+    #[test]
+    fn test_mock_nanai_user_input_and_resources() {
+        let (mut engine, _dir) = load_mock_nanai();
+
+        assert_eq!(
+            get(&mut engine, "OnUserInput", &["ユーザ名", "ナナイ"]).as_deref(),
+            Some("\\1\\s[10]\\0\\s[0]ナナイだね。\\nこれからもよろしくね、ナナイ！\\e")
+        );
+        assert_eq!(
+            get(&mut engine, "OnUserInput", &["ユーザ名", ""]).as_deref(),
+            Some(
+                "\\1\\s[10]\\0\\s[0]あれ？\\n\\n（空打ちされた為エラーになりました。再入力して下さい）\\n\\![open,inputbox,ユーザ名]\\e"
+            )
+        );
+        assert_eq!(
+            get(&mut engine, "homeurl", &[]).as_deref(),
+            Some("http://更新元url")
+        );
+        assert_eq!(
+            get(&mut engine, "sakura.portalbuttoncaption", &[]).as_deref(),
+            Some("きほん(&P)")
+        );
+        assert_eq!(get(&mut engine, "username", &[]).as_deref(), Some("ナナイ"));
+
+        engine.unload().unwrap();
+    }
+
+    #[test]
+    fn test_savedata_is_persisted() {
+        let (mut engine, dir) = load_mock_nanai();
+        assert_eq!(engine.variable("起動回数"), Some("5"));
+        assert_eq!(engine.variable("喋り間隔"), Some("１８０秒"));
+
+        get(&mut engine, "OnChoiceSelect", &["だまってて"]);
+        engine.unload().unwrap();
+
+        let saved =
+            ShioriCharset::ShiftJis.decode(&fs::read(dir.path().join(SAVEDATA_FILE)).unwrap());
+        assert!(saved.starts_with("＊セーブデータ\r\n"));
+        assert!(saved.contains("＄起動回数\t5\r\n"));
+        assert!(saved.contains("＄喋り間隔\t０秒\r\n"));
+        let backup =
+            ShioriCharset::ShiftJis.decode(&fs::read(dir.path().join(SAVEBACKUP_FILE)).unwrap());
+        assert!(backup.contains("＄起動回数\t4"));
+
+        // 読み込み直すと保存した値が使われる
+        let mut engine = SatoriEngine::new(ShioriCharset::ShiftJis).with_seed(1);
+        engine.load(dir.path()).unwrap();
+        assert_eq!(engine.variable("起動回数"), Some("6"));
+        assert_eq!(engine.variable("喋り間隔"), Some("０秒"));
+        engine.unload().unwrap();
+    }
+
+    #[test]
+    fn test_speaker_switch_and_conditions() {
+        let mut engine = SatoriEngine::new(ShioriCharset::Utf8)
+            .with_seed(1)
+            .with_clock(fixed_clock());
+        engine.dictionary.parse(
+            "＊OnTest\n：やあ。\n：こんにちは。\n続き\n：（現在月）月（現在日）日\n\n＊OnCheck\n＞大きい\t（Ｒ０）＞＝１０\n：小さい\n＊大きい\n：大きい（Ｒ０）\n＠果物\nりんご\n",
+            &[],
+        );
+        engine
+            .variables
+            .insert("会話時サーフェス戻し".to_string(), "無効".to_string());
+
+        assert_eq!(
+            engine.get("OnTest").as_deref(),
+            Some("\\0やあ。\\1こんにちは。\\n続き\\0\\n[half]4月1日\\e")
+        );
+
+        engine.references = vec!["１２".to_string()];
+        assert_eq!(engine.get("OnCheck").as_deref(), Some("\\0大きい１２\\e"));
+        engine.references = vec!["3".to_string()];
+        assert_eq!(engine.get("OnCheck").as_deref(), Some("\\0小さい\\e"));
+
+        assert_eq!(engine.expand("（果物）と（２）", 0), "りんごと\\s[2]");
+        assert_eq!(
+            engine.expand("φ（果物φ）（閉じない", 0),
+            "（果物）（閉じない"
+        );
+    }
+}
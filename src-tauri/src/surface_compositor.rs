@@ -0,0 +1,497 @@
+//! Surface Compositor
+//!
+//! surfaces.txt の `element` を重ねて、サーフェスの最終的な画像（RGBA）を作る。
+//! 画像の透過は `.pna`（同じ名前のグレースケール画像）、PNGのアルファチャンネル、
+//! 左上の1ピクセルと同じ色を抜く、の順に決める。
+//!
+//! `element` が無いサーフェスは `surface<ID>.png` をそのまま使う。
+//! 合成した結果はサーフェスごとにキャッシュする。
+
+use mascot_nanai_ui::surfaces::{Element, Method, ShellDefinition};
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// 合成したサーフェスの幅・高さの上限
+const MAX_SURFACE_SIZE: u32 = 4096;
+
+/// RGBAの画像（アルファは乗算済みではない）
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub width: u32,
+    pub height: u32,
+    pub rgba: Vec<u8>,
+}
+
+impl Frame {
+    /// 透明な画像
+    pub fn new(width: u32, height: u32) -> Self {
+        Frame {
+            width,
+            height,
+            rgba: vec![0; width as usize * height as usize * 4],
+        }
+    }
+
+    pub fn pixel(&self, x: u32, y: u32) -> [u8; 4] {
+        let index = self.index(x, y);
+        self.rgba[index..index + 4].try_into().unwrap()
+    }
+
+    fn index(&self, x: u32, y: u32) -> usize {
+        (y as usize * self.width as usize + x as usize) * 4
+    }
+
+    /// PNGを読み込む（8bitのRGBAに変換する）。戻り値の2つ目はアルファチャンネルの有無
+    pub fn decode_png(bytes: &[u8]) -> Result<(Self, bool), String> {
+        let mut decoder = png::Decoder::new(bytes);
+        decoder.set_transformations(png::Transformations::normalize_to_color8());
+        let mut reader = decoder
+            .read_info()
+            .map_err(|e| format!("Invalid PNG: {}", e))?;
+        let mut buffer = vec![0; reader.output_buffer_size()];
+        let info = reader
+            .next_frame(&mut buffer)
+            .map_err(|e| format!("Invalid PNG: {}", e))?;
+        let pixels = &buffer[..info.buffer_size()];
+
+        let (rgba, has_alpha): (Vec<u8>, bool) = match info.color_type {
+            png::ColorType::Rgba => (pixels.to_vec(), true),
+            png::ColorType::Rgb => (
+                pixels
+                    .chunks(3)
+                    .flat_map(|p| [p[0], p[1], p[2], 255])
+                    .collect(),
+                false,
+            ),
+            png::ColorType::GrayscaleAlpha => (
+                pixels
+                    .chunks(2)
+                    .flat_map(|p| [p[0], p[0], p[0], p[1]])
+                    .collect(),
+                true,
+            ),
+            png::ColorType::Grayscale => {
+                (pixels.iter().flat_map(|&g| [g, g, g, 255]).collect(), false)
+            }
+            png::ColorType::Indexed => return Err("Unexpanded indexed PNG".to_string()),
+        };
+        let frame = Frame {
+            width: info.width,
+            height: info.height,
+            rgba,
+        };
+        Ok((frame, has_alpha))
+    }
+
+    /// PNGに書き出す
+    pub fn to_png(&self) -> Result<Vec<u8>, String> {
+        let mut bytes = Vec::new();
+        let mut encoder = png::Encoder::new(&mut bytes, self.width, self.height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder
+            .write_header()
+            .map_err(|e| format!("Failed to encode PNG: {}", e))?;
+        writer
+            .write_image_data(&self.rgba)
+            .map_err(|e| format!("Failed to encode PNG: {}", e))?;
+        writer
+            .finish()
+            .map_err(|e| format!("Failed to encode PNG: {}", e))?;
+        Ok(bytes)
+    }
+
+    /// 左上の1ピクセルと同じ色を透明にする
+    fn apply_color_key(&mut self) {
+        if self.rgba.len() < 4 {
+            return;
+        }
+        let key = [self.rgba[0], self.rgba[1], self.rgba[2]];
+        for pixel in self.rgba.chunks_mut(4) {
+            if pixel[..3] == key {
+                pixel[3] = 0;
+            }
+        }
+    }
+
+    /// `.pna` の明るさをアルファにする
+    fn apply_mask(&mut self, mask: &Frame) {
+        for y in 0..self.height.min(mask.height) {
+            for x in 0..self.width.min(mask.width) {
+                let index = self.index(x, y);
+                self.rgba[index + 3] = mask.rgba[mask.index(x, y)];
+            }
+        }
+    }
+
+    /// `source` を (x, y) に描画メソッドで重ねる
+    pub fn draw(&mut self, source: &Frame, method: Method, x: i32, y: i32) {
+        if method == Method::Base {
+            self.rgba.fill(0);
+        }
+        for source_y in 0..source.height {
+            let target_y = y + source_y as i32;
+            if target_y < 0 || target_y >= self.height as i32 {
+                continue;
+            }
+            for source_x in 0..source.width {
+                let target_x = x + source_x as i32;
+                if target_x < 0 || target_x >= self.width as i32 {
+                    continue;
+                }
+                let index = self.index(target_x as u32, target_y as u32);
+                let destination: [u8; 4] = self.rgba[index..index + 4].try_into().unwrap();
+                let pixel = blend(destination, source.pixel(source_x, source_y), method);
+                self.rgba[index..index + 4].copy_from_slice(&pixel);
+            }
+        }
+    }
+}
+
+/// 1ピクセルの合成
+fn blend(destination: [u8; 4], source: [u8; 4], method: Method) -> [u8; 4] {
+    match method {
+        Method::Base | Method::Replace => source,
+        // 透過を無視してそのまま描く
+        Method::Asis => [source[0], source[1], source[2], 255],
+        // 下が透明でない部分にだけ描く
+        Method::OverlayFast => {
+            let [r, g, b, _] = over(destination, source);
+            [r, g, b, destination[3]]
+        }
+        // 下が透明な部分にだけ描く（下に潜らせる）
+        Method::Interpolate => over(source, destination),
+        // 透明な部分で下を抜く
+        Method::Reduce => {
+            let alpha = destination[3] as u32 * source[3] as u32 / 255;
+            [destination[0], destination[1], destination[2], alpha as u8]
+        }
+        Method::OverlayMultiply => {
+            let multiplied =
+                [0, 1, 2].map(|i| (destination[i] as u32 * source[i] as u32 / 255) as u8);
+            let [r, g, b, _] = over(
+                destination,
+                [multiplied[0], multiplied[1], multiplied[2], source[3]],
+            );
+            [r, g, b, destination[3]]
+        }
+        Method::Overlay | Method::Add | Method::Bind => over(destination, source),
+        // アニメーションの操作は描かない
+        _ => destination,
+    }
+}
+
+/// アルファ合成（`source` を上に重ねる）
+fn over(destination: [u8; 4], source: [u8; 4]) -> [u8; 4] {
+    let source_alpha = source[3] as u32;
+    let destination_alpha = destination[3] as u32 * (255 - source_alpha) / 255;
+    let alpha = source_alpha + destination_alpha;
+    if alpha == 0 {
+        return [0, 0, 0, 0];
+    }
+    let channel = |i: usize| {
+        ((source[i] as u32 * source_alpha + destination[i] as u32 * destination_alpha) / alpha)
+            as u8
+    };
+    [channel(0), channel(1), channel(2), alpha as u8]
+}
+
+/// シェルのサーフェスを合成する（結果はキャッシュする）
+pub struct SurfaceCompositor {
+    shell_dir: PathBuf,
+    definition: ShellDefinition,
+    /// 透過処理済みの画像（ファイル名の小文字ごと）
+    images: Mutex<HashMap<String, Arc<Frame>>>,
+    frames: Mutex<HashMap<u32, Arc<Frame>>>,
+    pngs: Mutex<HashMap<u32, Arc<Vec<u8>>>>,
+}
+
+impl SurfaceCompositor {
+    pub fn new(shell_dir: &Path, definition: ShellDefinition) -> Self {
+        SurfaceCompositor {
+            shell_dir: shell_dir.to_path_buf(),
+            definition,
+            images: Mutex::new(HashMap::new()),
+            frames: Mutex::new(HashMap::new()),
+            pngs: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// シェルのディレクトリの surfaces.txt を読み込む
+    pub fn load(shell_dir: &Path) -> Result<Self, String> {
+        let definition = ShellDefinition::load(shell_dir)
+            .map_err(|e| format!("Failed to read shell {:?}: {}", shell_dir, e))?;
+        for warning in &definition.warnings {
+            println!("⚠️ {}:{}: {}", warning.file, warning.line, warning.message);
+        }
+        Ok(Self::new(shell_dir, definition))
+    }
+
+    pub fn shell_dir(&self) -> &Path {
+        &self.shell_dir
+    }
+
+    pub fn definition(&self) -> &ShellDefinition {
+        &self.definition
+    }
+
+    /// サーフェスを合成する
+    pub fn compose(&self, surface_id: u32) -> Result<Arc<Frame>, String> {
+        if let Some(frame) = self.frames.lock().get(&surface_id) {
+            return Ok(frame.clone());
+        }
+
+        let elements: Vec<&Element> = self
+            .definition
+            .surface(surface_id)
+            .map(|surface| surface.elements.values().collect())
+            .unwrap_or_default();
+        let frame = if elements.is_empty() {
+            self.image(&format!("surface{}.png", surface_id))?
+                .as_ref()
+                .clone()
+        } else {
+            self.compose_elements(surface_id, &elements)?
+        };
+
+        let frame = Arc::new(frame);
+        self.frames.lock().insert(surface_id, frame.clone());
+        Ok(frame)
+    }
+
+    /// 合成したサーフェスのPNG
+    pub fn png(&self, surface_id: u32) -> Result<Arc<Vec<u8>>, String> {
+        if let Some(png) = self.pngs.lock().get(&surface_id) {
+            return Ok(png.clone());
+        }
+        let png = Arc::new(self.compose(surface_id)?.to_png()?);
+        self.pngs.lock().insert(surface_id, png.clone());
+        Ok(png)
+    }
+
+    /// サーフェスの点 (x, y) にある当たり判定の名前
+    pub fn hit_test(&self, surface_id: u32, x: i32, y: i32) -> Option<String> {
+        let surface = self.definition.surface(surface_id)?;
+        let collision = surface.hit_test(x, y, |file, x, y| {
+            let image = self
+                .image(file)
+                .map_err(|e| println!("⚠️ Collision region: {}", e))
+                .ok()?;
+            let (x, y) = (u32::try_from(x).ok()?, u32::try_from(y).ok()?);
+            if x >= image.width || y >= image.height {
+                return None;
+            }
+            let [red, green, blue, _] = image.pixel(x, y);
+            Some((red, green, blue))
+        })?;
+        Some(collision.name.clone())
+    }
+
+    /// 画像を読み直すためにキャッシュを消す
+    pub fn clear_cache(&self) {
+        self.images.lock().clear();
+        self.frames.lock().clear();
+        self.pngs.lock().clear();
+    }
+
+    fn compose_elements(&self, surface_id: u32, elements: &[&Element]) -> Result<Frame, String> {
+        let mut layers = Vec::new();
+        for element in elements {
+            match self.image(&element.file) {
+                Ok(image) => layers.push((*element, image)),
+                // 足りない画像があっても描けるところまで描く
+                Err(e) => println!("⚠️ surface{}: {}", surface_id, e),
+            }
+        }
+        if layers.is_empty() {
+            return Err(format!("No images for surface{}", surface_id));
+        }
+
+        // 位置は surfaces.txt に書かれたままなので、大きすぎるものは描かない
+        let size = |offset: i32, length: u32| {
+            (offset.max(0) as u32)
+                .checked_add(length)
+                .filter(|size| *size <= MAX_SURFACE_SIZE)
+        };
+        let (mut width, mut height) = (0, 0);
+        for (element, image) in &layers {
+            let (Some(right), Some(bottom)) =
+                (size(element.x, image.width), size(element.y, image.height))
+            else {
+                return Err(format!(
+                    "surface{} exceeds {}x{}",
+                    surface_id, MAX_SURFACE_SIZE, MAX_SURFACE_SIZE
+                ));
+            };
+            width = width.max(right);
+            height = height.max(bottom);
+        }
+        let mut frame = Frame::new(width, height);
+        for (element, image) in layers {
+            frame.draw(&image, element.method, element.x, element.y);
+        }
+        Ok(frame)
+    }
+
+    /// シェルの画像を透過処理して読み込む
+    fn image(&self, file: &str) -> Result<Arc<Frame>, String> {
+        let key = file.to_lowercase();
+        if let Some(image) = self.images.lock().get(&key) {
+            return Ok(image.clone());
+        }
+
+        let image = Arc::new(load_image(&self.shell_dir, file)?);
+        self.images.lock().insert(key, image.clone());
+        Ok(image)
+    }
+}
+
+/// ディレクトリの画像を透過処理して読み込む（バルーンの画像にも使う）
+pub fn load_image(dir: &Path, file: &str) -> Result<Frame, String> {
+    let path = find_file(dir, file).ok_or_else(|| format!("Image not found: {}", file))?;
+    let bytes = fs::read(&path).map_err(|e| format!("Failed to read {:?}: {}", path, e))?;
+    let (mut image, has_alpha) =
+        Frame::decode_png(&bytes).map_err(|e| format!("{:?}: {}", path, e))?;
+
+    match find_file(dir, &pna_name(file)) {
+        Some(mask) => {
+            let bytes = fs::read(&mask).map_err(|e| format!("Failed to read {:?}: {}", mask, e))?;
+            let (mask, _) = Frame::decode_png(&bytes).map_err(|e| format!("{:?}: {}", mask, e))?;
+            image.apply_mask(&mask);
+        }
+        None if !has_alpha => image.apply_color_key(),
+        None => {}
+    }
+    Ok(image)
+}
+
+/// `parts/eye.png` → `parts/eye.pna`
+fn pna_name(file: &str) -> String {
+    let stem = file.rsplit_once('.').map(|(stem, _)| stem).unwrap_or(file);
+    format!("{}.pna", stem)
+}
+
+/// シェルのディレクトリ内のファイル（Windows向けのシェルのため、大文字小文字は区別しない）
+fn find_file(dir: &Path, file: &str) -> Option<PathBuf> {
+    let path = dir.join(file);
+    if path.is_file() {
+        return Some(path);
+    }
+    let parent = path.parent()?;
+    let name = path.file_name()?.to_string_lossy().to_lowercase();
+    fs::read_dir(parent)
+        .ok()?
+        .filter_map(Result::ok)
+        .find(|entry| entry.file_name().to_string_lossy().to_lowercase() == name)
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: [u8; 4] = [255, 0, 255, 255];
+    const RED: [u8; 4] = [255, 0, 0, 255];
+    const BLUE: [u8; 4] = [0, 0, 255, 255];
+
+    fn frame(width: u32, pixels: &[[u8; 4]]) -> Frame {
+        Frame {
+            width,
+            height: pixels.len() as u32 / width,
+            rgba: pixels.concat(),
+        }
+    }
+
+    fn write_png(path: &Path, frame: &Frame) {
+        fs::write(path, frame.to_png().unwrap()).unwrap();
+    }
+
+    fn write_rgb_png(path: &Path, width: u32, pixels: &[[u8; 4]]) {
+        let mut bytes = Vec::new();
+        let mut encoder = png::Encoder::new(&mut bytes, width, pixels.len() as u32 / width);
+        encoder.set_color(png::ColorType::Rgb);
+        let rgb: Vec<u8> = pixels.iter().flat_map(|p| [p[0], p[1], p[2]]).collect();
+        encoder
+            .write_header()
+            .unwrap()
+            .write_image_data(&rgb)
+            .unwrap();
+        fs::write(path, bytes).unwrap();
+    }
+
+    #[test]
+    fn test_compose_elements() {
+        let temp = tempfile::tempdir().unwrap();
+        let shell = temp.path();
+
+        // 左上の色（マゼンタ）を抜く
+        write_rgb_png(&shell.join("body.png"), 2, &[KEY, RED, RED, RED]);
+        // .pna の明るさがアルファになる
+        write_rgb_png(&shell.join("Eye.PNG"), 1, &[BLUE, BLUE]);
+        write_rgb_png(&shell.join("eye.pna"), 1, &[[255; 4], [0, 0, 0, 255]]);
+        // アルファチャンネルがあればそのまま使う
+        write_png(&shell.join("shadow.png"), &frame(1, &[[0, 0, 0, 128]]));
+
+        let definition = ShellDefinition::parse(
+            "surface0\n{\n\
+             element0,base,body.png,0,0\n\
+             element1,overlay,eye.png,1,0\n\
+             element2,interpolate,shadow.png,0,0\n\
+             element3,overlayfast,shadow.png,1,1\n\
+             }\n\
+             surface1\n{\nelement0,asis,body.png,0,0\nelement1,replace,eye.png,0,0\n}\n\
+             surface2\n{\nelement0,base,missing.png,0,0\n}\n\
+             surface4\n{\nelement0,overlay,body.png,100000000,100000000\n}\n",
+        );
+        let compositor = SurfaceCompositor::new(shell, definition);
+
+        let surface0 = compositor.compose(0).unwrap();
+        assert_eq!((surface0.width, surface0.height), (2, 2));
+        assert_eq!(
+            [0, 1, 2, 3].map(|i| surface0.pixel(i % 2, i / 2)),
+            [[0, 0, 0, 128], BLUE, RED, [127, 0, 0, 255]]
+        );
+
+        let surface1 = compositor.compose(1).unwrap();
+        assert_eq!(
+            [0, 1, 2, 3].map(|i| surface1.pixel(i % 2, i / 2)),
+            [BLUE, RED, [0, 0, 255, 0], RED]
+        );
+        assert!(compositor.compose(2).is_err());
+        assert!(compositor.compose(4).is_err());
+
+        // PNGにしても同じ画像
+        let png = compositor.png(0).unwrap();
+        assert_eq!(Frame::decode_png(&png).unwrap().0, *surface0);
+
+        // surfaces.txt に無いサーフェスは surface<ID>.png
+        write_rgb_png(&shell.join("surface3.png"), 1, &[KEY, RED]);
+        let surface3 = compositor.compose(3).unwrap();
+        assert_eq!(
+            [surface3.pixel(0, 0), surface3.pixel(1, 0)],
+            [[255, 0, 255, 0], RED]
+        );
+    }
+
+    #[test]
+    fn test_compose_mock_shell() {
+        let shell =
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("../assets/ghost/mock_nanai/shell/master");
+        let compositor = SurfaceCompositor::load(&shell).unwrap();
+
+        let surface0 = compositor.compose(0).unwrap();
+        assert_eq!((surface0.width, surface0.height), (400, 600));
+        assert_eq!(surface0.pixel(0, 0)[3], 0);
+        assert!(Arc::ptr_eq(&surface0, &compositor.compose(0).unwrap()));
+
+        // 重ねる画像が無くても、ある画像だけで合成する
+        assert_eq!(compositor.compose(1).unwrap().rgba, surface0.rgba);
+
+        assert_eq!(compositor.hit_test(0, 100, 50).as_deref(), Some("head"));
+        assert_eq!(compositor.hit_test(0, 0, 0), None);
+    }
+}
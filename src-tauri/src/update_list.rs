@@ -0,0 +1,114 @@
+//! Update List
+//!
+//! ネットワーク更新に使う updates2.dau・updates.txt（ファイルごとのMD5とサイズ）の読み書き。
+//!
+//! ```text
+//! updates2.dau   ghost/master/dic_talk.txt\x01<md5>\x01size=1234\x01\r\n
+//! updates.txt    file,ghost/master/dic_talk.txt\x01<md5>\x01size=1234\x01\r\n
+//! ```
+
+use md5::{Digest, Md5};
+
+pub const UPDATES2_DAU: &str = "updates2.dau";
+pub const UPDATES_TXT: &str = "updates.txt";
+
+const SEPARATOR: char = '\u{1}';
+
+/// 更新対象のファイル
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UpdateEntry {
+    /// ゴーストのディレクトリからの `/` 区切りのパス
+    pub path: String,
+    /// 小文字16進のMD5
+    pub md5: String,
+    pub size: Option<u64>,
+}
+
+impl UpdateEntry {
+    pub fn new(path: &str, data: &[u8]) -> Self {
+        UpdateEntry {
+            path: path.to_string(),
+            md5: md5_hex(data),
+            size: Some(data.len() as u64),
+        }
+    }
+
+    fn to_line(&self) -> String {
+        let size = self
+            .size
+            .map(|size| format!("size={}{}", size, SEPARATOR))
+            .unwrap_or_default();
+        format!(
+            "{}{}{}{}{}\r\n",
+            self.path, SEPARATOR, self.md5, SEPARATOR, size
+        )
+    }
+}
+
+/// 小文字16進のMD5
+pub fn md5_hex(data: &[u8]) -> String {
+    Md5::digest(data)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// updates2.dau の内容
+pub fn format_updates2(entries: &[UpdateEntry]) -> String {
+    entries.iter().map(UpdateEntry::to_line).collect()
+}
+
+/// updates.txt の内容（`charset` 行付き）
+pub fn format_updates_txt(entries: &[UpdateEntry]) -> String {
+    let lines: String = entries
+        .iter()
+        .map(|entry| format!("file,{}", entry.to_line()))
+        .collect();
+    format!("charset,UTF-8\r\n{}", lines)
+}
+
+/// updates2.dau・updates.txt のどちらの形式でも読む
+pub fn parse(text: &str) -> Vec<UpdateEntry> {
+    text.trim_start_matches('\u{feff}')
+        .lines()
+        .filter_map(|line| {
+            let line = line.trim_end_matches('\r');
+            // updates.txt は `file,` で始まる行だけがファイル
+            let line = match line.split_once(',') {
+                Some((key, rest)) if key.eq_ignore_ascii_case("file") => rest,
+                _ if line.contains(SEPARATOR) => line,
+                _ => return None,
+            };
+            let mut fields = line.split(SEPARATOR);
+            let path = fields.next()?.trim().replace('\\', "/");
+            let md5 = fields.next()?.trim().to_lowercase();
+            let size = fields
+                .filter_map(|field| field.trim().strip_prefix("size="))
+                .find_map(|size| size.parse().ok());
+            (!path.is_empty() && !md5.is_empty()).then_some(UpdateEntry { path, md5, size })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip_both_formats() {
+        let entries = vec![
+            UpdateEntry::new("ghost/master/descript.txt", b"name,nanai"),
+            UpdateEntry::new("shell/master/surface0.png", b""),
+        ];
+        assert_eq!(entries[1].md5, "d41d8cd98f00b204e9800998ecf8427e");
+
+        assert_eq!(parse(&format_updates2(&entries)), entries);
+        assert_eq!(parse(&format_updates_txt(&entries)), entries);
+
+        // 古い形式（`\` 区切り・サイズ無し・大文字のMD5）
+        let old = parse("file,ghost\\master\\a.txt\u{1}D41D8CD98F00B204E9800998ECF8427E\u{1}\r\n");
+        assert_eq!(old[0].path, "ghost/master/a.txt");
+        assert_eq!(old[0].md5, "d41d8cd98f00b204e9800998ecf8427e");
+        assert_eq!(old[0].size, None);
+    }
+}
@@ -0,0 +1,661 @@
+//! バルーン
+//!
+//! バルーンの descript.txt（文字の表示範囲・フォント・カーソルの色など）を読み、
+//! さくらスクリプトのテキストを表示範囲に合わせて行・ページに割り付ける。
+//!
+//! 画像は `balloons<n>.png`（\0 側）、`balloonk<n>.png`（\1 以降）、
+//! `balloonc<n>.png`（コミュニケートボックス）、`online<n>.png` を使う。
+//! `<n>` は `\b[n]` の値で、偶数が左向き・奇数が右向き。
+//! `balloons0s.txt` のように画像ごとの設定があれば descript.txt に上書きする。
+//!
+//! ```text
+//! origin.x,16                   文字の表示を始める位置
+//! validrect.right,-16           文字を表示する範囲（負の値は右端・下端から）
+//! font.height,12
+//! font.color.r,40               色は .r .g .b に分けて書く
+//! arrow0.x,-22                  スクロールの矢印
+//! ```
+
+use crate::descript::Descript;
+use crate::sakura_script::{self, SakuraToken, SakuraTokenKind};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io;
+use std::path::{Path, PathBuf};
+
+pub const DESCRIPT_TXT: &str = "descript.txt";
+
+/// 既定の文字の大きさ（ピクセル）
+pub const DEFAULT_FONT_HEIGHT: i32 = 12;
+
+/// 行の高さ（文字の大きさに対する割合）
+pub const LINE_HEIGHT_PERCENT: i32 = 125;
+
+/// 色
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+/// 位置（負の値はバルーンの右端・下端から）
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Point {
+    pub x: i32,
+    pub y: i32,
+}
+
+impl Point {
+    /// 幅 `width`・高さ `height` のバルーン上の位置
+    pub fn resolve(&self, width: i32, height: i32) -> Point {
+        Point {
+            x: from_edge(self.x, width),
+            y: from_edge(self.y, height),
+        }
+    }
+}
+
+/// 範囲（`right` `bottom` が0以下なら右端・下端から）
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rect {
+    pub left: i32,
+    pub top: i32,
+    pub right: i32,
+    pub bottom: i32,
+}
+
+impl Rect {
+    pub fn width(&self) -> i32 {
+        (self.right - self.left).max(0)
+    }
+
+    pub fn height(&self) -> i32 {
+        (self.bottom - self.top).max(0)
+    }
+}
+
+/// 負の値を右端・下端からの位置にする
+fn from_edge(value: i32, size: i32) -> i32 {
+    if value < 0 {
+        size + value
+    } else {
+        value
+    }
+}
+
+/// 文字のフォント
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BalloonFont {
+    pub name: Option<String>,
+    pub height: i32,
+    pub color: Color,
+    pub bold: bool,
+    pub italic: bool,
+    pub shadow_color: Option<Color>,
+}
+
+/// 選択肢のカーソル
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BalloonCursor {
+    /// `square` `underline` など
+    pub style: Option<String>,
+    pub pen_color: Option<Color>,
+    pub brush_color: Option<Color>,
+    /// カーソルが乗った選択肢の文字の色
+    pub font_color: Option<Color>,
+}
+
+/// バルーンの番号の表示（`number.*`）
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NumberDisplay {
+    pub font_name: Option<String>,
+    pub font_height: i32,
+    pub color: Color,
+    /// 右端から数えた位置
+    pub x_right: i32,
+    pub y: i32,
+}
+
+/// バルーンの descript.txt
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BalloonDescript {
+    pub origin: Point,
+    /// `validrect.*`（無ければ `origin` と `wordwrappoint.x` から決める）
+    pub valid_rect: Option<Rect>,
+    /// `wordwrappoint.x`（負の値は右端から）
+    pub word_wrap_point: Option<i32>,
+    pub font: BalloonFont,
+    /// アンカー（`\_a`）の文字の色
+    pub anchor_font_color: Option<Color>,
+    pub cursor: BalloonCursor,
+    /// スクロールの矢印（上・下）
+    pub arrows: [Option<Point>; 2],
+    pub sstp_marker: Option<Point>,
+    pub sstp_message: Option<Point>,
+    pub number: Option<NumberDisplay>,
+    /// すべてのキーと値
+    pub descript: Descript,
+}
+
+impl BalloonDescript {
+    /// バルーンのディレクトリの descript.txt を読み込む
+    pub fn load(balloon_dir: &Path) -> Result<Self, io::Error> {
+        Ok(Self::from_descript(Descript::load(
+            &balloon_dir.join(DESCRIPT_TXT),
+        )?))
+    }
+
+    pub fn from_descript(descript: Descript) -> Self {
+        let number = |key: &str| -> Option<i32> { descript.get(key)?.trim().parse().ok() };
+        let flag = |key: &str| number(key).is_some_and(|value| value != 0);
+        let point = |prefix: &str| -> Option<Point> {
+            Some(Point {
+                x: number(&format!("{}.x", prefix))?,
+                y: number(&format!("{}.y", prefix))?,
+            })
+        };
+        let color = |prefix: &str| -> Option<Color> {
+            let [r, g, b] = ["r", "g", "b"].map(|c| number(&format!("{}.{}", prefix, c)));
+            if r.is_none() && g.is_none() && b.is_none() {
+                return None;
+            }
+            let channel = |value: Option<i32>| value.unwrap_or(0).clamp(0, 255) as u8;
+            Some(Color {
+                r: channel(r),
+                g: channel(g),
+                b: channel(b),
+            })
+        };
+        let text = |key: &str| descript.get(key).map(str::to_string);
+
+        let valid_rect = ["left", "top", "right", "bottom"]
+            .map(|side| number(&format!("validrect.{}", side)))
+            .iter()
+            .any(Option::is_some)
+            .then(|| Rect {
+                left: number("validrect.left").unwrap_or(0),
+                top: number("validrect.top").unwrap_or(0),
+                right: number("validrect.right").unwrap_or(0),
+                bottom: number("validrect.bottom").unwrap_or(0),
+            });
+        let number_display = number("number.font.height")
+            .or(number("number.xr"))
+            .map(|_| NumberDisplay {
+                font_name: text("number.font.name"),
+                font_height: number("number.font.height").unwrap_or(DEFAULT_FONT_HEIGHT),
+                color: color("number.font.color").unwrap_or_default(),
+                x_right: number("number.xr").unwrap_or(0),
+                y: number("number.y").unwrap_or(0),
+            });
+
+        BalloonDescript {
+            origin: Point {
+                x: number("origin.x").unwrap_or(0),
+                y: number("origin.y").unwrap_or(0),
+            },
+            valid_rect,
+            word_wrap_point: number("wordwrappoint.x"),
+            font: BalloonFont {
+                name: text("font.name"),
+                height: number("font.height").unwrap_or(DEFAULT_FONT_HEIGHT),
+                color: color("font.color").unwrap_or_default(),
+                bold: flag("font.bold"),
+                italic: flag("font.italic"),
+                shadow_color: color("font.shadowcolor"),
+            },
+            anchor_font_color: color("anchor.font.color"),
+            cursor: BalloonCursor {
+                style: text("cursor.style"),
+                pen_color: color("cursor.pen.color"),
+                brush_color: color("cursor.brush.color"),
+                font_color: color("cursor.font.color"),
+            },
+            arrows: [point("arrow0"), point("arrow1")],
+            sstp_marker: point("sstpmarker"),
+            sstp_message: point("sstpmessage"),
+            number: number_display,
+            descript,
+        }
+    }
+
+    /// 行の高さ
+    pub fn line_height(&self) -> i32 {
+        self.font.height * LINE_HEIGHT_PERCENT / 100
+    }
+
+    /// 幅 `width`・高さ `height` のバルーンで文字を表示する範囲
+    pub fn text_rect(&self, width: i32, height: i32) -> Rect {
+        match self.valid_rect {
+            Some(rect) => Rect {
+                left: rect.left,
+                top: rect.top,
+                right: if rect.right <= 0 {
+                    width + rect.right
+                } else {
+                    rect.right
+                },
+                bottom: if rect.bottom <= 0 {
+                    height + rect.bottom
+                } else {
+                    rect.bottom
+                },
+            },
+            None => Rect {
+                left: self.origin.x,
+                top: self.origin.y,
+                right: self
+                    .word_wrap_point
+                    .map_or(width - self.origin.x, |x| from_edge(x, width)),
+                bottom: height - self.origin.y,
+            },
+        }
+    }
+}
+
+/// バルーンの画像の種類
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum BalloonImage {
+    /// スコープごとのバルーン（`id` は `\b[n]`、`right` なら右向き）
+    Character { scope: u32, id: u32, right: bool },
+    /// コミュニケートボックス
+    Communicate { id: u32 },
+    /// オンラインの表示
+    Online { id: u32 },
+}
+
+impl BalloonImage {
+    /// 探すファイル名（先にあるものほど近い）
+    pub fn candidates(&self) -> Vec<String> {
+        let (prefix, indexes) = match *self {
+            BalloonImage::Character { scope, id, right } => {
+                let index = id - id % 2 + u32::from(right);
+                let prefix = if scope == 0 { "balloons" } else { "balloonk" };
+                // 向きが無ければ反対向き、サイズが無ければ \b[0] の画像
+                (prefix, vec![index, index ^ 1, u32::from(right), 0])
+            }
+            BalloonImage::Communicate { id } => ("balloonc", vec![id, 0]),
+            BalloonImage::Online { id } => ("online", vec![id, 0]),
+        };
+        let mut files: Vec<String> = Vec::new();
+        for index in indexes {
+            let file = format!("{}{}.png", prefix, index);
+            if !files.contains(&file) {
+                files.push(file);
+            }
+        }
+        files
+    }
+}
+
+/// バルーンのディレクトリ
+#[derive(Debug, Clone)]
+pub struct Balloon {
+    pub dir: PathBuf,
+    pub descript: BalloonDescript,
+    /// ディレクトリ内のファイル（小文字 → 実際の名前）
+    files: BTreeMap<String, String>,
+}
+
+impl Balloon {
+    pub fn load(dir: &Path) -> Result<Self, io::Error> {
+        let descript = BalloonDescript::load(dir)?;
+        let files = std::fs::read_dir(dir)?
+            .filter_map(Result::ok)
+            .filter(|entry| entry.path().is_file())
+            .map(|entry| entry.file_name().to_string_lossy().to_string());
+        Ok(Self::new(dir, descript, files))
+    }
+
+    pub fn new(
+        dir: &Path,
+        descript: BalloonDescript,
+        files: impl IntoIterator<Item = String>,
+    ) -> Self {
+        Balloon {
+            dir: dir.to_path_buf(),
+            descript,
+            files: files
+                .into_iter()
+                .map(|file| (file.to_lowercase(), file))
+                .collect(),
+        }
+    }
+
+    /// descript.txt の `name`
+    pub fn name(&self) -> Option<&str> {
+        self.descript.descript.name.as_deref()
+    }
+
+    /// ディレクトリ内のファイルの実際の名前（大文字小文字は区別しない）
+    pub fn file(&self, file: &str) -> Option<&str> {
+        self.files.get(&file.to_lowercase()).map(String::as_str)
+    }
+
+    /// 画像のファイル名（無ければ近いものを使う）
+    pub fn resolve(&self, image: BalloonImage) -> Option<&str> {
+        image
+            .candidates()
+            .iter()
+            .find_map(|candidate| self.file(candidate))
+    }
+
+    /// 画像の設定（`balloons0s.txt` などがあれば descript.txt に上書きする）
+    pub fn descript_for(&self, image_file: &str) -> Result<BalloonDescript, io::Error> {
+        let stem = image_file
+            .rsplit_once('.')
+            .map_or(image_file, |(stem, _)| stem);
+        let Some(file) = self.file(&format!("{}s.txt", stem)) else {
+            return Ok(self.descript.clone());
+        };
+        let variant = Descript::load(&self.dir.join(file))?;
+        let mut descript = self.descript.descript.clone();
+        descript.entries.extend(variant.entries);
+        Ok(BalloonDescript::from_descript(descript))
+    }
+}
+
+/// 文字の幅を測る
+pub trait TextMeasure {
+    fn char_width(&self, c: char) -> i32;
+}
+
+/// 全角を文字の高さ、半角をその半分の幅とみなす（テストや概算用）
+#[derive(Debug, Clone, Copy)]
+pub struct MonospaceMeasure {
+    pub font_height: i32,
+}
+
+impl TextMeasure for MonospaceMeasure {
+    fn char_width(&self, c: char) -> i32 {
+        if c.is_ascii() || ('\u{ff61}'..='\u{ff9f}').contains(&c) {
+            (self.font_height + 1) / 2
+        } else {
+            self.font_height
+        }
+    }
+}
+
+/// 割り付けた1行
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LayoutLine {
+    pub text: String,
+    /// 表示範囲の上端からの位置
+    pub y: i32,
+    pub width: i32,
+}
+
+/// バルーンへの文字の割り付け
+///
+/// `\n` で1行、`\n[half]` で半行、`\n[percent]` で指定した割合だけ行を送り、
+/// 範囲の右端を越える文字は折り返す。下端を越えた分は `overflow` で分かる。
+#[derive(Debug, Clone)]
+pub struct BalloonLayout<M: TextMeasure> {
+    measure: M,
+    width: i32,
+    height: i32,
+    line_height: i32,
+    lines: Vec<LayoutLine>,
+}
+
+impl<M: TextMeasure> BalloonLayout<M> {
+    /// `rect` の範囲に、1行 `line_height` ピクセルで割り付ける
+    pub fn new(measure: M, rect: Rect, line_height: i32) -> Self {
+        BalloonLayout {
+            measure,
+            width: rect.width(),
+            height: rect.height(),
+            line_height: line_height.max(1),
+            lines: vec![LayoutLine::default()],
+        }
+    }
+
+    pub fn lines(&self) -> &[LayoutLine] {
+        &self.lines
+    }
+
+    /// 文字を足す（範囲の右端を越えたら折り返す）
+    pub fn push_text(&mut self, text: &str) {
+        for c in text.chars() {
+            let width = self.measure.char_width(c);
+            let line = self.lines.last().expect("layout always has a line");
+            if line.width > 0 && line.width + width > self.width {
+                self.new_line(100);
+            }
+            let line = self.lines.last_mut().expect("layout always has a line");
+            line.text.push(c);
+            line.width += width;
+        }
+    }
+
+    /// 行の高さの `percent` パーセントだけ送って次の行にする
+    ///
+    /// `percent` はスクリプトに書かれたままなので、大きすぎても溢れないようにする。
+    pub fn new_line(&mut self, percent: u32) {
+        let y = self.lines.last().map_or(0, |line| line.y);
+        let step = i64::from(self.line_height) * i64::from(percent) / 100;
+        self.lines.push(LayoutLine {
+            y: y.saturating_add(step.clamp(0, i64::from(i32::MAX)) as i32),
+            ..LayoutLine::default()
+        });
+    }
+
+    /// `\c`
+    pub fn clear(&mut self) {
+        self.lines = vec![LayoutLine::default()];
+    }
+
+    /// 最後の行が下端を越えた高さ（ピクセル、収まっていれば `None`）
+    ///
+    /// 自動スクロールならこの分だけ上に送る。
+    pub fn overflow(&self) -> Option<i32> {
+        let bottom = self
+            .lines
+            .last()
+            .map_or(0, |line| line.y)
+            .saturating_add(self.line_height);
+        (bottom > self.height).then_some(bottom - self.height)
+    }
+
+    /// 範囲に収まるように行をページに分ける（`\x` などで送る場合）
+    pub fn pages(&self) -> Vec<Vec<LayoutLine>> {
+        let mut pages: Vec<Vec<LayoutLine>> = Vec::new();
+        let mut top = 0;
+        for line in &self.lines {
+            let fits = (line.y - top).saturating_add(self.line_height) <= self.height;
+            match pages.last_mut() {
+                Some(page) if fits => page.push(LayoutLine {
+                    y: line.y - top,
+                    ..line.clone()
+                }),
+                _ => {
+                    top = line.y;
+                    pages.push(vec![LayoutLine {
+                        y: 0,
+                        ..line.clone()
+                    }]);
+                }
+            }
+        }
+        pages
+    }
+
+    /// トークン列のうち `scope` のキャラクターの分を割り付ける
+    pub fn push_tokens(&mut self, tokens: &[SakuraToken], scope: u32) {
+        let mut current = 0;
+        for token in tokens {
+            match &token.kind {
+                SakuraTokenKind::Scope(n) => current = *n,
+                _ if current != scope => {}
+                SakuraTokenKind::Text(text) => self.push_text(text),
+                SakuraTokenKind::Variable { .. } => self.push_text(&token.raw),
+                SakuraTokenKind::Choice { title, .. } => self.push_text(title),
+                SakuraTokenKind::NewLine => self.new_line(100),
+                SakuraTokenKind::HalfNewLine => self.new_line(50),
+                SakuraTokenKind::NewLinePercent(percent) => self.new_line(*percent),
+                SakuraTokenKind::ClearBalloon => self.clear(),
+                SakuraTokenKind::End => break,
+                _ => {}
+            }
+        }
+    }
+
+    /// スクリプトを割り付ける
+    pub fn push_script(&mut self, script: &str, scope: u32) {
+        self.push_tokens(&sakura_script::parse(script), scope);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_nanai_std() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("../assets/balloon/nanai_std");
+        let balloon = BalloonDescript::load(&dir).unwrap();
+
+        assert_eq!(balloon.descript.name.as_deref(), Some("なない標準"));
+        assert_eq!(balloon.font.height, 12);
+        assert_eq!(
+            balloon.font.color,
+            Color {
+                r: 40,
+                g: 40,
+                b: 40
+            }
+        );
+        assert_eq!(
+            balloon.anchor_font_color,
+            Some(Color {
+                r: 0,
+                g: 80,
+                b: 200
+            })
+        );
+        assert_eq!(balloon.cursor.style.as_deref(), Some("square"));
+        assert_eq!(
+            balloon.text_rect(240, 160),
+            Rect {
+                left: 16,
+                top: 14,
+                right: 224,
+                bottom: 134,
+            }
+        );
+        assert_eq!(
+            balloon.arrows[1].map(|arrow| arrow.resolve(240, 160)),
+            Some(Point { x: 218, y: 126 })
+        );
+        assert_eq!(
+            balloon.number.as_ref().map(|number| number.x_right),
+            Some(-12)
+        );
+    }
+
+    #[test]
+    fn test_old_descript_uses_origin_and_wordwrappoint() {
+        let balloon = BalloonDescript::from_descript(Descript::parse(
+            "origin.x,10\norigin.y,8\nwordwrappoint.x,-20\nfont.color.r,300\n",
+        ));
+        assert_eq!(balloon.valid_rect, None);
+        assert_eq!(
+            balloon.text_rect(200, 100),
+            Rect {
+                left: 10,
+                top: 8,
+                right: 180,
+                bottom: 92,
+            }
+        );
+        assert_eq!(balloon.font.color, Color { r: 255, g: 0, b: 0 });
+        assert_eq!(balloon.font.height, DEFAULT_FONT_HEIGHT);
+        assert_eq!(balloon.number, None);
+    }
+
+    #[test]
+    fn test_resolve_balloon_images() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        std::fs::write(dir.join(DESCRIPT_TXT), "name,test\nfont.height,12\n").unwrap();
+        std::fs::write(dir.join("balloons2s.txt"), "font.height,20\n").unwrap();
+        for file in [
+            "balloons0.png",
+            "Balloons1.PNG",
+            "balloons2.png",
+            "balloonk0.png",
+        ] {
+            std::fs::write(dir.join(file), []).unwrap();
+        }
+        let balloon = Balloon::load(dir).unwrap();
+        let character = |scope, id, right| BalloonImage::Character { scope, id, right };
+
+        assert_eq!(
+            balloon.resolve(character(0, 0, true)),
+            Some("Balloons1.PNG")
+        );
+        // \b[2] の右向きが無ければ左向き、\b[4] が無ければ \b[0]
+        assert_eq!(
+            balloon.resolve(character(0, 2, true)),
+            Some("balloons2.png")
+        );
+        assert_eq!(
+            balloon.resolve(character(0, 4, true)),
+            Some("Balloons1.PNG")
+        );
+        assert_eq!(
+            balloon.resolve(character(1, 1, false)),
+            Some("balloonk0.png")
+        );
+        assert_eq!(balloon.resolve(BalloonImage::Communicate { id: 0 }), None);
+
+        assert_eq!(
+            balloon.descript_for("balloons2.png").unwrap().font.height,
+            20
+        );
+        let descript = balloon.descript_for("balloons0.png").unwrap();
+        assert_eq!(descript.font.height, 12);
+        assert_eq!(descript.descript.name.as_deref(), Some("test"));
+    }
+
+    #[test]
+    fn test_layout_wraps_and_overflows() {
+        // 全角10ピクセルで5文字、3行分の範囲
+        let rect = Rect {
+            left: 0,
+            top: 0,
+            right: 50,
+            bottom: 36,
+        };
+        let mut layout = BalloonLayout::new(MonospaceMeasure { font_height: 10 }, rect, 12);
+        layout.push_script("\\0あいうえおかき\\n[half]ab\\1無視\\0\\n[150]く", 0);
+
+        let lines: Vec<(&str, i32)> = layout
+            .lines()
+            .iter()
+            .map(|line| (line.text.as_str(), line.y))
+            .collect();
+        assert_eq!(
+            lines,
+            vec![("あいうえお", 0), ("かき", 12), ("ab", 18), ("く", 36)]
+        );
+        assert_eq!(layout.overflow(), Some(12));
+        assert_eq!(
+            layout
+                .pages()
+                .iter()
+                .map(|page| page.iter().map(|line| line.y).collect::<Vec<_>>())
+                .collect::<Vec<_>>(),
+            vec![vec![0, 12, 18], vec![0]]
+        );
+
+        layout.push_script("\\c短い", 0);
+        assert_eq!(layout.lines().len(), 1);
+        assert_eq!(layout.overflow(), None);
+        // 大きすぎる改行幅でも溢れない
+        layout.push_script(&"\\n[4000000000]".repeat(5), 0);
+        assert_eq!(layout.lines().last().unwrap().y, i32::MAX);
+        assert_eq!(layout.overflow(), Some(i32::MAX - 36));
+    }
+}
@@ -0,0 +1,273 @@
+//! descript.txt
+//!
+//! ゴースト・シェル・バルーンに共通の `key,value` 形式の設定ファイル。
+//! `charset` 行を先に読んでから全体を変換するので、Shift_JIS 以外のファイルも読める。
+
+use encoding_rs::{Encoding, SHIFT_JIS, UTF_8};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io;
+use std::path::Path;
+
+/// 型付きのフィールドとして読み出すキー
+const KNOWN_KEYS: [&str; 18] = [
+    "type",
+    "name",
+    "id",
+    "sakura.name",
+    "sakura.name2",
+    "kero.name",
+    "craftman",
+    "craftmanw",
+    "craftmanurl",
+    "homeurl",
+    "shiori",
+    "charset",
+    "icon",
+    "balloon",
+    "shell",
+    "sakura.seriko.defaultsurface",
+    "kero.seriko.defaultsurface",
+    "seriko.alignmenttodesktop",
+];
+
+/// descript.txt の内容
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Descript {
+    /// `type`（ghost / shell / balloon など）
+    pub kind: Option<String>,
+    pub name: Option<String>,
+    pub id: Option<String>,
+    pub sakura_name: Option<String>,
+    pub sakura_name2: Option<String>,
+    pub kero_name: Option<String>,
+    pub craftman: Option<String>,
+    pub craftmanw: Option<String>,
+    pub craftmanurl: Option<String>,
+    pub homeurl: Option<String>,
+    pub shiori: Option<String>,
+    pub charset: Option<String>,
+    pub icon: Option<String>,
+    /// 既定のバルーン
+    pub balloon: Option<String>,
+    /// 既定のシェル（省略時は master）
+    pub default_shell: Option<String>,
+    /// `sakura.seriko.defaultsurface`
+    pub sakura_default_surface: Option<i32>,
+    /// `kero.seriko.defaultsurface`
+    pub kero_default_surface: Option<i32>,
+    /// `seriko.alignmenttodesktop`（top / bottom / free）
+    pub alignment_to_desktop: Option<String>,
+    /// すべてのキーと値（キーは小文字、重複したキーは後の値）
+    pub entries: BTreeMap<String, String>,
+}
+
+/// シェルの着せ替え（`sakura.bindgroupN.name,カテゴリ,パーツ`）
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BindGroup {
+    /// キャラクター（0がsakura、1がkero、`charN` はN）
+    pub scope: u32,
+    /// アニメーションID
+    pub id: u32,
+    pub category: String,
+    pub part: String,
+    /// `.default,1` なら最初から付けている
+    pub default: bool,
+}
+
+impl Descript {
+    /// ファイルを読み込む
+    pub fn load(path: &Path) -> Result<Self, io::Error> {
+        Ok(Self::from_bytes(&std::fs::read(path)?))
+    }
+
+    /// `charset` 行に従って変換してから読み込む（指定が無ければ Shift_JIS）
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        let (text, _, _) = detect_encoding(bytes).decode(bytes);
+        Self::parse(&text)
+    }
+
+    /// 変換済みのテキストを読み込む
+    pub fn parse(text: &str) -> Self {
+        let mut entries = BTreeMap::new();
+        for line in text.trim_start_matches('\u{feff}').lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with("//") || line.starts_with('#') {
+                continue;
+            }
+            if let Some((key, value)) = line.split_once(',') {
+                let key = key.trim().to_lowercase();
+                if !key.is_empty() {
+                    entries.insert(key, value.trim().to_string());
+                }
+            }
+        }
+
+        let text = |key: &str| entries.get(key).cloned();
+        let number = |key: &str| entries.get(key).and_then(|value| value.parse().ok());
+        Descript {
+            kind: text("type"),
+            name: text("name"),
+            id: text("id"),
+            sakura_name: text("sakura.name"),
+            sakura_name2: text("sakura.name2"),
+            kero_name: text("kero.name"),
+            craftman: text("craftman"),
+            craftmanw: text("craftmanw"),
+            craftmanurl: text("craftmanurl"),
+            homeurl: text("homeurl"),
+            shiori: text("shiori"),
+            charset: text("charset"),
+            icon: text("icon"),
+            balloon: text("balloon"),
+            default_shell: text("shell"),
+            sakura_default_surface: number("sakura.seriko.defaultsurface"),
+            kero_default_surface: number("kero.seriko.defaultsurface"),
+            alignment_to_desktop: text("seriko.alignmenttodesktop"),
+            entries,
+        }
+    }
+
+    /// 任意のキーの値
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.entries.get(&key.to_lowercase()).map(String::as_str)
+    }
+
+    /// シェルの着せ替えの一覧（キャラクター、IDの順）
+    pub fn bind_groups(&self) -> Vec<BindGroup> {
+        let mut groups: Vec<BindGroup> = self
+            .entries
+            .iter()
+            .filter_map(|(key, value)| {
+                let (character, rest) = key.split_once(".bindgroup")?;
+                let id = rest.strip_suffix(".name")?.parse().ok()?;
+                let scope = match character {
+                    "sakura" => 0,
+                    "kero" => 1,
+                    _ => character.strip_prefix("char")?.parse().ok()?,
+                };
+                let mut names = value.split(',').map(str::trim);
+                let category = names.next()?.to_string();
+                let part = names.next().unwrap_or_default().to_string();
+                let default = self
+                    .get(&format!("{}.bindgroup{}.default", character, id))
+                    .is_some_and(|value| value.trim() == "1");
+                Some(BindGroup {
+                    scope,
+                    id,
+                    category,
+                    part,
+                    default,
+                })
+            })
+            .collect();
+        groups.sort_by_key(|group| (group.scope, group.id));
+        groups
+    }
+
+    /// 型付きのフィールドに無いキー
+    pub fn unknown(&self) -> BTreeMap<&str, &str> {
+        self.entries
+            .iter()
+            .filter(|(key, _)| !KNOWN_KEYS.contains(&key.as_str()))
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+            .collect()
+    }
+}
+
+/// BOM と `charset` 行から文字コードを決める
+pub(crate) fn detect_encoding(bytes: &[u8]) -> &'static Encoding {
+    if let Some((encoding, _)) = Encoding::for_bom(bytes) {
+        return encoding;
+    }
+
+    // `charset,` はASCIIなので、変換前のバイト列のまま探せる
+    for line in bytes.split(|b| *b == b'\n') {
+        let line = String::from_utf8_lossy(line);
+        let Some((key, value)) = line.trim().split_once(',') else {
+            continue;
+        };
+        if key.trim().eq_ignore_ascii_case("charset") {
+            let label = value.trim();
+            if label.eq_ignore_ascii_case("UTF-8") {
+                return UTF_8;
+            }
+            return Encoding::for_label(label.as_bytes()).unwrap_or(SHIFT_JIS);
+        }
+    }
+    SHIFT_JIS
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_known_and_unknown_keys() {
+        let descript = Descript::parse(
+            "\u{feff}// コメント\r\ncharset,UTF-8\r\ntype,ghost\r\nName,ナナイ\r\nsakura.name,ナナイ\r\nkero.name,モック\r\nsakura.seriko.defaultsurface,5\r\nseriko.alignmenttodesktop,bottom\r\nhomeurl,http://example.com/,x\r\nname,ナナイ2\r\nsakura.balloon.offsetx,10\r\n壊れた行\r\n",
+        );
+        assert_eq!(descript.kind.as_deref(), Some("ghost"));
+        // 重複したキーは後の値、値の中のカンマはそのまま
+        assert_eq!(descript.name.as_deref(), Some("ナナイ2"));
+        assert_eq!(descript.homeurl.as_deref(), Some("http://example.com/,x"));
+        assert_eq!(descript.sakura_default_surface, Some(5));
+        assert_eq!(descript.alignment_to_desktop.as_deref(), Some("bottom"));
+        assert_eq!(descript.get("Sakura.Balloon.OffsetX"), Some("10"));
+        assert_eq!(
+            descript.unknown().into_iter().collect::<Vec<_>>(),
+            vec![("sakura.balloon.offsetx", "10")]
+        );
+    }
+
+    #[test]
+    fn test_bind_groups() {
+        let descript = Descript::parse(
+            "sakura.bindgroup20.name,頭,リボン,thumb.png\nsakura.bindgroup20.default,1\n\
+             kero.bindgroup3.name,手,傘\nchar2.bindgroup1.name,服,制服\nsakura.bindgroupx.name,壊れた\n",
+        );
+        let groups: Vec<_> = descript
+            .bind_groups()
+            .into_iter()
+            .map(|group| {
+                (
+                    group.scope,
+                    group.id,
+                    group.category,
+                    group.part,
+                    group.default,
+                )
+            })
+            .collect();
+        assert_eq!(
+            groups,
+            vec![
+                (0, 20, "頭".to_string(), "リボン".to_string(), true),
+                (1, 3, "手".to_string(), "傘".to_string(), false),
+                (2, 1, "服".to_string(), "制服".to_string(), false),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_charset_line_selects_encoding() {
+        let (sjis, _, _) = SHIFT_JIS.encode("charset,Shift_JIS\r\nsakura.name,さくら\r\n");
+        assert_eq!(
+            Descript::from_bytes(&sjis).sakura_name.as_deref(),
+            Some("さくら")
+        );
+
+        let utf8 = "sakura.name,さくら\r\ncharset,UTF-8\r\n".as_bytes();
+        assert_eq!(
+            Descript::from_bytes(utf8).sakura_name.as_deref(),
+            Some("さくら")
+        );
+
+        // 指定が無ければ Shift_JIS
+        let (sjis, _, _) = SHIFT_JIS.encode("kero.name,うにゅう\r\n");
+        assert_eq!(
+            Descript::from_bytes(&sjis).kero_name.as_deref(),
+            Some("うにゅう")
+        );
+    }
+}
@@ -1,4 +1,16 @@
+pub mod balloon;
+pub mod descript;
+pub mod sakura_choice;
+pub mod sakura_env;
+pub mod sakura_player;
+pub mod sakura_script;
+pub mod seriko;
+pub mod surfaces;
+
 use encoding_rs::SHIFT_JIS;
+use sakura_choice::SakuraChoice;
+use sakura_env::VariableProvider;
+use sakura_script::{SakuraToken, SakuraTokenKind};
 use std::fs::File;
 use std::io::{self, Read};
 use std::path::Path;
@@ -19,52 +31,55 @@ pub fn open_shift_jis_file(path: &str) -> Result<String, io::Error> {
     Ok(cow.into_owned())
 }
 
-/// さくらスクリプトの簡易パーサと実行（内部コマンド）
-pub fn execute_sakura_script(script: &str, mut callback: impl FnMut(SakuraCommand)) {
-    // 例: "\0\s[0]こんにちは\e" → [SakuraCommand::Surface(0), SakuraCommand::Text("こんにちは"), SakuraCommand::End]
-    let mut chars = script.chars().peekable();
-    let mut buffer = String::new();
-    while let Some(c) = chars.next() {
-        if c == '\\' {
-            // コマンド開始
-            if !buffer.is_empty() {
-                callback(SakuraCommand::Text(buffer.clone()));
-                buffer.clear();
-            }
-            match chars.next() {
-                Some('0') => callback(SakuraCommand::Target(0)),
-                Some('1') => callback(SakuraCommand::Target(1)),
-                Some('s') => {
-                    if chars.next() == Some('[') {
-                        let mut num = String::new();
-                        while let Some(&nc) = chars.peek() {
-                            if nc == ']' { chars.next(); break; }
-                            num.push(nc); chars.next();
-                        }
-                        if let Ok(n) = num.parse() {
-                            callback(SakuraCommand::Surface(n));
-                        }
-                    }
-                },
-                Some('e') => callback(SakuraCommand::End),
-                Some(_) | None => {},
+/// さくらスクリプトを解釈して内部コマンドを順に呼び出す
+pub fn execute_sakura_script(script: &str, callback: impl FnMut(SakuraCommand)) {
+    execute_tokens(sakura_script::parse(script), callback);
+}
+
+/// 環境変数を展開してから `execute_sakura_script` と同様に実行する
+pub fn execute_sakura_script_with(
+    script: &str,
+    variables: &dyn VariableProvider,
+    callback: impl FnMut(SakuraCommand),
+) {
+    let tokens = sakura_env::expand(&sakura_script::parse(script), variables);
+    execute_tokens(tokens, callback);
+}
+
+fn execute_tokens(tokens: Vec<SakuraToken>, mut callback: impl FnMut(SakuraCommand)) {
+    // 例: "\0\s[0]こんにちは\e" → [SakuraCommand::Target(0), SakuraCommand::Surface(0), SakuraCommand::Text("こんにちは"), SakuraCommand::End]
+    let mut choices = sakura_choice::collect(&tokens).into_iter();
+    for token in tokens {
+        if sakura_choice::is_choice_token(&token.kind) {
+            if let Some(choice) = choices.next() {
+                callback(SakuraCommand::Choice(choice));
             }
-        } else {
-            buffer.push(c);
+            continue;
+        }
+        match token.kind {
+            SakuraTokenKind::Text(text) => callback(SakuraCommand::Text(text)),
+            // Target に収まらないスコープ（\p[256] など）は切り替えない
+            SakuraTokenKind::Scope(n) => match u8::try_from(n) {
+                Ok(scope) => callback(SakuraCommand::Target(scope)),
+                Err(_) => println!("⚠️ Scope out of range: {}", token.raw),
+            },
+            SakuraTokenKind::Surface(n) if n >= 0 => callback(SakuraCommand::Surface(n as u32)),
+            SakuraTokenKind::End => callback(SakuraCommand::End),
+            // 値の無い環境変数はそのまま表示する
+            SakuraTokenKind::Variable { .. } => callback(SakuraCommand::Text(token.raw)),
+            _ => {}
         }
-    }
-    if !buffer.is_empty() {
-        callback(SakuraCommand::Text(buffer));
     }
 }
 
 /// さくらスクリプトの内部コマンド列挙
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum SakuraCommand {
-    Target(u8),      // \0, \1 など
-    Surface(u32),    // \s[0] など
-    Text(String),    // 通常テキスト
-    End,             // \e
+    Target(u8),           // \0, \1 など
+    Surface(u32),         // \s[0] など
+    Text(String),         // 通常テキスト
+    Choice(SakuraChoice), // \q[タイトル,ID], \__q[ID], \_a[ID]
+    End,                  // \e
 }
 
 #[cfg(test)]
@@ -79,4 +94,17 @@ mod tests {
             println!("Content: {}", content);
         }
     }
+
+    #[test]
+    fn test_out_of_range_scope_is_skipped() {
+        let mut commands = Vec::new();
+        execute_sakura_script("\\1\\p[256]あ", |command| commands.push(command));
+        assert_eq!(
+            commands,
+            vec![
+                SakuraCommand::Target(1),
+                SakuraCommand::Text("あ".to_string())
+            ]
+        );
+    }
 }
@@ -8,7 +8,18 @@ class MascotNanaiApp {
     this.isInitialized = false;
     this.ghosts = [];
     this.currentGhost = null;
-    this.currentBalloon = "default";
+    // 表示中のサーフェス（当たり判定に使う）
+    this.currentSurface = 0;
+    // アニメーションごとに重ねるパターン（seriko-event）
+    this.animationFrames = {};
+    // シェルが更新されたら変えて、サーフェスの画像を読み込み直す
+    this.shellVersion = Date.now();
+    // ユーザーが選んだバルーン（null ならゴーストの既定）
+    this.currentBalloon = localStorage.getItem("mascot-nanai-balloon");
+    // 表示中のバルーンの画像と文字を置く範囲（get_balloon_metrics）
+    this.balloonMetrics = null;
+    // バルーンを選び直したら変えて、画像を読み込み直す
+    this.balloonVersion = Date.now();
     this.settings = {
       autoLoadGhost: true,
       enableNotifications: true,
@@ -16,6 +27,8 @@ class MascotNanaiApp {
       alwaysOnTop: true,
       ghostSize: "medium",
       debugLevel: "info",
+      // %username に使う名前（空ならSHIORIの既定）
+      userName: "",
     };
 
     this.init();
@@ -35,6 +48,8 @@ class MascotNanaiApp {
       // イベントリスナーの設定
       console.log("イベントリスナー設定中...");
       this.setupEventListeners();
+      await this.listenSakuraEvents();
+      await this.listenGhostEvents();
       console.log("イベントリスナー設定完了");
 
       // 設定読み込み
@@ -54,6 +69,9 @@ class MascotNanaiApp {
         console.log("自動ゴースト読み込み完了");
       }
 
+      // 選んであったバルーンを使う
+      await this.setBalloon(this.currentBalloon);
+
       // 初期化完了
       this.isInitialized = true;
       console.log("=== Mascot Nanai 初期化完了 ===");
@@ -80,6 +98,7 @@ class MascotNanaiApp {
       connectionStatus: document.getElementById("connection-status"),
       ghostCharacter: document.getElementById("ghost-character"),
       balloonDisplay: document.getElementById("balloon-display"),
+      balloon: document.querySelector("#balloon-display .balloon"),
       balloonText: document.getElementById("balloon-text"),
 
       // モーダル要素
@@ -135,7 +154,7 @@ class MascotNanaiApp {
         ghostPath: ghostInfo.path || "assets/ghost",
       });
 
-      return result.ghosts?.some((g) => g.name === ghostInfo.name) || false;
+      return result.ghosts?.some((g) => g.id === ghostInfo.id) || false;
     } catch (error) {
       console.error("ゴースト存在確認エラー:", error);
       return false;
@@ -168,7 +187,7 @@ class MascotNanaiApp {
           defaultGhost = this.ghosts[0];
         }
 
-        this.selectGhost(defaultGhost.name);
+        this.selectGhost(defaultGhost.id);
         console.log(`🎯 「${defaultGhost.name}」を自動選択`);
       } else {
         this.updateStatus("ゴーストが見つかりません", false);
@@ -218,9 +237,27 @@ class MascotNanaiApp {
     // 旧メニューアイテム（削除済みのため、コメントアウト）
     // これらのイベントリスナーはiframeメニューで処理される
 
-    // ゴーストキャラクターのクリック
-    this.elements.ghostCharacter?.addEventListener("click", () => {
-      this.onGhostClick();
+    // ゴーストキャラクターのマウス操作（当たり判定はRust側で調べる）
+    const ghostCharacter = this.elements.ghostCharacter;
+    const mouseActions = {
+      click: "click",
+      dblclick: "doubleClick",
+      pointerdown: "down",
+      pointerup: "up",
+      pointermove: "move",
+      pointerleave: "leave",
+      wheel: "wheel",
+    };
+    for (const [type, action] of Object.entries(mouseActions)) {
+      ghostCharacter?.addEventListener(type, (e) => this.onGhostMouse(action, e));
+    }
+
+    // バルーンのクリックでクリック待ちを解除する
+    this.elements.balloonDisplay?.addEventListener("click", (e) => {
+      e.stopPropagation();
+      globalThis.__TAURI__?.core?.invoke("sakura_click").catch((error) => {
+        console.log("クリック待ちの解除エラー:", error);
+      });
     });
 
     // 右クリックでiframeメニュー表示
@@ -237,6 +274,11 @@ class MascotNanaiApp {
       }
     });
 
+    // 画面の大きさが変わったら %screenwidth などを更新する
+    globalThis.addEventListener("resize", () => {
+      this.updateEnvironmentSettings();
+    });
+
     // キーボードショートカット
     document.addEventListener("keydown", (e) => {
       if (e.key === "Escape") {
@@ -253,19 +295,66 @@ class MascotNanaiApp {
   // ゴースト・バルーン表示機能
   // ===========================================
 
-  onGhostClick() {
-    // ゴーストクリック時の動作
-    if (this.currentGhost) {
-      // Tauri側のマウスクリックイベントも送信
-      this.notifyMouseClick();
-      // SHIORI経由でマウスクリックイベント送信
-      this.sendShioriEvent("OnMouseClick");
-    } else {
-      // ゴーストが選択されていない場合は何もしない
-      console.log("ゴーストが選択されていません");
+  /**
+   * ゴーストのマウス操作をSHIORIに送る（OnMouseClick など）
+   * @param {string} action - click, doubleClick, down, up, move, leave, wheel
+   * @param {PointerEvent|WheelEvent} event
+   */
+  async onGhostMouse(action, event) {
+    if (!this.currentGhost) {
+      if (action === "click") console.log("ゴーストが選択されていません");
+      return;
+    }
+    // 移動は50msごとに間引く
+    if (action === "move") {
+      const now = Date.now();
+      if (now - (this.lastMouseMove || 0) < 50) return;
+      this.lastMouseMove = now;
+    }
+    const invoke = globalThis.__TAURI__?.core?.invoke;
+    const point = this.surfacePoint(event);
+    if (!invoke || !point) return;
+
+    try {
+      await invoke("mouse_event", {
+        input: {
+          action,
+          scope: 0,
+          surface: this.currentSurface,
+          x: point.x,
+          y: point.y,
+          wheel: action === "wheel" ? -Math.sign(event.deltaY) * 120 : 0,
+          // DOMは0:左 1:中 2:右、SHIORIは0:左 1:右 2:中
+          button: [0, 2, 1][event.button] ?? 0,
+          device: event.pointerType === "touch" ? "touch" : "mouse",
+        },
+      });
+    } catch (error) {
+      console.log("マウスイベントエラー:", error);
     }
   }
 
+  /**
+   * マウスの位置をサーフェスの画像上の座標にする（object-fit: contain の余白を除く）
+   * @param {MouseEvent} event
+   * @returns {{x: number, y: number}|null} 画像が無ければnull
+   */
+  surfacePoint(event) {
+    const image = this.elements.ghostCharacter?.querySelector("img");
+    if (!image || !image.naturalWidth) return null;
+    const rect = image.getBoundingClientRect();
+    const scale = Math.min(
+      rect.width / image.naturalWidth,
+      rect.height / image.naturalHeight
+    );
+    const left = rect.left + (rect.width - image.naturalWidth * scale) / 2;
+    const top = rect.top + (rect.height - image.naturalHeight * scale) / 2;
+    return {
+      x: Math.floor((event.clientX - left) / scale),
+      y: Math.floor((event.clientY - top) / scale),
+    };
+  }
+
   startSecondTimer() {
     // 1秒間隔でOnSecondChangeイベントを送信
     this.secondTimer = setInterval(async () => {
@@ -286,32 +375,14 @@ class MascotNanaiApp {
     }
   }
 
-  async notifyMouseClick() {
-    try {
-      await globalThis.__TAURI__.invoke("on_mouse_click");
-      console.log("🖱️ マウスクリック通知送信");
-    } catch (error) {
-      console.log("マウスクリック通知エラー:", error);
-    }
-  }
-
   async sendShioriEvent(eventName) {
     try {
       console.log(`📤 SHIORIイベント送信: ${eventName}`);
 
-      const response = await globalThis.__TAURI__.invoke("send_shiori_event", {
+      // 返ってきたスクリプトは sakura-event で表示する
+      await globalThis.__TAURI__.invoke("send_shiori_event", {
         event: eventName,
       });
-
-      if (response && response.trim()) {
-        this.showBalloon(response);
-      } else {
-        // SHIORIからの応答がない場合のフォールバック
-        this.showBalloon("こんにちは！");
-      }
-
-      // 3秒後に自動で隠す
-      setTimeout(() => this.hideBalloon(), 3000);
     } catch (error) {
       console.error("❌ SHIORIイベントエラー:", error);
       this.showBalloon("エラーが発生しました。");
@@ -328,6 +399,125 @@ class MascotNanaiApp {
     await this.sendShioriEvent(randomEvent);
   }
 
+  /**
+   * さくらスクリプトの再生イベント（sakura-event）を受け取る
+   */
+  async listenSakuraEvents() {
+    const listen = globalThis.__TAURI__?.event?.listen;
+    if (!listen) return;
+    await listen("sakura-event", ({ payload }) => this.onSakuraEvent(payload));
+  }
+
+  /**
+   * 再生イベントをバルーンとサーフェスに反映する
+   * @param {Object} event - generation と type（text, surface, newLine など）
+   */
+  onSakuraEvent(event) {
+    const text = this.elements.balloonText;
+    if (!text) return;
+    // 新しい再生が始まったらバルーンを空にする
+    if (event.generation !== this.sakuraGeneration) {
+      this.sakuraGeneration = event.generation;
+      this.hasChoices = false;
+      clearTimeout(this.balloonTimer);
+      text.replaceChildren();
+      this.sakuraTarget = text;
+      this.getBalloonMetrics();
+    }
+
+    switch (event.type) {
+      case "text":
+        this.sakuraTarget.append(event.text);
+        this.elements.balloonDisplay.style.display = "block";
+        break;
+      case "newLine":
+        text.append(document.createElement("br"));
+        break;
+      case "clearBalloon":
+        text.replaceChildren();
+        this.sakuraTarget = text;
+        break;
+      case "surface":
+        // 表示するキャラクターはsakuraだけ
+        if (event.scope === 0) {
+          this.currentSurface = event.id;
+          this.updateGhostCharacter(
+            event.id < 0 ? null : this.currentGhost?.name
+          );
+        }
+        break;
+      case "balloon":
+        // \b[-1] はバルーンを消す
+        if (event.scope !== 0) break;
+        if (event.id < 0) {
+          this.hideBalloon();
+        } else {
+          this.getBalloonMetrics({
+            kind: "character",
+            scope: 0,
+            id: event.id,
+            right: false,
+          });
+        }
+        break;
+      case "choice": {
+        const choice = this.choiceElement(event, "button");
+        choice.textContent = event.title;
+        text.append(choice);
+        this.elements.balloonDisplay.style.display = "block";
+        break;
+      }
+      case "choiceStart":
+      case "anchorStart":
+        // 終わりまでの文字を選択肢・アンカーの中に表示する
+        this.sakuraTarget = this.choiceElement(
+          event,
+          event.type === "choiceStart" ? "button" : "a"
+        );
+        text.append(this.sakuraTarget);
+        break;
+      case "choiceEnd":
+      case "anchorEnd":
+        this.sakuraTarget = text;
+        break;
+      case "end":
+        // 選択肢があれば選ばれるかタイムアウトするまで表示しておく
+        if (!this.hasChoices) {
+          this.balloonTimer = setTimeout(() => this.hideBalloon(), 3000);
+        }
+        break;
+      case "choiceTimeout":
+      case "exit":
+      case "cancelled":
+        this.hideBalloon();
+        break;
+    }
+  }
+
+  /**
+   * 選択肢・アンカーの要素（クリックで select_sakura_choice を呼ぶ）
+   * @param {Object} event - generation と index
+   * @param {string} tag - button か a
+   * @returns {HTMLElement}
+   */
+  choiceElement({ generation, index }, tag) {
+    const element = document.createElement(tag);
+    element.className = tag === "a" ? "sakura-anchor" : "sakura-choice";
+    element.addEventListener("click", async (e) => {
+      e.stopPropagation();
+      try {
+        await globalThis.__TAURI__.core.invoke("select_sakura_choice", {
+          generation,
+          index,
+        });
+      } catch (error) {
+        console.log("選択肢の選択エラー:", error);
+      }
+    });
+    this.hasChoices = true;
+    return element;
+  }
+
   showBalloon(text) {
     if (this.elements.balloonText && this.elements.balloonDisplay) {
       this.elements.balloonText.textContent = text;
@@ -341,6 +531,88 @@ class MascotNanaiApp {
     }
   }
 
+  /**
+   * バルーンを選ぶ
+   * @param {string|null} name - ディレクトリ名か名前（null ならゴーストの既定のバルーン）
+   */
+  async setBalloon(name) {
+    try {
+      await globalThis.__TAURI__.core.invoke("set_balloon", { name });
+    } catch (error) {
+      console.error("❌ バルーンの選択エラー:", error);
+      return;
+    }
+    this.currentBalloon = name;
+    if (name) {
+      localStorage.setItem("mascot-nanai-balloon", name);
+    } else {
+      localStorage.removeItem("mascot-nanai-balloon");
+    }
+    this.balloonVersion = Date.now();
+    await this.getBalloonMetrics();
+    this.updateCurrentBalloonDisplay();
+  }
+
+  /**
+   * バルーンの画像を選んで表示に使う（取得できなければ画像の無いバルーン）
+   * @param {Object} image - { kind: "character", scope, id, right } など
+   */
+  async getBalloonMetrics(
+    image = { kind: "character", scope: 0, id: 0, right: false }
+  ) {
+    // 続けて呼ばれたら最後の画像を使う
+    const request = (this.balloonRequest = (this.balloonRequest ?? 0) + 1);
+    let metrics = null;
+    try {
+      metrics = await globalThis.__TAURI__.core.invoke("get_balloon_metrics", {
+        image,
+      });
+    } catch (error) {
+      console.log("バルーンの画像を取得できません:", error);
+    }
+    if (request !== this.balloonRequest) return;
+    this.balloonMetrics = metrics;
+    this.applyBalloonMetrics();
+  }
+
+  /**
+   * バルーンの画像（balloon:// プロトコル）を背景にして、文字を textRect の中に置く
+   */
+  applyBalloonMetrics() {
+    const { balloon, balloonText: text } = this.elements;
+    if (!balloon || !text) return;
+    const metrics = this.balloonMetrics;
+    const convertFileSrc = globalThis.__TAURI__?.core?.convertFileSrc;
+    balloon.classList.toggle("image-balloon", !!(metrics && convertFileSrc));
+    if (!metrics || !convertFileSrc) {
+      balloon.removeAttribute("style");
+      text.removeAttribute("style");
+      return;
+    }
+
+    const { left, top, right, bottom } = metrics.textRect;
+    const { r, g, b } = metrics.font.color;
+    Object.assign(balloon.style, {
+      width: `${metrics.width}px`,
+      height: `${metrics.height}px`,
+      // バルーンを選び直したときに読み込み直すため、キャッシュを避ける
+      backgroundImage: `url("${convertFileSrc(metrics.image, "balloon")}?v=${
+        this.balloonVersion
+      }")`,
+    });
+    Object.assign(text.style, {
+      left: `${left}px`,
+      top: `${top}px`,
+      width: `${right - left}px`,
+      height: `${bottom - top}px`,
+      color: `rgb(${r}, ${g}, ${b})`,
+      fontSize: `${metrics.font.height}px`,
+      lineHeight: `${metrics.lineHeight}px`,
+      fontWeight: metrics.font.bold ? "bold" : "",
+      fontStyle: metrics.font.italic ? "italic" : "",
+    });
+  }
+
   updateGhostCharacter(ghostName) {
     // ゴーストキャラクターの更新
     if (this.elements.ghostCharacter) {
@@ -355,9 +627,10 @@ class MascotNanaiApp {
           return;
         }
 
-        // mock_nanaiの場合は実際の画像を表示
-        if (ghostName === "mock_nanai") {
-          placeholder.innerHTML = `<img src="assets/ghost/mock_nanai/shell/master/surface0.png" 
+        // 合成したサーフェスを表示（Tauri以外ではmock_nanaiの画像のみ）
+        const surfaceUrl = this.surfaceUrl(this.currentSurface);
+        if (surfaceUrl || ghostName === "mock_nanai") {
+          placeholder.innerHTML = `<img src="${surfaceUrl || "assets/ghost/mock_nanai/shell/master/surface0.png"}" 
                                          alt="${ghostName}" 
                                          style="width: 100%; height: 100%; object-fit: contain;" 
                                          onerror="console.error('画像読み込みエラー'); this.style.display='none';">`;
@@ -367,6 +640,103 @@ class MascotNanaiApp {
         }
 
         placeholder.title = ghostName || "";
+        this.renderAnimationFrames();
+      }
+    }
+  }
+
+  /**
+   * 現在のゴーストの合成したサーフェスのURL（surface:// プロトコル）
+   * @param {number} surfaceId - サーフェスID
+   * @returns {string|null} Tauri環境でなければnull
+   */
+  surfaceUrl(surfaceId) {
+    const convertFileSrc = globalThis.__TAURI__?.core?.convertFileSrc;
+    if (!convertFileSrc) return null;
+    // シェルが更新されたときに読み込み直すため、キャッシュを避ける
+    return `${convertFileSrc(String(surfaceId), "surface")}?v=${this.shellVersion}`;
+  }
+
+  /**
+   * ゴーストの読み込み直し（ghost-reloaded）とアニメーション（seriko-event）を受け取る
+   */
+  async listenGhostEvents() {
+    const listen = globalThis.__TAURI__?.event?.listen;
+    if (!listen) return;
+    await listen("ghost-reloaded", ({ payload }) =>
+      this.onGhostReloaded(payload)
+    );
+    await listen("seriko-event", ({ payload }) => this.onSerikoEvent(payload));
+  }
+
+  /**
+   * ゴーストのファイルが変わって読み込み直された
+   * @param {Object} reloaded - ghostId, kind（shiori / shell）, paths, error
+   */
+  onGhostReloaded({ ghostId, kind, error }) {
+    if (error) {
+      console.error(`❌ ゴーストの読み込み直しに失敗 (${ghostId}):`, error);
+      this.updateStatus("ゴーストの読み込み直しに失敗", false);
+      return;
+    }
+    console.log(`🔄 ゴーストを読み込み直しました (${kind}): ${ghostId}`);
+    if (kind === "shell") {
+      this.shellVersion = Date.now();
+      this.animationFrames = {};
+      this.updateGhostCharacter(this.currentGhost?.name);
+    }
+  }
+
+  /**
+   * アニメーションの表示が変わった
+   * @param {Object} event - type（frame / end）, scope, surface, animation, frames
+   */
+  onSerikoEvent(event) {
+    // 表示するキャラクターはsakuraだけ
+    if (event.type !== "frame" || event.scope !== 0) return;
+    this.animationFrames[event.animation] = event;
+    this.renderAnimationFrames();
+  }
+
+  /**
+   * 表示中のサーフェスのアニメーションのパターンを、サーフェスの画像の上に重ねる
+   * （描画メソッドは区別せず、アニメーションIDの順に重ねる）
+   */
+  renderAnimationFrames() {
+    const placeholder = this.elements.ghostCharacter?.querySelector(
+      ".character-placeholder"
+    );
+    const image = placeholder?.querySelector("img");
+    if (!image) return;
+    placeholder.querySelectorAll(".seriko-frame").forEach((e) => e.remove());
+    if (!image.naturalWidth) {
+      image.addEventListener("load", () => this.renderAnimationFrames(), {
+        once: true,
+      });
+      return;
+    }
+
+    const scale = Math.min(
+      image.clientWidth / image.naturalWidth,
+      image.clientHeight / image.naturalHeight
+    );
+    const left =
+      image.offsetLeft + (image.clientWidth - image.naturalWidth * scale) / 2;
+    const top =
+      image.offsetTop + (image.clientHeight - image.naturalHeight * scale) / 2;
+    for (const animation of Object.values(this.animationFrames)) {
+      if (animation.surface !== this.currentSurface) continue;
+      for (const frame of animation.frames) {
+        if (frame.surface < 0) continue;
+        const overlay = document.createElement("img");
+        overlay.className = "seriko-frame";
+        overlay.style.left = `${left + frame.x * scale}px`;
+        overlay.style.top = `${top + frame.y * scale}px`;
+        overlay.addEventListener("load", () => {
+          overlay.style.width = `${overlay.naturalWidth * scale}px`;
+        });
+        overlay.src = this.surfaceUrl(frame.surface);
+        placeholder.append(overlay);
       }
     }
   }
@@ -455,14 +825,17 @@ class MascotNanaiApp {
 
     this.elements.modalContent.innerHTML = content.innerHTML;
     this.elements.modalOverlay.style.display = "flex";
+    // 開いている間はトークを止める
+    globalThis.__TAURI__?.core?.invoke("pause_sakura_script").catch(() => {});
 
     // モーダル固有のイベントリスナーを設定
     this.setupModalEventListeners(contentId);
   }
 
   hideModal() {
-    if (this.elements.modalOverlay) {
+    if (this.elements.modalOverlay?.style.display === "flex") {
       this.elements.modalOverlay.style.display = "none";
+      globalThis.__TAURI__?.core?.invoke("resume_sakura_script").catch(() => {});
     }
   }
 
@@ -564,9 +937,9 @@ class MascotNanaiApp {
       .map(
         (ghost, index) =>
           `<div class="ghost-item ${
-            this.currentGhost?.name === ghost.name ? "active" : ""
+            this.currentGhost?.id === ghost.id ? "active" : ""
           }" 
-           data-ghost="${ghost.name}" data-index="${index}">
+           data-ghost="${ghost.id}" data-index="${index}">
         <strong>${ghost.name}</strong><br>
         <small>${ghost.path}</small>
       </div>`
@@ -588,9 +961,10 @@ class MascotNanaiApp {
     });
   }
 
-  selectGhost(ghostName) {
-    const ghost = this.ghosts.find((g) => g.name === ghostName);
+  selectGhost(ghostId) {
+    const ghost = this.ghosts.find((g) => g.id === ghostId);
     if (!ghost) return;
+    const ghostName = ghost.name;
 
     this.currentGhost = ghost;
     this.updateCurrentGhostDisplay();
@@ -613,10 +987,12 @@ class MascotNanaiApp {
       console.log(`🎭 SHIORI初期化開始: ${ghost.name}`);
 
       const result = await globalThis.__TAURI__.invoke("load_ghost", {
-        ghostName: ghost.name,
+        ghostId: ghost.id,
       });
 
       console.log("✅ SHIORI初期化成功:", result);
+      // 読み込んだゴーストのシェルで表示し直す
+      this.updateGhostCharacter(ghost.name);
 
       // 初期化後にOnBootイベントを送信
       setTimeout(async () => {
@@ -660,19 +1036,43 @@ class MascotNanaiApp {
   }
 
   setupBalloonModalListeners() {
+    this.updateCurrentBalloonDisplay();
+    const nameInput = document.getElementById("balloon-name");
+    if (nameInput) nameInput.value = this.currentBalloon || "";
+
     document
-      .getElementById("refresh-balloons-btn")
+      .getElementById("apply-balloon-btn")
       ?.addEventListener("click", () => {
-        this.refreshBalloons();
+        this.applyBalloon(nameInput?.value.trim() || null);
+      });
+    document
+      .getElementById("default-balloon-btn")
+      ?.addEventListener("click", () => {
+        if (nameInput) nameInput.value = "";
+        this.applyBalloon(null);
       });
   }
 
-  refreshBalloons() {
-    console.log("💭 バルーン更新");
-    this.showBalloon("バルーン機能は今後実装予定です。");
+  async applyBalloon(name) {
+    console.log("💭 バルーン変更:", name ?? "ゴーストの既定");
+    await this.setBalloon(name);
+    this.showBalloon(
+      this.balloonMetrics
+        ? "バルーンを変更しました。"
+        : "バルーンが見つかりません。"
+    );
     setTimeout(() => this.hideBalloon(), 2000);
   }
 
+  updateCurrentBalloonDisplay() {
+    const currentBalloonEl = document.getElementById("current-balloon");
+    if (!currentBalloonEl) return;
+    const metrics = this.balloonMetrics;
+    currentBalloonEl.textContent = metrics
+      ? metrics.name || metrics.balloon
+      : "なし";
+  }
+
   showScanModal() {
     this.showModal("scan-modal-content");
   }
@@ -896,6 +1296,9 @@ class MascotNanaiApp {
     if (debugLevelSelect) {
       debugLevelSelect.value = this.settings.debugLevel;
     }
+
+    const userName = document.getElementById("user-name");
+    if (userName) userName.value = this.settings.userName;
   }
 
   saveSettings() {
@@ -912,6 +1315,8 @@ class MascotNanaiApp {
       document.getElementById("ghost-size")?.value || "medium";
     this.settings.debugLevel =
       document.getElementById("debug-level")?.value || "info";
+    this.settings.userName =
+      document.getElementById("user-name")?.value.trim() || "";
 
     // ローカルストレージに保存
     localStorage.setItem(
@@ -935,6 +1340,7 @@ class MascotNanaiApp {
       alwaysOnTop: true,
       ghostSize: "medium",
       debugLevel: "info",
+      userName: "",
     };
 
     localStorage.removeItem("mascot-nanai-settings");
@@ -955,9 +1361,30 @@ class MascotNanaiApp {
     // ゴーストサイズの適用
     this.applyGhostSize();
 
+    this.updateEnvironmentSettings();
+
     // その他の設定適用は今後実装
   }
 
+  /**
+   * さくらスクリプトの環境変数（%username %screenwidth %screenheight）をRust側に渡す
+   */
+  async updateEnvironmentSettings() {
+    const invoke = globalThis.__TAURI__?.core?.invoke;
+    if (!invoke) return;
+    try {
+      await invoke("set_environment_settings", {
+        settings: {
+          username: this.settings.userName || null,
+          screen_width: globalThis.screen.width,
+          screen_height: globalThis.screen.height,
+        },
+      });
+    } catch (error) {
+      console.log("環境変数の設定エラー:", error);
+    }
+  }
+
   // ===========================================
   // デバッグモーダル
   // ===========================================
@@ -0,0 +1,204 @@
+//! 選択肢とアンカー
+//!
+//! `\q` `\__q` `\_a` で表示された項目を集め、選ばれたときに
+//! SHIORIへ送るイベント（`OnChoiceSelect` など）を決める。
+
+use crate::sakura_script::{SakuraToken, SakuraTokenKind};
+use serde::Serialize;
+
+/// 選択肢のタイムアウトの既定値（ミリ秒）
+pub const DEFAULT_CHOICE_TIMEOUT_MS: u64 = 15_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ChoiceKind {
+    /// `\q` `\__q`
+    Choice,
+    /// `\_a`
+    Anchor,
+}
+
+/// 表示された選択肢・アンカー
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SakuraChoice {
+    pub kind: ChoiceKind,
+    /// スクリプト内での通し番号
+    pub index: usize,
+    /// 表示されたテキスト
+    pub title: String,
+    pub id: String,
+    pub references: Vec<String>,
+}
+
+/// SHIORIに送るイベント
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChoiceEvent {
+    pub id: String,
+    pub references: Vec<String>,
+}
+
+/// 選ばれたときの動作
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChoiceAction {
+    /// `script:` で始まるIDはそのまま再生する
+    Script(String),
+    /// 先頭から順に送り、最初に応答のあったものを使う
+    Events(Vec<ChoiceEvent>),
+}
+
+impl SakuraChoice {
+    pub fn action(&self) -> ChoiceAction {
+        if let Some(script) = self.id.strip_prefix("script:") {
+            return ChoiceAction::Script(script.to_string());
+        }
+
+        // `On` で始まるIDはそのイベントとして送る
+        if self.id.starts_with("On") {
+            return ChoiceAction::Events(vec![ChoiceEvent {
+                id: self.id.clone(),
+                references: self.references.clone(),
+            }]);
+        }
+
+        let (ex, plain) = match self.kind {
+            ChoiceKind::Choice => ("OnChoiceSelectEx", "OnChoiceSelect"),
+            ChoiceKind::Anchor => ("OnAnchorSelectEx", "OnAnchorSelect"),
+        };
+        let with_id = |prefix: &[&String]| {
+            prefix
+                .iter()
+                .map(|s| s.to_string())
+                .chain(self.references.iter().cloned())
+                .collect()
+        };
+        ChoiceAction::Events(vec![
+            ChoiceEvent {
+                id: ex.to_string(),
+                references: with_id(&[&self.title, &self.id]),
+            },
+            ChoiceEvent {
+                id: plain.to_string(),
+                references: with_id(&[&self.id]),
+            },
+        ])
+    }
+}
+
+/// 選択肢として数えるトークンか（`collect` と再生で番号を揃えるため）
+pub fn is_choice_token(kind: &SakuraTokenKind) -> bool {
+    matches!(
+        kind,
+        SakuraTokenKind::Choice { .. }
+            | SakuraTokenKind::ChoiceStart { .. }
+            | SakuraTokenKind::AnchorStart { .. }
+    )
+}
+
+/// スクリプト中の選択肢・アンカーを出現順に集める
+pub fn collect(tokens: &[SakuraToken]) -> Vec<SakuraChoice> {
+    let mut choices: Vec<SakuraChoice> = Vec::new();
+    // `\__q` `\_a` で囲まれたテキストを受け取る項目
+    let mut open: Option<usize> = None;
+
+    for token in tokens {
+        let index = choices.len();
+        match &token.kind {
+            SakuraTokenKind::Choice {
+                title,
+                id,
+                references,
+            } => choices.push(SakuraChoice {
+                kind: ChoiceKind::Choice,
+                index,
+                title: title.clone(),
+                id: id.clone(),
+                references: references.clone(),
+            }),
+            SakuraTokenKind::ChoiceStart { id, references }
+            | SakuraTokenKind::AnchorStart { id, references } => {
+                let kind = match token.kind {
+                    SakuraTokenKind::ChoiceStart { .. } => ChoiceKind::Choice,
+                    _ => ChoiceKind::Anchor,
+                };
+                choices.push(SakuraChoice {
+                    kind,
+                    index,
+                    title: String::new(),
+                    id: id.clone(),
+                    references: references.clone(),
+                });
+                open = Some(index);
+            }
+            SakuraTokenKind::ChoiceEnd | SakuraTokenKind::AnchorEnd => open = None,
+            SakuraTokenKind::Text(text) => {
+                if let Some(open) = open {
+                    choices[open].title.push_str(text);
+                }
+            }
+            _ => {}
+        }
+    }
+    choices
+}
+
+/// `\*` が無ければタイムアウトあり
+pub fn has_timeout(tokens: &[SakuraToken]) -> bool {
+    !tokens
+        .iter()
+        .any(|token| token.kind == SakuraTokenKind::NoChoiceTimeout)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sakura_script::parse;
+
+    fn event(id: &str, references: &[&str]) -> ChoiceEvent {
+        ChoiceEvent {
+            id: id.to_string(),
+            references: references.iter().map(|r| r.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_collect_and_actions() {
+        let tokens = parse(
+            "\\q[はい,yes,r0]\\n\\__q[OnNo,x]いいえ\\__q\\_a[link]リンク\\_a\\q[再生,script:\\e]",
+        );
+        let choices = collect(&tokens);
+        assert_eq!(choices.len(), 4);
+        assert_eq!(choices[1].title, "いいえ");
+        assert_eq!(choices[2].kind, ChoiceKind::Anchor);
+        assert_eq!(choices[2].title, "リンク");
+
+        assert_eq!(
+            choices[0].action(),
+            ChoiceAction::Events(vec![
+                event("OnChoiceSelectEx", &["はい", "yes", "r0"]),
+                event("OnChoiceSelect", &["yes", "r0"]),
+            ])
+        );
+        assert_eq!(
+            choices[1].action(),
+            ChoiceAction::Events(vec![event("OnNo", &["x"])])
+        );
+        assert_eq!(
+            choices[2].action(),
+            ChoiceAction::Events(vec![
+                event("OnAnchorSelectEx", &["リンク", "link"]),
+                event("OnAnchorSelect", &["link"]),
+            ])
+        );
+        assert_eq!(choices[3].action(), ChoiceAction::Script("\\e".to_string()));
+    }
+
+    #[test]
+    fn test_has_timeout() {
+        assert!(has_timeout(&parse("\\q[a,b]")));
+        // `\*` は選択肢の前でも後でもよい
+        assert!(!has_timeout(&parse("\\*\\q[a,b]")));
+        assert!(!has_timeout(&parse("\\q[a,b]\\*")));
+        // `\*` をテキストとして書いたものは数えない
+        assert!(has_timeout(&parse("\\\\*\\q[a,b]")));
+    }
+}
@@ -0,0 +1,165 @@
+//! さくらスクリプトの環境変数
+//!
+//! `%username` `%month` などを値に置き換える。
+//! 値は `VariableProvider` から取るので、テストでは時刻を固定した環境を渡せる。
+
+use crate::sakura_script::{self, SakuraToken, SakuraTokenKind};
+use std::collections::HashMap;
+
+/// 環境変数の値を返すもの
+pub trait VariableProvider {
+    /// 値が無い変数は `None`（スクリプト上はそのまま残る）
+    fn variable(&self, name: &str, arg: Option<&str>) -> Option<String>;
+}
+
+/// 現地時刻
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LocalTime {
+    pub year: i32,
+    pub month: u32,
+    pub day: u32,
+    pub hour: u32,
+    pub minute: u32,
+    pub second: u32,
+}
+
+/// 標準の環境変数
+#[derive(Debug, Clone, Default)]
+pub struct SakuraEnvironment {
+    pub username: Option<String>,
+    /// `sakura.name`
+    pub selfname: Option<String>,
+    /// `sakura.name2`（無ければ `selfname`）
+    pub selfname2: Option<String>,
+    /// `kero.name`
+    pub keroname: Option<String>,
+    pub friendname: Option<String>,
+    pub screen_width: Option<u32>,
+    pub screen_height: Option<u32>,
+    /// OSが起動してからの秒数
+    pub uptime_secs: Option<u64>,
+    pub time: LocalTime,
+    /// `%property[...]` の値
+    pub properties: HashMap<String, String>,
+}
+
+impl VariableProvider for SakuraEnvironment {
+    fn variable(&self, name: &str, arg: Option<&str>) -> Option<String> {
+        let time = &self.time;
+        match name {
+            "username" => self.username.clone(),
+            "selfname" => self.selfname.clone(),
+            "selfname2" => self.selfname2.clone().or_else(|| self.selfname.clone()),
+            "keroname" => self.keroname.clone(),
+            "friendname" => self.friendname.clone(),
+            "year" => Some(time.year.to_string()),
+            "month" => Some(time.month.to_string()),
+            "day" => Some(time.day.to_string()),
+            "hour" => Some(time.hour.to_string()),
+            "minute" => Some(time.minute.to_string()),
+            "second" => Some(time.second.to_string()),
+            // わざと1時間ずらした時
+            "wronghour" => Some(((time.hour + 1) % 24).to_string()),
+            "screenwidth" => self.screen_width.map(|width| width.to_string()),
+            "screenheight" => self.screen_height.map(|height| height.to_string()),
+            "exh" => self.uptime_secs.map(|secs| (secs / 3600).to_string()),
+            // OS起動からの分数を「万年」で表す冗談の表記
+            "et" => self.uptime_secs.map(|secs| format!("{}万年", secs / 60)),
+            "property" => arg.and_then(|key| self.properties.get(key).cloned()),
+            _ => None,
+        }
+    }
+}
+
+/// 環境変数のトークンをテキストに置き換える
+pub fn expand(tokens: &[SakuraToken], provider: &dyn VariableProvider) -> Vec<SakuraToken> {
+    tokens
+        .iter()
+        .map(|token| match &token.kind {
+            SakuraTokenKind::Variable { name, arg } => {
+                match provider.variable(name, arg.as_deref()) {
+                    Some(value) => SakuraToken {
+                        span: token.span.clone(),
+                        ..SakuraToken::new(SakuraTokenKind::Text(value))
+                    },
+                    None => token.clone(),
+                }
+            }
+            _ => token.clone(),
+        })
+        .collect()
+}
+
+/// スクリプトの環境変数を展開する
+pub fn expand_script(script: &str, provider: &dyn VariableProvider) -> String {
+    sakura_script::to_script(&expand(&sakura_script::parse(script), provider))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn environment() -> SakuraEnvironment {
+        SakuraEnvironment {
+            username: Some("ユーザー".to_string()),
+            selfname: Some("ナナイ".to_string()),
+            keroname: Some("モック".to_string()),
+            screen_width: Some(1920),
+            uptime_secs: Some(2 * 3600 + 5 * 60),
+            time: LocalTime {
+                year: 2024,
+                month: 3,
+                day: 14,
+                hour: 23,
+                minute: 5,
+                second: 9,
+            },
+            properties: HashMap::from([(
+                "currentghost.name".to_string(),
+                "Mock SHIORI".to_string(),
+            )]),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_expand_script() {
+        let env = environment();
+        assert_eq!(
+            expand_script(
+                "\\0%usernameさん、%selfname2と%keronameです。%month/%day %hour:%minute:%second",
+                &env
+            ),
+            "\\0ユーザーさん、ナナイとモックです。3/14 23:5:9"
+        );
+        assert_eq!(
+            expand_script("%wronghour %screenwidth %exh %et", &env),
+            "0 1920 2 125万年"
+        );
+        assert_eq!(
+            expand_script("%property[currentghost.name]", &env),
+            "Mock SHIORI"
+        );
+    }
+
+    #[test]
+    fn test_unknown_values_are_kept() {
+        let env = environment();
+        assert_eq!(
+            expand_script("%friendname %screenheight %property[x] 100\\%", &env),
+            "%friendname %screenheight %property[x] 100\\%"
+        );
+
+        // 値に含まれる `\` や `%` はタグとして解釈されない
+        let env = SakuraEnvironment {
+            username: Some("50%\\e".to_string()),
+            ..Default::default()
+        };
+        let tokens = expand(&sakura_script::parse("%username"), &env);
+        let reparsed = sakura_script::parse(&sakura_script::to_script(&tokens));
+        assert_eq!(
+            reparsed[0].kind,
+            SakuraTokenKind::Text("50%\\e".to_string())
+        );
+    }
+}
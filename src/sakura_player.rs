@@ -0,0 +1,833 @@
+//! さくらスクリプトの再生
+//!
+//! トークン列を時間軸に沿ったイベント（1文字ずつのテキスト、サーフェス変更など）に変換する。
+//! 時刻は `PlayerClock` から取るので、テストでは `VirtualClock` を進めて確認できる。
+
+use crate::sakura_choice::{self, SakuraChoice, DEFAULT_CHOICE_TIMEOUT_MS};
+use crate::sakura_script::{self, SakuraToken, SakuraTokenKind};
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+/// 1文字ごとの既定の待ち時間（ミリ秒）
+pub const DEFAULT_TEXT_INTERVAL_MS: u64 = 50;
+
+/// `\w1` 1つ分の待ち時間（ミリ秒）
+const WAIT_UNIT_MS: u64 = 50;
+
+/// 再生に使う時計（ミリ秒）
+pub trait PlayerClock {
+    fn now_ms(&self) -> u64;
+}
+
+/// 実時間の時計
+#[derive(Debug, Clone)]
+pub struct SystemClock {
+    origin: Instant,
+}
+
+impl SystemClock {
+    pub fn new() -> Self {
+        SystemClock {
+            origin: Instant::now(),
+        }
+    }
+}
+
+impl Default for SystemClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PlayerClock for SystemClock {
+    fn now_ms(&self) -> u64 {
+        self.origin.elapsed().as_millis() as u64
+    }
+}
+
+/// 手で進める時計（テスト用、複製しても同じ時刻を共有する）
+#[derive(Debug, Clone, Default)]
+pub struct VirtualClock {
+    now: Arc<AtomicU64>,
+}
+
+impl VirtualClock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn advance(&self, ms: u64) {
+        self.now.fetch_add(ms, Ordering::SeqCst);
+    }
+}
+
+impl PlayerClock for VirtualClock {
+    fn now_ms(&self) -> u64 {
+        self.now.load(Ordering::SeqCst)
+    }
+}
+
+/// フロントエンドに送るイベント
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum PlayerEvent {
+    /// 発話するキャラクターの切り替え
+    Scope {
+        scope: u32,
+    },
+    Surface {
+        scope: u32,
+        id: i32,
+    },
+    Balloon {
+        scope: u32,
+        id: i32,
+    },
+    Animation {
+        scope: u32,
+        id: u32,
+    },
+    /// `\![anim,...]` によるアニメーションの操作
+    AnimationControl {
+        scope: u32,
+        id: u32,
+        command: AnimationCommand,
+    },
+    /// `\![bind,カテゴリ,パーツ,1]` による着せ替え（`bound` が `None` なら付け外しを切り替える）
+    Bind {
+        scope: u32,
+        category: String,
+        part: String,
+        bound: Option<bool>,
+    },
+    /// 表示するテキスト（通常は1文字ずつ）
+    Text {
+        scope: u32,
+        text: String,
+    },
+    NewLine {
+        scope: u32,
+        half: bool,
+    },
+    ClearBalloon {
+        scope: u32,
+    },
+    /// `\q[タイトル,ID]`
+    Choice {
+        scope: u32,
+        index: usize,
+        title: String,
+    },
+    /// `\__q[ID]`（`ChoiceEnd` までのテキストが選択肢）
+    ChoiceStart {
+        scope: u32,
+        index: usize,
+    },
+    ChoiceEnd {
+        scope: u32,
+    },
+    /// `\_a[ID]`（`AnchorEnd` までのテキストがアンカー）
+    AnchorStart {
+        scope: u32,
+        index: usize,
+    },
+    AnchorEnd {
+        scope: u32,
+    },
+    /// 選択肢が選ばれないまま時間切れになった
+    ChoiceTimeout {
+        script: String,
+    },
+    /// クリック待ち（`\x`）
+    WaitClick,
+    /// ゴーストの終了（`\-`）
+    Exit,
+    /// スクリプトの終わり
+    End,
+    /// 新しいスクリプトなどで再生が打ち切られた
+    Cancelled,
+}
+
+/// `\![anim,...]` の操作
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum AnimationCommand {
+    /// 止めて重ねていたものを消す（`clear`）
+    Clear,
+    Pause,
+    Resume,
+    /// 重ねる位置をずらす（`offset`）
+    Offset {
+        x: i32,
+        y: i32,
+    },
+}
+
+impl AnimationCommand {
+    /// `\![anim,...]` の引数（`clear,ID` など）を読む
+    fn parse(args: &[String]) -> Option<(u32, Self)> {
+        let id = args.get(1)?.trim().parse().ok()?;
+        let number = |index: usize| -> Option<i32> { args.get(index)?.trim().parse().ok() };
+        let command = match args[0].trim().to_lowercase().as_str() {
+            "clear" | "stop" => AnimationCommand::Clear,
+            "pause" => AnimationCommand::Pause,
+            "resume" => AnimationCommand::Resume,
+            "offset" => AnimationCommand::Offset {
+                x: number(2)?,
+                y: number(3)?,
+            },
+            _ => return None,
+        };
+        Some((id, command))
+    }
+}
+
+/// `generation` 付きのイベント（古い再生のイベントを見分けるため）
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SakuraEvent {
+    pub generation: u64,
+    #[serde(flatten)]
+    pub event: PlayerEvent,
+}
+
+/// 再生の状態
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlayerState {
+    Idle,
+    Playing,
+    Paused,
+    WaitingClick,
+}
+
+/// 再生の1手順
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Step {
+    Event(PlayerEvent),
+    Delay(u64),
+    WaitClick { clear: bool, scope: u32 },
+}
+
+/// さくらスクリプトのプレイヤー
+pub struct SakuraPlayer<C: PlayerClock> {
+    clock: C,
+    text_interval_ms: u64,
+    steps: VecDeque<Step>,
+    state: PlayerState,
+    /// 次の手順を実行する時刻
+    due_at: u64,
+    /// 一時停止した時刻と、その時点での残り時間
+    paused_at: u64,
+    paused_remaining: u64,
+    paused_from: PlayerState,
+    /// クリックで残りを一気に表示している
+    skipping: bool,
+    generation: u64,
+    /// 次の `poll` で返す、時刻に関係ないイベント
+    pending: Vec<SakuraEvent>,
+    /// 再生中のスクリプト（`OnChoiceTimeout` で返す）
+    script: String,
+    /// 表示中の選択肢・アンカー
+    choices: Vec<SakuraChoice>,
+    /// 選択肢のタイムアウト（`None` なら無効）
+    choice_timeout_ms: Option<u64>,
+    choice_deadline: Option<u64>,
+    /// 再生中のスクリプトに `\*` が無い
+    choice_timeout_enabled: bool,
+}
+
+impl<C: PlayerClock> SakuraPlayer<C> {
+    pub fn new(clock: C) -> Self {
+        SakuraPlayer {
+            clock,
+            text_interval_ms: DEFAULT_TEXT_INTERVAL_MS,
+            steps: VecDeque::new(),
+            state: PlayerState::Idle,
+            due_at: 0,
+            paused_at: 0,
+            paused_remaining: 0,
+            paused_from: PlayerState::Idle,
+            skipping: false,
+            generation: 0,
+            pending: Vec::new(),
+            script: String::new(),
+            choices: Vec::new(),
+            choice_timeout_ms: Some(DEFAULT_CHOICE_TIMEOUT_MS),
+            choice_deadline: None,
+            choice_timeout_enabled: false,
+        }
+    }
+
+    /// 1文字ごとの待ち時間を設定
+    pub fn with_text_interval(mut self, ms: u64) -> Self {
+        self.text_interval_ms = ms;
+        self
+    }
+
+    /// 選択肢のタイムアウトを設定（`None` で無効）
+    pub fn with_choice_timeout(mut self, ms: Option<u64>) -> Self {
+        self.choice_timeout_ms = ms;
+        self
+    }
+
+    pub fn state(&self) -> PlayerState {
+        self.state
+    }
+
+    /// 再生ごとに増える番号
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// スクリプトを再生する（再生中のものは打ち切る）
+    pub fn play(&mut self, script: &str) {
+        self.play_tokens(&sakura_script::parse(script))
+    }
+
+    pub fn play_tokens(&mut self, tokens: &[SakuraToken]) {
+        self.cancel();
+        self.generation += 1;
+        self.script = sakura_script::to_script(tokens);
+        self.choices = sakura_choice::collect(tokens);
+        self.choice_timeout_enabled = sakura_choice::has_timeout(tokens);
+        self.steps = self.schedule(tokens);
+        self.state = PlayerState::Playing;
+        self.due_at = self.clock.now_ms();
+    }
+
+    /// 再生を打ち切る（再生中だった場合は次の `poll` で `Cancelled` を返す）
+    pub fn cancel(&mut self) {
+        if self.state != PlayerState::Idle {
+            let cancelled = self.wrap(PlayerEvent::Cancelled);
+            self.pending.push(cancelled);
+        }
+        self.steps.clear();
+        self.state = PlayerState::Idle;
+        self.skipping = false;
+        self.choices.clear();
+        self.choice_deadline = None;
+    }
+
+    /// 表示中の選択肢
+    pub fn choices(&self) -> &[SakuraChoice] {
+        &self.choices
+    }
+
+    /// 選択肢を選ぶ（古い再生の選択肢や、もう選ばれた選択肢なら `None`）
+    pub fn select_choice(&mut self, generation: u64, index: usize) -> Option<SakuraChoice> {
+        if generation != self.generation {
+            return None;
+        }
+        let choice = self.choices.get(index).cloned()?;
+        self.choices.clear();
+        self.choice_deadline = None;
+        Some(choice)
+    }
+
+    /// 時刻になったイベントを取り出す
+    pub fn poll(&mut self) -> Vec<SakuraEvent> {
+        let now = self.clock.now_ms();
+        let mut events = std::mem::take(&mut self.pending);
+
+        while self.state == PlayerState::Playing && self.due_at <= now {
+            match self.steps.pop_front() {
+                None => self.state = PlayerState::Idle,
+                Some(Step::Event(PlayerEvent::End)) => {
+                    events.push(self.wrap(PlayerEvent::End));
+                    self.steps.clear();
+                    self.state = PlayerState::Idle;
+                    self.start_choice_timeout();
+                }
+                Some(Step::Event(event)) => events.push(self.wrap(event)),
+                Some(Step::Delay(ms)) => {
+                    if !self.skipping {
+                        self.due_at += ms;
+                    }
+                }
+                Some(Step::WaitClick { clear, scope }) => {
+                    if clear {
+                        self.steps
+                            .push_front(Step::Event(PlayerEvent::ClearBalloon { scope }));
+                    }
+                    events.push(self.wrap(PlayerEvent::WaitClick));
+                    self.state = PlayerState::WaitingClick;
+                    self.skipping = false;
+                }
+            }
+        }
+
+        // 一時停止中は選択肢のタイムアウトも止める
+        if self.state != PlayerState::Paused
+            && self.choice_deadline.is_some_and(|deadline| deadline <= now)
+        {
+            self.choice_deadline = None;
+            self.choices.clear();
+            let script = self.script.clone();
+            events.push(self.wrap(PlayerEvent::ChoiceTimeout { script }));
+        }
+        events
+    }
+
+    /// 選択肢を表示し終えたらタイムアウトを数え始める
+    fn start_choice_timeout(&mut self) {
+        if self.choices.is_empty() || !self.choice_timeout_enabled {
+            return;
+        }
+        if let Some(timeout) = self.choice_timeout_ms {
+            self.choice_deadline = Some(self.due_at + timeout);
+        }
+    }
+
+    /// 次のイベントまでの時間（ミリ秒、待つものが無ければ `None`）
+    pub fn next_due_in(&self) -> Option<u64> {
+        if !self.pending.is_empty() {
+            return Some(0);
+        }
+        let now = self.clock.now_ms();
+        let playing = (self.state == PlayerState::Playing).then_some(self.due_at);
+        let deadline = match self.state {
+            PlayerState::Paused => None,
+            _ => self.choice_deadline,
+        };
+        [playing, deadline]
+            .into_iter()
+            .flatten()
+            .min()
+            .map(|at| at.saturating_sub(now))
+    }
+
+    /// クリック（クリック待ちなら再開、再生中ならクリック待ちか終わりまで飛ばす）
+    pub fn click(&mut self) {
+        match self.state {
+            PlayerState::WaitingClick => {
+                self.state = PlayerState::Playing;
+                self.due_at = self.clock.now_ms();
+            }
+            PlayerState::Playing => {
+                self.skipping = true;
+                self.due_at = self.clock.now_ms();
+            }
+            PlayerState::Idle | PlayerState::Paused => {}
+        }
+    }
+
+    /// 一時停止（再生中・クリック待ち・選択肢の表示中）
+    pub fn pause(&mut self) {
+        let waiting_choice = self.state == PlayerState::Idle && self.choice_deadline.is_some();
+        if matches!(self.state, PlayerState::Playing | PlayerState::WaitingClick) || waiting_choice
+        {
+            self.paused_at = self.clock.now_ms();
+            self.paused_remaining = self.due_at.saturating_sub(self.paused_at);
+            self.paused_from = self.state;
+            self.state = PlayerState::Paused;
+        }
+    }
+
+    /// 再開（選択肢のタイムアウトは止めていた時間だけ延ばす）
+    pub fn resume(&mut self) {
+        if self.state == PlayerState::Paused {
+            let now = self.clock.now_ms();
+            self.due_at = now + self.paused_remaining;
+            if let Some(deadline) = &mut self.choice_deadline {
+                *deadline += now.saturating_sub(self.paused_at);
+            }
+            self.state = self.paused_from;
+        }
+    }
+
+    fn wrap(&self, event: PlayerEvent) -> SakuraEvent {
+        SakuraEvent {
+            generation: self.generation,
+            event,
+        }
+    }
+
+    /// トークン列を手順に変換する
+    fn schedule(&self, tokens: &[SakuraToken]) -> VecDeque<Step> {
+        let mut steps = VecDeque::new();
+        let mut scope = 0;
+        let mut quick = false;
+        let mut choice_index = 0;
+        let delay = |steps: &mut VecDeque<Step>, ms: u64, quick: bool| {
+            if !quick && ms > 0 {
+                steps.push_back(Step::Delay(ms));
+            }
+        };
+
+        for token in tokens {
+            match &token.kind {
+                SakuraTokenKind::Text(text) => {
+                    for c in text.chars() {
+                        steps.push_back(Step::Event(PlayerEvent::Text {
+                            scope,
+                            text: c.to_string(),
+                        }));
+                        delay(&mut steps, self.text_interval_ms, quick);
+                    }
+                }
+                // 値の無い環境変数はそのまま表示する
+                SakuraTokenKind::Variable { .. } => {
+                    steps.push_back(Step::Event(PlayerEvent::Text {
+                        scope,
+                        text: token.raw.clone(),
+                    }));
+                    delay(&mut steps, self.text_interval_ms, quick);
+                }
+                SakuraTokenKind::Scope(n) => {
+                    scope = *n;
+                    steps.push_back(Step::Event(PlayerEvent::Scope { scope }));
+                }
+                SakuraTokenKind::Surface(id) => {
+                    steps.push_back(Step::Event(PlayerEvent::Surface { scope, id: *id }));
+                }
+                SakuraTokenKind::Balloon(id) => {
+                    steps.push_back(Step::Event(PlayerEvent::Balloon { scope, id: *id }));
+                }
+                SakuraTokenKind::Animation { id, .. } => {
+                    steps.push_back(Step::Event(PlayerEvent::Animation { scope, id: *id }));
+                }
+                SakuraTokenKind::Action { name, args } if name.eq_ignore_ascii_case("anim") => {
+                    if let Some((id, command)) = AnimationCommand::parse(args) {
+                        steps.push_back(Step::Event(PlayerEvent::AnimationControl {
+                            scope,
+                            id,
+                            command,
+                        }));
+                    }
+                }
+                SakuraTokenKind::Action { name, args }
+                    if name.eq_ignore_ascii_case("bind") && args.len() >= 2 =>
+                {
+                    steps.push_back(Step::Event(PlayerEvent::Bind {
+                        scope,
+                        category: args[0].trim().to_string(),
+                        part: args[1].trim().to_string(),
+                        bound: match args.get(2).map(|value| value.trim()) {
+                            Some("1") | Some("true") => Some(true),
+                            Some("0") | Some("false") => Some(false),
+                            _ => None,
+                        },
+                    }));
+                }
+                SakuraTokenKind::NewLine => {
+                    steps.push_back(Step::Event(PlayerEvent::NewLine { scope, half: false }));
+                }
+                SakuraTokenKind::HalfNewLine => {
+                    steps.push_back(Step::Event(PlayerEvent::NewLine { scope, half: true }));
+                }
+                SakuraTokenKind::ClearBalloon => {
+                    steps.push_back(Step::Event(PlayerEvent::ClearBalloon { scope }));
+                }
+                SakuraTokenKind::Wait(n) => delay(&mut steps, *n as u64 * WAIT_UNIT_MS, quick),
+                SakuraTokenKind::WaitMs(ms) => delay(&mut steps, *ms as u64, quick),
+                SakuraTokenKind::WaitClick { clear } => {
+                    steps.push_back(Step::WaitClick {
+                        clear: *clear,
+                        scope,
+                    });
+                }
+                SakuraTokenKind::Choice { title, .. } => {
+                    steps.push_back(Step::Event(PlayerEvent::Choice {
+                        scope,
+                        index: choice_index,
+                        title: title.clone(),
+                    }));
+                }
+                SakuraTokenKind::ChoiceStart { .. } => {
+                    steps.push_back(Step::Event(PlayerEvent::ChoiceStart {
+                        scope,
+                        index: choice_index,
+                    }));
+                }
+                SakuraTokenKind::ChoiceEnd => {
+                    steps.push_back(Step::Event(PlayerEvent::ChoiceEnd { scope }));
+                }
+                SakuraTokenKind::AnchorStart { .. } => {
+                    steps.push_back(Step::Event(PlayerEvent::AnchorStart {
+                        scope,
+                        index: choice_index,
+                    }));
+                }
+                SakuraTokenKind::AnchorEnd => {
+                    steps.push_back(Step::Event(PlayerEvent::AnchorEnd { scope }));
+                }
+                SakuraTokenKind::Quick => quick = !quick,
+                SakuraTokenKind::Exit => steps.push_back(Step::Event(PlayerEvent::Exit)),
+                SakuraTokenKind::End => {
+                    steps.push_back(Step::Event(PlayerEvent::End));
+                    return steps;
+                }
+                _ => {}
+            }
+            if sakura_choice::is_choice_token(&token.kind) {
+                choice_index += 1;
+            }
+        }
+
+        steps.push_back(Step::Event(PlayerEvent::End));
+        steps
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn player() -> (SakuraPlayer<VirtualClock>, VirtualClock) {
+        let clock = VirtualClock::new();
+        (SakuraPlayer::new(clock.clone()), clock)
+    }
+
+    fn events(events: Vec<SakuraEvent>) -> Vec<PlayerEvent> {
+        events.into_iter().map(|event| event.event).collect()
+    }
+
+    fn text(scope: u32, text: &str) -> PlayerEvent {
+        PlayerEvent::Text {
+            scope,
+            text: text.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_text_and_waits_follow_the_clock() {
+        let (mut player, clock) = player();
+        player.play("\\0\\s[5]あい\\w2\\1う\\e無視");
+
+        assert_eq!(
+            events(player.poll()),
+            vec![
+                PlayerEvent::Scope { scope: 0 },
+                PlayerEvent::Surface { scope: 0, id: 5 },
+                text(0, "あ"),
+            ]
+        );
+        assert_eq!(player.next_due_in(), Some(50));
+
+        clock.advance(49);
+        assert!(player.poll().is_empty());
+        clock.advance(1);
+        assert_eq!(events(player.poll()), vec![text(0, "い")]);
+
+        // 「い」の後の50ミリ秒と \w2 の100ミリ秒
+        clock.advance(149);
+        assert!(player.poll().is_empty());
+        clock.advance(1);
+        assert_eq!(
+            events(player.poll()),
+            vec![PlayerEvent::Scope { scope: 1 }, text(1, "う")]
+        );
+
+        clock.advance(50);
+        assert_eq!(events(player.poll()), vec![PlayerEvent::End]);
+        assert_eq!(player.state(), PlayerState::Idle);
+    }
+
+    #[test]
+    fn test_quick_session_and_click_to_skip() {
+        let (mut player, clock) = player();
+        player.play("\\_qあい\\w9\\_qうえお\\x続き");
+        assert_eq!(
+            events(player.poll()),
+            vec![text(0, "あ"), text(0, "い"), text(0, "う")]
+        );
+
+        // クリックでクリック待ちまで飛ばす
+        player.click();
+        assert_eq!(
+            events(player.poll()),
+            vec![text(0, "え"), text(0, "お"), PlayerEvent::WaitClick]
+        );
+        assert_eq!(player.state(), PlayerState::WaitingClick);
+
+        clock.advance(1000);
+        assert!(player.poll().is_empty());
+        player.click();
+        assert_eq!(
+            events(player.poll()),
+            vec![PlayerEvent::ClearBalloon { scope: 0 }, text(0, "続")]
+        );
+    }
+
+    #[test]
+    fn test_pause_resume_and_cancel() {
+        let (mut player, clock) = player();
+        player.play("あ\\_w[1000]い");
+        player.poll();
+
+        clock.advance(400);
+        assert!(player.poll().is_empty());
+        player.pause();
+        clock.advance(5000);
+        assert!(player.poll().is_empty());
+        assert_eq!(player.next_due_in(), None);
+
+        player.resume();
+        assert_eq!(player.next_due_in(), Some(650));
+        clock.advance(650);
+        assert_eq!(events(player.poll()), vec![text(0, "い")]);
+
+        let first = player.generation();
+        player.play("\\1");
+        let next = player.poll();
+        assert_eq!(
+            next[0],
+            SakuraEvent {
+                generation: first,
+                event: PlayerEvent::Cancelled,
+            }
+        );
+        assert!(next[1..].iter().all(|event| event.generation == first + 1));
+        assert_eq!(
+            events(next),
+            vec![
+                PlayerEvent::Cancelled,
+                PlayerEvent::Scope { scope: 1 },
+                PlayerEvent::End
+            ]
+        );
+    }
+
+    #[test]
+    fn test_choice_selection() {
+        let clock = VirtualClock::new();
+        let mut player = SakuraPlayer::new(clock.clone())
+            .with_text_interval(0)
+            .with_choice_timeout(Some(1000));
+        player.play("\\q[はい,yes]\\_a[OnLink]リンク\\_a\\e");
+        assert_eq!(
+            events(player.poll()),
+            vec![
+                PlayerEvent::Choice {
+                    scope: 0,
+                    index: 0,
+                    title: "はい".to_string(),
+                },
+                PlayerEvent::AnchorStart { scope: 0, index: 1 },
+                text(0, "リ"),
+                text(0, "ン"),
+                text(0, "ク"),
+                PlayerEvent::AnchorEnd { scope: 0 },
+                PlayerEvent::End,
+            ]
+        );
+        let generation = player.generation();
+        assert_eq!(player.next_due_in(), Some(1000));
+        assert!(player.select_choice(generation + 1, 0).is_none());
+        assert_eq!(player.select_choice(generation, 1).unwrap().id, "OnLink");
+        assert!(player.select_choice(generation, 0).is_none());
+        assert_eq!(player.next_due_in(), None);
+    }
+
+    #[test]
+    fn test_choice_timeout() {
+        let clock = VirtualClock::new();
+        let mut player = SakuraPlayer::new(clock.clone())
+            .with_text_interval(0)
+            .with_choice_timeout(Some(1000));
+
+        // 選ばれなければタイムアウト
+        player.play("\\q[はい,yes]");
+        player.poll();
+        clock.advance(999);
+        assert!(player.poll().is_empty());
+        clock.advance(1);
+        assert_eq!(
+            events(player.poll()),
+            vec![PlayerEvent::ChoiceTimeout {
+                script: "\\q[はい,yes]".to_string(),
+            }]
+        );
+        assert!(player.choices().is_empty());
+        assert!(player.select_choice(player.generation(), 0).is_none());
+
+        // `\*` があればタイムアウトしない
+        player.play("\\*\\q[はい,yes]");
+        player.poll();
+        assert_eq!(player.next_due_in(), None);
+        clock.advance(10_000);
+        assert!(player.poll().is_empty());
+        assert_eq!(player.choices().len(), 1);
+    }
+
+    #[test]
+    fn test_paused_choice_does_not_time_out() {
+        let clock = VirtualClock::new();
+        let mut player = SakuraPlayer::new(clock.clone())
+            .with_text_interval(0)
+            .with_choice_timeout(Some(1000));
+        player.play("\\q[はい,yes]");
+        player.poll();
+        clock.advance(600);
+
+        // 一時停止している間は時間が進んでもタイムアウトしない
+        player.pause();
+        clock.advance(5000);
+        assert!(player.poll().is_empty());
+        assert_eq!(player.next_due_in(), None);
+        assert_eq!(player.choices().len(), 1);
+
+        // 再開すると残りの時間から数える
+        player.resume();
+        assert_eq!(player.next_due_in(), Some(400));
+        clock.advance(399);
+        assert!(player.poll().is_empty());
+        clock.advance(1);
+        assert_eq!(
+            events(player.poll()),
+            vec![PlayerEvent::ChoiceTimeout {
+                script: "\\q[はい,yes]".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_animation_control() {
+        let (mut player, _clock) = player();
+        player
+            .play("\\1\\![anim,pause,3]\\![anim,offset,3,10,-5]\\![anim,unknown,3]\\![anim,clear]\\![bind,頭,リボン,0]\\![bind,頭,帽子]");
+        assert_eq!(
+            events(player.poll()),
+            vec![
+                PlayerEvent::Scope { scope: 1 },
+                PlayerEvent::AnimationControl {
+                    scope: 1,
+                    id: 3,
+                    command: AnimationCommand::Pause,
+                },
+                PlayerEvent::AnimationControl {
+                    scope: 1,
+                    id: 3,
+                    command: AnimationCommand::Offset { x: 10, y: -5 },
+                },
+                PlayerEvent::Bind {
+                    scope: 1,
+                    category: "頭".to_string(),
+                    part: "リボン".to_string(),
+                    bound: Some(false),
+                },
+                PlayerEvent::Bind {
+                    scope: 1,
+                    category: "頭".to_string(),
+                    part: "帽子".to_string(),
+                    bound: None,
+                },
+                PlayerEvent::End,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_event_serialization() {
+        let event = SakuraEvent {
+            generation: 3,
+            event: PlayerEvent::Surface { scope: 1, id: 10 },
+        };
+        assert_eq!(
+            serde_json::to_string(&event).unwrap(),
+            r#"{"generation":3,"type":"surface","scope":1,"id":10}"#
+        );
+    }
+}
@@ -0,0 +1,579 @@
+//! さくらスクリプトのパーサ
+//!
+//! SHIORIが返したスクリプトをトークン列に分解する。
+//! 各トークンは元の文字列（`raw`）と位置（`span`）を持つので、
+//! `to_script` で元のスクリプトをそのまま復元できる。
+
+use std::ops::Range;
+
+/// 位置付きのトークン
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SakuraToken {
+    pub kind: SakuraTokenKind,
+    /// 元のスクリプト上のバイト範囲
+    pub span: Range<usize>,
+    /// 元のスクリプトの該当部分
+    pub raw: String,
+}
+
+/// トークンの種類
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SakuraTokenKind {
+    /// 通常のテキスト（`\\` `\%` はエスケープを外した状態）
+    Text(String),
+    /// `\0` `\1` `\h` `\u` `\p[n]`
+    Scope(u32),
+    /// `\s[n]` `\sN`（`-1` は非表示）
+    Surface(i32),
+    /// `\n`
+    NewLine,
+    /// `\n[half]`
+    HalfNewLine,
+    /// `\n[percent]`（行送りの割合）
+    NewLinePercent(u32),
+    /// `\w1`〜`\w9`（50ミリ秒単位）
+    Wait(u32),
+    /// `\_w[ms]`
+    WaitMs(u32),
+    /// `\b[n]` `\bN`
+    Balloon(i32),
+    /// `\i[n]` `\i[n,wait]`
+    Animation { id: u32, wait: bool },
+    /// `\q[タイトル,ID,Reference...]`
+    Choice {
+        title: String,
+        id: String,
+        references: Vec<String>,
+    },
+    /// `\__q[ID,Reference...]`（ここから `ChoiceEnd` までが選択肢）
+    ChoiceStart { id: String, references: Vec<String> },
+    /// `\__q`
+    ChoiceEnd,
+    /// `\_a[ID,Reference...]`（ここから `AnchorEnd` までがアンカー）
+    AnchorStart { id: String, references: Vec<String> },
+    /// `\_a`
+    AnchorEnd,
+    /// `\*`（選択肢のタイムアウトを無効にする）
+    NoChoiceTimeout,
+    /// `\x` `\x[noclear]`
+    WaitClick { clear: bool },
+    /// `\c`
+    ClearBalloon,
+    /// `\-`
+    Exit,
+    /// `\4`
+    MoveAway,
+    /// `\5`
+    MoveClose,
+    /// `\_s` `\_s[0,1]`（同時発話の開始・終了）
+    Sync(Vec<u32>),
+    /// `\_q`（クイックセッションの開始・終了）
+    Quick,
+    /// `\e`
+    End,
+    /// `\![コマンド,引数...]`
+    Action { name: String, args: Vec<String> },
+    /// `%username` などの環境変数（`%property[...]` は引数付き）
+    Variable { name: String, arg: Option<String> },
+    /// 上記以外のタグ（`\t` `\_l[x,y]` など）
+    Other { name: String, args: Vec<String> },
+}
+
+/// `%` に続けて解釈する環境変数（長いものを先に照合する）
+const VARIABLES: [&str; 17] = [
+    "screenheight",
+    "screenwidth",
+    "wronghour",
+    "selfname2",
+    "selfname",
+    "keroname",
+    "username",
+    "friendname",
+    "property",
+    "minute",
+    "second",
+    "month",
+    "year",
+    "hour",
+    "day",
+    "exh",
+    "et",
+];
+
+/// 1文字の数字を引数として取るタグ（`\s0` `\w9` など）
+const DIGIT_TAGS: [&str; 5] = ["s", "w", "p", "b", "i"];
+
+impl SakuraToken {
+    /// 元のスクリプトを持たないトークンを作る（`raw` は標準の書式）
+    pub fn new(kind: SakuraTokenKind) -> Self {
+        SakuraToken {
+            raw: kind.to_script(),
+            kind,
+            span: 0..0,
+        }
+    }
+}
+
+impl SakuraTokenKind {
+    /// 標準の書式でスクリプトに戻す
+    pub fn to_script(&self) -> String {
+        match self {
+            SakuraTokenKind::Text(text) => text.replace('\\', "\\\\").replace('%', "\\%"),
+            SakuraTokenKind::Scope(0) => "\\0".to_string(),
+            SakuraTokenKind::Scope(1) => "\\1".to_string(),
+            SakuraTokenKind::Scope(n) => format!("\\p[{}]", n),
+            SakuraTokenKind::Surface(n) => format!("\\s[{}]", n),
+            SakuraTokenKind::NewLine => "\\n".to_string(),
+            SakuraTokenKind::HalfNewLine => "\\n[half]".to_string(),
+            SakuraTokenKind::NewLinePercent(n) => format!("\\n[{}]", n),
+            SakuraTokenKind::Wait(n) => format!("\\w{}", n),
+            SakuraTokenKind::WaitMs(ms) => format!("\\_w[{}]", ms),
+            SakuraTokenKind::Balloon(n) => format!("\\b[{}]", n),
+            SakuraTokenKind::Animation { id, wait: false } => format!("\\i[{}]", id),
+            SakuraTokenKind::Animation { id, wait: true } => format!("\\i[{},wait]", id),
+            SakuraTokenKind::Choice {
+                title,
+                id,
+                references,
+            } => tag("q", [title, id].into_iter().chain(references)),
+            SakuraTokenKind::ChoiceStart { id, references } => {
+                tag("__q", std::iter::once(id).chain(references))
+            }
+            SakuraTokenKind::ChoiceEnd => "\\__q".to_string(),
+            SakuraTokenKind::AnchorStart { id, references } => {
+                tag("_a", std::iter::once(id).chain(references))
+            }
+            SakuraTokenKind::AnchorEnd => "\\_a".to_string(),
+            SakuraTokenKind::NoChoiceTimeout => "\\*".to_string(),
+            SakuraTokenKind::WaitClick { clear: true } => "\\x".to_string(),
+            SakuraTokenKind::WaitClick { clear: false } => "\\x[noclear]".to_string(),
+            SakuraTokenKind::ClearBalloon => "\\c".to_string(),
+            SakuraTokenKind::Exit => "\\-".to_string(),
+            SakuraTokenKind::MoveAway => "\\4".to_string(),
+            SakuraTokenKind::MoveClose => "\\5".to_string(),
+            SakuraTokenKind::Sync(scopes) if scopes.is_empty() => "\\_s".to_string(),
+            SakuraTokenKind::Sync(scopes) => {
+                let scopes: Vec<String> = scopes.iter().map(u32::to_string).collect();
+                tag("_s", &scopes)
+            }
+            SakuraTokenKind::Quick => "\\_q".to_string(),
+            SakuraTokenKind::End => "\\e".to_string(),
+            SakuraTokenKind::Action { name, args } => tag("!", std::iter::once(name).chain(args)),
+            SakuraTokenKind::Variable { name, arg: None } => format!("%{}", name),
+            SakuraTokenKind::Variable {
+                name,
+                arg: Some(arg),
+            } => format!("%{}[{}]", name, arg),
+            SakuraTokenKind::Other { name, args } if args.is_empty() => format!("\\{}", name),
+            SakuraTokenKind::Other { name, args } => tag(name, args),
+        }
+    }
+}
+
+/// `\name[arg,...]` を組み立てる（必要なら引数を引用符で囲む）
+fn tag<'a>(name: &str, args: impl IntoIterator<Item = &'a String>) -> String {
+    let args: Vec<String> = args.into_iter().map(|arg| quote_arg(arg)).collect();
+    format!("\\{}[{}]", name, args.join(","))
+}
+
+fn quote_arg(arg: &str) -> String {
+    let arg = arg.replace('\\', "\\\\");
+    if arg.contains([',', ']', '"']) {
+        format!("\"{}\"", arg.replace('"', "\"\""))
+    } else {
+        arg
+    }
+}
+
+/// トークン列をスクリプトに戻す
+pub fn to_script(tokens: &[SakuraToken]) -> String {
+    tokens.iter().map(|token| token.raw.as_str()).collect()
+}
+
+/// スクリプトをトークン列に分解する
+///
+/// 解釈できない部分もテキストか `Other` として残すため、失敗はしない。
+pub fn parse(script: &str) -> Vec<SakuraToken> {
+    Parser {
+        script,
+        pos: 0,
+        tokens: Vec::new(),
+    }
+    .run()
+}
+
+struct Parser<'a> {
+    script: &'a str,
+    pos: usize,
+    tokens: Vec<SakuraToken>,
+}
+
+impl Parser<'_> {
+    fn run(mut self) -> Vec<SakuraToken> {
+        while self.pos < self.script.len() {
+            let start = self.pos;
+            let rest = &self.script[start..];
+
+            if let Some(after) = rest.strip_prefix('\\') {
+                if let Some(escaped) = after.chars().next().filter(|c| *c == '\\' || *c == '%') {
+                    self.pos += 1 + escaped.len_utf8();
+                    self.push_text(start, escaped.to_string());
+                    continue;
+                }
+                if let Some(kind) = self.tag() {
+                    self.push(start, kind);
+                    continue;
+                }
+                self.pos = start + 1;
+                self.push_text(start, "\\".to_string());
+            } else if rest.starts_with('%') {
+                if let Some(kind) = self.variable() {
+                    self.push(start, kind);
+                    continue;
+                }
+                self.pos = start + 1;
+                self.push_text(start, "%".to_string());
+            } else {
+                let len = rest.find(['\\', '%']).unwrap_or(rest.len());
+                self.pos += len;
+                self.push_text(start, rest[..len].to_string());
+            }
+        }
+        self.tokens
+    }
+
+    fn push(&mut self, start: usize, kind: SakuraTokenKind) {
+        self.tokens.push(SakuraToken {
+            kind,
+            span: start..self.pos,
+            raw: self.script[start..self.pos].to_string(),
+        });
+    }
+
+    /// テキストは直前のテキストにつなげる
+    fn push_text(&mut self, start: usize, text: String) {
+        match self.tokens.last_mut() {
+            Some(SakuraToken {
+                kind: SakuraTokenKind::Text(previous),
+                span,
+                raw,
+            }) if span.end == start => {
+                previous.push_str(&text);
+                span.end = self.pos;
+                raw.push_str(&self.script[start..self.pos]);
+            }
+            _ => self.push(start, SakuraTokenKind::Text(text)),
+        }
+    }
+
+    /// `\` の直後から1つのタグを読む（失敗したら位置を戻して `None`）
+    fn tag(&mut self) -> Option<SakuraTokenKind> {
+        let start = self.pos;
+        let rest = &self.script[start + 1..];
+        let underscores = (rest.len() - rest.trim_start_matches('_').len()).min(2);
+        let letter = rest[underscores..].chars().next()?;
+        let name_len = underscores + letter.len_utf8();
+        let name = rest[..name_len].to_string();
+        self.pos = start + 1 + name_len;
+
+        let args = if self.script[self.pos..].starts_with('[') {
+            match self.bracket_args() {
+                Some(args) => Some(args),
+                None => {
+                    self.pos = start;
+                    return None;
+                }
+            }
+        } else if DIGIT_TAGS.contains(&name.as_str()) {
+            let digit = self.script[self.pos..]
+                .chars()
+                .next()
+                .filter(char::is_ascii_digit);
+            digit.map(|digit| {
+                self.pos += 1;
+                vec![digit.to_string()]
+            })
+        } else {
+            None
+        };
+
+        let kind = interpret(&name, args);
+        if kind.is_none() {
+            self.pos = start;
+        }
+        kind
+    }
+
+    /// `[...]` の引数を読む（引用符 `"..."` と `\]` などのエスケープに対応）
+    fn bracket_args(&mut self) -> Option<Vec<String>> {
+        let mut args = Vec::new();
+        let mut current = String::new();
+        let mut quoted = false;
+        let mut chars = self.script[self.pos + 1..].char_indices().peekable();
+
+        while let Some((offset, c)) = chars.next() {
+            match c {
+                '"' if quoted && chars.peek().map(|(_, c)| *c) == Some('"') => {
+                    chars.next();
+                    current.push('"');
+                }
+                '"' => quoted = !quoted,
+                '\\' if matches!(chars.peek(), Some((_, '\\' | ']' | ',' | '"'))) => {
+                    current.push(chars.next()?.1);
+                }
+                ',' if !quoted => args.push(std::mem::take(&mut current)),
+                ']' if !quoted => {
+                    args.push(current);
+                    self.pos += 1 + offset + 1;
+                    return Some(args);
+                }
+                c => current.push(c),
+            }
+        }
+        None
+    }
+
+    /// `%` の直後から環境変数名を読む
+    fn variable(&mut self) -> Option<SakuraTokenKind> {
+        let rest = &self.script[self.pos + 1..];
+        let name = VARIABLES.iter().find(|name| rest.starts_with(*name))?;
+        let mut len = 1 + name.len();
+
+        let mut arg = None;
+        if *name == "property" {
+            let rest = &rest[name.len()..];
+            let end = rest.strip_prefix('[').and_then(|inner| inner.find(']'))?;
+            arg = Some(rest[1..end + 1].to_string());
+            len += end + 2;
+        }
+        self.pos += len;
+
+        Some(SakuraTokenKind::Variable {
+            name: name.to_string(),
+            arg,
+        })
+    }
+}
+
+/// 最初の引数を数値として読む
+fn number<T: std::str::FromStr>(args: &[String]) -> Option<T> {
+    args.first().and_then(|arg| arg.trim().parse().ok())
+}
+
+/// タグ名と引数からトークンを決める
+fn interpret(name: &str, args: Option<Vec<String>>) -> Option<SakuraTokenKind> {
+    let kind = match (name, args) {
+        ("0" | "h", None) => SakuraTokenKind::Scope(0),
+        ("1" | "u", None) => SakuraTokenKind::Scope(1),
+        ("p", Some(args)) => SakuraTokenKind::Scope(number(&args)?),
+        ("s", Some(args)) => SakuraTokenKind::Surface(number(&args)?),
+        ("n", None) => SakuraTokenKind::NewLine,
+        ("n", Some(args)) if args.first().map(String::as_str) == Some("half") => {
+            SakuraTokenKind::HalfNewLine
+        }
+        ("n", Some(args)) => SakuraTokenKind::NewLinePercent(number(&args)?),
+        ("w", Some(args)) => SakuraTokenKind::Wait(number(&args)?),
+        ("_w", Some(args)) => SakuraTokenKind::WaitMs(number(&args)?),
+        ("b", Some(args)) => SakuraTokenKind::Balloon(number(&args)?),
+        ("i", Some(args)) => SakuraTokenKind::Animation {
+            id: number(&args)?,
+            wait: args.get(1).map(String::as_str) == Some("wait"),
+        },
+        ("q", Some(mut args)) if args.len() >= 2 => {
+            let references = args.split_off(2);
+            let id = args.pop()?;
+            let title = args.pop()?;
+            SakuraTokenKind::Choice {
+                title,
+                id,
+                references,
+            }
+        }
+        ("__q", None) => SakuraTokenKind::ChoiceEnd,
+        ("__q", Some(mut args)) => {
+            let references = args.split_off(1);
+            SakuraTokenKind::ChoiceStart {
+                id: args.pop()?,
+                references,
+            }
+        }
+        ("_a", None) => SakuraTokenKind::AnchorEnd,
+        ("_a", Some(mut args)) => {
+            let references = args.split_off(1);
+            SakuraTokenKind::AnchorStart {
+                id: args.pop()?,
+                references,
+            }
+        }
+        ("*", None) => SakuraTokenKind::NoChoiceTimeout,
+        ("x", None) => SakuraTokenKind::WaitClick { clear: true },
+        ("x", Some(args)) => SakuraTokenKind::WaitClick {
+            clear: args.first().map(String::as_str) != Some("noclear"),
+        },
+        ("c", None) => SakuraTokenKind::ClearBalloon,
+        ("-", None) => SakuraTokenKind::Exit,
+        ("4", None) => SakuraTokenKind::MoveAway,
+        ("5", None) => SakuraTokenKind::MoveClose,
+        ("_s", None) => SakuraTokenKind::Sync(Vec::new()),
+        ("_s", Some(args)) => SakuraTokenKind::Sync(
+            args.iter()
+                .map(|arg| arg.trim().parse().ok())
+                .collect::<Option<Vec<u32>>>()?,
+        ),
+        ("_q", None) => SakuraTokenKind::Quick,
+        ("e", None) => SakuraTokenKind::End,
+        ("!", Some(mut args)) if !args.is_empty() => {
+            let rest = args.split_off(1);
+            SakuraTokenKind::Action {
+                name: args.pop()?,
+                args: rest,
+            }
+        }
+        ("!", _) => return None,
+        (name, args) => SakuraTokenKind::Other {
+            name: name.to_string(),
+            args: args.unwrap_or_default(),
+        },
+    };
+    Some(kind)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(script: &str) -> Vec<SakuraTokenKind> {
+        parse(script).into_iter().map(|token| token.kind).collect()
+    }
+
+    fn text(text: &str) -> SakuraTokenKind {
+        SakuraTokenKind::Text(text.to_string())
+    }
+
+    #[test]
+    fn test_basic_tags() {
+        assert_eq!(
+            kinds("\\h\\s0こんにちは\\w9\\n[half]\\u\\s[-1]\\p[2]\\_w[300]\\n\\e"),
+            vec![
+                SakuraTokenKind::Scope(0),
+                SakuraTokenKind::Surface(0),
+                text("こんにちは"),
+                SakuraTokenKind::Wait(9),
+                SakuraTokenKind::HalfNewLine,
+                SakuraTokenKind::Scope(1),
+                SakuraTokenKind::Surface(-1),
+                SakuraTokenKind::Scope(2),
+                SakuraTokenKind::WaitMs(300),
+                SakuraTokenKind::NewLine,
+                SakuraTokenKind::End,
+            ]
+        );
+        assert_eq!(
+            kinds("\\b[2]\\i[5,wait]\\x\\c\\-\\4\\5\\_s\\_q\\*\\t"),
+            vec![
+                SakuraTokenKind::Balloon(2),
+                SakuraTokenKind::Animation { id: 5, wait: true },
+                SakuraTokenKind::WaitClick { clear: true },
+                SakuraTokenKind::ClearBalloon,
+                SakuraTokenKind::Exit,
+                SakuraTokenKind::MoveAway,
+                SakuraTokenKind::MoveClose,
+                SakuraTokenKind::Sync(Vec::new()),
+                SakuraTokenKind::Quick,
+                SakuraTokenKind::NoChoiceTimeout,
+                SakuraTokenKind::Other {
+                    name: "t".to_string(),
+                    args: Vec::new(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_choices_and_anchors() {
+        assert_eq!(
+            kinds("\\q[はい,OnYes,r0,r1]\\__q[OnNo]いいえ\\__q\\_a[http://example.com]リンク\\_a"),
+            vec![
+                SakuraTokenKind::Choice {
+                    title: "はい".to_string(),
+                    id: "OnYes".to_string(),
+                    references: vec!["r0".to_string(), "r1".to_string()],
+                },
+                SakuraTokenKind::ChoiceStart {
+                    id: "OnNo".to_string(),
+                    references: Vec::new(),
+                },
+                text("いいえ"),
+                SakuraTokenKind::ChoiceEnd,
+                SakuraTokenKind::AnchorStart {
+                    id: "http://example.com".to_string(),
+                    references: Vec::new(),
+                },
+                text("リンク"),
+                SakuraTokenKind::AnchorEnd,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_quoted_arguments_and_escapes() {
+        assert_eq!(
+            kinds(r#"\![raise,OnTest,"a,b","say ""hi""",c\]d]"#),
+            vec![SakuraTokenKind::Action {
+                name: "raise".to_string(),
+                args: vec![
+                    "OnTest".to_string(),
+                    "a,b".to_string(),
+                    "say \"hi\"".to_string(),
+                    "c]d".to_string(),
+                ],
+            }]
+        );
+        assert_eq!(kinds("C:\\\\dir 100\\%"), vec![text("C:\\dir 100%")]);
+        // 閉じていない括弧と不明な `%` はテキストのまま
+        assert_eq!(kinds("\\s[0 50%"), vec![text("\\s[0 50%")]);
+    }
+
+    #[test]
+    fn test_variables() {
+        assert_eq!(
+            kinds("%selfname2と%usernameさん、%property[currentghost.name]"),
+            vec![
+                SakuraTokenKind::Variable {
+                    name: "selfname2".to_string(),
+                    arg: None,
+                },
+                text("と"),
+                SakuraTokenKind::Variable {
+                    name: "username".to_string(),
+                    arg: None,
+                },
+                text("さん、"),
+                SakuraTokenKind::Variable {
+                    name: "property".to_string(),
+                    arg: Some("currentghost.name".to_string()),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_lossless_round_trip_and_spans() {
+        let script = "\\0\\s0%month月\\w5\\![open,\"x,y\"]\\q[a,b]\\\\\\%\\zz\\";
+        let tokens = parse(script);
+        assert_eq!(to_script(&tokens), script);
+        for token in &tokens {
+            assert_eq!(&script[token.span.clone()], token.raw);
+        }
+
+        // 標準の書式に戻しても同じ意味になる
+        let canonical: Vec<SakuraToken> = tokens
+            .iter()
+            .map(|token| SakuraToken::new(token.kind.clone()))
+            .collect();
+        assert_eq!(
+            kinds(&to_script(&canonical)),
+            tokens.into_iter().map(|t| t.kind).collect::<Vec<_>>()
+        );
+    }
+}
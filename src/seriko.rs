@@ -0,0 +1,903 @@
+//! SERIKO（サーフェスのアニメーション）
+//!
+//! サーフェスの `animationN.*` の定義から、キャラクターごとにいつどのパターンを重ねるかを決める。
+//! 時刻は `PlayerClock` から取るので、`SakuraPlayer` と同じく `VirtualClock` でテストできる。
+
+use crate::sakura_player::{AnimationCommand, PlayerClock, PlayerEvent};
+use crate::surfaces::{Animation, Interval, Method, Pattern, ShellDefinition};
+use serde::Serialize;
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeMap, BTreeSet};
+use std::hash::BuildHasher;
+
+/// `sometimes` `random` `periodic` を判定する間隔（ミリ秒）
+const CHECK_INTERVAL_MS: u64 = 1000;
+
+/// `poll` 1回で進めるパターンの上限（ウェイト0のループで止まらないように）
+const MAX_STEPS_PER_POLL: usize = 1000;
+
+/// 重ねるパターン1つ
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct SerikoFrame {
+    pub method: Method,
+    pub surface: i32,
+    pub x: i32,
+    pub y: i32,
+}
+
+/// フロントエンドに送るイベント
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum SerikoEvent {
+    /// アニメーションの表示が変わった（`frames` が空なら何も重ねない）
+    Frame {
+        scope: u32,
+        surface: u32,
+        animation: u32,
+        frames: Vec<SerikoFrame>,
+    },
+    /// 最後のパターンまで進んだか、止められた
+    End { scope: u32, animation: u32 },
+}
+
+/// 再生中のアニメーション
+#[derive(Debug, Clone)]
+struct Running {
+    /// 次に表示するパターンの位置
+    next: usize,
+    due_at: u64,
+    /// 一時停止した時点での残り時間
+    paused: Option<u64>,
+}
+
+/// キャラクター1人分の状態
+#[derive(Debug, Clone, Default)]
+struct ScopeState {
+    surface: Option<u32>,
+    running: BTreeMap<u32, Running>,
+    /// アニメーションごとに重ねているパターン
+    frames: BTreeMap<u32, Vec<SerikoFrame>>,
+    /// `\![anim,offset,...]` でずらした位置
+    offsets: BTreeMap<u32, (i32, i32)>,
+    /// 次に `sometimes` などを判定する時刻と、サーフェスを変えてからの判定回数
+    next_check: u64,
+    ticks: u64,
+    /// `talk` のアニメーションごとのしゃべった文字数
+    talked: BTreeMap<u32, u32>,
+}
+
+/// 乱数（xorshift、シードを決めればテストで同じ結果になる）
+#[derive(Debug, Clone)]
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Rng(seed.max(1))
+    }
+
+    /// 0以上 `n` 未満
+    fn below(&mut self, n: u32) -> u32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 % n.max(1) as u64) as u32
+    }
+}
+
+/// シェルの descript.txt で最初から付けている着せ替え
+fn default_binds(definition: &ShellDefinition) -> BTreeSet<(u32, u32)> {
+    definition
+        .bind_groups
+        .iter()
+        .filter(|group| group.default)
+        .map(|group| (group.scope, group.id))
+        .collect()
+}
+
+/// SERIKOのスケジューラ
+pub struct SerikoScheduler<C: PlayerClock> {
+    clock: C,
+    definition: ShellDefinition,
+    rng: Rng,
+    scopes: BTreeMap<u32, ScopeState>,
+    /// 着せ替えで付けているアニメーション（キャラクター、ID）
+    binds: BTreeSet<(u32, u32)>,
+    /// 次の `poll` で返すイベント
+    pending: Vec<SerikoEvent>,
+}
+
+impl<C: PlayerClock> SerikoScheduler<C> {
+    pub fn new(clock: C, definition: ShellDefinition) -> Self {
+        SerikoScheduler {
+            clock,
+            rng: Rng::new(RandomState::new().hash_one(0)),
+            scopes: BTreeMap::new(),
+            binds: default_binds(&definition),
+            definition,
+            pending: Vec::new(),
+        }
+    }
+
+    /// 乱数のシードを決める（テスト用）
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.rng = Rng::new(seed);
+        self
+    }
+
+    pub fn definition(&self) -> &ShellDefinition {
+        &self.definition
+    }
+
+    /// シェルを切り替える（表示中のサーフェスのアニメーションは最初からやり直す、着せ替えは既定に戻す）
+    pub fn set_definition(&mut self, definition: ShellDefinition) {
+        self.binds = default_binds(&definition);
+        self.definition = definition;
+        let surfaces: Vec<(u32, i32)> = self
+            .scopes
+            .iter()
+            .map(|(scope, state)| (*scope, state.surface.map_or(-1, |id| id as i32)))
+            .collect();
+        for (scope, surface) in surfaces {
+            self.set_surface(scope, surface);
+        }
+    }
+
+    /// 表示中のサーフェス
+    pub fn surface(&self, scope: u32) -> Option<u32> {
+        self.scopes.get(&scope)?.surface
+    }
+
+    /// 重ねているパターン（アニメーションIDの順）
+    pub fn active_frames(&self, scope: u32) -> Vec<SerikoFrame> {
+        let Some(state) = self.scopes.get(&scope) else {
+            return Vec::new();
+        };
+        state
+            .frames
+            .keys()
+            .flat_map(|id| Self::offset_frames(state, *id))
+            .collect()
+    }
+
+    /// サーフェスを変える（-1なら非表示）。`runonce` `always` と着せ替えはここで始まる
+    pub fn set_surface(&mut self, scope: u32, id: i32) {
+        let now = self.clock.now_ms();
+        let state = self.scopes.entry(scope).or_default();
+        *state = ScopeState {
+            surface: u32::try_from(id).ok(),
+            next_check: now + CHECK_INTERVAL_MS,
+            ..ScopeState::default()
+        };
+
+        for (animation, intervals) in self.intervals(scope) {
+            if intervals.contains(&Interval::Bind) {
+                if self.binds.contains(&(scope, animation)) {
+                    self.show_bind(scope, animation);
+                }
+                continue;
+            }
+            if intervals
+                .iter()
+                .any(|interval| matches!(interval, Interval::Runonce | Interval::Always))
+            {
+                self.trigger(scope, animation);
+            }
+        }
+    }
+
+    /// アニメーションを最初から始める（`\i[ID]`）
+    pub fn start(&mut self, scope: u32, id: u32) {
+        let Some(animation) = self.animation(scope, id) else {
+            return;
+        };
+        if animation.intervals.contains(&Interval::Bind) && !self.binds.contains(&(scope, id)) {
+            return;
+        }
+        let exclusive = animation.has_option("exclusive");
+        let Some(first) = animation
+            .patterns
+            .values()
+            .next()
+            .map(|pattern| pattern.wait)
+        else {
+            return;
+        };
+
+        if exclusive {
+            let others: Vec<u32> = self.scopes[&scope]
+                .running
+                .keys()
+                .copied()
+                .filter(|other| *other != id)
+                .collect();
+            for other in others {
+                self.stop(scope, other);
+            }
+        }
+        let due_at = self.clock.now_ms() + self.wait(first.min, first.max);
+        if let Some(state) = self.scopes.get_mut(&scope) {
+            state.running.insert(
+                id,
+                Running {
+                    next: 0,
+                    due_at,
+                    paused: None,
+                },
+            );
+        }
+    }
+
+    /// アニメーションを止めて、重ねていたものを消す
+    pub fn stop(&mut self, scope: u32, id: u32) {
+        let Some(state) = self.scopes.get_mut(&scope) else {
+            return;
+        };
+        let was_running = state.running.remove(&id).is_some();
+        let had_frames = state
+            .frames
+            .remove(&id)
+            .is_some_and(|frames| !frames.is_empty());
+        state.offsets.remove(&id);
+        let surface = state.surface.unwrap_or_default();
+        if had_frames {
+            self.pending.push(SerikoEvent::Frame {
+                scope,
+                surface,
+                animation: id,
+                frames: Vec::new(),
+            });
+        }
+        if was_running {
+            self.pending.push(SerikoEvent::End {
+                scope,
+                animation: id,
+            });
+        }
+    }
+
+    pub fn pause(&mut self, scope: u32, id: u32) {
+        let now = self.clock.now_ms();
+        if let Some(running) = self.running_mut(scope, id) {
+            let remaining = running.due_at.saturating_sub(now);
+            running.paused.get_or_insert(remaining);
+        }
+    }
+
+    pub fn resume(&mut self, scope: u32, id: u32) {
+        let now = self.clock.now_ms();
+        if let Some(running) = self.running_mut(scope, id) {
+            running.due_at = running
+                .paused
+                .take()
+                .map_or(running.due_at, |remaining| now + remaining);
+        }
+    }
+
+    /// 重ねる位置をずらす（`\![anim,offset,ID,x,y]`）
+    pub fn set_offset(&mut self, scope: u32, id: u32, x: i32, y: i32) {
+        let Some(state) = self.scopes.get_mut(&scope) else {
+            return;
+        };
+        state.offsets.insert(id, (x, y));
+        if state.frames.contains_key(&id) {
+            self.push_frame(scope, id);
+        }
+    }
+
+    /// 着せ替えを付け外しする
+    pub fn set_bind(&mut self, scope: u32, id: u32, bound: bool) {
+        if bound {
+            self.binds.insert((scope, id));
+        } else {
+            self.binds.remove(&(scope, id));
+        }
+        let is_bind = self
+            .animation(scope, id)
+            .is_some_and(|animation| animation.intervals.contains(&Interval::Bind));
+        match (is_bind, bound) {
+            (false, _) => {}
+            (true, true) => self.show_bind(scope, id),
+            (true, false) => self.stop(scope, id),
+        }
+    }
+
+    /// 着せ替えを名前で付け外しする（`bound` が `None` なら切り替える）
+    pub fn set_bind_by_name(
+        &mut self,
+        scope: u32,
+        category: &str,
+        part: &str,
+        bound: Option<bool>,
+    ) {
+        let ids: Vec<u32> = self
+            .definition
+            .bind_groups
+            .iter()
+            .filter(|group| {
+                group.scope == scope && group.category == category && group.part == part
+            })
+            .map(|group| group.id)
+            .collect();
+        for id in ids {
+            let bound = bound.unwrap_or(!self.binds.contains(&(scope, id)));
+            self.set_bind(scope, id, bound);
+        }
+    }
+
+    /// プレイヤーのイベントを反映する（`\s` `\i` `\![anim,...]`、しゃべった文字数、`\e`）
+    pub fn apply(&mut self, event: &PlayerEvent) {
+        match event {
+            PlayerEvent::Surface { scope, id } => self.set_surface(*scope, *id),
+            PlayerEvent::Animation { scope, id } => self.start(*scope, *id),
+            PlayerEvent::AnimationControl { scope, id, command } => match *command {
+                AnimationCommand::Clear => self.stop(*scope, *id),
+                AnimationCommand::Pause => self.pause(*scope, *id),
+                AnimationCommand::Resume => self.resume(*scope, *id),
+                AnimationCommand::Offset { x, y } => self.set_offset(*scope, *id, x, y),
+            },
+            PlayerEvent::Bind {
+                scope,
+                category,
+                part,
+                bound,
+            } => self.set_bind_by_name(*scope, category, part, *bound),
+            PlayerEvent::Text { scope, text } => self.talk(*scope, text.chars().count() as u32),
+            PlayerEvent::End => {
+                let scopes: Vec<u32> = self.scopes.keys().copied().collect();
+                for scope in scopes {
+                    for (animation, intervals) in self.intervals(scope) {
+                        if intervals.contains(&Interval::YenE) {
+                            self.trigger(scope, animation);
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// 時刻になったパターンを進めて、イベントを取り出す
+    pub fn poll(&mut self) -> Vec<SerikoEvent> {
+        let now = self.clock.now_ms();
+        self.check_intervals(now);
+
+        for _ in 0..MAX_STEPS_PER_POLL {
+            let due = self
+                .scopes
+                .iter()
+                .flat_map(|(scope, state)| {
+                    state
+                        .running
+                        .iter()
+                        .filter(|(_, running)| running.paused.is_none())
+                        .map(move |(id, running)| (running.due_at, *scope, *id))
+                })
+                .filter(|(due_at, _, _)| *due_at <= now)
+                .min();
+            match due {
+                Some((_, scope, id)) => self.advance(scope, id),
+                None => break,
+            }
+        }
+        std::mem::take(&mut self.pending)
+    }
+
+    /// 次のイベントまでの時間（ミリ秒、待つものが無ければ `None`）
+    pub fn next_due_in(&self) -> Option<u64> {
+        if !self.pending.is_empty() {
+            return Some(0);
+        }
+        let now = self.clock.now_ms();
+        let running = self.scopes.values().flat_map(|state| {
+            state
+                .running
+                .values()
+                .filter(|running| running.paused.is_none())
+                .map(|running| running.due_at)
+        });
+        let checks = self.scopes.iter().filter_map(|(scope, state)| {
+            let timed = self.intervals(*scope).iter().any(|(_, intervals)| {
+                intervals.iter().any(|interval| {
+                    matches!(
+                        interval,
+                        Interval::Sometimes
+                            | Interval::Rarely
+                            | Interval::Random(_)
+                            | Interval::Periodic(_)
+                    )
+                })
+            });
+            timed.then_some(state.next_check)
+        });
+        running.chain(checks).min().map(|at| at.saturating_sub(now))
+    }
+
+    /// 表示中のサーフェスのアニメーション
+    fn animation(&self, scope: u32, id: u32) -> Option<&Animation> {
+        let surface = self.surface(scope)?;
+        self.definition.surface(surface)?.animations.get(&id)
+    }
+
+    /// 表示中のサーフェスのアニメーションIDとタイミング
+    fn intervals(&self, scope: u32) -> Vec<(u32, Vec<Interval>)> {
+        self.surface(scope)
+            .and_then(|surface| self.definition.surface(surface))
+            .map(|surface| {
+                surface
+                    .animations
+                    .iter()
+                    .map(|(id, animation)| (*id, animation.intervals.clone()))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    fn running_mut(&mut self, scope: u32, id: u32) -> Option<&mut Running> {
+        self.scopes.get_mut(&scope)?.running.get_mut(&id)
+    }
+
+    /// タイミングによる開始（再生中のものや、排他のアニメーションの最中は始めない）
+    fn trigger(&mut self, scope: u32, id: u32) {
+        let Some(state) = self.scopes.get(&scope) else {
+            return;
+        };
+        let blocked = state.running.contains_key(&id)
+            || state.running.keys().any(|other| {
+                self.animation(scope, *other)
+                    .is_some_and(|animation| animation.has_option("exclusive"))
+            });
+        if !blocked {
+            self.start(scope, id);
+        }
+    }
+
+    /// `sometimes` `rarely` `random` `periodic` を1秒ごとに判定する
+    fn check_intervals(&mut self, now: u64) {
+        let scopes: Vec<u32> = self.scopes.keys().copied().collect();
+        for scope in scopes {
+            let animations = self.intervals(scope);
+            while let Some(state) = self.scopes.get_mut(&scope) {
+                if state.next_check > now {
+                    break;
+                }
+                state.next_check += CHECK_INTERVAL_MS;
+                state.ticks += 1;
+                let ticks = state.ticks;
+
+                for (animation, intervals) in &animations {
+                    if intervals.contains(&Interval::Bind)
+                        && !self.binds.contains(&(scope, *animation))
+                    {
+                        continue;
+                    }
+                    let fires = intervals.iter().any(|interval| match interval {
+                        Interval::Sometimes => self.rng.below(2) == 0,
+                        Interval::Rarely => self.rng.below(4) == 0,
+                        Interval::Random(n) => self.rng.below(*n) == 0,
+                        Interval::Periodic(n) => ticks % (*n).max(1) as u64 == 0,
+                        _ => false,
+                    });
+                    if fires {
+                        self.trigger(scope, *animation);
+                    }
+                }
+            }
+        }
+    }
+
+    /// `talk,N` はN文字しゃべるごとに始まる
+    fn talk(&mut self, scope: u32, chars: u32) {
+        for (animation, intervals) in self.intervals(scope) {
+            let Some(limit) = intervals.iter().find_map(|interval| match interval {
+                Interval::Talk(n) => Some(*n),
+                _ => None,
+            }) else {
+                continue;
+            };
+            let Some(state) = self.scopes.get_mut(&scope) else {
+                return;
+            };
+            let talked = state.talked.entry(animation).or_default();
+            *talked += chars;
+            if *talked >= limit {
+                *talked = 0;
+                self.trigger(scope, animation);
+            }
+        }
+    }
+
+    /// 次のパターンを表示する
+    fn advance(&mut self, scope: u32, id: u32) {
+        let Some(animation) = self.animation(scope, id).cloned() else {
+            if let Some(state) = self.scopes.get_mut(&scope) {
+                state.running.remove(&id);
+            }
+            return;
+        };
+        let Some(Running { next, due_at, .. }) = self.running_mut(scope, id).cloned() else {
+            return;
+        };
+        let patterns: Vec<&Pattern> = animation.patterns.values().collect();
+        if let Some(pattern) = patterns.get(next) {
+            self.show_pattern(scope, id, pattern);
+        }
+
+        // パターンの中で止められていなければ次へ
+        if self.running_mut(scope, id).is_none() {
+            return;
+        }
+        let following = match patterns.get(next + 1) {
+            Some(pattern) => Some((next + 1, pattern.wait)),
+            None if animation.intervals.contains(&Interval::Always) => {
+                patterns.first().map(|pattern| (0, pattern.wait))
+            }
+            None => None,
+        };
+        match following {
+            Some((index, wait)) => {
+                // ウェイト0のループで時刻が進まなくならないように
+                let delay = self.wait(wait.min, wait.max).max((index == 0) as u64);
+                if let Some(running) = self.running_mut(scope, id) {
+                    running.next = index;
+                    running.due_at = due_at + delay;
+                }
+            }
+            None => {
+                if let Some(state) = self.scopes.get_mut(&scope) {
+                    state.running.remove(&id);
+                }
+                self.pending.push(SerikoEvent::End {
+                    scope,
+                    animation: id,
+                });
+            }
+        }
+    }
+
+    /// パターン1つを反映する（start系は他のアニメーションを操作する）
+    fn show_pattern(&mut self, scope: u32, id: u32, pattern: &Pattern) {
+        let ids = &pattern.animation_ids;
+        match pattern.method {
+            Method::Start | Method::Insert => {
+                ids.iter().for_each(|other| self.start(scope, *other))
+            }
+            Method::ParallelStart => ids.iter().for_each(|other| self.start(scope, *other)),
+            Method::Stop | Method::AlternativeStop | Method::ParallelStop => {
+                ids.iter().for_each(|other| self.stop(scope, *other))
+            }
+            Method::AlternativeStart => {
+                if !ids.is_empty() {
+                    let other = ids[self.rng.below(ids.len() as u32) as usize];
+                    self.start(scope, other);
+                }
+            }
+            _ if pattern.surface == -2 => {
+                let running: Vec<u32> = self.scopes[&scope].running.keys().copied().collect();
+                for other in running {
+                    self.stop(scope, other);
+                }
+            }
+            _ => {
+                let Some(state) = self.scopes.get_mut(&scope) else {
+                    return;
+                };
+                let frames = state.frames.entry(id).or_default();
+                let frame = SerikoFrame {
+                    method: pattern.method,
+                    surface: pattern.surface,
+                    x: pattern.x,
+                    y: pattern.y,
+                };
+                match pattern.method {
+                    _ if pattern.surface == -1 => frames.clear(),
+                    Method::Add => frames.push(frame),
+                    _ => *frames = vec![frame],
+                }
+                self.push_frame(scope, id);
+            }
+        }
+    }
+
+    /// 着せ替えは全てのパターンを重ねたまま表示する
+    fn show_bind(&mut self, scope: u32, id: u32) {
+        let Some(animation) = self.animation(scope, id).cloned() else {
+            return;
+        };
+        if let Some(state) = self.scopes.get_mut(&scope) {
+            state.frames.insert(id, Vec::new());
+        }
+        for pattern in animation.patterns.values() {
+            let mut pattern = pattern.clone();
+            if pattern.method != Method::Base && !pattern.method.is_control() {
+                pattern.method = Method::Add;
+            }
+            self.show_pattern(scope, id, &pattern);
+        }
+    }
+
+    fn push_frame(&mut self, scope: u32, id: u32) {
+        let Some(state) = self.scopes.get(&scope) else {
+            return;
+        };
+        self.pending.push(SerikoEvent::Frame {
+            scope,
+            surface: state.surface.unwrap_or_default(),
+            animation: id,
+            frames: Self::offset_frames(state, id),
+        });
+    }
+
+    fn offset_frames(state: &ScopeState, id: u32) -> Vec<SerikoFrame> {
+        let (dx, dy) = state.offsets.get(&id).copied().unwrap_or_default();
+        state
+            .frames
+            .get(&id)
+            .into_iter()
+            .flatten()
+            .map(|frame| SerikoFrame {
+                x: frame.x + dx,
+                y: frame.y + dy,
+                ..*frame
+            })
+            .collect()
+    }
+
+    /// `min`〜`max` のウェイト（ミリ秒）
+    fn wait(&mut self, min: u32, max: u32) -> u64 {
+        (min + self.rng.below((max - min).saturating_add(1))) as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::descript::BindGroup;
+    use crate::sakura_player::{SakuraPlayer, VirtualClock};
+
+    fn scheduler(surfaces: &str) -> (SerikoScheduler<VirtualClock>, VirtualClock) {
+        let definition =
+            ShellDefinition::parse(&format!("descript\n{{\nversion,1\n}}\n{}", surfaces));
+        assert!(definition.warnings.is_empty(), "{:?}", definition.warnings);
+        let clock = VirtualClock::new();
+        let scheduler = SerikoScheduler::new(clock.clone(), definition).with_seed(1);
+        (scheduler, clock)
+    }
+
+    fn frame(animation: u32, frames: &[(Method, i32, i32, i32)]) -> SerikoEvent {
+        SerikoEvent::Frame {
+            scope: 0,
+            surface: 0,
+            animation,
+            frames: frames
+                .iter()
+                .map(|&(method, surface, x, y)| SerikoFrame {
+                    method,
+                    surface,
+                    x,
+                    y,
+                })
+                .collect(),
+        }
+    }
+
+    fn end(animation: u32) -> SerikoEvent {
+        SerikoEvent::End {
+            scope: 0,
+            animation,
+        }
+    }
+
+    #[test]
+    fn test_runonce_and_periodic_follow_the_clock() {
+        let (mut scheduler, clock) = scheduler(
+            "surface0\n{\n\
+             animation0.interval,runonce\n\
+             animation0.pattern0,overlay,100,0,0,0\n\
+             animation0.pattern1,overlay,101,50,0,0\n\
+             animation0.pattern2,overlay,-1,50,0,0\n\
+             animation1.interval,periodic,2\n\
+             animation1.pattern0,add,200,0,5,5\n\
+             animation1.pattern1,add,201,100,0,0\n\
+             animation1.pattern2,overlay,-1,100,0,0\n\
+             }\n",
+        );
+        scheduler.set_surface(0, 0);
+
+        assert_eq!(
+            scheduler.poll(),
+            vec![frame(0, &[(Method::Overlay, 100, 0, 0)])]
+        );
+        assert_eq!(scheduler.next_due_in(), Some(50));
+        clock.advance(49);
+        assert!(scheduler.poll().is_empty());
+        clock.advance(1);
+        assert_eq!(
+            scheduler.poll(),
+            vec![frame(0, &[(Method::Overlay, 101, 0, 0)])]
+        );
+        clock.advance(50);
+        assert_eq!(scheduler.poll(), vec![frame(0, &[]), end(0)]);
+
+        // periodic,2 は2秒ごと（add は前のパターンに重ねる）
+        assert_eq!(scheduler.next_due_in(), Some(900));
+        clock.advance(900);
+        assert!(scheduler.poll().is_empty());
+        clock.advance(1000);
+        assert_eq!(
+            scheduler.poll(),
+            vec![frame(1, &[(Method::Add, 200, 5, 5)])]
+        );
+        clock.advance(100);
+        scheduler.poll();
+        assert_eq!(
+            scheduler.active_frames(0),
+            vec![
+                SerikoFrame {
+                    method: Method::Add,
+                    surface: 200,
+                    x: 5,
+                    y: 5,
+                },
+                SerikoFrame {
+                    method: Method::Add,
+                    surface: 201,
+                    x: 0,
+                    y: 0,
+                },
+            ]
+        );
+        clock.advance(100);
+        assert_eq!(scheduler.poll(), vec![frame(1, &[]), end(1)]);
+    }
+
+    #[test]
+    fn test_random_intervals_fire_at_their_rates() {
+        let (mut scheduler, clock) = scheduler(
+            "surface0\n{\n\
+             animation0.interval,sometimes\n\
+             animation0.pattern0,overlay,100,0,0,0\n\
+             animation1.interval,rarely\n\
+             animation1.pattern0,overlay,101,0,0,0\n\
+             animation2.interval,random,10\n\
+             animation2.pattern0,overlay,102,0,0,0\n\
+             animation3.interval,never\n\
+             animation3.pattern0,overlay,103,0,0,0\n\
+             }\n",
+        );
+        scheduler.set_surface(0, 0);
+        assert!(scheduler.poll().is_empty());
+        assert_eq!(scheduler.next_due_in(), Some(CHECK_INTERVAL_MS));
+
+        // 1秒ごとに 1/2、1/4、1/10 の確率で始まり、never は始まらない
+        let mut starts = [0; 4];
+        for _ in 0..1000 {
+            clock.advance(CHECK_INTERVAL_MS);
+            for event in scheduler.poll() {
+                if let SerikoEvent::End { animation, .. } = event {
+                    starts[animation as usize] += 1;
+                }
+            }
+        }
+        assert!((400..600).contains(&starts[0]), "{:?}", starts);
+        assert!((170..330).contains(&starts[1]), "{:?}", starts);
+        assert!((50..150).contains(&starts[2]), "{:?}", starts);
+        assert_eq!(starts[3], 0);
+    }
+
+    #[test]
+    fn test_script_controls_animations() {
+        let (mut scheduler, clock) = scheduler(
+            "surface0\n{\n\
+             animation1.interval,yen-e\n\
+             animation1.pattern0,overlay,300,0,0,0\n\
+             animation2.interval,talk,3\n\
+             animation2.pattern0,overlay,400,0,0,0\n\
+             animation3.interval,never\n\
+             animation3.pattern0,overlay,500,100,0,0\n\
+             animation3.pattern1,start,[1]\n\
+             }\n",
+        );
+        let mut player = SakuraPlayer::new(clock.clone()).with_text_interval(0);
+        player.play("\\0\\s[0]\\i[3]あい\\![anim,pause,3]う\\![anim,offset,3,10,20]\\e");
+        for event in player.poll() {
+            scheduler.apply(&event.event);
+        }
+
+        // 3文字しゃべった後の talk と、\e の yen-e
+        assert_eq!(
+            scheduler.poll(),
+            vec![
+                frame(1, &[(Method::Overlay, 300, 0, 0)]),
+                end(1),
+                frame(2, &[(Method::Overlay, 400, 0, 0)]),
+                end(2),
+            ]
+        );
+        // \i[3] は一時停止中
+        assert_eq!(scheduler.next_due_in(), None);
+        clock.advance(500);
+        scheduler.apply(&PlayerEvent::AnimationControl {
+            scope: 0,
+            id: 3,
+            command: AnimationCommand::Resume,
+        });
+        // start パターンで yen-e のアニメーションも始まる
+        clock.advance(100);
+        assert_eq!(
+            scheduler.poll(),
+            vec![
+                frame(3, &[(Method::Overlay, 500, 10, 20)]),
+                end(3),
+                frame(1, &[(Method::Overlay, 300, 0, 0)]),
+                end(1),
+            ]
+        );
+
+        // サーフェスを変えると消える
+        scheduler.apply(&PlayerEvent::Surface { scope: 0, id: -1 });
+        assert_eq!(scheduler.surface(0), None);
+        assert!(scheduler.active_frames(0).is_empty());
+    }
+
+    #[test]
+    fn test_bind_exclusive_and_always() {
+        let (scheduler, clock) = scheduler(
+            "surface0\n{\n\
+             animation5.interval,bind\n\
+             animation5.pattern0,overlay,600,0,0,0\n\
+             animation5.pattern1,overlay,601,0,0,0\n\
+             animation6.interval,random,1\n\
+             animation6.option,exclusive\n\
+             animation6.pattern0,overlay,700,0,0,0\n\
+             animation6.pattern1,overlay,-1,500,0,0\n\
+             animation7.interval,always\n\
+             animation7.pattern0,overlay,800,300,0,0\n\
+             animation7.pattern1,overlay,-1,300,0,0\n\
+             }\n",
+        );
+        // descript.txt で最初から付けている着せ替え
+        let mut definition = scheduler.definition().clone();
+        definition.bind_groups.push(BindGroup {
+            scope: 0,
+            id: 5,
+            category: "頭".to_string(),
+            part: "リボン".to_string(),
+            default: true,
+        });
+        let mut scheduler = SerikoScheduler::new(clock.clone(), definition).with_seed(1);
+        scheduler.set_surface(0, 0);
+        assert_eq!(
+            scheduler.poll(),
+            vec![
+                frame(5, &[(Method::Add, 600, 0, 0)]),
+                frame(5, &[(Method::Add, 600, 0, 0), (Method::Add, 601, 0, 0)]),
+            ]
+        );
+
+        // always は繰り返す
+        clock.advance(600);
+        assert_eq!(
+            scheduler.poll(),
+            vec![frame(7, &[(Method::Overlay, 800, 0, 0)]), frame(7, &[]),]
+        );
+
+        // random,1 は毎秒、exclusive なので always は止まり、その間は始まらない
+        clock.advance(400);
+        assert_eq!(
+            scheduler.poll(),
+            vec![end(7), frame(6, &[(Method::Overlay, 700, 0, 0)])]
+        );
+        clock.advance(500);
+        assert_eq!(scheduler.poll(), vec![frame(6, &[]), end(6)]);
+        assert_eq!(scheduler.surface(0), Some(0));
+
+        scheduler.apply(&PlayerEvent::Bind {
+            scope: 0,
+            category: "頭".to_string(),
+            part: "リボン".to_string(),
+            bound: None,
+        });
+        assert_eq!(scheduler.poll(), vec![frame(5, &[])]);
+        assert!(scheduler.active_frames(0).is_empty());
+    }
+}
@@ -24,8 +24,12 @@ class ShioriManager {
    */
   async scanGhostDirectory(ghostDir) {
     try {
-      this.ghosts = await invoke("scan_ghost_directory", { ghostDir });
+      const result = await invoke("scan_ghost_directory", { ghostDir });
+      this.ghosts = result.ghosts;
       console.log(`🔍 検出されたゴースト: ${this.ghosts.length}個`);
+      result.errors.forEach((error) =>
+        console.warn(`⚠️ 読み込めないゴースト: ${error.path}`, error.error)
+      );
       return this.ghosts;
     } catch (error) {
       console.error("❌ ゴーストディレクトリスキャンエラー:", error);
@@ -35,15 +39,15 @@ class ShioriManager {
 
   /**
    * ゴーストを読み込み
-   * @param {string} ghostName - 読み込むゴースト名
+   * @param {string} ghostId - 読み込むゴーストのID
    * @returns {Promise<string>} 成功メッセージ
    */
-  async loadGhost(ghostName) {
+  async loadGhost(ghostId) {
     try {
-      const result = await invoke("load_ghost", { ghostName });
-      this.currentGhost = ghostName;
+      const result = await invoke("load_ghost", { ghostId });
+      this.currentGhost = ghostId;
       this.isInitialized = true;
-      console.log(`👻 ゴースト読み込み成功: ${ghostName}`);
+      console.log(`👻 ゴースト読み込み成功: ${ghostId}`);
       return result;
     } catch (error) {
       console.error("❌ ゴースト読み込みエラー:", error);
@@ -51,6 +55,38 @@ class ShioriManager {
     }
   }
 
+  /**
+   * .narファイルをインストール
+   * @param {string} path - .narファイルのパス
+   * @returns {Promise<Object>} インストール結果
+   */
+  async installNar(path) {
+    try {
+      const report = await invoke("install_nar", { path });
+      console.log(`📦 インストール完了: ${report.name}`, report);
+      await this.getAllGhosts();
+      return report;
+    } catch (error) {
+      console.error("❌ インストールエラー:", error);
+      throw error;
+    }
+  }
+
+  /**
+   * 現在のゴーストをネットワーク更新
+   * @returns {Promise<Object>} 更新結果
+   */
+  async networkUpdate() {
+    try {
+      const report = await invoke("network_update");
+      console.log(`🔄 ネットワーク更新完了: ${report.updated.length}ファイル`, report);
+      return report;
+    } catch (error) {
+      console.error("❌ ネットワーク更新エラー:", error);
+      throw error;
+    }
+  }
+
   /**
    * SHIORIリクエストを送信
    * @param {string} request - SHIORI/3.0形式のリクエスト文字列
@@ -72,7 +108,7 @@ class ShioriManager {
    * SHIORIイベントを送信
    * @param {string} event - イベント名
    * @param {Array<string>} references - リファレンス配列
-   * @returns {Promise<string>} SHIORIからのレスポンス
+   * @returns {Promise<void>} 返ってきたスクリプトは sakura-event で届く
    */
   async sendEvent(event, references = []) {
     try {
@@ -86,22 +122,83 @@ class ShioriManager {
     }
   }
 
+  /**
+   * さくらスクリプトを再生（イベントは sakura-event で届く）
+   * @param {string} script - さくらスクリプト
+   * @returns {Promise<number>} 再生の番号（選択肢を選ぶときに使う）
+   */
+  async playScript(script) {
+    return await invoke("play_sakura_script", { script });
+  }
+
+  /**
+   * さくらスクリプトの再生を一時停止・再開
+   * @param {boolean} paused - trueなら一時停止
+   */
+  async setScriptPaused(paused) {
+    await invoke(paused ? "pause_sakura_script" : "resume_sakura_script");
+  }
+
+  /**
+   * マウスイベントを送信（当たり判定を調べて OnMouseClick などにする）
+   * @param {Object} input - action, scope, surface, x, y, wheel, button, device
+   * @returns {Promise<void>} 返ってきたスクリプトは sakura-event で届く
+   */
+  async mouseEvent(input) {
+    try {
+      return await invoke("mouse_event", { input });
+    } catch (error) {
+      console.error("❌ マウスイベントエラー:", error);
+      throw error;
+    }
+  }
+
+  /**
+   * なでなでの認識の設定を変更
+   * @param {Object} config - event, reversals, minDistance, timeoutMs（省略した項目は既定値）
+   */
+  async setStrokeConfig(config) {
+    await invoke("set_stroke_config", { config });
+  }
+
+  /**
+   * バルーンを選ぶ
+   * @param {string|null} name - ディレクトリ名か名前（null ならゴーストの既定のバルーン）
+   */
+  async setBalloon(name) {
+    await invoke("set_balloon", { name });
+  }
+
+  /**
+   * バルーンの画像と文字を置く範囲を取得
+   * @param {Object} image - { kind: "character", scope, id, right } / { kind: "communicate", id } / { kind: "online", id }
+   * @returns {Promise<Object>} image（balloon://localhost/ に続くファイル名）、width、height、textRect など
+   */
+  async getBalloonMetrics(image) {
+    return await invoke("get_balloon_metrics", { image });
+  }
+
   /**
    * マウスクリックイベントを送信
    * @param {number} x - X座標
    * @param {number} y - Y座標
    * @param {string} button - ボタン名 ('left', 'right', 'middle')
+   * @param {number} scope - キャラクター（0がsakura、1がkero）
+   * @param {number} surface - 表示中のサーフェス
    * @returns {Promise<string>} SHIORIからのレスポンス
    */
-  async onMouseClick(x, y, button = "left") {
-    try {
-      const response = await invoke("on_mouse_click", { x, y, button });
-      console.log(`🖱️ マウスクリック: (${x}, ${y}) ${button}`);
-      return response;
-    } catch (error) {
-      console.error("❌ マウスクリックイベントエラー:", error);
-      throw error;
-    }
+  async onMouseClick(x, y, button = "left", scope = 0, surface = 0) {
+    const buttons = { left: 0, right: 1, middle: 2 };
+    const response = await this.mouseEvent({
+      action: "click",
+      scope,
+      surface,
+      x,
+      y,
+      button: buttons[button] ?? 0,
+    });
+    console.log(`🖱️ マウスクリック: (${x}, ${y}) ${button}`);
+    return response;
   }
 
   /**
@@ -125,7 +222,7 @@ class ShioriManager {
   async getCurrentGhost() {
     try {
       const ghost = await invoke("get_current_ghost");
-      this.currentGhost = ghost?.name || null;
+      this.currentGhost = ghost?.id || null;
       return ghost;
     } catch (error) {
       console.error("❌ 現在のゴースト取得エラー:", error);
@@ -252,13 +349,16 @@ class ShioriManager {
    */
   searchGhosts(query) {
     const lowercaseQuery = query.toLowerCase();
-    return this.ghosts.filter(
-      (ghost) =>
-        ghost.name.toLowerCase().includes(lowercaseQuery) ||
-        (ghost.description &&
-          ghost.description.toLowerCase().includes(lowercaseQuery)) ||
-        (ghost.craftman &&
-          ghost.craftman.toLowerCase().includes(lowercaseQuery))
+    // 名前や作者は descript（descript.txt の内容）にある
+    return this.ghosts.filter((ghost) =>
+      [
+        ghost.name,
+        ghost.descript?.name,
+        ghost.descript?.sakura_name,
+        ghost.descript?.kero_name,
+        ghost.descript?.craftman,
+        ghost.descript?.craftmanw,
+      ].some((value) => value?.toLowerCase().includes(lowercaseQuery))
     );
   }
 
@@ -335,6 +435,7 @@ globalThis.debugShiori = {
       await manager.sendEvent("OnTalk", ["テストメッセージ"]);
       await manager.onMouseClick(100, 150, "left");
       await manager.sendEvent("OnAITalk");
+      await manager.playScript("\\0\\s[0]テストです。\\w9\\e");
 
       console.log("✅ テストイベント完了");
     } catch (error) {
@@ -0,0 +1,1243 @@
+//! surfaces.txt
+//!
+//! シェルのサーフェス定義（element・当たり判定・SERIKO/MAYURAのアニメーション）を読み込む。
+//! 旧定義（`0interval`）と新定義（`animation0.interval`）のどちらも読み、
+//! 読めなかった行は行番号付きの警告として残す。
+//!
+//! ```text
+//! surface0-9,!5                          サーフェス0〜9（5を除く）
+//! {
+//! element0,base,surface0.png,0,0
+//! collisionex0,head,rect,34,20,157,72
+//! animation0.interval,random,3
+//! animation0.pattern0,overlay,6,50,0,0
+//! }
+//! surface.append0                        定義済みのサーフェスに追加
+//! sakura.surface.alias                   `\s[笑顔]` などの別名
+//! ```
+
+use crate::descript::{detect_encoding, BindGroup, Descript};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+pub const SURFACES_TXT: &str = "surfaces.txt";
+pub const SURFACETABLE_TXT: &str = "surfacetable.txt";
+
+/// 旧定義のパターンのウェイトの単位（ミリ秒）
+const OLD_WAIT_UNIT: u32 = 10;
+
+/// `surface0-N` の1つの範囲に含められるサーフェスの数の上限
+const MAX_SURFACE_RANGE: u32 = 10_000;
+
+/// シェルの定義（surfaces*.txt と surfacetable.txt）
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ShellDefinition {
+    /// `descript` ブロックの `version`（0が旧定義、1が新定義）
+    pub version: u32,
+    /// `descript` ブロックの値（キーは小文字）
+    pub descript: BTreeMap<String, String>,
+    pub surfaces: BTreeMap<u32, Surface>,
+    /// キャラクター（0がsakura、1がkero）ごとの `\s[...]` の別名
+    pub aliases: BTreeMap<u32, BTreeMap<String, Vec<u32>>>,
+    pub surface_table: SurfaceTable,
+    /// シェルの descript.txt の着せ替え
+    pub bind_groups: Vec<BindGroup>,
+    pub warnings: Vec<SurfaceWarning>,
+}
+
+/// 読めなかった行
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SurfaceWarning {
+    pub file: String,
+    /// 1から数えた行番号
+    pub line: usize,
+    pub message: String,
+}
+
+/// 1つのサーフェス
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Surface {
+    /// 重ねる画像（IDの順に描く）
+    pub elements: BTreeMap<u32, Element>,
+    pub collisions: BTreeMap<u32, Collision>,
+    pub animations: BTreeMap<u32, Animation>,
+    /// キャラクターごとのバルーンの位置（`sakura.balloon.offsetx` など）
+    pub balloon_offsets: BTreeMap<u32, Offset>,
+    /// `point.centerx` などの値（キーは小文字）
+    pub points: BTreeMap<String, i32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Element {
+    pub method: Method,
+    pub file: String,
+    pub x: i32,
+    pub y: i32,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Offset {
+    pub x: Option<i32>,
+    pub y: Option<i32>,
+}
+
+/// 描画メソッド
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Method {
+    Base,
+    Overlay,
+    OverlayFast,
+    OverlayMultiply,
+    Replace,
+    Interpolate,
+    Asis,
+    Reduce,
+    Bind,
+    Add,
+    Move,
+    Insert,
+    Start,
+    Stop,
+    AlternativeStart,
+    AlternativeStop,
+    ParallelStart,
+    ParallelStop,
+}
+
+impl Method {
+    pub fn parse(value: &str) -> Option<Self> {
+        let method = match value.trim().to_lowercase().as_str() {
+            "base" => Method::Base,
+            "overlay" => Method::Overlay,
+            "overlayfast" => Method::OverlayFast,
+            "overlaymultiply" => Method::OverlayMultiply,
+            "replace" => Method::Replace,
+            "interpolate" => Method::Interpolate,
+            "asis" => Method::Asis,
+            "reduce" => Method::Reduce,
+            "bind" => Method::Bind,
+            "add" => Method::Add,
+            "move" => Method::Move,
+            "insert" => Method::Insert,
+            "start" => Method::Start,
+            "stop" => Method::Stop,
+            "alternativestart" => Method::AlternativeStart,
+            "alternativestop" => Method::AlternativeStop,
+            "parallelstart" => Method::ParallelStart,
+            "parallelstop" => Method::ParallelStop,
+            _ => return None,
+        };
+        Some(method)
+    }
+
+    /// 画像を描かず、他のアニメーションを操作するメソッド
+    pub fn is_control(&self) -> bool {
+        matches!(
+            self,
+            Method::Insert
+                | Method::Start
+                | Method::Stop
+                | Method::AlternativeStart
+                | Method::AlternativeStop
+                | Method::ParallelStart
+                | Method::ParallelStop
+        )
+    }
+}
+
+/// 当たり判定
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Collision {
+    /// `Head` `bust` など（SHIORIに送る名前）
+    pub name: String,
+    pub shape: CollisionShape,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CollisionShape {
+    Rect {
+        left: i32,
+        top: i32,
+        right: i32,
+        bottom: i32,
+    },
+    Ellipse {
+        left: i32,
+        top: i32,
+        right: i32,
+        bottom: i32,
+    },
+    Circle {
+        x: i32,
+        y: i32,
+        radius: i32,
+    },
+    Polygon(Vec<(i32, i32)>),
+    /// 画像の指定した色の部分
+    Region {
+        file: String,
+        color: (u8, u8, u8),
+    },
+}
+
+impl CollisionShape {
+    /// 点 (x, y) が含まれるか（`Region` は `region` で画像のその点の色を調べる）
+    pub fn contains(
+        &self,
+        x: i32,
+        y: i32,
+        region: impl FnOnce(&str, i32, i32) -> Option<(u8, u8, u8)>,
+    ) -> bool {
+        match self {
+            CollisionShape::Rect {
+                left,
+                top,
+                right,
+                bottom,
+            } => {
+                (*left.min(right)..=*left.max(right)).contains(&x)
+                    && (*top.min(bottom)..=*top.max(bottom)).contains(&y)
+            }
+            CollisionShape::Ellipse {
+                left,
+                top,
+                right,
+                bottom,
+            } => {
+                let radius_x = (right - left).abs() as f64 / 2.0;
+                let radius_y = (bottom - top).abs() as f64 / 2.0;
+                if radius_x == 0.0 || radius_y == 0.0 {
+                    return false;
+                }
+                let dx = (x as f64 - (left + right) as f64 / 2.0) / radius_x;
+                let dy = (y as f64 - (top + bottom) as f64 / 2.0) / radius_y;
+                dx * dx + dy * dy <= 1.0
+            }
+            CollisionShape::Circle {
+                x: center_x,
+                y: center_y,
+                radius,
+            } => {
+                let (dx, dy) = ((x - center_x) as i64, (y - center_y) as i64);
+                dx * dx + dy * dy <= (*radius as i64) * (*radius as i64)
+            }
+            CollisionShape::Polygon(points) => polygon_contains(points, x, y),
+            CollisionShape::Region { file, color } => region(file, x, y) == Some(*color),
+        }
+    }
+}
+
+/// 偶奇規則で点が多角形の内側か調べる
+fn polygon_contains(points: &[(i32, i32)], x: i32, y: i32) -> bool {
+    let (x, y) = (x as f64 + 0.5, y as f64 + 0.5);
+    let mut inside = false;
+    let mut previous = match points.last() {
+        Some(point) => *point,
+        None => return false,
+    };
+    for &point in points {
+        let ((x1, y1), (x2, y2)) = (
+            (previous.0 as f64, previous.1 as f64),
+            (point.0 as f64, point.1 as f64),
+        );
+        if (y1 > y) != (y2 > y) && x < x1 + (y - y1) * (x2 - x1) / (y2 - y1) {
+            inside = !inside;
+        }
+        previous = point;
+    }
+    inside
+}
+
+/// SERIKOのアニメーション
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Animation {
+    pub intervals: Vec<Interval>,
+    /// `exclusive` `background` など（小文字）
+    pub options: Vec<String>,
+    pub patterns: BTreeMap<u32, Pattern>,
+    pub collisions: BTreeMap<u32, Collision>,
+}
+
+impl Animation {
+    pub fn has_option(&self, option: &str) -> bool {
+        self.options
+            .iter()
+            .any(|value| value.eq_ignore_ascii_case(option))
+    }
+}
+
+/// アニメーションを始めるタイミング
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Interval {
+    Sometimes,
+    Rarely,
+    /// 毎秒 1/n の確率
+    Random(u32),
+    /// n秒ごと
+    Periodic(u32),
+    Always,
+    Runonce,
+    Never,
+    /// `\e` のとき
+    YenE,
+    /// n文字しゃべるごと
+    Talk(u32),
+    /// 着せ替え
+    Bind,
+}
+
+/// アニメーションの1コマ
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Pattern {
+    pub method: Method,
+    /// 重ねるサーフェス（-1で消す、-2で全て消す）
+    pub surface: i32,
+    pub wait: Wait,
+    pub x: i32,
+    pub y: i32,
+    /// start・stop などで操作するアニメーション
+    pub animation_ids: Vec<u32>,
+}
+
+/// ウェイト（ミリ秒、範囲指定なら min〜max から選ぶ）
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Wait {
+    pub min: u32,
+    pub max: u32,
+}
+
+/// surfacetable.txt（サーフェスの説明）
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SurfaceTable {
+    pub groups: Vec<SurfaceGroup>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SurfaceGroup {
+    pub name: String,
+    pub surfaces: BTreeMap<u32, String>,
+}
+
+/// `__disabled` グループのサーフェスは一覧に出さない
+const DISABLED_GROUP: &str = "__disabled";
+
+impl SurfaceTable {
+    /// `グループ/説明` 形式の名前
+    pub fn name(&self, id: u32) -> Option<String> {
+        self.groups
+            .iter()
+            .filter(|group| group.name != DISABLED_GROUP)
+            .find_map(|group| {
+                let description = group.surfaces.get(&id)?;
+                Some(format!("{}/{}", group.name, description))
+            })
+    }
+
+    pub fn is_disabled(&self, id: u32) -> bool {
+        self.groups
+            .iter()
+            .any(|group| group.name == DISABLED_GROUP && group.surfaces.contains_key(&id))
+    }
+}
+
+impl Surface {
+    /// 点 (x, y) にある当たり判定（重なっていればIDの小さいもの）
+    pub fn hit_test(
+        &self,
+        x: i32,
+        y: i32,
+        mut region: impl FnMut(&str, i32, i32) -> Option<(u8, u8, u8)>,
+    ) -> Option<&Collision> {
+        self.collisions
+            .values()
+            .find(|collision| collision.shape.contains(x, y, &mut region))
+    }
+}
+
+impl ShellDefinition {
+    /// シェルのディレクトリから surfaces*.txt（surfaces.txt が先）と surfacetable.txt を読み込む
+    pub fn load(shell_dir: &Path) -> Result<Self, io::Error> {
+        let mut files: Vec<(String, std::path::PathBuf)> = fs::read_dir(shell_dir)?
+            .filter_map(Result::ok)
+            .map(|entry| {
+                let name = entry.file_name().to_string_lossy().to_lowercase();
+                (name, entry.path())
+            })
+            .filter(|(name, path)| {
+                name.starts_with("surfaces") && name.ends_with(".txt") && path.is_file()
+            })
+            .collect();
+        files.sort_by(|a, b| (a.0 != SURFACES_TXT, &a.0).cmp(&(b.0 != SURFACES_TXT, &b.0)));
+
+        let mut definition = ShellDefinition::default();
+        for (name, path) in files {
+            definition.parse_file(&name, &decode(&fs::read(path)?));
+        }
+        let table = shell_dir.join(SURFACETABLE_TXT);
+        if table.is_file() {
+            definition.parse_surface_table(&decode(&fs::read(table)?));
+        }
+        let descript = shell_dir.join("descript.txt");
+        if descript.is_file() {
+            definition.bind_groups = Descript::load(&descript)?.bind_groups();
+        }
+        Ok(definition)
+    }
+
+    /// `charset` 行に従って変換してから読み込む（指定が無ければ Shift_JIS）
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        Self::parse(&decode(bytes))
+    }
+
+    /// 変換済みの surfaces.txt を読み込む
+    pub fn parse(text: &str) -> Self {
+        let mut definition = ShellDefinition::default();
+        definition.parse_file(SURFACES_TXT, text);
+        definition
+    }
+
+    pub fn surface(&self, id: u32) -> Option<&Surface> {
+        self.surfaces.get(&id)
+    }
+
+    /// `\s[...]` の別名の候補
+    pub fn alias(&self, scope: u32, key: &str) -> Option<&[u32]> {
+        self.aliases
+            .get(&scope)?
+            .get(&key.trim().to_lowercase())
+            .map(Vec::as_slice)
+    }
+
+    /// surfaces.txt をもう1つ読み込む（先に読んだ定義に追加する）
+    pub fn parse_file(&mut self, file: &str, text: &str) {
+        let mut parser = Parser {
+            definition: self,
+            file,
+        };
+        parser.parse(text);
+    }
+
+    /// surfacetable.txt を読み込む
+    pub fn parse_surface_table(&mut self, text: &str) {
+        let mut group: Option<SurfaceGroup> = None;
+        let mut pending: Option<String> = None;
+        for (line_number, line) in lines(text) {
+            let mut warn = |message: String| {
+                self.warnings.push(SurfaceWarning {
+                    file: SURFACETABLE_TXT.to_string(),
+                    line: line_number,
+                    message,
+                })
+            };
+            if let Some(current) = group.as_mut() {
+                if line == "}" {
+                    self.surface_table.groups.extend(group.take());
+                    continue;
+                }
+                match line
+                    .split_once(',')
+                    .map(|(id, text)| (id.trim().parse(), text))
+                {
+                    Some((Ok(id), description)) => {
+                        current.surfaces.insert(id, description.trim().to_string());
+                    }
+                    _ => warn(format!("Invalid surface description: {}", line)),
+                }
+                continue;
+            }
+
+            if line == "{" {
+                group = Some(SurfaceGroup {
+                    name: pending.take().unwrap_or_default(),
+                    surfaces: BTreeMap::new(),
+                });
+                continue;
+            }
+            match line.split_once(',') {
+                Some((key, name)) if key.trim().eq_ignore_ascii_case("group") => {
+                    pending = Some(name.trim().to_string());
+                }
+                Some((key, _)) if is_setting(key, &["charset", "version"]) => {}
+                _ => warn(format!("Unknown line: {}", line)),
+            }
+        }
+        self.surface_table.groups.extend(group);
+    }
+}
+
+/// BOM・`charset` 行に従ってテキストにする
+fn decode(bytes: &[u8]) -> String {
+    let (text, _, _) = detect_encoding(bytes).decode(bytes);
+    text.into_owned()
+}
+
+/// コメントと空行を除いた行（1から数えた行番号付き）
+fn lines(text: &str) -> impl Iterator<Item = (usize, &str)> {
+    text.trim_start_matches('\u{feff}')
+        .lines()
+        .enumerate()
+        .map(|(index, line)| (index + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with("//"))
+}
+
+fn is_setting(key: &str, names: &[&str]) -> bool {
+    names
+        .iter()
+        .any(|name| key.trim().eq_ignore_ascii_case(name))
+}
+
+/// ブロックの種類
+enum Block {
+    Descript,
+    Surfaces {
+        ids: Vec<u32>,
+    },
+    Alias(u32),
+    /// 読まないブロック（`cursor` `tooltips`）
+    Ignored,
+}
+
+/// 読んでいる途中のブロック
+struct OpenBlock {
+    block: Block,
+    line: usize,
+    /// ブロックの内容（閉じたときに対象のサーフェスへ追加する）
+    surface: Surface,
+}
+
+struct Parser<'a> {
+    definition: &'a mut ShellDefinition,
+    file: &'a str,
+}
+
+impl Parser<'_> {
+    fn warn(&mut self, line: usize, message: String) {
+        self.definition.warnings.push(SurfaceWarning {
+            file: self.file.to_string(),
+            line,
+            message,
+        });
+    }
+
+    fn parse(&mut self, text: &str) {
+        // `{` を待っているブロック名
+        let mut pending: Option<(usize, String)> = None;
+        let mut current: Option<OpenBlock> = None;
+
+        for (line_number, line) in lines(text) {
+            if let Some(open) = current.as_mut() {
+                if line == "}" {
+                    let open = current.take().unwrap();
+                    self.close(open);
+                } else if let Err(message) = self.block_line(open, line) {
+                    self.warn(line_number, message);
+                }
+                continue;
+            }
+
+            if let Some(header) = line.strip_suffix('{') {
+                let (header_line, header) = match (header.trim(), pending.take()) {
+                    ("", Some(pending)) => pending,
+                    ("", None) => (line_number, String::new()),
+                    (header, previous) => {
+                        if let Some((previous_line, previous)) = previous {
+                            self.warn(previous_line, format!("Expected '{{' after {}", previous));
+                        }
+                        (line_number, header.to_string())
+                    }
+                };
+                current = Some(self.open(header_line, &header));
+                continue;
+            }
+
+            if let Some((previous_line, previous)) = pending.take() {
+                self.warn(previous_line, format!("Expected '{{' after {}", previous));
+            }
+            match line.split_once(',') {
+                Some((key, _)) if is_setting(key, &["charset"]) => {}
+                _ => pending = Some((line_number, line.to_string())),
+            }
+        }
+
+        if let Some((line_number, header)) = pending {
+            self.warn(line_number, format!("Expected '{{' after {}", header));
+        }
+        if let Some(open) = current {
+            self.warn(open.line, "Missing '}'".to_string());
+            self.close(open);
+        }
+    }
+
+    fn open(&mut self, line: usize, header: &str) -> OpenBlock {
+        let block = match parse_header(header) {
+            Ok(Block::Surfaces { ids }) if is_append(header) => {
+                // surface.append は定義済みのサーフェスだけ
+                let (ids, missing): (Vec<u32>, Vec<u32>) = ids
+                    .into_iter()
+                    .partition(|id| self.definition.surfaces.contains_key(id));
+                if !missing.is_empty() {
+                    self.warn(
+                        line,
+                        format!("surface.append to undefined surfaces: {:?}", missing),
+                    );
+                }
+                Block::Surfaces { ids }
+            }
+            Ok(block) => block,
+            Err(message) => {
+                self.warn(line, message);
+                Block::Ignored
+            }
+        };
+        OpenBlock {
+            block,
+            line,
+            surface: Surface::default(),
+        }
+    }
+
+    fn block_line(&mut self, open: &mut OpenBlock, line: &str) -> Result<(), String> {
+        let (key, value) = line
+            .split_once(',')
+            .ok_or_else(|| format!("Invalid line: {}", line))?;
+        let key = key.trim().to_lowercase();
+        let value = value.trim();
+        match &open.block {
+            Block::Descript => {
+                if key == "version" {
+                    self.definition.version = number(value, "version")?;
+                }
+                self.definition.descript.insert(key, value.to_string());
+                Ok(())
+            }
+            Block::Alias(scope) => {
+                let ids = parse_ids(value)?;
+                self.definition
+                    .aliases
+                    .entry(*scope)
+                    .or_default()
+                    .insert(key, ids);
+                Ok(())
+            }
+            Block::Surfaces { .. } => surface_line(&mut open.surface, &key, value),
+            Block::Ignored => Ok(()),
+        }
+    }
+
+    fn close(&mut self, open: OpenBlock) {
+        if let Block::Surfaces { ids } = open.block {
+            for id in ids {
+                merge(
+                    self.definition.surfaces.entry(id).or_default(),
+                    open.surface.clone(),
+                );
+            }
+        }
+    }
+}
+
+fn is_append(header: &str) -> bool {
+    header.trim().to_lowercase().starts_with("surface.append")
+}
+
+fn parse_header(header: &str) -> Result<Block, String> {
+    let lower = header.trim().to_lowercase();
+    if lower == "descript" {
+        return Ok(Block::Descript);
+    }
+    if let Some(scope) = lower.strip_suffix(".surface.alias") {
+        return scope_id(scope)
+            .map(Block::Alias)
+            .ok_or_else(|| format!("Unknown character: {}", header));
+    }
+    if let Some(ids) = lower
+        .strip_prefix("surface.append")
+        .or_else(|| lower.strip_prefix("surface"))
+    {
+        return parse_surface_ids(ids).map(|ids| Block::Surfaces { ids });
+    }
+    let name = lower.rsplit('.').next().unwrap_or_default();
+    if name == "cursor" || name == "tooltips" {
+        return Ok(Block::Ignored);
+    }
+    Err(format!("Unknown block: {}", header))
+}
+
+/// `0-9,20-22,!5` → [0, 1, 2, 3, 4, 6, 7, 8, 9, 20, 21, 22]
+pub fn parse_surface_ids(spec: &str) -> Result<Vec<u32>, String> {
+    let mut ids = Vec::new();
+    let mut excluded = Vec::new();
+    for item in spec
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+    {
+        let (item, target) = match item.strip_prefix('!') {
+            Some(item) => (item, &mut excluded),
+            None => (item, &mut ids),
+        };
+        let item = item.strip_prefix("surface").unwrap_or(item);
+        let invalid = || format!("Invalid surface range: {}", spec);
+        match item.split_once('-') {
+            Some((start, end)) => {
+                let start: u32 = start.trim().parse().map_err(|_| invalid())?;
+                let end: u32 = end.trim().parse().map_err(|_| invalid())?;
+                if start.abs_diff(end) >= MAX_SURFACE_RANGE {
+                    return Err(format!("Surface range too large: {}", spec));
+                }
+                target.extend(start.min(end)..=start.max(end));
+            }
+            None => target.push(item.parse().map_err(|_| invalid())?),
+        }
+    }
+    ids.sort_unstable();
+    ids.dedup();
+    ids.retain(|id| !excluded.contains(id));
+    if ids.is_empty() {
+        return Err(format!("No surfaces in: surface{}", spec));
+    }
+    Ok(ids)
+}
+
+/// `sakura` → 0、`kero` → 1、`char2` → 2
+fn scope_id(name: &str) -> Option<u32> {
+    match name {
+        "sakura" => Some(0),
+        "kero" => Some(1),
+        _ => name.strip_prefix("char")?.parse().ok(),
+    }
+}
+
+/// 先頭の数字と残り（`12pattern0` → (12, "pattern0")）
+fn leading_number(text: &str) -> Option<(u32, &str)> {
+    let end = text
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(text.len());
+    Some((text[..end].parse().ok()?, &text[end..]))
+}
+
+/// `prefix` の後が数字だけならその数字
+fn indexed(key: &str, prefix: &str) -> Option<u32> {
+    match leading_number(key.strip_prefix(prefix)?)? {
+        (index, "") => Some(index),
+        _ => None,
+    }
+}
+
+fn number<T: FromStr>(value: &str, what: &str) -> Result<T, String> {
+    value
+        .trim()
+        .parse()
+        .map_err(|_| format!("Invalid {}: {:?}", what, value))
+}
+
+fn field<'a>(fields: &[&'a str], index: usize, what: &str) -> Result<&'a str, String> {
+    fields
+        .get(index)
+        .copied()
+        .ok_or_else(|| format!("Missing {}", what))
+}
+
+/// 省略できる座標（無ければ0）
+fn coordinate(fields: &[&str], index: usize) -> Result<i32, String> {
+    match fields.get(index) {
+        Some(value) if !value.is_empty() => number(value, "coordinate"),
+        _ => Ok(0),
+    }
+}
+
+/// `[1.2]` `(1,2)` `1` → [1, 2]
+fn parse_ids(value: &str) -> Result<Vec<u32>, String> {
+    value
+        .split(['.', ',', '[', ']', '(', ')'])
+        .map(str::trim)
+        .filter(|id| !id.is_empty())
+        .map(|id| number(id, "id"))
+        .collect()
+}
+
+/// サーフェスのブロック内の1行
+fn surface_line(surface: &mut Surface, key: &str, value: &str) -> Result<(), String> {
+    let fields: Vec<&str> = value.split(',').map(str::trim).collect();
+
+    if let Some(id) = indexed(key, "element") {
+        let method = field(&fields, 0, "method")?;
+        let element = Element {
+            method: Method::parse(method).ok_or_else(|| format!("Unknown method: {}", method))?,
+            file: field(&fields, 1, "file")?.replace('\\', "/"),
+            x: coordinate(&fields, 2)?,
+            y: coordinate(&fields, 3)?,
+        };
+        surface.elements.insert(id, element);
+        return Ok(());
+    }
+    if let Some(id) = indexed(key, "collisionex") {
+        surface.collisions.insert(id, collision_ex(&fields)?);
+        return Ok(());
+    }
+    if let Some(id) = indexed(key, "collision") {
+        surface.collisions.insert(id, collision(&fields)?);
+        return Ok(());
+    }
+    if let Some(rest) = key.strip_prefix("animation") {
+        let (id, rest) = leading_number(rest).ok_or_else(|| format!("Unknown key: {}", key))?;
+        let rest = rest
+            .strip_prefix('.')
+            .ok_or_else(|| format!("Unknown key: {}", key))?;
+        let animation = surface.animations.entry(id).or_default();
+        return animation_line(animation, rest, &fields, false);
+    }
+    if let Some((id, rest)) = leading_number(key) {
+        // 旧定義
+        let animation = surface.animations.entry(id).or_default();
+        return animation_line(animation, rest, &fields, true);
+    }
+    if let Some(name) = key.strip_prefix("point.") {
+        surface
+            .points
+            .insert(name.to_string(), number(value, "point")?);
+        return Ok(());
+    }
+    if let Some((scope, axis)) = key.split_once(".balloon.offset") {
+        let scope = scope_id(scope).ok_or_else(|| format!("Unknown character: {}", key))?;
+        let offset = surface.balloon_offsets.entry(scope).or_default();
+        let value = Some(number(value, "offset")?);
+        match axis {
+            "x" => offset.x = value,
+            "y" => offset.y = value,
+            _ => return Err(format!("Unknown key: {}", key)),
+        }
+        return Ok(());
+    }
+    Err(format!("Unknown key: {}", key))
+}
+
+/// `collision0,left,top,right,bottom,name`
+fn collision(fields: &[&str]) -> Result<Collision, String> {
+    let coordinates = (0..4)
+        .map(|index| number(field(fields, index, "coordinate")?, "coordinate"))
+        .collect::<Result<Vec<i32>, String>>()?;
+    Ok(Collision {
+        name: field(fields, 4, "collision name")?.to_string(),
+        shape: CollisionShape::Rect {
+            left: coordinates[0],
+            top: coordinates[1],
+            right: coordinates[2],
+            bottom: coordinates[3],
+        },
+    })
+}
+
+/// `collisionex0,name,rect|ellipse|circle|polygon|region,...`
+fn collision_ex(fields: &[&str]) -> Result<Collision, String> {
+    let name = field(fields, 0, "collision name")?.to_string();
+    let kind = field(fields, 1, "collision shape")?.to_lowercase();
+    let values = &fields[2..];
+    let numbers = |count: usize| -> Result<Vec<i32>, String> {
+        if values.len() < count {
+            return Err(format!("Too few values for {}", kind));
+        }
+        values[..count]
+            .iter()
+            .map(|value| number(value, "coordinate"))
+            .collect()
+    };
+    let shape = match kind.as_str() {
+        "rect" | "ellipse" => {
+            let n = numbers(4)?;
+            let (left, top, right, bottom) = (n[0], n[1], n[2], n[3]);
+            if kind == "rect" {
+                CollisionShape::Rect {
+                    left,
+                    top,
+                    right,
+                    bottom,
+                }
+            } else {
+                CollisionShape::Ellipse {
+                    left,
+                    top,
+                    right,
+                    bottom,
+                }
+            }
+        }
+        "circle" => {
+            let n = numbers(3)?;
+            CollisionShape::Circle {
+                x: n[0],
+                y: n[1],
+                radius: n[2],
+            }
+        }
+        "polygon" => {
+            let n = numbers(values.len())?;
+            if n.len() < 6 || n.len() % 2 != 0 {
+                return Err("A polygon needs at least 3 points".to_string());
+            }
+            CollisionShape::Polygon(n.chunks(2).map(|point| (point[0], point[1])).collect())
+        }
+        "region" => {
+            let file = field(values, 0, "region file")?.replace('\\', "/");
+            let color = (1..4)
+                .map(|index| number(field(values, index, "color")?, "color"))
+                .collect::<Result<Vec<u8>, String>>()?;
+            CollisionShape::Region {
+                file,
+                color: (color[0], color[1], color[2]),
+            }
+        }
+        _ => return Err(format!("Unknown collision shape: {}", kind)),
+    };
+    Ok(Collision { name, shape })
+}
+
+/// `interval` `option` `patternN` `collisionN` `collisionexN`
+fn animation_line(
+    animation: &mut Animation,
+    key: &str,
+    fields: &[&str],
+    old: bool,
+) -> Result<(), String> {
+    if key == "interval" {
+        animation.intervals = parse_intervals(fields)?;
+    } else if key == "option" {
+        animation.options = fields
+            .iter()
+            .flat_map(|value| value.split('+'))
+            .map(|value| value.trim().to_lowercase())
+            .filter(|value| !value.is_empty())
+            .collect();
+    } else if let Some(id) = indexed(key, "pattern") {
+        let pattern = if old {
+            old_pattern(fields)?
+        } else {
+            new_pattern(fields)?
+        };
+        animation.patterns.insert(id, pattern);
+    } else if let (Some(id), false) = (indexed(key, "collisionex"), old) {
+        animation.collisions.insert(id, collision_ex(fields)?);
+    } else if let (Some(id), false) = (indexed(key, "collision"), old) {
+        animation.collisions.insert(id, collision(fields)?);
+    } else {
+        return Err(format!("Unknown animation key: {}", key));
+    }
+    Ok(())
+}
+
+/// `random,3` `bind+sometimes` `talk,2`
+fn parse_intervals(fields: &[&str]) -> Result<Vec<Interval>, String> {
+    let kinds = field(fields, 0, "interval")?;
+    let argument = || -> Result<u32, String> {
+        number(field(fields, 1, "interval argument")?, "interval argument")
+    };
+    kinds
+        .split('+')
+        .map(|kind| {
+            let interval = match kind.trim().to_lowercase().as_str() {
+                "sometimes" => Interval::Sometimes,
+                "rarely" => Interval::Rarely,
+                "random" => Interval::Random(argument()?),
+                "periodic" => Interval::Periodic(argument()?),
+                "always" => Interval::Always,
+                "runonce" => Interval::Runonce,
+                "never" => Interval::Never,
+                "yen-e" => Interval::YenE,
+                "talk" => Interval::Talk(argument()?),
+                "bind" => Interval::Bind,
+                _ => return Err(format!("Unknown interval: {}", kind)),
+            };
+            Ok(interval)
+        })
+        .collect()
+}
+
+/// `10` `50-100`（`unit` 倍してミリ秒にする）
+fn parse_wait(value: &str, unit: u32) -> Result<Wait, String> {
+    let (min, max) = match value.split_once('-') {
+        Some((min, max)) => (number::<u32>(min, "wait")?, number::<u32>(max, "wait")?),
+        None => {
+            let wait = number(value, "wait")?;
+            (wait, wait)
+        }
+    };
+    let scale = |wait: u32| {
+        wait.checked_mul(unit)
+            .ok_or_else(|| format!("Wait too large: {}", value))
+    };
+    Ok(Wait {
+        min: scale(min.min(max))?,
+        max: scale(min.max(max))?,
+    })
+}
+
+fn method(value: &str) -> Result<Method, String> {
+    Method::parse(value).ok_or_else(|| format!("Unknown method: {}", value))
+}
+
+/// 新定義: `method,surface,wait,x,y`（start系は `method,[wait,]ids`）
+fn new_pattern(fields: &[&str]) -> Result<Pattern, String> {
+    let method = method(field(fields, 0, "method")?)?;
+    if method.is_control() {
+        let rest = &fields[1..];
+        let bracket = rest.iter().position(|value| value.starts_with(['[', '(']));
+        let (wait, ids) = match (bracket, rest.len()) {
+            (Some(index), _) => (rest[..index].first(), rest[index..].join(",")),
+            (None, 0) => return Err("Missing animation id".to_string()),
+            (None, 1) => (None, rest[0].to_string()),
+            (None, _) => (rest.first(), rest[1..].join(",")),
+        };
+        return Ok(Pattern {
+            method,
+            surface: -1,
+            wait: wait.map_or(Ok(Wait::default()), |wait| parse_wait(wait, 1))?,
+            x: 0,
+            y: 0,
+            animation_ids: parse_ids(&ids)?,
+        });
+    }
+    Ok(Pattern {
+        method,
+        surface: number(field(fields, 1, "surface")?, "surface")?,
+        wait: parse_wait(field(fields, 2, "wait")?, 1)?,
+        x: coordinate(fields, 3)?,
+        y: coordinate(fields, 4)?,
+        animation_ids: Vec::new(),
+    })
+}
+
+/// 旧定義: `surface,wait,method,x,y`（ウェイトは10ミリ秒単位、start系は `surface,wait,method,ids`）
+fn old_pattern(fields: &[&str]) -> Result<Pattern, String> {
+    let surface = number(field(fields, 0, "surface")?, "surface")?;
+    let wait = parse_wait(field(fields, 1, "wait")?, OLD_WAIT_UNIT)?;
+    let method = method(field(fields, 2, "method")?)?;
+    if method.is_control() {
+        return Ok(Pattern {
+            method,
+            surface,
+            wait,
+            x: 0,
+            y: 0,
+            animation_ids: parse_ids(&fields[3..].join(","))?,
+        });
+    }
+    Ok(Pattern {
+        method,
+        surface,
+        wait,
+        x: coordinate(fields, 3)?,
+        y: coordinate(fields, 4)?,
+        animation_ids: Vec::new(),
+    })
+}
+
+/// ブロックの内容をサーフェスに追加する（同じIDは後の定義で置き換える）
+fn merge(surface: &mut Surface, addition: Surface) {
+    surface.elements.extend(addition.elements);
+    surface.collisions.extend(addition.collisions);
+    for (id, addition) in addition.animations {
+        let animation = surface.animations.entry(id).or_default();
+        if !addition.intervals.is_empty() {
+            animation.intervals = addition.intervals;
+        }
+        if !addition.options.is_empty() {
+            animation.options = addition.options;
+        }
+        animation.patterns.extend(addition.patterns);
+        animation.collisions.extend(addition.collisions);
+    }
+    for (scope, offset) in addition.balloon_offsets {
+        let current = surface.balloon_offsets.entry(scope).or_default();
+        current.x = offset.x.or(current.x);
+        current.y = offset.y.or(current.y);
+    }
+    surface.points.extend(addition.points);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_old_and_new_syntax() {
+        let definition = ShellDefinition::parse(
+            "charset,UTF-8\r\n\
+             descript\r\n{\r\nversion,1\r\n}\r\n\
+             surface0-3,!2\r\n{\r\n\
+             element0,base,surface0.png,0,0\r\n\
+             collision0,10,20,30,40,Head\r\n\
+             animation0.interval,sometimes+bind\r\n\
+             animation0.option,exclusive\r\n\
+             animation0.pattern0,overlay,100,50-100,5,6\r\n\
+             animation0.pattern1,alternativestart,[1.2]\r\n\
+             1interval,random,3\r\n\
+             1pattern0,101,5,overlay,0,0\r\n\
+             sakura.balloon.offsetx,40\r\n\
+             element1,unknown,x.png,0,0\r\n\
+             }\r\n\
+             surface.append1 {\r\n\
+             element1,overlay,face.png,10,10\r\n\
+             }\r\n",
+        );
+        assert_eq!(definition.version, 1);
+        assert_eq!(
+            definition.surfaces.keys().copied().collect::<Vec<_>>(),
+            vec![0, 1, 3]
+        );
+        assert_eq!(definition.surfaces[&0].elements.len(), 1);
+        assert_eq!(
+            definition.surfaces[&1].elements[&1],
+            Element {
+                method: Method::Overlay,
+                file: "face.png".to_string(),
+                x: 10,
+                y: 10
+            }
+        );
+
+        let surface = &definition.surfaces[&3];
+        assert_eq!(surface.collisions[&0].name, "Head");
+        assert_eq!(surface.balloon_offsets[&0].x, Some(40));
+
+        let animation = &surface.animations[&0];
+        assert_eq!(
+            animation.intervals,
+            vec![Interval::Sometimes, Interval::Bind]
+        );
+        assert!(animation.has_option("exclusive"));
+        assert_eq!(animation.patterns[&0].wait, Wait { min: 50, max: 100 });
+        assert_eq!(animation.patterns[&1].animation_ids, vec![1, 2]);
+
+        // 旧定義のウェイトは10ミリ秒単位
+        let old = &surface.animations[&1];
+        assert_eq!(old.intervals, vec![Interval::Random(3)]);
+        assert_eq!(old.patterns[&0].surface, 101);
+        assert_eq!(old.patterns[&0].method, Method::Overlay);
+        assert_eq!(old.patterns[&0].wait, Wait { min: 50, max: 50 });
+
+        assert_eq!(definition.warnings.len(), 1);
+        assert_eq!(definition.warnings[0].line, 17);
+    }
+
+    #[test]
+    fn test_warnings_and_aliases() {
+        let definition = ShellDefinition::parse(
+            "surface.append9\n{\n}\n\
+             surface4\n{\n\
+             collisionex0,bust,polygon,0,0,10,0,10,10\n\
+             collisionex1,face,circle,5\n\
+             broken\n\
+             }\n\
+             sakura.surface.alias\n{\n笑顔,[1,2]\n}\n\
+             surfaceX\n{\n}\n\
+             surface5\n{\n",
+        );
+        let messages: Vec<(usize, &str)> = definition
+            .warnings
+            .iter()
+            .map(|warning| (warning.line, warning.message.as_str()))
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                (1, "surface.append to undefined surfaces: [9]"),
+                (7, "Too few values for circle"),
+                (8, "Invalid line: broken"),
+                (14, "Invalid surface range: x"),
+                (17, "Missing '}'"),
+            ]
+        );
+        assert_eq!(
+            definition.surfaces[&4].collisions[&0].shape,
+            CollisionShape::Polygon(vec![(0, 0), (10, 0), (10, 10)])
+        );
+        assert!(definition.surfaces.contains_key(&5));
+        assert_eq!(definition.alias(0, "笑顔"), Some(&[1, 2][..]));
+    }
+
+    #[test]
+    fn test_parse_surface_ids() {
+        assert_eq!(parse_surface_ids("0-2,surface5,!1").unwrap(), vec![0, 2, 5]);
+        // 逆順の範囲、重複、範囲の除外
+        assert_eq!(
+            parse_surface_ids("5-3, 4, 10-12, !surface11-12").unwrap(),
+            vec![3, 4, 5, 10]
+        );
+        // 除外は書いた順によらない
+        assert_eq!(parse_surface_ids("!0,0-1").unwrap(), vec![1]);
+        assert!(parse_surface_ids("0-1,!0-1").is_err());
+        assert!(parse_surface_ids("1-x").is_err());
+
+        // 大きすぎる範囲は展開しない
+        assert_eq!(parse_surface_ids("0-9999").unwrap().len(), 10_000);
+        assert!(parse_surface_ids("0-10000").is_err());
+        assert!(parse_surface_ids("4294967295-0").is_err());
+    }
+
+    #[test]
+    fn test_parse_wait() {
+        assert_eq!(parse_wait("100-50", 1).unwrap(), Wait { min: 50, max: 100 });
+        assert_eq!(
+            parse_wait("5", OLD_WAIT_UNIT).unwrap(),
+            Wait { min: 50, max: 50 }
+        );
+        assert!(parse_wait("429496730", OLD_WAIT_UNIT).is_err());
+        assert!(parse_wait("-1", 1).is_err());
+    }
+
+    #[test]
+    fn test_load_mock_shell() {
+        let shell =
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("../assets/ghost/mock_nanai/shell/master");
+        let definition = ShellDefinition::load(&shell).unwrap();
+        assert_eq!(definition.warnings, vec![]);
+        assert_eq!(definition.surfaces.len(), 41);
+
+        let surface = &definition.surfaces[&0];
+        assert_eq!(surface.elements[&0].file, "surface0.png");
+        assert_eq!(surface.collisions.len(), 4);
+        assert_eq!(surface.collisions[&0].name, "head");
+        assert_eq!(surface.animations[&0].intervals, vec![Interval::Random(3)]);
+        assert_eq!(surface.animations[&0].patterns[&1].surface, -1);
+        assert_eq!(surface.animations[&100].intervals, vec![Interval::Bind]);
+        assert_eq!(definition.surfaces[&1].elements[&1].file, "surface01.png");
+
+        let table = &definition.surface_table;
+        assert_eq!(table.name(0).as_deref(), Some("本体基本/素"));
+        assert!(table.is_disabled(100));
+        assert_eq!(table.name(100), None);
+    }
+
+    #[test]
+    fn test_collision_hit_test() {
+        let definition = ShellDefinition::parse(
+            "surface0\n{\n\
+             collisionex0,face,ellipse,10,10,30,20\n\
+             collisionex1,hand,polygon,0,40,20,40,0,60\n\
+             collisionex2,ribbon,circle,50,50,5\n\
+             collisionex3,skirt,region,skirt.png,255,0,0\n\
+             collision4,0,0,100,100,body\n\
+             }\n",
+        );
+        let surface = definition.surface(0).unwrap();
+        let hit = |x, y| {
+            surface
+                .hit_test(x, y, |file, x, _| {
+                    assert_eq!(file, "skirt.png");
+                    (x < 100).then_some(if x >= 80 { (255, 0, 0) } else { (0, 0, 0) })
+                })
+                .map(|collision| collision.name.as_str())
+        };
+        assert_eq!(hit(20, 15), Some("face"));
+        assert_eq!(hit(11, 11), Some("body"));
+        assert_eq!(hit(5, 45), Some("hand"));
+        assert_eq!(hit(19, 59), Some("body"));
+        assert_eq!(hit(53, 53), Some("ribbon"));
+        assert_eq!(hit(90, 90), Some("skirt"));
+        assert_eq!(hit(101, 50), None);
+    }
+}